@@ -14,6 +14,7 @@ use capsules::timer::TimerDriver;
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use capsules::virtual_i2c::{I2CDevice, MuxI2C};
 use capsules::virtual_spi::{VirtualSpiMasterDevice, MuxSpiMaster};
+use capsules::virtual_uart::{MuxUart, VirtualUartDevice};
 use kernel::{Chip, Platform};
 use kernel::hil;
 use kernel::hil::Controller;
@@ -28,6 +29,8 @@ mod test_take_map_cell;
 
 static mut SPI_READ_BUF: [u8; 64] = [0; 64];
 static mut SPI_WRITE_BUF: [u8; 64] = [0; 64];
+// Chip selects apps may use through the SPI syscall driver
+static SPI_CHIP_SELECTS: [u8; 1] = [0];
 
 unsafe fn load_processes() -> &'static mut [Option<kernel::process::Process<'static>>] {
     extern "C" {
@@ -40,6 +43,14 @@ unsafe fn load_processes() -> &'static mut [Option<kernel::process::Process<'sta
     // how should the kernel respond when a process faults
     const FAULT_RESPONSE: kernel::process::FaultResponse = kernel::process::FaultResponse::Panic;
 
+    // how many callbacks each process can have pending, unless its header
+    // requests a different queue length
+    const CALLBACK_QUEUE: kernel::process::CallbackQueueConfig =
+        kernel::process::CallbackQueueConfig {
+            default_len: 10,
+            coalesce: false,
+        };
+
     #[link_section = ".app_memory"]
     static mut APP_MEMORY: [u8; 49152] = [0; 49152];
 
@@ -54,13 +65,17 @@ unsafe fn load_processes() -> &'static mut [Option<kernel::process::Process<'sta
             kernel::process::Process::create(apps_in_flash_ptr,
                                              app_memory_ptr,
                                              app_memory_size,
-                                             FAULT_RESPONSE);
+                                             FAULT_RESPONSE,
+                                             CALLBACK_QUEUE);
 
         if process.is_none() {
-            break;
+            // An app that failed to load is skipped
+            if flash_offset == 0 {
+                break;
+            }
+        } else {
+            PROCESSES[i] = process;
         }
-
-        PROCESSES[i] = process;
         apps_in_flash_ptr = apps_in_flash_ptr.offset(flash_offset as isize);
         app_memory_ptr = app_memory_ptr.offset(memory_offset as isize);
         app_memory_size -= memory_offset;
@@ -70,16 +85,17 @@ unsafe fn load_processes() -> &'static mut [Option<kernel::process::Process<'sta
 }
 
 struct Hail {
-    console: &'static Console<'static, usart::USART>,
+    console: &'static Console<'static, VirtualUartDevice<'static, usart::USART>>,
     gpio: &'static capsules::gpio::GPIO<'static, sam4l::gpio::GPIOPin>,
     timer: &'static TimerDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+    kernel_alarm: &'static VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
     isl29035: &'static capsules::isl29035::Isl29035<'static,
                                                     VirtualMuxAlarm<'static,
                                                                     sam4l::ast::Ast<'static>>>,
     si7021: &'static capsules::si7021::SI7021<'static,
                                               VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
     fxos8700: &'static capsules::fxos8700_cq::Fxos8700cq<'static>,
-    spi: &'static capsules::spi::Spi<'static, VirtualSpiMasterDevice<'static, sam4l::spi::Spi>>,
+    spi: &'static capsules::spi::Spi<'static, sam4l::spi::Spi>,
     nrf51822: &'static Nrf51822Serialization<'static, usart::USART>,
     adc: &'static capsules::adc::ADC<'static, sam4l::adc::Adc>,
     led: &'static capsules::led::LED<'static, sam4l::gpio::GPIOPin>,
@@ -87,6 +103,11 @@ struct Hail {
     rng: &'static capsules::rng::SimpleRng<'static, sam4l::trng::Trng<'static>>,
     ipc: kernel::ipc::IPC,
     crc: &'static capsules::crc::Crc<'static, sam4l::crccu::Crccu<'static>>,
+    nv_storage: &'static capsules::nonvolatile_storage::NonvolatileStorage<'static,
+                                                                         sam4l::flashcalw::FLASHCALW>,
+    i2c_master: &'static capsules::i2c_master::I2CMasterDriver<'static,
+                                                              VirtualMuxAlarm<'static,
+                                                                              sam4l::ast::Ast<'static>>>,
 }
 
 impl Platform for Hail {
@@ -112,10 +133,18 @@ impl Platform for Hail {
 
             16 => f(Some(self.crc)),
 
+            18 => f(Some(self.nv_storage)),
+
+            21 => f(Some(self.i2c_master)),
+
             0xff => f(Some(&self.ipc)),
             _ => f(None),
         }
     }
+
+    fn kernel_alarm(&self) -> Option<&kernel::KernelAlarm> {
+        Some(self.kernel_alarm)
+    }
 }
 
 
@@ -184,14 +213,28 @@ pub unsafe fn reset_handler() {
 
     set_pin_primary_functions();
 
+    // Share the FTDI USART between the console and any other users
+    let uart_mux = static_init!(
+        MuxUart<'static, usart::USART>,
+        MuxUart::new(&usart::USART0, &mut capsules::virtual_uart::RX_BUF, 115200),
+        224/8);
+    hil::uart::UART::set_client(&usart::USART0, uart_mux);
+    uart_mux.initialize();
+
+    let console_uart = static_init!(
+        VirtualUartDevice<'static, usart::USART>,
+        VirtualUartDevice::new(uart_mux),
+        416/8);
+    console_uart.setup();
     let console = static_init!(
-        Console<usart::USART>,
-        Console::new(&usart::USART0,
+        Console<VirtualUartDevice<'static, usart::USART>>,
+        Console::new(console_uart,
                      115200,
                      &mut console::WRITE_BUF,
+                     &mut console::READ_BUF,
                      kernel::Container::create()),
-        224/8);
-    hil::uart::UART::set_client(&usart::USART0, console);
+        768/8);
+    hil::uart::UART::set_client(console_uart, console);
 
     // Create the Nrf51822Serialization driver for passing BLE commands
     // over UART to the nRF51822 radio.
@@ -200,7 +243,7 @@ pub unsafe fn reset_handler() {
         Nrf51822Serialization::new(&usart::USART3,
                                    &mut nrf51822_serialization::WRITE_BUF,
                                    &mut nrf51822_serialization::READ_BUF),
-        608/8);
+        672/8);
     hil::uart::UART::set_client(&usart::USART3, nrf_serialization);
 
     let ast = &sam4l::ast::AST;
@@ -208,41 +251,56 @@ pub unsafe fn reset_handler() {
     let mux_alarm = static_init!(
         MuxAlarm<'static, sam4l::ast::Ast>,
         MuxAlarm::new(&sam4l::ast::AST),
-        16);
+        20);
     ast.configure(mux_alarm);
 
-    let sensors_i2c = static_init!(MuxI2C<'static>, MuxI2C::new(&sam4l::i2c::I2C1), 20);
+    let sensors_i2c_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm),
+        32);
+    let sensors_i2c = static_init!(
+        MuxI2C<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        MuxI2C::new(&sam4l::i2c::I2C1, sensors_i2c_alarm),
+        52);
+    sensors_i2c.register_deferred_call();
     sam4l::i2c::I2C1.set_master_client(sensors_i2c);
+    sam4l::i2c::I2C1.set_recovery_pins(&sam4l::gpio::PB[01],
+                                       &sam4l::gpio::PB[00],
+                                       sam4l::gpio::PeripheralFunction::A);
+    sensors_i2c_alarm.set_client(sensors_i2c);
 
     // SI7021 Temperature / Humidity Sensor, address: 0x40
     let si7021_i2c = static_init!(
-        capsules::virtual_i2c::I2CDevice,
-        capsules::virtual_i2c::I2CDevice::new(sensors_i2c, 0x40),
+        I2CDevice<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        I2CDevice::new(sensors_i2c, 0x40),
         32);
     let si7021_virtual_alarm = static_init!(
         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
         VirtualMuxAlarm::new(mux_alarm),
-        192/8);
+        256/8);
     let si7021 = static_init!(
         capsules::si7021::SI7021<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
         capsules::si7021::SI7021::new(si7021_i2c,
             si7021_virtual_alarm,
             &mut capsules::si7021::BUFFER),
-        352/8);
+        416/8);
     si7021_i2c.set_client(si7021);
     si7021_virtual_alarm.set_client(si7021);
 
     // Configure the ISL29035, device address 0x44
-    let isl29035_i2c = static_init!(I2CDevice, I2CDevice::new(sensors_i2c, 0x44), 32);
+    let isl29035_i2c = static_init!(
+        I2CDevice<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        I2CDevice::new(sensors_i2c, 0x44),
+        32);
     let isl29035_virtual_alarm = static_init!(
         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
         VirtualMuxAlarm::new(mux_alarm),
-        192/8);
+        256/8);
     let isl29035 = static_init!(
         capsules::isl29035::Isl29035<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
         capsules::isl29035::Isl29035::new(isl29035_i2c, isl29035_virtual_alarm,
                                           &mut capsules::isl29035::BUF),
-        384/8);
+        448/8);
     isl29035_i2c.set_client(isl29035);
     isl29035_virtual_alarm.set_client(isl29035);
 
@@ -250,27 +308,55 @@ pub unsafe fn reset_handler() {
     let virtual_alarm1 = static_init!(
         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
         VirtualMuxAlarm::new(mux_alarm),
-        24);
+        32);
     let timer = static_init!(
         TimerDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
         TimerDriver::new(virtual_alarm1, kernel::Container::create()),
-        12);
+        8);
     virtual_alarm1.set_client(timer);
 
+    // Alarm the kernel uses to time out yields and timestamp the info page
+    let kernel_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm),
+        32);
+    kernel_alarm.set_client(&kernel::KERNEL_ALARM_CLIENT);
+
     // FXOS8700CQ accelerometer, device address 0x
-    let fxos8700_i2c = static_init!(I2CDevice, I2CDevice::new(sensors_i2c, 0x1e), 32);
+    let fxos8700_i2c = static_init!(
+        I2CDevice<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        I2CDevice::new(sensors_i2c, 0x1e),
+        32);
     let fxos8700 = static_init!(
         capsules::fxos8700_cq::Fxos8700cq<'static>,
         capsules::fxos8700_cq::Fxos8700cq::new(fxos8700_i2c, &mut capsules::fxos8700_cq::BUF),
-        352/8);
+        416/8);
     fxos8700_i2c.set_client(fxos8700);
 
+    // Raw I2C for apps on the sensor bus. The onboard sensors belong to the
+    // kernel, so apps may only reach addresses listed here.
+    static I2C_MASTER_ALLOWED: [(&'static str, &'static [u8]); 1] =
+        [("i2c_scan", &[0x48, 0x49, 0x4a, 0x4b])];
+    let i2c_master_i2c = static_init!(
+        I2CDevice<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        I2CDevice::new(sensors_i2c, 0),
+        32);
+    let i2c_master = static_init!(
+        capsules::i2c_master::I2CMasterDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        capsules::i2c_master::I2CMasterDriver::new(i2c_master_i2c,
+                                                   &I2C_MASTER_ALLOWED,
+                                                   &mut capsules::i2c_master::BUFFER,
+                                                   kernel::Container::create()),
+        448/8);
+    i2c_master_i2c.set_client(i2c_master);
+
     // Initialize and enable SPI HAL
     // Set up an SPI MUX, so there can be multiple clients
     let mux_spi = static_init!(
         MuxSpiMaster<'static, sam4l::spi::Spi>,
         MuxSpiMaster::new(&sam4l::spi::SPI),
-        96/8);
+        160/8);
+    mux_spi.register_deferred_call();
 
     sam4l::spi::SPI.set_client(mux_spi);
     sam4l::spi::SPI.init();
@@ -281,13 +367,15 @@ pub unsafe fn reset_handler() {
     let syscall_spi_device = static_init!(
         VirtualSpiMasterDevice<'static, sam4l::spi::Spi>,
         VirtualSpiMasterDevice::new(mux_spi, 0),
-        352/8);
+        448/8);
 
     // Create the SPI systemc call capsule, passing the client
     let spi_syscalls = static_init!(
-        capsules::spi::Spi<'static, VirtualSpiMasterDevice<'static, sam4l::spi::Spi>>,
-        capsules::spi::Spi::new(syscall_spi_device),
-        672/8);
+        capsules::spi::Spi<'static, sam4l::spi::Spi>,
+        capsules::spi::Spi::new(syscall_spi_device,
+                                &SPI_CHIP_SELECTS,
+                                kernel::Container::create()),
+        352/8);
 
     spi_syscalls.config_buffers(&mut SPI_READ_BUF, &mut SPI_WRITE_BUF);
     syscall_spi_device.set_client(spi_syscalls);
@@ -320,9 +408,13 @@ pub unsafe fn reset_handler() {
     // Setup ADC
     let adc = static_init!(
         capsules::adc::ADC<'static, sam4l::adc::Adc>,
-        capsules::adc::ADC::new(&mut sam4l::adc::ADC, kernel::Container::create()),
-        96/8);
+        capsules::adc::ADC::new(&mut sam4l::adc::ADC,
+                                kernel::Container::create(),
+                                &mut capsules::adc::BUFFER1,
+                                &mut capsules::adc::BUFFER2),
+        448/8);
     sam4l::adc::ADC.set_client(adc);
+    sam4l::adc::ADC.set_continuous_client(adc);
 
     // Setup RNG
     let rng = static_init!(
@@ -344,7 +436,7 @@ pub unsafe fn reset_handler() {
     let gpio = static_init!(
         capsules::gpio::GPIO<'static, sam4l::gpio::GPIOPin>,
         capsules::gpio::GPIO::new(gpio_pins),
-        224/8);
+        288/8);
     for pin in gpio_pins.iter() {
         pin.set_client(gpio);
     }
@@ -356,11 +448,30 @@ pub unsafe fn reset_handler() {
         128/8);
     sam4l::crccu::CRCCU.set_client(crc);
 
+    // Nonvolatile storage for apps, in the 64KB of flash after the apps.
+    // Each app listed here owns the 8KB region with the given number.
+    static NV_STORAGE_REGIONS: [(&'static str, usize); 1] = [("sensors", 0)];
+    sam4l::flashcalw::FLASH_CONTROLLER.configure();
+    sam4l::flashcalw::FLASH_CONTROLLER.register_deferred_call();
+    let nv_storage = static_init!(
+        capsules::nonvolatile_storage::NonvolatileStorage<'static, sam4l::flashcalw::FLASHCALW>,
+        capsules::nonvolatile_storage::NonvolatileStorage::new(
+            &sam4l::flashcalw::FLASH_CONTROLLER,
+            0x70000,
+            0x10000,
+            0x2000,
+            &NV_STORAGE_REGIONS,
+            &mut capsules::nonvolatile_storage::BUFFER,
+            kernel::Container::create()),
+        544/8);
+    hil::flash::Flash::set_client(&sam4l::flashcalw::FLASH_CONTROLLER, nv_storage);
+
 
     let hail = Hail {
         console: console,
         gpio: gpio,
         timer: timer,
+        kernel_alarm: kernel_alarm,
         si7021: si7021,
         isl29035: isl29035,
         fxos8700: fxos8700,
@@ -372,6 +483,8 @@ pub unsafe fn reset_handler() {
         rng: rng,
         ipc: kernel::ipc::IPC::new(),
         crc: crc,
+        nv_storage: nv_storage,
+        i2c_master: i2c_master,
     };
 
     // Need to reset the nRF on boot
@@ -385,7 +498,7 @@ pub unsafe fn reset_handler() {
     let kc = static_init!(
         capsules::console::App,
         capsules::console::App::default(),
-        480/8);
+        832/8);
     kernel::debug::assign_console_driver(Some(hail.console), kc);
 
     hail.nrf51822.initialize();
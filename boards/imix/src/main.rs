@@ -33,6 +33,7 @@ struct Imix {
     console: &'static capsules::console::Console<'static, sam4l::usart::USART>,
     gpio: &'static capsules::gpio::GPIO<'static, sam4l::gpio::GPIOPin>,
     timer: &'static TimerDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+    kernel_alarm: &'static VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
     si7021: &'static capsules::si7021::SI7021<'static,
                                               VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
     isl29035: &'static capsules::isl29035::Isl29035<'static,
@@ -41,7 +42,7 @@ struct Imix {
     adc: &'static capsules::adc::ADC<'static, sam4l::adc::Adc>,
     led: &'static capsules::led::LED<'static, sam4l::gpio::GPIOPin>,
     button: &'static capsules::button::Button<'static, sam4l::gpio::GPIOPin>,
-    spi: &'static capsules::spi::Spi<'static, VirtualSpiMasterDevice<'static, sam4l::spi::Spi>>,
+    spi: &'static capsules::spi::Spi<'static, sam4l::spi::Spi>,
     ipc: kernel::ipc::IPC,
     fxos8700_cq: &'static capsules::fxos8700_cq::Fxos8700cq<'static>,
     radio: &'static capsules::radio::RadioDriver<'static,
@@ -90,6 +91,10 @@ impl kernel::Platform for Imix {
             _ => f(None),
         }
     }
+
+    fn kernel_alarm(&self) -> Option<&kernel::KernelAlarm> {
+        Some(self.kernel_alarm)
+    }
 }
 
 unsafe fn set_pin_primary_functions() {
@@ -175,8 +180,9 @@ pub unsafe fn reset_handler() {
         capsules::console::Console::new(&sam4l::usart::USART3,
                      115200,
                      &mut capsules::console::WRITE_BUF,
+                     &mut capsules::console::READ_BUF,
                      kernel::Container::create()),
-        224/8);
+        768/8);
     hil::uart::UART::set_client(&sam4l::usart::USART3, console);
     console.initialize();
 
@@ -184,7 +190,7 @@ pub unsafe fn reset_handler() {
     let kc = static_init!(
         capsules::console::App,
         capsules::console::App::default(),
-        480/8);
+        832/8);
     kernel::debug::assign_console_driver(Some(console), kc);
 
     // # TIMER
@@ -194,37 +200,59 @@ pub unsafe fn reset_handler() {
     let mux_alarm = static_init!(
         MuxAlarm<'static, sam4l::ast::Ast>,
         MuxAlarm::new(&sam4l::ast::AST),
-        16);
+        20);
     ast.configure(mux_alarm);
 
     let virtual_alarm1 = static_init!(
         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
         VirtualMuxAlarm::new(mux_alarm),
-        24);
+        32);
     let timer = static_init!(
         TimerDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
         TimerDriver::new(virtual_alarm1, kernel::Container::create()),
-        12);
+        8);
     virtual_alarm1.set_client(timer);
 
+    // Alarm the kernel uses to time out yields and timestamp the info page
+    let kernel_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm),
+        32);
+    kernel_alarm.set_client(&kernel::KERNEL_ALARM_CLIENT);
+
     // # I2C Sensors
 
-    let mux_i2c = static_init!(MuxI2C<'static>, MuxI2C::new(&sam4l::i2c::I2C2), 20);
+    let i2c_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm),
+        32);
+    let mux_i2c = static_init!(
+        MuxI2C<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        MuxI2C::new(&sam4l::i2c::I2C2, i2c_alarm),
+        52);
+    mux_i2c.register_deferred_call();
     sam4l::i2c::I2C2.set_master_client(mux_i2c);
+    sam4l::i2c::I2C2.set_recovery_pins(&sam4l::gpio::PA[22],
+                                       &sam4l::gpio::PA[21],
+                                       sam4l::gpio::PeripheralFunction::E);
+    i2c_alarm.set_client(mux_i2c);
 
     // Configure the ISL29035, device address 0x44
-    let isl29035_i2c = static_init!(I2CDevice, I2CDevice::new(mux_i2c, 0x44), 32);
+    let isl29035_i2c = static_init!(
+        I2CDevice<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        I2CDevice::new(mux_i2c, 0x44),
+        32);
     let isl29035_virtual_alarm = static_init!(
         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
         VirtualMuxAlarm::new(mux_alarm),
-        192/8);
+        256/8);
     let isl29035 = static_init!(
         capsules::isl29035::Isl29035<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
         capsules::isl29035::Isl29035::new(
             isl29035_i2c,
             isl29035_virtual_alarm,
             &mut capsules::isl29035::BUF),
-        384/8);
+        448/8);
     isl29035_i2c.set_client(isl29035);
     isl29035_virtual_alarm.set_client(isl29035);
 
@@ -232,7 +260,8 @@ pub unsafe fn reset_handler() {
     let mux_spi = static_init!(
         MuxSpiMaster<'static, sam4l::spi::Spi>,
         MuxSpiMaster::new(&sam4l::spi::SPI),
-        12);
+        20);
+    mux_spi.register_deferred_call();
     sam4l::spi::SPI.set_client(mux_spi);
     sam4l::spi::SPI.init();
     sam4l::spi::SPI.enable();
@@ -242,13 +271,17 @@ pub unsafe fn reset_handler() {
     let syscall_spi_device = static_init!(
         VirtualSpiMasterDevice<'static, sam4l::spi::Spi>,
         VirtualSpiMasterDevice::new(mux_spi, 3),
-        352/8);
+        448/8);
 
-    // Create the SPI systemc call capsule, passing the client
+    // Create the SPI systemc call capsule, passing the client. Apps start
+    // out on CS3 and may switch to CS0-CS2.
+    static SPI_CHIP_SELECTS: [u8; 4] = [3, 0, 1, 2];
     let spi_syscalls = static_init!(
-        capsules::spi::Spi<'static, VirtualSpiMasterDevice<'static, sam4l::spi::Spi>>,
-        capsules::spi::Spi::new(syscall_spi_device),
-        672/8);
+        capsules::spi::Spi<'static, sam4l::spi::Spi>,
+        capsules::spi::Spi::new(syscall_spi_device,
+                                &SPI_CHIP_SELECTS,
+                                kernel::Container::create()),
+        352/8);
 
     // System call capsule requires static buffers so it can
     // copy from application slices to DMA
@@ -262,19 +295,22 @@ pub unsafe fn reset_handler() {
     let si7021_alarm = static_init!(
         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
         VirtualMuxAlarm::new(mux_alarm),
-        24);
-    let si7021_i2c = static_init!(I2CDevice, I2CDevice::new(mux_i2c, 0x40), 32);
+        32);
+    let si7021_i2c = static_init!(
+        I2CDevice<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        I2CDevice::new(mux_i2c, 0x40),
+        32);
     let si7021 = static_init!(
         capsules::si7021::SI7021<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
         capsules::si7021::SI7021::new(si7021_i2c, si7021_alarm, &mut capsules::si7021::BUFFER),
-        352/8);
+        416/8);
     si7021_i2c.set_client(si7021);
     si7021_alarm.set_client(si7021);
 
     // Create a second virtualized SPI client, for the RF233
     let rf233_spi = static_init!(VirtualSpiMasterDevice<'static, sam4l::spi::Spi>,
                                  VirtualSpiMasterDevice::new(mux_spi, 3),
-                                 352/8);
+                                 448/8);
     // Create the RF233 driver, passing its pins and SPI client
     let rf233: &RF233<'static, VirtualSpiMasterDevice<'static, sam4l::spi::Spi>> =
         static_init!(RF233<'static, VirtualSpiMasterDevice<'static, sam4l::spi::Spi>>,
@@ -288,11 +324,14 @@ pub unsafe fn reset_handler() {
     sam4l::gpio::PA[08].set_client(rf233);
 
     // FXOS8700CQ accelerometer
-    let fx0_i2c = static_init!(I2CDevice, I2CDevice::new(mux_i2c, 0x1e), 32);
+    let fx0_i2c = static_init!(
+        I2CDevice<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        I2CDevice::new(mux_i2c, 0x1e),
+        32);
     let fx0 = static_init!(
         capsules::fxos8700_cq::Fxos8700cq<'static>,
         capsules::fxos8700_cq::Fxos8700cq::new(fx0_i2c, &mut capsules::fxos8700_cq::BUF),
-        352/8);
+        416/8);
     fx0_i2c.set_client(fx0);
 
     // Clear sensors enable pin to enable sensor rail
@@ -304,9 +343,13 @@ pub unsafe fn reset_handler() {
     // Setup ADC
     let adc = static_init!(
         capsules::adc::ADC<'static, sam4l::adc::Adc>,
-        capsules::adc::ADC::new(&mut sam4l::adc::ADC, kernel::Container::create()),
-        96/8);
+        capsules::adc::ADC::new(&mut sam4l::adc::ADC,
+                                kernel::Container::create(),
+                                &mut capsules::adc::BUFFER1,
+                                &mut capsules::adc::BUFFER2),
+        448/8);
     sam4l::adc::ADC.set_client(adc);
+    sam4l::adc::ADC.set_continuous_client(adc);
 
     // # GPIO
     // set GPIO driver controlling remaining GPIO pins
@@ -326,7 +369,7 @@ pub unsafe fn reset_handler() {
     let gpio = static_init!(
         capsules::gpio::GPIO<'static, sam4l::gpio::GPIOPin>,
         capsules::gpio::GPIO::new(gpio_pins),
-        224/8);
+        288/8);
     for pin in gpio_pins.iter() {
         pin.set_client(gpio);
     }
@@ -369,7 +412,7 @@ pub unsafe fn reset_handler() {
                                      RF233<'static,
                                            VirtualSpiMasterDevice<'static, sam4l::spi::Spi>>>,
         capsules::radio::RadioDriver::new(rf233),
-        832/8);
+        1024/8);
     radio_capsule.config_buffer(&mut RADIO_BUF);
     rf233.set_transmit_client(radio_capsule);
     rf233.set_receive_client(radio_capsule, &mut RF233_RX_BUF);
@@ -378,6 +421,7 @@ pub unsafe fn reset_handler() {
     let imix = Imix {
         console: console,
         timer: timer,
+        kernel_alarm: kernel_alarm,
         gpio: gpio,
         si7021: si7021,
         isl29035: isl29035,
@@ -417,6 +461,14 @@ unsafe fn load_processes() -> &'static mut [Option<kernel::process::Process<'sta
     // how should the kernel respond when a process faults
     const FAULT_RESPONSE: kernel::process::FaultResponse = kernel::process::FaultResponse::Panic;
 
+    // how many callbacks each process can have pending, unless its header
+    // requests a different queue length
+    const CALLBACK_QUEUE: kernel::process::CallbackQueueConfig =
+        kernel::process::CallbackQueueConfig {
+            default_len: 10,
+            coalesce: false,
+        };
+
     #[link_section = ".app_memory"]
     static mut APP_MEMORY: [u8; 16384] = [0; 16384];
 
@@ -430,13 +482,17 @@ unsafe fn load_processes() -> &'static mut [Option<kernel::process::Process<'sta
             kernel::process::Process::create(apps_in_flash_ptr,
                                              app_memory_ptr,
                                              app_memory_size,
-                                             FAULT_RESPONSE);
+                                             FAULT_RESPONSE,
+                                             CALLBACK_QUEUE);
 
         if process.is_none() {
-            break;
+            // An app that failed to load is skipped
+            if flash_offset == 0 {
+                break;
+            }
+        } else {
+            PROCESSES[i] = process;
         }
-
-        PROCESSES[i] = process;
         apps_in_flash_ptr = apps_in_flash_ptr.offset(flash_offset as isize);
         app_memory_ptr = app_memory_ptr.offset(memory_offset as isize);
         app_memory_size -= memory_offset;
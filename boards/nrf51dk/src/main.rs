@@ -2,8 +2,7 @@
 //! kit (DK), a.k.a. the PCA10028. This is an nRF51422 SoC (a
 //! Cortex M0 core with a BLE transciver) with many exported
 //! pins, LEDs, and buttons. Currently the kernel provides
-//! application timers, and GPIO. It will provide a console
-//! once the UART is fully implemented and debugged. The
+//! application timers, GPIO, and a console on the UART. The
 //! application GPIO pins are:
 //!
 //!   0 -> LED1 (pin 21)
@@ -76,6 +75,14 @@ unsafe fn load_process() -> &'static mut [Option<kernel::process::Process<'stati
     // how should the kernel respond when a process faults
     const FAULT_RESPONSE: kernel::process::FaultResponse = kernel::process::FaultResponse::Panic;
 
+    // how many callbacks each process can have pending, unless its header
+    // requests a different queue length
+    const CALLBACK_QUEUE: kernel::process::CallbackQueueConfig =
+        kernel::process::CallbackQueueConfig {
+            default_len: 10,
+            coalesce: false,
+        };
+
     #[link_section = ".app_memory"]
     static mut APP_MEMORY: [u8; 8192] = [0; 8192];
 
@@ -89,13 +96,17 @@ unsafe fn load_process() -> &'static mut [Option<kernel::process::Process<'stati
             kernel::process::Process::create(apps_in_flash_ptr,
                                              app_memory_ptr,
                                              app_memory_size,
-                                             FAULT_RESPONSE);
+                                             FAULT_RESPONSE,
+                                             CALLBACK_QUEUE);
 
         if process.is_none() {
-            break;
+            // An app that failed to load is skipped
+            if flash_offset == 0 {
+                break;
+            }
+        } else {
+            PROCESSES[i] = process;
         }
-
-        PROCESSES[i] = process;
         apps_in_flash_ptr = apps_in_flash_ptr.offset(flash_offset as isize);
         app_memory_ptr = app_memory_ptr.offset(memory_offset as isize);
         app_memory_size -= memory_offset;
@@ -107,6 +118,7 @@ unsafe fn load_process() -> &'static mut [Option<kernel::process::Process<'stati
 pub struct Platform {
     gpio: &'static capsules::gpio::GPIO<'static, nrf51::gpio::GPIOPin>,
     timer: &'static TimerDriver<'static, VirtualMuxAlarm<'static, Rtc>>,
+    kernel_alarm: &'static VirtualMuxAlarm<'static, Rtc>,
     console: &'static capsules::console::Console<'static, nrf51::uart::UART>,
     led: &'static capsules::led::LED<'static, nrf51::gpio::GPIOPin>,
     button: &'static capsules::button::Button<'static, nrf51::gpio::GPIOPin>,
@@ -132,6 +144,10 @@ impl kernel::Platform for Platform {
             _ => f(None),
         }
     }
+
+    fn kernel_alarm(&self) -> Option<&kernel::KernelAlarm> {
+        Some(self.kernel_alarm)
+    }
 }
 
 #[no_mangle]
@@ -189,7 +205,7 @@ pub unsafe fn reset_handler() {
     let gpio = static_init!(
         capsules::gpio::GPIO<'static, nrf51::gpio::GPIOPin>,
         capsules::gpio::GPIO::new(gpio_pins),
-        224/8);
+        288/8);
     for pin in gpio_pins.iter() {
         pin.set_client(gpio);
     }
@@ -203,35 +219,45 @@ pub unsafe fn reset_handler() {
         capsules::console::Console::new(&nrf51::uart::UART0,
                                         115200,
                                         &mut capsules::console::WRITE_BUF,
+                                        &mut capsules::console::READ_BUF,
                                         kernel::Container::create()),
-                                        224/8);
+                                        768/8);
     UART::set_client(&nrf51::uart::UART0, console);
+    nrf51::uart::UART0.set_timeout_timer(&nrf51::timer::TIMER2);
+    nrf51::timer::TIMER2.set_client(&nrf51::uart::UART0);
     console.initialize();
 
     // Attach the kernel debug interface to this console
     let kc = static_init!(
         capsules::console::App,
         capsules::console::App::default(),
-        480/8);
+        832/8);
     kernel::debug::assign_console_driver(Some(console), kc);
 
     let alarm = &nrf51::rtc::RTC;
     alarm.start();
-    let mux_alarm = static_init!(MuxAlarm<'static, Rtc>, MuxAlarm::new(&RTC), 16);
+    let mux_alarm = static_init!(MuxAlarm<'static, Rtc>, MuxAlarm::new(&RTC), 20);
     alarm.set_client(mux_alarm);
 
 
     let virtual_alarm1 = static_init!(
         VirtualMuxAlarm<'static, Rtc>,
         VirtualMuxAlarm::new(mux_alarm),
-        24);
+        32);
     let timer = static_init!(
         TimerDriver<'static, VirtualMuxAlarm<'static, Rtc>>,
         TimerDriver::new(virtual_alarm1,
                          kernel::Container::create()),
-                         12);
+                         8);
     virtual_alarm1.set_client(timer);
 
+    // Alarm the kernel uses to time out yields and timestamp the info page
+    let kernel_alarm = static_init!(
+        VirtualMuxAlarm<'static, Rtc>,
+        VirtualMuxAlarm::new(mux_alarm),
+        32);
+    kernel_alarm.set_client(&kernel::KERNEL_ALARM_CLIENT);
+
     let temp = static_init!(
         capsules::temp_nrf51dk::Temperature<'static, nrf51::temperature::Temperature>,
         capsules::temp_nrf51dk::Temperature::new(&mut nrf51::temperature::TEMP,
@@ -270,6 +296,7 @@ pub unsafe fn reset_handler() {
     let platform = Platform {
         gpio: gpio,
         timer: timer,
+        kernel_alarm: kernel_alarm,
         console: console,
         led: led,
         button: button,
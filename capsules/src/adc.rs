@@ -1,30 +1,114 @@
 //! ADC Capsule
 //!
 //! Provides userspace applications with the ability to sample
-//! ADC channels.
+//! ADC channels, either one sample at a time or into a buffer at a
+//! fixed frequency.
+//!
+//! Buffered sampling streams samples from the hardware into two kernel
+//! buffers, which the hardware fills in turn while the capsule copies the
+//! other one out to the application, so applications can allow buffers of
+//! any size. Filling a single buffer takes the number of samples the
+//! application last set with command 6, which must fit in the buffer;
+//! continuous sampling fills each buffer completely. Only one application
+//! can sample into buffers at a time, and
+//! single samples are refused while it does. Revoking a buffer that is being
+//! sampled into, by allowing an empty one, stops the sampling.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let adc = static_init!(
+//!     capsules::adc::ADC<'static, sam4l::adc::Adc>,
+//!     capsules::adc::ADC::new(&mut sam4l::adc::ADC,
+//!                             kernel::Container::create(),
+//!                             &mut capsules::adc::BUFFER1,
+//!                             &mut capsules::adc::BUFFER2),
+//!     448/8);
+//! sam4l::adc::ADC.set_client(adc);
+//! sam4l::adc::ADC.set_continuous_client(adc);
+//! ```
 
 use core::cell::Cell;
-use kernel::{AppId, Callback, Container, Driver, ReturnCode};
-use kernel::hil::adc::{Client, AdcSingle};
+use core::cmp;
+use kernel::{AppId, AppSlice, Callback, Container, Driver, ReturnCode, Shared};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::adc::{Client, ContinuousClient, AdcSingle, AdcContinuous};
+use kernel::process::Error;
+
+pub static mut BUFFER1: [u16; 128] = [0; 128];
+pub static mut BUFFER2: [u16; 128] = [0; 128];
 
 #[derive(Default)]
 pub struct AppData {
     channel: Option<u8>,
     callback: Option<Callback>,
+    buffers: [Option<AppSlice<Shared, u8>>; 2],
+    // Samples to take when filling buffer 0 once
+    sample_count: usize,
+}
+
+impl AppData {
+    /// How many samples app buffer `index` holds.
+    fn samples(&self, index: usize) -> usize {
+        self.buffers[index].as_ref().map_or(0, |buffer| buffer.len() / 2)
+    }
+
+    /// Stores `sample` little-endian at `offset` in app buffer `index`, if
+    /// it fits.
+    fn store(&mut self, index: usize, offset: usize, sample: u16) -> bool {
+        let buffer = match self.buffers[index].as_mut() {
+            Some(buffer) => buffer,
+            None => return false,
+        };
+        if (offset + 1) * 2 > buffer.len() {
+            return false;
+        }
+        buffer.as_mut()[offset * 2] = sample as u8;
+        buffer.as_mut()[offset * 2 + 1] = (sample >> 8) as u8;
+        true
+    }
 }
 
-pub struct ADC<'a, A: AdcSingle + 'a> {
+pub struct ADC<'a, A: AdcSingle + AdcContinuous + 'a> {
     adc: &'a A,
     channel: Cell<Option<u8>>,
     app: Container<AppData>,
+
+    // Buffered sampling
+    sampling_app: Cell<Option<AppId>>,
+    sampling_channel: Cell<u8>,
+    continuous: Cell<bool>,
+    // The app buffer being filled, how many samples it takes and how many
+    // it has
+    app_buffer: Cell<usize>,
+    app_samples: Cell<usize>,
+    app_offset: Cell<usize>,
+    // Samples not yet asked of the hardware when sampling into one buffer
+    samples_remaining: Cell<usize>,
+    kernel_buffer1: TakeCell<'static, [u16]>,
+    kernel_buffer2: TakeCell<'static, [u16]>,
 }
 
-impl<'a, A: AdcSingle + 'a> ADC<'a, A> {
-    pub fn new(adc: &'a A, container: Container<AppData>) -> ADC<'a, A> {
+impl<'a, A: AdcSingle + AdcContinuous + 'a> ADC<'a, A> {
+    pub fn new(adc: &'a A,
+               container: Container<AppData>,
+               buffer1: &'static mut [u16],
+               buffer2: &'static mut [u16])
+               -> ADC<'a, A> {
         ADC {
             adc: adc,
             channel: Cell::new(None),
             app: container,
+            sampling_app: Cell::new(None),
+            sampling_channel: Cell::new(0),
+            continuous: Cell::new(false),
+            app_buffer: Cell::new(0),
+            app_samples: Cell::new(0),
+            app_offset: Cell::new(0),
+            samples_remaining: Cell::new(0),
+            kernel_buffer1: TakeCell::new(buffer1),
+            kernel_buffer2: TakeCell::new(buffer2),
         }
     }
 
@@ -33,6 +117,9 @@ impl<'a, A: AdcSingle + 'a> ADC<'a, A> {
     }
 
     fn sample(&self, channel: u8, appid: AppId) -> ReturnCode {
+        if self.sampling_app.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
         self.app
             .enter(appid, |app, _| {
                 app.channel = Some(channel);
@@ -46,9 +133,117 @@ impl<'a, A: AdcSingle + 'a> ADC<'a, A> {
             })
             .unwrap_or(ReturnCode::ENOMEM)
     }
+
+    /// Starts sampling into the app's first buffer, and for continuous
+    /// sampling then its second buffer, and so on. `data` holds the channel
+    /// in its low byte and the frequency in Hz above that. Sampling into one
+    /// buffer takes the app's sample count, which must fit in the buffer.
+    fn sample_buffer(&self, data: usize, continuous: bool, appid: AppId) -> ReturnCode {
+        let channel = data as u8;
+        let frequency = (data >> 8) as u32;
+        if self.sampling_app.get().is_some() || self.channel.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+
+        let samples = match self.app.enter(appid, |app, _| {
+            if continuous && app.samples(1) == 0 {
+                0
+            } else if continuous {
+                app.samples(0)
+            } else if app.sample_count > app.samples(0) {
+                0
+            } else {
+                app.sample_count
+            }
+        }) {
+            Ok(0) => return ReturnCode::EINVAL,
+            Ok(samples) => samples,
+            Err(_) => return ReturnCode::ENOMEM,
+        };
+
+        let buffer = match self.kernel_buffer1.take().or_else(|| self.kernel_buffer2.take()) {
+            Some(buffer) => buffer,
+            None => return ReturnCode::EBUSY,
+        };
+        self.continuous.set(continuous);
+        self.samples_remaining.set(samples);
+        let length = self.next_length(buffer.len());
+        let (result, buffer) = self.adc.sample_continuous(channel, frequency, buffer, length);
+        if result != ReturnCode::SUCCESS {
+            buffer.map(|buffer| self.put_back(buffer));
+            return result;
+        }
+
+        self.sampling_app.set(Some(appid));
+        self.sampling_channel.set(channel);
+        self.app_buffer.set(0);
+        self.app_samples.set(samples);
+        self.app_offset.set(0);
+
+        // Line up the other kernel buffer so sampling does not pause
+        let buffer = self.kernel_buffer1.take().or_else(|| self.kernel_buffer2.take());
+        buffer.map(|buffer| self.provide(buffer));
+        ReturnCode::SUCCESS
+    }
+
+    fn stop_sampling(&self, appid: AppId) -> ReturnCode {
+        match self.sampling_app.get() {
+            Some(sampling) if sampling.idx() == appid.idx() => {
+                self.stop();
+                ReturnCode::SUCCESS
+            }
+            _ => ReturnCode::EINVAL,
+        }
+    }
+
+    fn stop(&self) {
+        self.sampling_app.set(None);
+        // Sampling may have stopped by itself, once out of buffers
+        if self.adc.cancel_sampling() == ReturnCode::SUCCESS {
+            let (buffer1, buffer2) = self.adc.retrieve_buffers();
+            buffer1.map(|buffer| self.put_back(buffer));
+            buffer2.map(|buffer| self.put_back(buffer));
+        }
+    }
+
+    /// How many samples to ask for in a kernel buffer of `capacity`.
+    fn next_length(&self, capacity: usize) -> usize {
+        if self.continuous.get() {
+            capacity
+        } else {
+            let length = cmp::min(capacity, self.samples_remaining.get());
+            self.samples_remaining.set(self.samples_remaining.get() - length);
+            length
+        }
+    }
+
+    /// Gives a kernel buffer back to the hardware, if more samples are
+    /// needed.
+    fn provide(&self, buffer: &'static mut [u16]) {
+        let length = self.next_length(buffer.len());
+        if length == 0 {
+            self.put_back(buffer);
+            return;
+        }
+        let (result, buffer) = self.adc.provide_buffer(buffer, length);
+        if result != ReturnCode::SUCCESS {
+            if !self.continuous.get() {
+                self.samples_remaining.set(self.samples_remaining.get() + length);
+            }
+            buffer.map(|buffer| self.put_back(buffer));
+        }
+    }
+
+    fn put_back(&self, buffer: &'static mut [u16]) {
+        if self.kernel_buffer1.is_none() {
+            self.kernel_buffer1.replace(buffer);
+        } else {
+            self.kernel_buffer2.replace(buffer);
+        }
+    }
 }
 
-impl<'a, A: AdcSingle + 'a> Client for ADC<'a, A> {
+impl<'a, A: AdcSingle + AdcContinuous + 'a> Client for ADC<'a, A> {
     fn sample_done(&self, sample: u16) {
         self.channel.get().map(|cur_channel| {
             self.channel.set(None);
@@ -63,10 +258,93 @@ impl<'a, A: AdcSingle + 'a> Client for ADC<'a, A> {
     }
 }
 
-impl<'a, A: AdcSingle + 'a> Driver for ADC<'a, A> {
+impl<'a, A: AdcSingle + AdcContinuous + 'a> ContinuousClient for ADC<'a, A> {
+    fn samples_ready(&self, buffer: &'static mut [u16], length: usize) {
+        let appid = match self.sampling_app.get() {
+            Some(appid) => appid,
+            None => {
+                self.put_back(buffer);
+                return;
+            }
+        };
+        let channel = self.sampling_channel.get() as usize;
+        let continuous = self.continuous.get();
+
+        let done = self.app
+            .enter(appid, |app, _| {
+                for &sample in buffer[..length].iter() {
+                    let index = self.app_buffer.get();
+                    let offset = self.app_offset.get();
+                    if !app.store(index, offset, sample) {
+                        // The app took its buffer back
+                        return true;
+                    }
+                    self.app_offset.set(offset + 1);
+                    if offset + 1 < self.app_samples.get() {
+                        continue;
+                    }
+
+                    if !continuous {
+                        app.callback.map(|mut cb| cb.schedule(1, channel, offset + 1));
+                        return true;
+                    }
+                    app.callback.map(|mut cb| cb.schedule(2, channel, offset + 1));
+                    let next = 1 - index;
+                    if app.samples(next) == 0 {
+                        return true;
+                    }
+                    self.app_buffer.set(next);
+                    self.app_samples.set(app.samples(next));
+                    self.app_offset.set(0);
+                }
+                false
+            })
+            .unwrap_or(true);
+
+        if done {
+            self.put_back(buffer);
+            self.stop();
+        } else {
+            self.provide(buffer);
+        }
+    }
+}
+
+impl<'a, A: AdcSingle + AdcContinuous + 'a> Driver for ADC<'a, A> {
+    fn allow(&self, appid: AppId, allow_num: usize, slice: AppSlice<Shared, u8>) -> ReturnCode {
+        match allow_num {
+            // Buffers for sampling into
+            0 | 1 => {
+                let revoked = slice.len() == 0;
+                let result = self.app
+                    .enter(appid, |app, _| slice.store_in(&mut app.buffers[allow_num]))
+                    .unwrap_or_else(|err| match err {
+                        Error::OutOfMemory => ReturnCode::ENOMEM,
+                        Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                        Error::NoSuchApp => ReturnCode::EINVAL,
+                    });
+                // Revoking a buffer being sampled into stops the sampling
+                let in_use = allow_num == 0 || self.continuous.get();
+                if revoked && in_use &&
+                   self.sampling_app.get().map_or(false, |sampling| sampling.idx() == appid.idx()) {
+                    self.stop();
+                }
+                result
+            }
+
+            // default
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
     fn subscribe(&self, subscribe_num: usize, callback: Callback) -> ReturnCode {
         match subscribe_num {
             // subscribe to ADC sample done
+            //
+            // The callback gets (0, channel, sample) for a single sample,
+            // (1, channel, count) once command 3 has taken its count, and
+            // (2, channel, count) each time a buffer is full after command 4.
+            // Continuous sampling fills buffer 0 first, then alternates.
             0 => {
                 self.app
                     .enter(callback.app_id(),
@@ -82,14 +360,31 @@ impl<'a, A: AdcSingle + 'a> Driver for ADC<'a, A> {
 
     fn command(&self, command_num: usize, data: usize, appid: AppId) -> ReturnCode {
         match command_num {
-            // TODO: This should return the number of valid ADC channels.
-            0 /* check if present */ => ReturnCode::SUCCESS,
+            // Check if present, returning the number of channels
+            0 => ReturnCode::SuccessWithValue { value: self.adc.channel_count() },
             // Initialize ADC
             1 => self.initialize(),
             // Sample on channel
             2 => {
                 self.sample(data as u8, appid)
             },
+            // Take the sample count in buffer 0; data is
+            // channel | frequency << 8. Fails with EINVAL if no count is
+            // set or buffer 0 is too small for it.
+            3 => self.sample_buffer(data, false, appid),
+            // Sample continuously into buffers 0 and 1 in turn
+            4 => self.sample_buffer(data, true, appid),
+            // Stop buffered sampling
+            5 => self.stop_sampling(appid),
+            // Set how many samples command 3 takes
+            6 => {
+                self.app
+                    .enter(appid, |app, _| {
+                        app.sample_count = data;
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or(ReturnCode::ENOMEM)
+            }
 
             // default
             _ => ReturnCode::ENOSUPPORT,
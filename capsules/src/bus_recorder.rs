@@ -0,0 +1,676 @@
+//! Record and replay I2C and SPI transactions.
+//!
+//! `I2CRecorder` and `SpiRecorder` sit between a capsule and the bus device
+//! it was given, passing every operation through unchanged while saving it
+//! to a `TransactionLog`. The log is a ring buffer in RAM that keeps the most
+//! recent transactions and can be printed with `debug!`.
+//!
+//! `I2CReplay` and `SpiReplay` do the opposite: they stand in for the bus and
+//! answer a capsule from a list of recorded transactions, so a failure seen
+//! in the field can be reproduced off-device, for example on the host chip.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let log = static_init!(
+//!     capsules::bus_recorder::TransactionLog<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::bus_recorder::TransactionLog::new(recorder_virtual_alarm,
+//!                                                 &mut capsules::bus_recorder::RECORDS),
+//!     192/8);
+//! let si7021_recorder = static_init!(
+//!     capsules::bus_recorder::I2CRecorder<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::bus_recorder::I2CRecorder::new(si7021_i2c, 0x40, log),
+//!     544/8);
+//! si7021_i2c.set_client(si7021_recorder);
+//! let si7021 = static_init!(
+//!     capsules::si7021::SI7021<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::si7021::SI7021::new(si7021_recorder,
+//!         si7021_virtual_alarm,
+//!         &mut capsules::si7021::BUFFER),
+//!     416/8);
+//! si7021_recorder.set_client(si7021);
+//! si7021_virtual_alarm.set_client(si7021);
+//!
+//! // Later, for example from a panic handler or a button press:
+//! log.dump();
+//! ```
+//!
+//! Each transaction is dumped as one line:
+//!
+//! ```text
+//! i2c 40 write_read t=0001a2f0 w=e0 r=6666 CommandComplete
+//! ```
+//!
+//! Only the first `MAX_TRANSACTION_BYTES` bytes written and read are kept,
+//! but the lengths are always recorded in full.
+
+use core::cell::Cell;
+use core::cmp;
+use core::fmt;
+use kernel::common::take_cell::TakeCell;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::i2c::{self, I2CClient};
+use kernel::hil::spi::{self, ClockPhase, ClockPolarity, SpiMasterClient};
+use kernel::hil::time;
+
+/// Bytes of each direction of a transaction that are saved.
+pub const MAX_TRANSACTION_BYTES: usize = 16;
+
+pub static mut RECORDS: [Transaction; 32] = [Transaction::empty(); 32];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Operation {
+    I2CWrite,
+    I2CRead,
+    I2CWriteRead,
+    SpiTransfer,
+}
+
+#[derive(Copy, Clone)]
+pub struct Transaction {
+    /// Alarm time when the transaction was issued.
+    pub timestamp: u32,
+    /// I2C address, or the identifier an SPI recorder was created with.
+    pub address: u8,
+    pub operation: Operation,
+    /// Result of an I2C transaction. SPI transfers do not fail.
+    pub error: Option<i2c::Error>,
+    pub write_len: u16,
+    pub write: [u8; MAX_TRANSACTION_BYTES],
+    pub read_len: u16,
+    pub read: [u8; MAX_TRANSACTION_BYTES],
+}
+
+impl Transaction {
+    pub const fn empty() -> Transaction {
+        Transaction {
+            timestamp: 0,
+            address: 0,
+            operation: Operation::I2CWrite,
+            error: None,
+            write_len: 0,
+            write: [0; MAX_TRANSACTION_BYTES],
+            read_len: 0,
+            read: [0; MAX_TRANSACTION_BYTES],
+        }
+    }
+
+    /// The saved bytes that were written.
+    pub fn written(&self) -> &[u8] {
+        &self.write[..cmp::min(self.write_len as usize, MAX_TRANSACTION_BYTES)]
+    }
+
+    /// The saved bytes that were read.
+    pub fn read_bytes(&self) -> &[u8] {
+        &self.read[..cmp::min(self.read_len as usize, MAX_TRANSACTION_BYTES)]
+    }
+
+    fn set_written(&mut self, data: &[u8]) {
+        self.write_len = data.len() as u16;
+        let len = cmp::min(data.len(), MAX_TRANSACTION_BYTES);
+        self.write[..len].copy_from_slice(&data[..len]);
+    }
+
+    fn set_read(&mut self, data: &[u8]) {
+        self.read_len = data.len() as u16;
+        let len = cmp::min(data.len(), MAX_TRANSACTION_BYTES);
+        self.read[..len].copy_from_slice(&data[..len]);
+    }
+}
+
+/// Prints bytes as hex digits with no separators.
+struct Hex<'a>(&'a [u8]);
+
+impl<'a> fmt::Display for Hex<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (bus, operation) = match self.operation {
+            Operation::I2CWrite => ("i2c", "write"),
+            Operation::I2CRead => ("i2c", "read"),
+            Operation::I2CWriteRead => ("i2c", "write_read"),
+            Operation::SpiTransfer => ("spi", "transfer"),
+        };
+        write!(f,
+               "{} {:02x} {} t={:08x} w={} r={}",
+               bus,
+               self.address,
+               operation,
+               self.timestamp,
+               Hex(self.written()),
+               Hex(self.read_bytes()))?;
+        if self.write_len as usize > MAX_TRANSACTION_BYTES ||
+           self.read_len as usize > MAX_TRANSACTION_BYTES {
+            write!(f, " ({}/{} bytes)", self.write_len, self.read_len)?;
+        }
+        match self.error {
+            Some(error) => write!(f, " {:?}", error),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Ring buffer of the most recent transactions.
+pub struct TransactionLog<'a, A: time::Alarm + 'a> {
+    alarm: &'a A,
+    records: TakeCell<'static, [Transaction]>,
+    // Index the next transaction is saved at
+    next: Cell<usize>,
+    len: Cell<usize>,
+    overwritten: Cell<usize>,
+}
+
+impl<'a, A: time::Alarm + 'a> TransactionLog<'a, A> {
+    pub fn new(alarm: &'a A, records: &'static mut [Transaction]) -> TransactionLog<'a, A> {
+        TransactionLog {
+            alarm: alarm,
+            records: TakeCell::new(records),
+            next: Cell::new(0),
+            len: Cell::new(0),
+            overwritten: Cell::new(0),
+        }
+    }
+
+    fn now(&self) -> u32 {
+        self.alarm.now()
+    }
+
+    /// Saves `transaction`, replacing the oldest one if the log is full.
+    fn record(&self, transaction: Transaction) {
+        self.records.map(|records| {
+            if records.len() == 0 {
+                return;
+            }
+            let next = self.next.get();
+            records[next] = transaction;
+            self.next.set((next + 1) % records.len());
+            if self.len.get() == records.len() {
+                self.overwritten.set(self.overwritten.get() + 1);
+            } else {
+                self.len.set(self.len.get() + 1);
+            }
+        });
+    }
+
+    /// Number of transactions in the log.
+    pub fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    /// Number of transactions that were dropped to make room for newer ones.
+    pub fn overwritten(&self) -> usize {
+        self.overwritten.get()
+    }
+
+    /// Calls `f` with each saved transaction, oldest first.
+    pub fn each<F: FnMut(&Transaction)>(&self, mut f: F) {
+        self.records.map(|records| {
+            let len = self.len.get();
+            if len == 0 {
+                return;
+            }
+            let first = (self.next.get() + records.len() - len) % records.len();
+            for i in 0..len {
+                f(&records[(first + i) % records.len()]);
+            }
+        });
+    }
+
+    /// Copies the saved transactions, oldest first, into `buffer`, for
+    /// example to replay them. Returns how many were copied.
+    pub fn copy_to(&self, buffer: &mut [Transaction]) -> usize {
+        let mut count = 0;
+        self.each(|transaction| if count < buffer.len() {
+            buffer[count] = *transaction;
+            count += 1;
+        });
+        count
+    }
+
+    pub fn clear(&self) {
+        self.len.set(0);
+        self.overwritten.set(0);
+    }
+
+    /// Prints every saved transaction with `debug!`. Large logs can fill the
+    /// debug buffer faster than the console drains it, so dump them soon
+    /// after boot or at a panic, where output is flushed synchronously.
+    pub fn dump(&self) {
+        debug!("Bus log: {} transactions, {} overwritten",
+               self.len.get(),
+               self.overwritten.get());
+        self.each(|transaction| debug!("{}", transaction));
+    }
+}
+
+/// Records the transactions of one I2C device.
+pub struct I2CRecorder<'a, A: time::Alarm + 'a> {
+    device: &'a i2c::I2CDevice,
+    address: u8,
+    log: &'a TransactionLog<'a, A>,
+    pending: Cell<Transaction>,
+    client: Cell<Option<&'a I2CClient>>,
+}
+
+impl<'a, A: time::Alarm + 'a> I2CRecorder<'a, A> {
+    /// `address` is only used to label the device's transactions.
+    pub fn new(device: &'a i2c::I2CDevice,
+               address: u8,
+               log: &'a TransactionLog<'a, A>)
+               -> I2CRecorder<'a, A> {
+        I2CRecorder {
+            device: device,
+            address: address,
+            log: log,
+            pending: Cell::new(Transaction::empty()),
+            client: Cell::new(None),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a I2CClient) {
+        self.client.set(Some(client));
+    }
+
+    fn start(&self, operation: Operation, written: &[u8], read_len: u8) {
+        let mut transaction = Transaction::empty();
+        transaction.timestamp = self.log.now();
+        transaction.address = self.address;
+        transaction.operation = operation;
+        transaction.set_written(written);
+        transaction.read_len = read_len as u16;
+        self.pending.set(transaction);
+    }
+}
+
+impl<'a, A: time::Alarm + 'a> i2c::I2CDevice for I2CRecorder<'a, A> {
+    fn enable(&self) {
+        self.device.enable();
+    }
+
+    fn disable(&self) {
+        self.device.disable();
+    }
+
+    fn write_read(&self, data: &'static mut [u8], write_len: u8, read_len: u8) {
+        self.start(Operation::I2CWriteRead, &data[..write_len as usize], read_len);
+        self.device.write_read(data, write_len, read_len);
+    }
+
+    fn write(&self, data: &'static mut [u8], len: u8) {
+        self.start(Operation::I2CWrite, &data[..len as usize], 0);
+        self.device.write(data, len);
+    }
+
+    fn read(&self, buffer: &'static mut [u8], len: u8) {
+        self.start(Operation::I2CRead, &[], len);
+        self.device.read(buffer, len);
+    }
+}
+
+impl<'a, A: time::Alarm + 'a> I2CClient for I2CRecorder<'a, A> {
+    fn command_complete(&self, buffer: &'static mut [u8], error: i2c::Error) {
+        let mut transaction = self.pending.get();
+        transaction.error = Some(error);
+        let read_len = transaction.read_len as usize;
+        transaction.set_read(&buffer[..read_len]);
+        self.log.record(transaction);
+
+        self.client.get().map(move |client| client.command_complete(buffer, error));
+    }
+}
+
+/// Records the transfers of one SPI device.
+pub struct SpiRecorder<'a, A: time::Alarm + 'a> {
+    device: &'a spi::SpiMasterDevice,
+    id: u8,
+    log: &'a TransactionLog<'a, A>,
+    pending: Cell<Transaction>,
+    client: Cell<Option<&'a SpiMasterClient>>,
+}
+
+impl<'a, A: time::Alarm + 'a> SpiRecorder<'a, A> {
+    /// `id` labels the device's transfers in the log, since SPI devices have
+    /// no address.
+    pub fn new(device: &'a spi::SpiMasterDevice,
+               id: u8,
+               log: &'a TransactionLog<'a, A>)
+               -> SpiRecorder<'a, A> {
+        SpiRecorder {
+            device: device,
+            id: id,
+            log: log,
+            pending: Cell::new(Transaction::empty()),
+            client: Cell::new(None),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a SpiMasterClient) {
+        self.client.set(Some(client));
+    }
+}
+
+impl<'a, A: time::Alarm + 'a> spi::SpiMasterDevice for SpiRecorder<'a, A> {
+    fn configure(&self, cpol: ClockPolarity, cpal: ClockPhase, rate: u32) {
+        self.device.configure(cpol, cpal, rate);
+    }
+
+    fn read_write_bytes(&self,
+                        write_buffer: &'static mut [u8],
+                        read_buffer: Option<&'static mut [u8]>,
+                        len: usize)
+                        -> bool {
+        let mut transaction = Transaction::empty();
+        transaction.timestamp = self.log.now();
+        transaction.address = self.id;
+        transaction.operation = Operation::SpiTransfer;
+        transaction.set_written(&write_buffer[..cmp::min(len, write_buffer.len())]);
+        self.pending.set(transaction);
+
+        self.device.read_write_bytes(write_buffer, read_buffer, len)
+    }
+
+    fn set_polarity(&self, cpol: ClockPolarity) {
+        self.device.set_polarity(cpol);
+    }
+
+    fn set_phase(&self, cpal: ClockPhase) {
+        self.device.set_phase(cpal);
+    }
+
+    fn set_rate(&self, rate: u32) {
+        self.device.set_rate(rate);
+    }
+
+    fn get_polarity(&self) -> ClockPolarity {
+        self.device.get_polarity()
+    }
+
+    fn get_phase(&self) -> ClockPhase {
+        self.device.get_phase()
+    }
+
+    fn get_rate(&self) -> u32 {
+        self.device.get_rate()
+    }
+}
+
+impl<'a, A: time::Alarm + 'a> SpiMasterClient for SpiRecorder<'a, A> {
+    fn read_write_done(&self,
+                       write_buffer: &'static mut [u8],
+                       read_buffer: Option<&'static mut [u8]>,
+                       len: usize) {
+        let mut transaction = self.pending.get();
+        // The written bytes are trimmed to the length actually transferred
+        transaction.write_len = cmp::min(transaction.write_len as usize, len) as u16;
+        read_buffer.as_ref().map(|read_buffer| {
+            transaction.set_read(&read_buffer[..cmp::min(len, read_buffer.len())]);
+        });
+        self.log.record(transaction);
+
+        self.client
+            .get()
+            .map(move |client| client.read_write_done(write_buffer, read_buffer, len));
+    }
+}
+
+/// Answers a capsule's I2C operations from recorded transactions.
+///
+/// Each operation takes the next transaction in the list. Reads are
+/// answered with the recorded bytes, zero past the saved ones, and complete
+/// with the recorded error. An operation that does not match its
+/// transaction is reported with `debug!` and counted in `mismatches`, and
+/// once the list is exhausted every operation fails with `AddressNak`.
+pub struct I2CReplay<'a> {
+    transactions: &'a [Transaction],
+    next: Cell<usize>,
+    mismatches: Cell<usize>,
+    buffer: TakeCell<'static, [u8]>,
+    error: Cell<i2c::Error>,
+    client: Cell<Option<&'a I2CClient>>,
+    deferred_call: DeferredCall,
+}
+
+impl<'a> I2CReplay<'a> {
+    pub fn new(transactions: &'a [Transaction]) -> I2CReplay<'a> {
+        I2CReplay {
+            transactions: transactions,
+            next: Cell::new(0),
+            mismatches: Cell::new(0),
+            buffer: TakeCell::empty(),
+            error: Cell::new(i2c::Error::CommandComplete),
+            client: Cell::new(None),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a I2CClient) {
+        self.client.set(Some(client));
+    }
+
+    /// Number of operations that did not match their recorded transaction.
+    pub fn mismatches(&self) -> usize {
+        self.mismatches.get()
+    }
+
+    /// Whether every recorded transaction has been replayed.
+    pub fn is_finished(&self) -> bool {
+        self.next.get() >= self.transactions.len()
+    }
+
+    fn replay(&self, operation: Operation, buffer: &'static mut [u8], write_len: u8, read_len: u8) {
+        let next = self.next.get();
+        let error = match self.transactions.get(next) {
+            Some(transaction) => {
+                self.next.set(next + 1);
+                let written = &buffer[..write_len as usize];
+                let saved = cmp::min(written.len(), MAX_TRANSACTION_BYTES);
+                if transaction.operation != operation ||
+                   transaction.write_len as usize != written.len() ||
+                   transaction.read_len != read_len as u16 ||
+                   transaction.written() != &written[..saved] {
+                    self.mismatches.set(self.mismatches.get() + 1);
+                    debug!("Replay {}: got {:?} w={}, recorded {}",
+                           next,
+                           operation,
+                           Hex(written),
+                           transaction);
+                }
+                transaction.error.unwrap_or(i2c::Error::CommandComplete)
+            }
+            None => i2c::Error::AddressNak,
+        };
+
+        if error == i2c::Error::CommandComplete {
+            let recorded = self.transactions.get(next).map_or(&[][..], |t| t.read_bytes());
+            for (i, byte) in buffer[..read_len as usize].iter_mut().enumerate() {
+                *byte = recorded.get(i).cloned().unwrap_or(0);
+            }
+        }
+        self.buffer.replace(buffer);
+        self.error.set(error);
+        self.deferred_call.set();
+    }
+}
+
+impl I2CReplay<'static> {
+    /// Must be called once before use, so operations can complete from the
+    /// main loop instead of from inside the capsule's call.
+    pub fn register_deferred_call(&'static self) -> bool {
+        self.deferred_call.register(self)
+    }
+}
+
+impl<'a> i2c::I2CDevice for I2CReplay<'a> {
+    fn enable(&self) {}
+
+    fn disable(&self) {}
+
+    fn write_read(&self, data: &'static mut [u8], write_len: u8, read_len: u8) {
+        self.replay(Operation::I2CWriteRead, data, write_len, read_len);
+    }
+
+    fn write(&self, data: &'static mut [u8], len: u8) {
+        self.replay(Operation::I2CWrite, data, len, 0);
+    }
+
+    fn read(&self, buffer: &'static mut [u8], len: u8) {
+        self.replay(Operation::I2CRead, buffer, 0, len);
+    }
+}
+
+impl<'a> DeferredCallClient for I2CReplay<'a> {
+    fn handle_deferred_call(&self) {
+        self.buffer.take().map(|buffer| {
+            let error = self.error.get();
+            self.client.get().map(move |client| client.command_complete(buffer, error));
+        });
+    }
+}
+
+/// Answers a capsule's SPI transfers from recorded transactions, in the same
+/// way as `I2CReplay`. Transfers past the end of the list read zeros.
+pub struct SpiReplay<'a> {
+    transactions: &'a [Transaction],
+    next: Cell<usize>,
+    mismatches: Cell<usize>,
+    polarity: Cell<ClockPolarity>,
+    phase: Cell<ClockPhase>,
+    rate: Cell<u32>,
+    write_buffer: TakeCell<'static, [u8]>,
+    read_buffer: TakeCell<'static, [u8]>,
+    len: Cell<usize>,
+    client: Cell<Option<&'a SpiMasterClient>>,
+    deferred_call: DeferredCall,
+}
+
+impl<'a> SpiReplay<'a> {
+    pub fn new(transactions: &'a [Transaction]) -> SpiReplay<'a> {
+        SpiReplay {
+            transactions: transactions,
+            next: Cell::new(0),
+            mismatches: Cell::new(0),
+            polarity: Cell::new(ClockPolarity::IdleLow),
+            phase: Cell::new(ClockPhase::SampleLeading),
+            rate: Cell::new(0),
+            write_buffer: TakeCell::empty(),
+            read_buffer: TakeCell::empty(),
+            len: Cell::new(0),
+            client: Cell::new(None),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a SpiMasterClient) {
+        self.client.set(Some(client));
+    }
+
+    /// Number of transfers that did not match their recorded transaction.
+    pub fn mismatches(&self) -> usize {
+        self.mismatches.get()
+    }
+
+    /// Whether every recorded transaction has been replayed.
+    pub fn is_finished(&self) -> bool {
+        self.next.get() >= self.transactions.len()
+    }
+}
+
+impl SpiReplay<'static> {
+    /// Must be called once before use, so transfers can complete from the
+    /// main loop instead of from inside the capsule's call.
+    pub fn register_deferred_call(&'static self) -> bool {
+        self.deferred_call.register(self)
+    }
+}
+
+impl<'a> spi::SpiMasterDevice for SpiReplay<'a> {
+    fn configure(&self, cpol: ClockPolarity, cpal: ClockPhase, rate: u32) {
+        self.polarity.set(cpol);
+        self.phase.set(cpal);
+        self.rate.set(rate);
+    }
+
+    fn read_write_bytes(&self,
+                        write_buffer: &'static mut [u8],
+                        read_buffer: Option<&'static mut [u8]>,
+                        len: usize)
+                        -> bool {
+        let len = cmp::min(len, write_buffer.len());
+        let len = read_buffer.as_ref().map_or(len, |read_buffer| cmp::min(len, read_buffer.len()));
+
+        let next = self.next.get();
+        let recorded = match self.transactions.get(next) {
+            Some(transaction) => {
+                self.next.set(next + 1);
+                let written = &write_buffer[..len];
+                let saved = cmp::min(len, MAX_TRANSACTION_BYTES);
+                if transaction.operation != Operation::SpiTransfer ||
+                   transaction.write_len as usize != len ||
+                   transaction.written() != &written[..saved] {
+                    self.mismatches.set(self.mismatches.get() + 1);
+                    debug!("Replay {}: got transfer w={}, recorded {}",
+                           next,
+                           Hex(written),
+                           transaction);
+                }
+                transaction.read_bytes()
+            }
+            None => &[][..],
+        };
+
+        read_buffer.map(|read_buffer| {
+            for (i, byte) in read_buffer[..len].iter_mut().enumerate() {
+                *byte = recorded.get(i).cloned().unwrap_or(0);
+            }
+            self.read_buffer.replace(read_buffer);
+        });
+        self.write_buffer.replace(write_buffer);
+        self.len.set(len);
+        self.deferred_call.set();
+        true
+    }
+
+    fn set_polarity(&self, cpol: ClockPolarity) {
+        self.polarity.set(cpol);
+    }
+
+    fn set_phase(&self, cpal: ClockPhase) {
+        self.phase.set(cpal);
+    }
+
+    fn set_rate(&self, rate: u32) {
+        self.rate.set(rate);
+    }
+
+    fn get_polarity(&self) -> ClockPolarity {
+        self.polarity.get()
+    }
+
+    fn get_phase(&self) -> ClockPhase {
+        self.phase.get()
+    }
+
+    fn get_rate(&self) -> u32 {
+        self.rate.get()
+    }
+}
+
+impl<'a> DeferredCallClient for SpiReplay<'a> {
+    fn handle_deferred_call(&self) {
+        self.write_buffer.take().map(|write_buffer| {
+            let read_buffer = self.read_buffer.take();
+            let len = self.len.get();
+            self.client
+                .get()
+                .map(move |client| client.read_write_done(write_buffer, read_buffer, len));
+        });
+    }
+}
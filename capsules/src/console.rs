@@ -1,45 +1,128 @@
 //! Console Capsule
 //!
 //! Console provides userspace with the ability to print text via a serial
-//! interface.
+//! interface, and to read lines typed on it.
+//!
+//! The buffer to print (allow number 1) may be shared read-only, so apps can
+//! print strings directly from flash.
+//!
+//! Reading
+//! -------
+//!
+//! An app provides a buffer with allow number 0 and a callback with
+//! subscribe number 0, then starts a read with command 2. With an argument
+//! of zero, the read takes a line: typed bytes are echoed, backspace and
+//! delete remove the last byte, and CR, LF or CR LF end the line. The line,
+//! without its ending, is left in the buffer, and the callback receives its
+//! length. A line that fills the buffer is delivered as it is. With a
+//! non-zero argument, the read takes that many bytes as they arrive, without
+//! echo or editing.
+//!
+//! Input goes to the foreground app if it is reading, and otherwise to the
+//! first app that is. The board may designate the foreground app with
+//! `set_foreground`. Until it does, an app can make itself the foreground
+//! app with command 3, which returns `ENOACCESS` once the board has chosen.
+//! Input that arrives while no app is reading is dropped.
+//!
+//! Writing
+//! -------
+//!
+//! Apps with writes queued take turns, in order of app index, sending up to
+//! one transmit buffer each, so a long write cannot hold up the others. The
+//! write-done callback receives the number of bytes written.
+//!
+//! The board may have each line of output tagged with the app it came from
+//! by calling `set_prefix`. Lines then start with `[name] ` or `[id] `, and
+//! kernel debug output with `[kernel] `. While tagging, a turn ends at the
+//! end of a line, and a line cut short by another app's turn is ended and
+//! tagged again when it continues.
 
 use core::cell::Cell;
-use kernel::{AppId, AppSlice, Container, Callback, Shared, Driver, ReturnCode};
+use core::cmp;
+use kernel::{AppId, AppSlice, Container, Callback, ReadOnlyAppSlice, Shared, Driver, ReturnCode};
 use kernel::common::take_cell::TakeCell;
+use kernel::debug;
 use kernel::hil::uart::{self, UART, Client};
-use kernel::process::Error;
+use kernel::process::{self, Error};
 
 pub struct App {
     write_callback: Option<Callback>,
+    read_callback: Option<Callback>,
     read_buffer: Option<AppSlice<Shared, u8>>,
-    write_buffer: Option<AppSlice<Shared, u8>>,
+    write_buffer: Option<ReadOnlyAppSlice<u8>>,
     write_len: usize,
     write_remaining: usize, // How many bytes didn't fit in the buffer and still need to be printed.
     pending_write: bool,
+    pending_read: bool,
     read_idx: usize,
+    read_len: usize, // Bytes to read, or 0 to read a line.
+    line_start: bool, // Whether the next byte written starts a line.
 }
 
 impl Default for App {
     fn default() -> App {
         App {
             write_callback: None,
+            read_callback: None,
             read_buffer: None,
             write_buffer: None,
             write_len: 0,
             write_remaining: 0,
             pending_write: false,
+            pending_read: false,
             read_idx: 0,
+            read_len: 0,
+            line_start: true,
         }
     }
 }
 
 pub static mut WRITE_BUF: [u8; 64] = [0; 64];
+pub static mut READ_BUF: [u8; 1] = [0; 1];
+
+const ECHO_LEN: usize = 16;
+
+// Longest line prefix, including the brackets and trailing space
+const MAX_PREFIX: usize = 20;
+
+/// What, if anything, starts each line of output.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Prefix {
+    None,
+    /// The app's index, such as `[2] `
+    AppId,
+    /// The app's package name, such as `[blink] `
+    PackageName,
+}
+
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
 
 pub struct Console<'a, U: UART + 'a> {
     uart: &'a U,
     apps: Container<App>,
     in_progress: Cell<Option<AppId>>,
     tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    receiving: Cell<bool>,
+    // Bytes waiting to be echoed, which go out ahead of queued writes
+    echo: Cell<[u8; ECHO_LEN]>,
+    echo_len: Cell<usize>,
+    echoing: Cell<bool>,
+    // Whether the last byte received was a CR, so a following LF is ignored
+    last_cr: Cell<bool>,
+    foreground: Cell<Option<AppId>>,
+    // Whether the board chose the foreground app, which apps then cannot
+    // change
+    foreground_pinned: Cell<bool>,
+    // The app whose write was sent last, which the next turn follows
+    last_writer: Cell<Option<AppId>>,
+    // Whether kernel debug output is waiting for a turn, since the kernel's
+    // state is not visited when iterating over apps
+    kernel_pending: Cell<bool>,
+    prefix: Cell<Prefix>,
+    // The app whose line was left unfinished on the terminal, when tagging
+    line_owner: Cell<Option<AppId>>,
     baud_rate: u32,
 }
 
@@ -47,6 +130,7 @@ impl<'a, U: UART> Console<'a, U> {
     pub fn new(uart: &'a U,
                baud_rate: u32,
                tx_buffer: &'static mut [u8],
+               rx_buffer: &'static mut [u8],
                container: Container<App>)
                -> Console<'a, U> {
         Console {
@@ -54,17 +138,42 @@ impl<'a, U: UART> Console<'a, U> {
             apps: container,
             in_progress: Cell::new(None),
             tx_buffer: TakeCell::new(tx_buffer),
+            rx_buffer: TakeCell::new(rx_buffer),
+            receiving: Cell::new(false),
+            echo: Cell::new([0; ECHO_LEN]),
+            echo_len: Cell::new(0),
+            echoing: Cell::new(false),
+            last_cr: Cell::new(false),
+            foreground: Cell::new(None),
+            foreground_pinned: Cell::new(false),
+            last_writer: Cell::new(None),
+            kernel_pending: Cell::new(false),
+            prefix: Cell::new(Prefix::None),
+            line_owner: Cell::new(None),
             baud_rate: baud_rate,
         }
     }
 
-    pub fn initialize(&self) {
+    pub fn initialize(&self) -> ReturnCode {
         self.uart.init(uart::UARTParams {
             baud_rate: self.baud_rate,
             stop_bits: uart::StopBits::One,
             parity: uart::Parity::None,
             hw_flow_control: false,
-        });
+        })
+    }
+
+    /// Makes `appid` the app that gets input when several are reading, and
+    /// stops apps from choosing another. `None` lets the first reading app
+    /// have input and apps choose again.
+    pub fn set_foreground(&self, appid: Option<AppId>) {
+        self.foreground.set(appid);
+        self.foreground_pinned.set(appid.is_some());
+    }
+
+    /// Chooses what starts each line of output.
+    pub fn set_prefix(&self, prefix: Prefix) {
+        self.prefix.set(prefix);
     }
 
     /// Internal helper function for setting up a new send transaction
@@ -99,37 +208,267 @@ impl<'a, U: UART> Console<'a, U> {
 
     /// Internal helper function for sending data for an existing transaction.
     /// Cannot fail. If can't send now, it will schedule for sending later.
-    fn send(&self, app_id: AppId, app: &mut App, slice: AppSlice<Shared, u8>) {
-        if self.in_progress.get().is_none() {
+    fn send(&self, app_id: AppId, app: &mut App, slice: ReadOnlyAppSlice<u8>) {
+        if self.in_progress.get().is_none() && !self.echoing.get() {
             self.in_progress.set(Some(app_id));
+            self.last_writer.set(Some(app_id));
             self.tx_buffer.take().map(|buffer| {
-                let mut transaction_len = app.write_remaining;
-                for (i, c) in slice.as_ref()[slice.len() - app.write_remaining..slice.len()]
-                    .iter()
-                    .enumerate() {
-                    if buffer.len() <= i {
-                        break;
-                    }
-                    buffer[i] = *c;
-                }
-
-                // Check if everything we wanted to print
-                // fit in the buffer.
-                if app.write_remaining > buffer.len() {
-                    transaction_len = buffer.len();
-                    app.write_remaining -= buffer.len();
+                let transaction_len = self.fill_turn(app_id, app, slice.as_ref(), buffer);
+                if app.write_remaining > 0 {
                     app.write_buffer = Some(slice);
-                } else {
-                    app.write_remaining = 0;
                 }
-
                 self.uart.transmit(buffer, transaction_len);
             });
         } else {
             app.pending_write = true;
             app.write_buffer = Some(slice);
+            if app_id.is_kernel() {
+                self.kernel_pending.set(true);
+            }
         }
     }
+
+    /// Copies the app's next turn of output from `data` into `buffer`,
+    /// tagging lines if enabled, and returns the number of bytes to send.
+    fn fill_turn(&self, appid: AppId, app: &mut App, data: &[u8], buffer: &mut [u8]) -> usize {
+        let tagging = self.prefix.get() != Prefix::None;
+        let mut len = 0;
+        let mut line_start = app.line_start;
+        let owner = self.line_owner.get().map(|owner| owner.idx());
+        if tagging && owner != Some(appid.idx()) {
+            if owner.is_some() {
+                // Another app's line was cut short
+                len = copy_into(buffer, b"\r\n");
+            }
+            line_start = true;
+        }
+
+        while app.write_remaining > 0 && len < buffer.len() {
+            if tagging && line_start {
+                let mut prefix = [0; MAX_PREFIX];
+                let prefix_len = self.line_prefix(appid, &mut prefix);
+                if len + prefix_len >= buffer.len() && len > 0 {
+                    break;
+                }
+                len += copy_into(&mut buffer[len..], &prefix[..prefix_len]);
+                if len == buffer.len() {
+                    break;
+                }
+            }
+            let byte = data[data.len() - app.write_remaining];
+            buffer[len] = byte;
+            len += 1;
+            app.write_remaining -= 1;
+            line_start = byte == b'\n';
+            if tagging && line_start {
+                break;
+            }
+        }
+
+        app.line_start = line_start;
+        self.line_owner.set(if tagging && !line_start {
+            Some(appid)
+        } else {
+            None
+        });
+        len
+    }
+
+    /// Writes the tag for lines from `appid` into `prefix`, returning its
+    /// length.
+    fn line_prefix(&self, appid: AppId, prefix: &mut [u8; MAX_PREFIX]) -> usize {
+        prefix[0] = b'[';
+        let mut len = 1;
+        {
+            let name = &mut prefix[1..MAX_PREFIX - 2];
+            let package_name = if self.prefix.get() == Prefix::PackageName {
+                process::package_name(appid).unwrap_or("")
+            } else {
+                ""
+            };
+            if appid.is_kernel() {
+                len += copy_into(name, b"kernel");
+            } else if package_name.len() > 0 {
+                len += copy_into(name, package_name.as_bytes());
+            } else {
+                let mut digits = [0; 20];
+                let mut start = digits.len();
+                let mut idx = appid.idx();
+                loop {
+                    start -= 1;
+                    digits[start] = b'0' + (idx % 10) as u8;
+                    idx /= 10;
+                    if idx == 0 {
+                        break;
+                    }
+                }
+                len += copy_into(name, &digits[start..]);
+            }
+        }
+        len += copy_into(&mut prefix[len..], b"] ");
+        len
+    }
+
+    /// Gives the next app with a queued write its turn, going round in order
+    /// of app index from the app that wrote last.
+    fn send_next_pending(&self) {
+        let last = self.last_writer.get().map(|appid| appid.idx());
+        let after_last = |idx: usize| last.map_or(true, |last| idx > last);
+        let kernel = AppId::kernel_new(debug::APPID_IDX);
+        for &later in [true, false].iter() {
+            for cntr in self.apps.iter() {
+                let started_tx = cntr.enter(|app, _| {
+                    let appid = app.appid();
+                    after_last(appid.idx()) == later && self.take_turn(appid, app)
+                });
+                if started_tx {
+                    return;
+                }
+            }
+            // The kernel's index is above every app's, so it goes last
+            if self.kernel_pending.get() && after_last(kernel.idx()) == later {
+                self.kernel_pending.set(false);
+                let started_tx = self.apps
+                    .enter(kernel, |app, _| self.take_turn(kernel, app))
+                    .unwrap_or(false);
+                if started_tx {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Sends the app's queued write, if it has one. Returns true if a
+    /// transmit was started.
+    fn take_turn(&self, appid: AppId, app: &mut App) -> bool {
+        if !app.pending_write {
+            return false;
+        }
+        app.pending_write = false;
+        match self.send_continue(appid, app) {
+            Ok(more_to_send) => more_to_send,
+            Err(return_code) => {
+                // XXX This shouldn't ever happen?
+                app.write_len = 0;
+                app.write_remaining = 0;
+                let r0 = isize::from(return_code) as usize;
+                app.write_callback.map(|mut cb| { cb.schedule(r0, 0, 0); });
+                false
+            }
+        }
+    }
+
+    /// Queues bytes to be echoed, dropping any that do not fit.
+    fn queue_echo(&self, bytes: &[u8]) {
+        let mut echo = self.echo.get();
+        let mut len = self.echo_len.get();
+        for byte in bytes.iter() {
+            if len < ECHO_LEN {
+                echo[len] = *byte;
+                len += 1;
+            }
+        }
+        self.echo.set(echo);
+        self.echo_len.set(len);
+        self.flush_echo();
+    }
+
+    /// Transmits the queued echo if the UART is idle. Returns true if it did.
+    fn flush_echo(&self) -> bool {
+        let len = self.echo_len.get();
+        if len == 0 || self.in_progress.get().is_some() || self.echoing.get() {
+            return false;
+        }
+        self.tx_buffer.take().map_or(false, |buffer| {
+            let len = cmp::min(len, buffer.len());
+            buffer[..len].copy_from_slice(&self.echo.get()[..len]);
+            self.echo_len.set(0);
+            self.echoing.set(true);
+            self.uart.transmit(buffer, len);
+            true
+        })
+    }
+
+    /// The app that gets the next byte of input, if any app is reading.
+    fn reader(&self) -> Option<AppId> {
+        let foreground = self.foreground.get().and_then(|appid| {
+            match self.apps.enter(appid, |app, _| app.pending_read) {
+                Ok(true) => Some(appid),
+                _ => None,
+            }
+        });
+        if foreground.is_some() {
+            return foreground;
+        }
+        for cntr in self.apps.iter() {
+            let reading = cntr.enter(|app, _| if app.pending_read {
+                Some(app.appid())
+            } else {
+                None
+            });
+            if reading.is_some() {
+                return reading;
+            }
+        }
+        None
+    }
+
+    /// Adds a received byte to the app's read, completing the read if it
+    /// is done.
+    fn receive_byte(&self, app: &mut App, byte: u8) {
+        let last_cr = self.last_cr.get();
+        self.last_cr.set(byte == b'\r');
+        let capacity = app.read_buffer.as_ref().map_or(0, |buffer| buffer.len());
+
+        if app.read_len > 0 {
+            // Fixed-length reads take every byte as it is
+            if app.read_idx < capacity {
+                let idx = app.read_idx;
+                app.read_buffer.as_mut().map(|buffer| buffer.as_mut()[idx] = byte);
+                app.read_idx += 1;
+            }
+            if app.read_idx >= cmp::min(app.read_len, capacity) {
+                self.complete_read(app);
+            }
+            return;
+        }
+
+        match byte {
+            b'\n' if last_cr => {
+                // The end of a CR LF, whose line was already delivered
+            }
+            b'\r' | b'\n' => {
+                self.queue_echo(b"\r\n");
+                self.complete_read(app);
+            }
+            BACKSPACE | DELETE => {
+                if app.read_idx > 0 {
+                    app.read_idx -= 1;
+                    self.queue_echo(b"\x08 \x08");
+                }
+            }
+            _ => {
+                if app.read_idx < capacity {
+                    let idx = app.read_idx;
+                    app.read_buffer.as_mut().map(|buffer| buffer.as_mut()[idx] = byte);
+                    app.read_idx += 1;
+                    self.queue_echo(&[byte]);
+                }
+                if app.read_idx >= capacity {
+                    // The line fills the buffer
+                    self.queue_echo(b"\r\n");
+                    self.complete_read(app);
+                }
+            }
+        }
+    }
+
+    fn complete_read(&self, app: &mut App) {
+        let len = app.read_idx;
+        app.read_idx = 0;
+        app.pending_read = false;
+        app.read_callback.map(|mut cb| { cb.schedule(len, 0, 0); });
+    }
 }
 
 impl<'a, U: UART> Driver for Console<'a, U> {
@@ -138,9 +477,8 @@ impl<'a, U: UART> Driver for Console<'a, U> {
             0 => {
                 self.apps
                     .enter(appid, |app, _| {
-                        app.read_buffer = Some(slice);
                         app.read_idx = 0;
-                        ReturnCode::SUCCESS
+                        slice.store_in(&mut app.read_buffer)
                     })
                     .unwrap_or_else(|err| match err {
                         Error::OutOfMemory => ReturnCode::ENOMEM,
@@ -148,12 +486,20 @@ impl<'a, U: UART> Driver for Console<'a, U> {
                         Error::NoSuchApp => ReturnCode::EINVAL,
                     })
             }
+            1 => self.allow_readonly(appid, allow_num, slice.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn allow_readonly(&self,
+                      appid: AppId,
+                      allow_num: usize,
+                      slice: ReadOnlyAppSlice<u8>)
+                      -> ReturnCode {
+        match allow_num {
             1 => {
                 self.apps
-                    .enter(appid, |app, _| {
-                        app.write_buffer = Some(slice);
-                        ReturnCode::SUCCESS
-                    })
+                    .enter(appid, |app, _| slice.store_in(&mut app.write_buffer))
                     .unwrap_or_else(|err| match err {
                         Error::OutOfMemory => ReturnCode::ENOMEM,
                         Error::AddressOutOfBounds => ReturnCode::EINVAL,
@@ -166,9 +512,17 @@ impl<'a, U: UART> Driver for Console<'a, U> {
 
     fn subscribe(&self, subscribe_num: usize, callback: Callback) -> ReturnCode {
         match subscribe_num {
-            0 /* read line */ => {
-                // read line is not implemented for console at this time
-                ReturnCode::ENOSUPPORT
+            0 /* read done */ => {
+                self.apps.enter(callback.app_id(), |app, _| {
+                    app.read_callback = Some(callback);
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| {
+                    match err {
+                        Error::OutOfMemory => ReturnCode::ENOMEM,
+                        Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                        Error::NoSuchApp => ReturnCode::EINVAL,
+                    }
+                })
             },
             1 /* putstr/write_done */ => {
                 self.apps.enter(callback.app_id(), |app, _| {
@@ -185,7 +539,7 @@ impl<'a, U: UART> Driver for Console<'a, U> {
         }
     }
 
-    fn command(&self, cmd_num: usize, arg1: usize, _: AppId) -> ReturnCode {
+    fn command(&self, cmd_num: usize, arg1: usize, appid: AppId) -> ReturnCode {
         match cmd_num {
             0 /* check if present */ => ReturnCode::SUCCESS,
             1 /* putc */ => {
@@ -195,6 +549,41 @@ impl<'a, U: UART> Driver for Console<'a, U> {
                 });
                 ReturnCode::SuccessWithValue { value: 1 }
             },
+            2 /* read line, or read arg1 bytes */ => {
+                let result = self.apps.enter(appid, |app, _| {
+                    if app.pending_read {
+                        ReturnCode::EBUSY
+                    } else if app.read_buffer.as_ref().map_or(true, |buffer| buffer.len() == 0) {
+                        ReturnCode::EINVAL
+                    } else {
+                        app.pending_read = true;
+                        app.read_idx = 0;
+                        app.read_len = arg1;
+                        ReturnCode::SUCCESS
+                    }
+                }).unwrap_or_else(|err| {
+                    match err {
+                        Error::OutOfMemory => ReturnCode::ENOMEM,
+                        Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                        Error::NoSuchApp => ReturnCode::EINVAL,
+                    }
+                });
+                if result == ReturnCode::SUCCESS && !self.receiving.get() {
+                    self.rx_buffer.take().map(|buffer| {
+                        self.receiving.set(true);
+                        self.uart.receive(buffer, 1);
+                    });
+                }
+                result
+            },
+            3 /* become the foreground app */ => {
+                if self.foreground_pinned.get() {
+                    ReturnCode::ENOACCESS
+                } else {
+                    self.foreground.set(Some(appid));
+                    ReturnCode::SUCCESS
+                }
+            },
             _ => ReturnCode::ENOSUPPORT
         }
     }
@@ -205,64 +594,48 @@ impl<'a, U: UART> Client for Console<'a, U> {
         // Either print more from the AppSlice or send a callback to the
         // application.
         self.tx_buffer.replace(buffer);
+        self.echoing.set(false);
         self.in_progress.get().map(|appid| {
             self.in_progress.set(None);
-            self.apps.enter(appid, |app, _| {
-                match self.send_continue(appid, app) {
-                    Ok(more_to_send) => {
-                        if !more_to_send {
-                            // Go ahead and signal the application
-                            let written = app.write_len;
-                            app.write_len = 0;
-                            app.write_callback.map(|mut cb| { cb.schedule(written, 0, 0); });
-                        }
-                    }
-                    Err(return_code) => {
-                        // XXX This shouldn't ever happen?
-                        app.write_len = 0;
-                        app.write_remaining = 0;
-                        app.pending_write = false;
-                        let r0 = isize::from(return_code) as usize;
-                        app.write_callback.map(|mut cb| { cb.schedule(r0, 0, 0); });
+            let _ = self.apps.enter(appid, |app, _| {
+                if app.write_remaining > 0 {
+                    // The rest waits for the app's next turn
+                    app.pending_write = true;
+                    if appid.is_kernel() {
+                        self.kernel_pending.set(true);
                     }
+                } else {
+                    // Go ahead and signal the application
+                    let written = app.write_len;
+                    app.write_len = 0;
+                    app.write_callback.map(|mut cb| { cb.schedule(written, 0, 0); });
                 }
-            })
+            });
         });
 
-        // If we are not printing more from the current AppSlice,
-        // see if any other applications have pending messages.
-        if self.in_progress.get().is_none() {
-            for cntr in self.apps.iter() {
-                let started_tx = cntr.enter(|app, _| {
-                    if app.pending_write {
-                        app.pending_write = false;
-                        match self.send_continue(app.appid(), app) {
-                            Ok(more_to_send) => more_to_send,
-                            Err(return_code) => {
-                                // XXX This shouldn't ever happen?
-                                app.write_len = 0;
-                                app.write_remaining = 0;
-                                app.pending_write = false;
-                                let r0 = isize::from(return_code) as usize;
-                                app.write_callback.map(|mut cb| { cb.schedule(r0, 0, 0); });
-                                false
-                            }
-                        }
-                    } else {
-                        false
-                    }
-                });
-                if started_tx {
-                    break;
-                }
-            }
+        // Echo any input, or give the next app with pending messages its turn
+        if self.in_progress.get().is_none() && !self.flush_echo() {
+            self.send_next_pending();
         }
     }
 
-    fn receive_complete(&self,
-                        _rx_buffer: &'static mut [u8],
-                        _rx_len: usize,
-                        _error: uart::Error) {
-        // this is currently unimplemented for console
+    fn receive_complete(&self, rx_buffer: &'static mut [u8], rx_len: usize, error: uart::Error) {
+        if error == uart::Error::CommandComplete && rx_len > 0 {
+            let byte = rx_buffer[0];
+            self.reader().map(|appid| {
+                let _ = self.apps.enter(appid, |app, _| self.receive_byte(app, byte));
+            });
+        }
+
+        // Keep listening, so input typed while an app is busy is not lost
+        self.uart.receive(rx_buffer, 1);
     }
 }
+
+/// Copies as much of `src` as fits into `dest`, returning the number of
+/// bytes copied.
+fn copy_into(dest: &mut [u8], src: &[u8]) -> usize {
+    let len = cmp::min(dest.len(), src.len());
+    dest[..len].copy_from_slice(&src[..len]);
+    len
+}
@@ -4,7 +4,8 @@
 //!
 //! The `allow` syscall for this driver supports the single
 //! `allow_number` zero, which is used to provide a buffer over which
-//! to compute a CRC computation.
+//! to compute a CRC computation.  The buffer may also be provided with
+//! the read-only `allow`, in which case it may reside in flash.
 //!
 //! The `subscribe` syscall supports the single `subscribe_number`
 //! zero, which is used to provide a callback that will receive the
@@ -79,7 +80,7 @@
 //!   can be performed purely in hardware on the SAM4L.
 
 use core::cell::Cell;
-use kernel::{AppId, AppSlice, Container, Callback, Driver, ReturnCode, Shared};
+use kernel::{AppId, AppSlice, Container, Callback, Driver, ReadOnlyAppSlice, ReturnCode, Shared};
 use kernel::hil;
 use kernel::hil::crc::CrcAlg;
 use kernel::process::Error;
@@ -88,7 +89,7 @@ use kernel::process::Error;
 #[derive(Default)]
 pub struct App {
     callback: Option<Callback>,
-    buffer: Option<AppSlice<Shared, u8>>,
+    buffer: Option<ReadOnlyAppSlice<u8>>,
 
     // if Some, the application is awaiting the result of a CRC
     //   using the given algorithm
@@ -145,6 +146,13 @@ impl<'a, C: hil::crc::CRC> Crc<'a, C> {
 
                         // Put back taken buffer
                         app.buffer = Some(buffer);
+                    } else {
+                        // The app revoked its buffer before the request
+                        // could be served
+                        if let Some(mut callback) = app.callback {
+                            callback.schedule(From::from(ReturnCode::EINVAL), 0, 0);
+                        }
+                        app.waiting = None;
                     }
                 }
             });
@@ -162,14 +170,19 @@ impl<'a, C: hil::crc::CRC> Crc<'a, C> {
 
 impl<'a, C: hil::crc::CRC> Driver for Crc<'a, C> {
     fn allow(&self, appid: AppId, allow_num: usize, slice: AppSlice<Shared, u8>) -> ReturnCode {
+        self.allow_readonly(appid, allow_num, slice.into())
+    }
+
+    fn allow_readonly(&self,
+                      appid: AppId,
+                      allow_num: usize,
+                      slice: ReadOnlyAppSlice<u8>)
+                      -> ReturnCode {
         match allow_num {
             // Provide user buffer to compute CRC over
             0 => {
                 self.apps
-                    .enter(appid, |app, _| {
-                        app.buffer = Some(slice);
-                        ReturnCode::SUCCESS
-                    })
+                    .enter(appid, |app, _| slice.store_in(&mut app.buffer))
                     .unwrap_or_else(|err| match err {
                         Error::OutOfMemory => ReturnCode::ENOMEM,
                         Error::AddressOutOfBounds => ReturnCode::EINVAL,
@@ -206,9 +206,10 @@ impl<'a, S: hil::spi::SpiMasterDevice + 'a> hil::spi::SpiMasterClient for FM25CL
 
                 read_buffer.map(|read_buffer| {
                     self.client_buffer.take().map(move |buffer| {
-                        let read_len = cmp::min(buffer.len(), len);
+                        // The first three bytes clocked in are the opcode and address
+                        let read_len = cmp::min(buffer.len(), len - 3);
 
-                        for i in 0..(read_len - 3) {
+                        for i in 0..read_len {
                             buffer[i] = read_buffer[i + 3];
                         }
 
@@ -224,6 +225,7 @@ impl<'a, S: hil::spi::SpiMasterDevice + 'a> hil::spi::SpiMasterClient for FM25CL
 }
 
 /// Holds buffers and whatnot that the application has passed us.
+#[derive(Default)]
 struct AppState {
     callback: Option<Callback>,
     read_buffer: Option<AppSlice<Shared, u8>>,
@@ -292,34 +294,17 @@ impl<'a, S: hil::spi::SpiMasterDevice + 'a> Driver for FM25CLDriver<'a, S> {
         match allow_num {
             // Pass read buffer in from application
             0 => {
-                let appst = match self.app_state.take() {
-                    None => {
-                        AppState {
-                            callback: None,
-                            read_buffer: Some(slice),
-                            write_buffer: None,
-                        }
-                    }
-                    Some(mut appst) => {
-                        appst.read_buffer = Some(slice);
-                        appst
-                    }
-                };
+                let mut appst = self.app_state.take().unwrap_or_default();
+                let res = slice.store_in(&mut appst.read_buffer);
                 self.app_state.replace(appst);
-                ReturnCode::SUCCESS
+                res
             }
             // Pass write buffer in from application
             1 => {
-                if self.app_state.is_none() {
-                    self.app_state.put(AppState {
-                        callback: None,
-                        write_buffer: Some(slice),
-                        read_buffer: None,
-                    });
-                } else {
-                    self.app_state.map(|appst| appst.write_buffer = Some(slice));
-                }
-                ReturnCode::SUCCESS
+                let mut appst = self.app_state.take().unwrap_or_default();
+                let res = slice.store_in(&mut appst.write_buffer);
+                self.app_state.replace(appst);
+                res
             }
             _ => ReturnCode::ENOSUPPORT,
         }
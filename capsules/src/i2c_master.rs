@@ -0,0 +1,422 @@
+//! Raw I2C master access for applications
+//!
+//! This capsule lets applications write to and read from I2C slaves. Unlike
+//! `I2CMasterSlaveDriver`, it sits on top of `MuxI2C`, so it shares the bus
+//! with the kernel's sensor drivers. The board lists the 7-bit addresses each
+//! application may access, by package name. An application that is not
+//! listed may access none, and neither may applications whose package name
+//! another loaded application also declares, so an application cannot reach
+//! another's devices by claiming its name.
+//!
+//! The `allow` syscall supports these `allow_number`s:
+//!
+//!   *   `0`: The buffer that reads fill.
+//!
+//!   *   `1`: The buffer holding the bytes to write. It may also be provided
+//!       with the read-only `allow`, in which case it may reside in flash.
+//!
+//! The `subscribe` syscall supports `subscribe_number` zero, for the callback
+//! at the end of a transaction. It receives a status and a length. The status
+//! is `SUCCESS`, `ENOACK` if the slave did not acknowledge its address or
+//! data, `EBUSY` if another master won the bus and `FAIL` if the transaction
+//! timed out. The length is the number of bytes transferred, or for a scan
+//! the number of slaves that answered.
+//!
+//! The `command` syscall supports these `command_number`s:
+//!
+//!   *   `0`: Returns non-zero to indicate the driver is present
+//!
+//!   *   `1`: Writes the first `len` bytes of the write buffer to the slave
+//!       at `address`. The argument is `address | len << 8`.
+//!
+//!   *   `2`: Reads `len` bytes from the slave at `address` into the read
+//!       buffer. The argument is `address | len << 8`.
+//!
+//!   *   `3`: Writes `write_len` bytes and then, after a repeated start, reads
+//!       `read_len` bytes. The argument is
+//!       `address | write_len << 8 | read_len << 16`.
+//!
+//!   *   `4`: Scans the bus, reading a byte from each address the application
+//!       may access. The addresses that answered are stored in the read
+//!       buffer, as many as fit.
+//!
+//! Commands return `ENOACCESS` for an address the application may not
+//! access, `EINVAL` if a buffer the transaction needs was not provided or is
+//! too short, `ESIZE` if a transfer is longer than the kernel buffer, and
+//! `EBUSY` if the application already has a transaction outstanding.
+//! Transactions from different applications are queued and served in turn.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let app_i2c_device = static_init!(
+//!     I2CDevice<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     I2CDevice::new(mux_i2c, 0),
+//!     32);
+//! static ALLOWED: [(&'static str, &'static [u8]); 1] = [("sensors", &[0x48, 0x49])];
+//! let app_i2c = static_init!(
+//!     capsules::i2c_master::I2CMasterDriver<'static,
+//!         VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::i2c_master::I2CMasterDriver::new(
+//!         app_i2c_device,
+//!         &ALLOWED,
+//!         &mut capsules::i2c_master::BUFFER,
+//!         kernel::Container::create()),
+//!     448/8);
+//! app_i2c_device.set_client(app_i2c);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::{AppId, AppSlice, Container, Callback, Driver, ReadOnlyAppSlice, ReturnCode, Shared};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::i2c::{self, I2CDevice as I2CDeviceTrait};
+use kernel::hil::time::Alarm;
+use kernel::process::{self, Error};
+use virtual_i2c::I2CDevice;
+
+pub static mut BUFFER: [u8; 64] = [0; 64];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Transaction {
+    Write { addr: u8, len: usize },
+    Read { addr: u8, len: usize },
+    WriteRead { addr: u8, write_len: usize, read_len: usize },
+    Scan,
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    read_buffer: Option<AppSlice<Shared, u8>>,
+    write_buffer: Option<ReadOnlyAppSlice<u8>>,
+
+    // if Some, the application is waiting for this transaction
+    pending: Option<Transaction>,
+}
+
+pub struct I2CMasterDriver<'a, A: Alarm + 'a> {
+    device: &'a I2CDevice<'a, A>,
+    apps: Container<App>,
+    // Addresses each application may access, by package name
+    allowed: &'static [(&'static str, &'static [u8])],
+    buffer: TakeCell<'static, [u8]>,
+    buffer_len: usize,
+    serving_app: Cell<Option<AppId>>,
+    transaction: Cell<Transaction>,
+    // Position in the allowed addresses of the scan in progress, and the
+    // number of slaves that have answered
+    scan_index: Cell<usize>,
+    scan_found: Cell<usize>,
+}
+
+impl<'a, A: Alarm> I2CMasterDriver<'a, A> {
+    pub fn new(device: &'a I2CDevice<'a, A>,
+               allowed: &'static [(&'static str, &'static [u8])],
+               buffer: &'static mut [u8],
+               apps: Container<App>)
+               -> I2CMasterDriver<'a, A> {
+        I2CMasterDriver {
+            device: device,
+            apps: apps,
+            allowed: allowed,
+            buffer_len: buffer.len(),
+            buffer: TakeCell::new(buffer),
+            serving_app: Cell::new(None),
+            transaction: Cell::new(Transaction::Scan),
+            scan_index: Cell::new(0),
+            scan_found: Cell::new(0),
+        }
+    }
+
+    /// The addresses the board lists for an application.
+    fn allowed_addresses(&self, appid: AppId) -> &'static [u8] {
+        process::package_entry(appid, self.allowed).map_or(&[], |&addresses| addresses)
+    }
+
+    /// Checks that the application may issue `transaction` with the buffers
+    /// it has provided.
+    fn check(&self, appid: AppId, app: &App, transaction: Transaction) -> ReturnCode {
+        let (addr, write_len, read_len) = match transaction {
+            Transaction::Write { addr, len } => (addr, len, 0),
+            Transaction::Read { addr, len } => (addr, 0, len),
+            Transaction::WriteRead { addr, write_len, read_len } => (addr, write_len, read_len),
+            Transaction::Scan => return ReturnCode::SUCCESS,
+        };
+        if !self.allowed_addresses(appid).contains(&addr) {
+            ReturnCode::ENOACCESS
+        } else if write_len > self.buffer_len || read_len > self.buffer_len {
+            ReturnCode::ESIZE
+        } else if write_len > app.write_buffer.as_ref().map_or(0, |buffer| buffer.len()) ||
+                  read_len > app.read_buffer.as_ref().map_or(0, |buffer| buffer.len()) {
+            ReturnCode::EINVAL
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn request(&self, appid: AppId, transaction: Transaction) -> ReturnCode {
+        let result = self.apps
+            .enter(appid, |app, _| {
+                if app.pending.is_some() {
+                    // Each app may make only one request at a time
+                    return ReturnCode::EBUSY;
+                }
+                let result = self.check(appid, app, transaction);
+                if result == ReturnCode::SUCCESS {
+                    app.pending = Some(transaction);
+                }
+                result
+            })
+            .unwrap_or_else(|err| match err {
+                Error::OutOfMemory => ReturnCode::ENOMEM,
+                Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                Error::NoSuchApp => ReturnCode::EINVAL,
+            });
+
+        if result == ReturnCode::SUCCESS {
+            self.serve_waiting_apps();
+        }
+        result
+    }
+
+    fn serve_waiting_apps(&self) {
+        if self.serving_app.get().is_some() {
+            // A transaction is in progress
+            return;
+        }
+
+        // Find a waiting app and start its transaction
+        for app in self.apps.iter() {
+            let found = app.enter(|app, _| {
+                let transaction = match app.pending {
+                    Some(transaction) => transaction,
+                    None => return None,
+                };
+                let result = self.check(app.appid(), app, transaction);
+                if result == ReturnCode::SUCCESS {
+                    self.transaction.set(transaction);
+                    self.scan_index.set(0);
+                    self.scan_found.set(0);
+                    Some(app.appid())
+                } else {
+                    // The app revoked or replaced a buffer before the
+                    // request could be served
+                    app.pending = None;
+                    app.callback.map(|mut cb| cb.schedule(From::from(result), 0, 0));
+                    None
+                }
+            });
+            if found.is_some() {
+                self.serving_app.set(found);
+                self.device.enable();
+                self.start();
+                return;
+            }
+        }
+    }
+
+    /// Starts the transaction being served, or the next probe of a scan.
+    fn start(&self) {
+        let appid = match self.serving_app.get() {
+            Some(appid) => appid,
+            None => return,
+        };
+        let buffer = match self.buffer.take() {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        match self.transaction.get() {
+            Transaction::Write { addr, len } => {
+                self.copy_from_app(appid, buffer, len);
+                self.device.set_address(addr);
+                self.device.write(buffer, len as u8);
+            }
+            Transaction::Read { addr, len } => {
+                self.device.set_address(addr);
+                self.device.read(buffer, len as u8);
+            }
+            Transaction::WriteRead { addr, write_len, read_len } => {
+                self.copy_from_app(appid, buffer, write_len);
+                self.device.set_address(addr);
+                self.device.write_read(buffer, write_len as u8, read_len as u8);
+            }
+            Transaction::Scan => {
+                match self.allowed_addresses(appid).get(self.scan_index.get()) {
+                    Some(&addr) => {
+                        self.device.set_address(addr);
+                        self.device.read(buffer, 1);
+                    }
+                    None => {
+                        self.buffer.replace(buffer);
+                        let found = self.scan_found.get();
+                        self.finish(ReturnCode::SUCCESS, found);
+                    }
+                }
+            }
+        }
+    }
+
+    fn copy_from_app(&self, appid: AppId, buffer: &mut [u8], len: usize) {
+        let _ = self.apps.enter(appid, |app, _| {
+            app.write_buffer.as_ref().map(|slice| {
+                buffer[..len].clone_from_slice(&slice.as_ref()[..len]);
+            });
+        });
+    }
+
+    fn copy_to_app(&self, appid: AppId, data: &[u8], offset: usize) {
+        let _ = self.apps.enter(appid, |app, _| {
+            app.read_buffer.as_mut().map(|slice| {
+                let end = cmp::min(offset + data.len(), slice.len());
+                if offset < end {
+                    slice.as_mut()[offset..end].clone_from_slice(&data[..end - offset]);
+                }
+            });
+        });
+    }
+
+    fn finish(&self, result: ReturnCode, len: usize) {
+        self.device.disable();
+        self.serving_app.get().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.pending = None;
+                app.callback.map(|mut cb| { cb.schedule(From::from(result), len, 0); });
+            });
+        });
+        self.serving_app.set(None);
+        self.serve_waiting_apps();
+    }
+}
+
+impl<'a, A: Alarm> i2c::I2CClient for I2CMasterDriver<'a, A> {
+    fn command_complete(&self, buffer: &'static mut [u8], error: i2c::Error) {
+        let result = match error {
+            i2c::Error::CommandComplete => ReturnCode::SUCCESS,
+            i2c::Error::AddressNak | i2c::Error::DataNak => ReturnCode::ENOACK,
+            i2c::Error::ArbitrationLost => ReturnCode::EBUSY,
+            i2c::Error::Timeout => ReturnCode::FAIL,
+        };
+        let appid = match self.serving_app.get() {
+            Some(appid) => appid,
+            None => {
+                self.buffer.replace(buffer);
+                return;
+            }
+        };
+
+        let len = match self.transaction.get() {
+            Transaction::Write { len, .. } => len,
+            Transaction::Read { len, .. } => {
+                if result == ReturnCode::SUCCESS {
+                    self.copy_to_app(appid, &buffer[..len], 0);
+                }
+                len
+            }
+            Transaction::WriteRead { read_len, .. } => {
+                if result == ReturnCode::SUCCESS {
+                    self.copy_to_app(appid, &buffer[..read_len], 0);
+                }
+                read_len
+            }
+            Transaction::Scan => {
+                let index = self.scan_index.get();
+                if result == ReturnCode::SUCCESS {
+                    let addr = self.allowed_addresses(appid)[index];
+                    self.copy_to_app(appid, &[addr], self.scan_found.get());
+                    self.scan_found.set(self.scan_found.get() + 1);
+                }
+                // Slaves that do not answer are what a scan looks for, so
+                // carry on whatever the result
+                self.scan_index.set(index + 1);
+                self.buffer.replace(buffer);
+                self.start();
+                return;
+            }
+        };
+        self.buffer.replace(buffer);
+        if result == ReturnCode::SUCCESS {
+            self.finish(result, len);
+        } else {
+            self.finish(result, 0);
+        }
+    }
+}
+
+impl<'a, A: Alarm> Driver for I2CMasterDriver<'a, A> {
+    fn allow(&self, appid: AppId, allow_num: usize, slice: AppSlice<Shared, u8>) -> ReturnCode {
+        match allow_num {
+            // Buffer for reads
+            0 => {
+                self.apps
+                    .enter(appid, |app, _| slice.store_in(&mut app.read_buffer))
+                    .unwrap_or_else(|err| match err {
+                        Error::OutOfMemory => ReturnCode::ENOMEM,
+                        Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                        Error::NoSuchApp => ReturnCode::EINVAL,
+                    })
+            }
+            1 => self.allow_readonly(appid, allow_num, slice.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn allow_readonly(&self,
+                      appid: AppId,
+                      allow_num: usize,
+                      slice: ReadOnlyAppSlice<u8>)
+                      -> ReturnCode {
+        match allow_num {
+            // Buffer for writes
+            1 => {
+                self.apps
+                    .enter(appid, |app, _| slice.store_in(&mut app.write_buffer))
+                    .unwrap_or_else(|err| match err {
+                        Error::OutOfMemory => ReturnCode::ENOMEM,
+                        Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                        Error::NoSuchApp => ReturnCode::EINVAL,
+                    })
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn subscribe(&self, subscribe_num: usize, callback: Callback) -> ReturnCode {
+        match subscribe_num {
+            0 => {
+                self.apps
+                    .enter(callback.app_id(), |app, _| {
+                        app.callback = Some(callback);
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| match err {
+                        Error::OutOfMemory => ReturnCode::ENOMEM,
+                        Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                        Error::NoSuchApp => ReturnCode::EINVAL,
+                    })
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn command(&self, command_num: usize, data: usize, appid: AppId) -> ReturnCode {
+        let addr = (data & 0xff) as u8;
+        let len = (data >> 8) & 0xff;
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.request(appid, Transaction::Write { addr: addr, len: len }),
+            2 => self.request(appid, Transaction::Read { addr: addr, len: len }),
+            3 => {
+                let transaction = Transaction::WriteRead {
+                    addr: addr,
+                    write_len: len,
+                    read_len: (data >> 16) & 0xff,
+                };
+                self.request(appid, transaction)
+            }
+            4 => self.request(appid, Transaction::Scan),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
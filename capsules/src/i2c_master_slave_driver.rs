@@ -82,6 +82,7 @@ impl<'a> hil::i2c::I2CHwMasterClient for I2CMasterSlaveDriver<'a> {
             hil::i2c::Error::AddressNak => -1,
             hil::i2c::Error::DataNak => -2,
             hil::i2c::Error::ArbitrationLost => -3,
+            hil::i2c::Error::Timeout => -4,
             hil::i2c::Error::CommandComplete => 0,
         };
 
@@ -197,23 +198,27 @@ impl<'a> Driver for I2CMasterSlaveDriver<'a> {
             // Pass in a buffer for transmitting a `write` to another
             // I2C device.
             0 => {
-                self.app_state.map(|app_state| { app_state.master_tx_buffer = Some(slice); });
-                ReturnCode::SUCCESS
+                self.app_state
+                    .map_or(ReturnCode::SUCCESS,
+                            |app_state| slice.store_in(&mut app_state.master_tx_buffer))
             }
             // Pass in a buffer for doing a read from another I2C device.
             1 => {
-                self.app_state.map(|app_state| { app_state.master_rx_buffer = Some(slice); });
-                ReturnCode::SUCCESS
+                self.app_state
+                    .map_or(ReturnCode::SUCCESS,
+                            |app_state| slice.store_in(&mut app_state.master_rx_buffer))
             }
             // Pass in a buffer for handling a read issued by another I2C master.
             2 => {
-                self.app_state.map(|app_state| { app_state.slave_tx_buffer = Some(slice); });
-                ReturnCode::SUCCESS
+                self.app_state
+                    .map_or(ReturnCode::SUCCESS,
+                            |app_state| slice.store_in(&mut app_state.slave_tx_buffer))
             }
             // Pass in a buffer for handling a write issued by another I2C master.
             3 => {
-                self.app_state.map(|app_state| { app_state.slave_rx_buffer = Some(slice); });
-                ReturnCode::SUCCESS
+                self.app_state
+                    .map_or(ReturnCode::SUCCESS,
+                            |app_state| slice.store_in(&mut app_state.slave_rx_buffer))
             }
             _ => ReturnCode::ENOSUPPORT,
         }
@@ -0,0 +1,1008 @@
+//! Key-value store in flash
+//!
+//! `KVStore` keeps small values under 32-bit keys in a few pages of flash,
+//! and `KVStoreDriver` gives each application its own set of keys.
+//!
+//! The store is a log. Each page starts with a header holding a sequence
+//! number, followed by records that each set or delete one key and end with
+//! a CRC-32 of their contents, and the sequence number is repeated in the
+//! last word of the page. The latest record of a key, in the page with the
+//! highest sequence number, holds its value.
+//!
+//! A record is appended to the newest page by programming it into the
+//! erased bytes after the page's last record, which leaves the records
+//! already there intact. Losing power during an append leaves a torn record
+//! that fails its CRC, so the key keeps its previous value; the torn bytes
+//! cannot be programmed again, so later records go to a new page. Only when
+//! the newest page is full is a record written to an erased page under a new
+//! sequence number. A page is written whole, so one torn while it is
+//! started lacks its last word and is erased when the store is mounted. New
+//! pages rotate through the erased pages, so wear spreads evenly over the
+//! store.
+//!
+//! When a set needs a new page and fewer than two pages are erased, the
+//! oldest page is collected: its records that no newer record replaces are
+//! copied to a new page and the page is erased. One erased page is always
+//! kept for this. A set that still finds no room once every page has been
+//! collected completes with `ENOMEM`.
+//!
+//! The store needs at least three pages. Its page buffers must each be one
+//! flash page long, and the value buffer sets the longest value it can hold.
+//!
+//! Syscalls
+//! --------
+//!
+//! Applications use `KVStoreDriver`. The board gives each application the
+//! namespace of its keys in a table keyed by package name, so an application
+//! keeps its keys however the applications on the board are loaded, and
+//! applications only share keys if the board gives them the same namespace.
+//! Operations from an application that is not in the table, or whose name
+//! another loaded application also declares, return `ENOACCESS`.
+//!
+//! The `allow` syscall supports `allow_number` zero for the buffer that gets
+//! fill, and one for the value that sets store. The value may also be
+//! provided with the read-only `allow`, in which case it may reside in flash.
+//!
+//! The `subscribe` syscall supports `subscribe_number` zero for a callback
+//! receiving a status and, for gets, the length of the value. A get that
+//! finds no value completes with `ENOSUPPORT`. Values longer than the get
+//! buffer are cut short.
+//!
+//! The `command` syscall supports these `command_number`s, where the
+//! argument is the key:
+//!
+//!   *   `0`: Returns non-zero to indicate the driver is present
+//!
+//!   *   `1`: Gets the value of the key.
+//!
+//!   *   `2`: Sets the key to the contents of the set buffer.
+//!
+//!   *   `3`: Deletes the key.
+//!
+//! Each application may have one operation outstanding, and `EBUSY` is
+//! returned otherwise. Operations from different applications are queued.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let kv_store = static_init!(
+//!     capsules::kv_store::KVStore<'static, sam4l::flashcalw::FLASHCALW>,
+//!     capsules::kv_store::KVStore::new(&sam4l::flashcalw::FLASH_CONTROLLER,
+//!                                      0x78000,
+//!                                      8,
+//!                                      &mut capsules::kv_store::PAGE_BUFFER,
+//!                                      &mut capsules::kv_store::SCRATCH_BUFFER,
+//!                                      &mut capsules::kv_store::VALUE_BUFFER),
+//!     1344/8);
+//! hil::flash::Flash::set_client(&sam4l::flashcalw::FLASH_CONTROLLER, kv_store);
+//! static NAMESPACES: [(&'static str, u32); 2] = [("sensors", 1), ("logger", 2)];
+//! let kv_driver = static_init!(
+//!     capsules::kv_store::KVStoreDriver<'static, sam4l::flashcalw::FLASHCALW>,
+//!     capsules::kv_store::KVStoreDriver::new(kv_store,
+//!                                            &NAMESPACES,
+//!                                            kernel::Container::create()),
+//!     192/8);
+//! kv_store.set_client(kv_driver);
+//! kv_store.mount();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::{AppId, AppSlice, Container, Callback, Driver, ReadOnlyAppSlice, ReturnCode, Shared};
+use kernel::common::crc32::crc32;
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::flash;
+use kernel::process::{self, Error};
+
+/// Most pages a store can span.
+pub const MAX_PAGES: usize = 16;
+
+/// Most records a page holds, so collection can track them in a bitmap.
+const MAX_RECORDS: usize = 64;
+
+const PAGE_MAGIC: u32 = 0x4b565332; // "KVS2"
+// Magic, sequence number and its complement
+const PAGE_HEADER_LEN: usize = 12;
+// Sequence number again, written last
+const PAGE_TRAILER_LEN: usize = 4;
+// Length, namespace and key
+const RECORD_HEADER_LEN: usize = 10;
+const CRC_LEN: usize = 4;
+// Set in the length of a record that deletes its key
+const DELETED: u16 = 0x8000;
+// Length read from erased flash, which ends the records of a page
+const ERASED: u16 = 0xffff;
+
+pub static mut PAGE_BUFFER: [u8; 512] = [0; 512];
+pub static mut SCRATCH_BUFFER: [u8; 512] = [0; 512];
+pub static mut VALUE_BUFFER: [u8; 64] = [0; 64];
+
+pub trait Client {
+    /// Called when the store has been read and can be used.
+    fn mount_complete(&self, result: ReturnCode);
+
+    /// `value` is the value of the key if `result` is `SUCCESS`. A key with
+    /// no value completes with `ENOSUPPORT`.
+    fn get_complete(&self, result: ReturnCode, value: &[u8]);
+
+    fn set_complete(&self, result: ReturnCode);
+
+    fn delete_complete(&self, result: ReturnCode);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Mount,
+    Get,
+    Set,
+    Delete,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    // Reading each page when mounting
+    MountRead(usize),
+    // Erasing a damaged page found when mounting
+    MountErase(usize),
+    // Looking for the key in a page, newest page first
+    GetRead(usize),
+    // Reading the newest page to append a record to it
+    SetRead(usize),
+    // Programming the record into the newest page, or writing it to a
+    // `fresh` page
+    SetWrite { page: usize, fresh: bool },
+    // Reading the oldest page to collect it
+    CollectRead(usize),
+    // Reading a newer page for records that replace the victim's
+    CollectScan { victim: usize, page: usize },
+    // Writing the victim's live records to a new page
+    CollectWrite { victim: usize, page: usize },
+    // Erasing the collected page
+    CollectErase(usize),
+}
+
+/// A record in a page buffer.
+#[derive(Clone, Copy)]
+struct Record {
+    offset: usize,
+    namespace: u32,
+    key: u32,
+    deleted: bool,
+    value_len: usize,
+}
+
+impl Record {
+    fn len(&self) -> usize {
+        RECORD_HEADER_LEN + self.value_len + CRC_LEN
+    }
+
+    fn value_range(&self) -> (usize, usize) {
+        let start = self.offset + RECORD_HEADER_LEN;
+        (start, start + self.value_len)
+    }
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    buf[offset] as u16 | (buf[offset + 1] as u16) << 8
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    read_u16(buf, offset) as u32 | (read_u16(buf, offset + 2) as u32) << 16
+}
+
+fn write_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset] = value as u8;
+    buf[offset + 1] = (value >> 8) as u8;
+}
+
+fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    write_u16(buf, offset, value as u16);
+    write_u16(buf, offset + 2, (value >> 16) as u16);
+}
+
+/// The sequence number of a page, if it was written completely.
+fn page_seq(page: &[u8]) -> Option<u32> {
+    let seq = read_u32(page, 4);
+    let trailer = read_u32(page, page.len() - PAGE_TRAILER_LEN);
+    if read_u32(page, 0) == PAGE_MAGIC && read_u32(page, 8) == !seq && trailer == seq &&
+       seq != 0 {
+        Some(seq)
+    } else {
+        None
+    }
+}
+
+/// Clears `page` and marks it with sequence number `seq`.
+fn seal_page(page: &mut [u8], seq: u32) {
+    for byte in page.iter_mut() {
+        *byte = 0xff;
+    }
+    let len = page.len();
+    write_u32(page, 0, PAGE_MAGIC);
+    write_u32(page, 4, seq);
+    write_u32(page, 8, !seq);
+    write_u32(page, len - PAGE_TRAILER_LEN, seq);
+}
+
+/// Parses the record at `offset`, returning None at the end of the records
+/// or at a damaged record.
+fn parse_record(page: &[u8], offset: usize) -> Option<Record> {
+    let limit = page.len() - PAGE_TRAILER_LEN;
+    if offset + RECORD_HEADER_LEN + CRC_LEN > limit {
+        return None;
+    }
+    let len = read_u16(page, offset);
+    if len == ERASED {
+        return None;
+    }
+    let record = Record {
+        offset: offset,
+        namespace: read_u32(page, offset + 2),
+        key: read_u32(page, offset + 6),
+        deleted: len & DELETED != 0,
+        value_len: (len & !DELETED) as usize,
+    };
+    let end = offset + RECORD_HEADER_LEN + record.value_len;
+    if end + CRC_LEN > limit || read_u32(page, end) != crc32(&page[offset..end]) {
+        return None;
+    }
+    Some(record)
+}
+
+/// Calls `f` with the index of each record of `page` and the record, and
+/// returns the number of records and the offset just past the last one.
+fn each_record<F: FnMut(usize, Record)>(page: &[u8], mut f: F) -> (usize, usize) {
+    let mut offset = PAGE_HEADER_LEN;
+    let mut count = 0;
+    while count < MAX_RECORDS {
+        match parse_record(page, offset) {
+            Some(record) => {
+                f(count, record);
+                offset += record.len();
+                count += 1;
+            }
+            None => break,
+        }
+    }
+    (count, offset)
+}
+
+/// Appends a record to `page` at `offset`, returning the offset past it.
+fn write_record(page: &mut [u8],
+                offset: usize,
+                namespace: u32,
+                key: u32,
+                deleted: bool,
+                value: &[u8])
+                -> usize {
+    let len = value.len() as u16 | if deleted { DELETED } else { 0 };
+    write_u16(page, offset, len);
+    write_u32(page, offset + 2, namespace);
+    write_u32(page, offset + 6, key);
+    let end = offset + RECORD_HEADER_LEN + value.len();
+    page[offset + RECORD_HEADER_LEN..end].copy_from_slice(value);
+    let crc = crc32(&page[offset..end]);
+    write_u32(page, end, crc);
+    end + CRC_LEN
+}
+
+pub struct KVStore<'a, F: flash::Flash + 'a> {
+    flash: &'a F,
+    client: Cell<Option<&'a Client>>,
+    // Flash offset of the first page
+    start: usize,
+    pages: usize,
+    page_size: usize,
+    // Sequence number of each page, or 0 for an erased page
+    seqs: Cell<[u32; MAX_PAGES]>,
+    next_seq: Cell<u32>,
+    // The page written last, to rotate writes through the erased pages
+    last_written: Cell<usize>,
+    mounted: Cell<bool>,
+    buffer: TakeCell<'static, [u8]>,
+    // Holds the page being collected, and the value handed to get clients
+    scratch: TakeCell<'static, [u8]>,
+    value: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    operation: Cell<Operation>,
+    namespace: Cell<u32>,
+    key: Cell<u32>,
+    value_len: Cell<usize>,
+    // Records of the page being collected that no newer record replaces
+    live: Cell<u64>,
+    // Pages collected for the current set, to give up once all have been
+    collected: Cell<usize>,
+}
+
+impl<'a, F: flash::Flash> KVStore<'a, F> {
+    /// A store of `pages` pages from flash offset `start`, which must be page
+    /// aligned.
+    pub fn new(flash: &'a F,
+               start: usize,
+               pages: usize,
+               buffer: &'static mut [u8],
+               scratch: &'static mut [u8],
+               value: &'static mut [u8])
+               -> KVStore<'a, F> {
+        KVStore {
+            flash: flash,
+            client: Cell::new(None),
+            start: start,
+            pages: cmp::min(pages, MAX_PAGES),
+            page_size: buffer.len(),
+            seqs: Cell::new([0; MAX_PAGES]),
+            next_seq: Cell::new(1),
+            last_written: Cell::new(0),
+            mounted: Cell::new(false),
+            buffer: TakeCell::new(buffer),
+            scratch: TakeCell::new(scratch),
+            value: TakeCell::new(value),
+            state: Cell::new(State::Idle),
+            operation: Cell::new(Operation::Mount),
+            namespace: Cell::new(0),
+            key: Cell::new(0),
+            value_len: Cell::new(0),
+            live: Cell::new(0),
+            collected: Cell::new(0),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a Client) {
+        self.client.set(Some(client));
+    }
+
+    /// Reads the store from flash, erasing any damaged pages. Must complete
+    /// before the store is used.
+    pub fn mount(&self) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if self.pages < 3 {
+            return ReturnCode::EINVAL;
+        }
+        self.mounted.set(false);
+        self.seqs.set([0; MAX_PAGES]);
+        self.next_seq.set(1);
+        self.operation.set(Operation::Mount);
+        self.read(State::MountRead(0), 0);
+        ReturnCode::SUCCESS
+    }
+
+    /// Looks up `key` in `namespace`. Returns `ENOSUPPORT` right away if the
+    /// store is empty.
+    pub fn get(&self, namespace: u32, key: u32) -> ReturnCode {
+        let ready = self.check_ready();
+        if ready != ReturnCode::SUCCESS {
+            return ready;
+        }
+        match self.newest_page() {
+            Some(page) => {
+                self.start_operation(Operation::Get, namespace, key);
+                self.read(State::GetRead(page), page);
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Sets `key` in `namespace` to `value`, which is copied.
+    pub fn set(&self, namespace: u32, key: u32, value: &[u8]) -> ReturnCode {
+        let ready = self.check_ready();
+        if ready != ReturnCode::SUCCESS {
+            return ready;
+        }
+        let fits = self.value.map_or(false, |buffer| value.len() <= buffer.len()) &&
+                   PAGE_HEADER_LEN + RECORD_HEADER_LEN + value.len() + CRC_LEN +
+                   PAGE_TRAILER_LEN <= self.page_size;
+        if !fits {
+            return ReturnCode::ESIZE;
+        }
+        self.value.map(|buffer| buffer[..value.len()].copy_from_slice(value));
+        self.value_len.set(value.len());
+        self.start_operation(Operation::Set, namespace, key);
+        self.start_write();
+        ReturnCode::SUCCESS
+    }
+
+    pub fn delete(&self, namespace: u32, key: u32) -> ReturnCode {
+        let ready = self.check_ready();
+        if ready != ReturnCode::SUCCESS {
+            return ready;
+        }
+        self.value_len.set(0);
+        self.start_operation(Operation::Delete, namespace, key);
+        self.start_write();
+        ReturnCode::SUCCESS
+    }
+
+    fn check_ready(&self) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            ReturnCode::EBUSY
+        } else if !self.mounted.get() {
+            ReturnCode::EOFF
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn start_operation(&self, operation: Operation, namespace: u32, key: u32) {
+        self.operation.set(operation);
+        self.namespace.set(namespace);
+        self.key.set(key);
+        self.collected.set(0);
+    }
+
+    fn seq(&self, page: usize) -> u32 {
+        self.seqs.get()[page]
+    }
+
+    fn set_seq(&self, page: usize, seq: u32) {
+        let mut seqs = self.seqs.get();
+        seqs[page] = seq;
+        self.seqs.set(seqs);
+    }
+
+    /// The used page with the highest sequence number below `below`.
+    fn page_before(&self, below: u32) -> Option<usize> {
+        (0..self.pages)
+            .filter(|&page| self.seq(page) != 0 && self.seq(page) < below)
+            .max_by_key(|&page| self.seq(page))
+    }
+
+    /// The used page with the lowest sequence number above `above`.
+    fn page_after(&self, above: u32) -> Option<usize> {
+        (0..self.pages)
+            .filter(|&page| self.seq(page) > above)
+            .min_by_key(|&page| self.seq(page))
+    }
+
+    fn newest_page(&self) -> Option<usize> {
+        self.page_before(u32::max_value())
+    }
+
+    fn free_pages(&self) -> usize {
+        (0..self.pages).filter(|&page| self.seq(page) == 0).count()
+    }
+
+    /// The next erased page after the one written last.
+    fn free_page(&self) -> Option<usize> {
+        (1..self.pages + 1)
+            .map(|i| (self.last_written.get() + i) % self.pages)
+            .find(|&page| self.seq(page) == 0)
+    }
+
+    fn is_key(&self, record: &Record) -> bool {
+        record.namespace == self.namespace.get() && record.key == self.key.get()
+    }
+
+    fn read(&self, state: State, page: usize) {
+        let buffer = match state {
+            State::CollectRead(_) => self.scratch.take(),
+            _ => self.buffer.take(),
+        };
+        buffer.map(|buffer| {
+            self.state.set(state);
+            self.flash.read(self.start + page * self.page_size, buffer);
+        });
+    }
+
+    fn write(&self, state: State, page: usize, buffer: &'static mut [u8]) {
+        self.state.set(state);
+        self.flash.write(self.start + page * self.page_size, buffer);
+    }
+
+    fn program(&self, state: State, page: usize, buffer: &'static mut [u8]) {
+        self.state.set(state);
+        self.flash.program(self.start + page * self.page_size, buffer);
+    }
+
+    fn erase(&self, state: State, page: usize) {
+        self.state.set(state);
+        self.flash.erase(self.start + page * self.page_size, self.page_size);
+    }
+
+    fn start_write(&self) {
+        match self.newest_page() {
+            Some(page) => self.read(State::SetRead(page), page),
+            None => self.buffer.take().map_or((), |buffer| self.write_record(buffer, None)),
+        }
+    }
+
+    /// Appends the pending record to the newest page, which is in `buffer`,
+    /// or writes it to an erased page if the newest page has no room.
+    fn write_record(&self, buffer: &'static mut [u8], newest: Option<usize>) {
+        let record_len = RECORD_HEADER_LEN + self.value_len.get() + CRC_LEN;
+        let (count, end) = each_record(buffer, |_, _| {});
+        let trailer = buffer.len() - PAGE_TRAILER_LEN;
+        // Bytes after the last record that are not erased were left by a
+        // torn append, and cannot be programmed again
+        let fits = count < MAX_RECORDS && end + record_len <= trailer &&
+                   buffer[end..trailer].iter().all(|byte| *byte == 0xff);
+
+        match newest {
+            Some(page) if fits => {
+                self.put_record(buffer, end);
+                self.program(State::SetWrite {
+                                 page: page,
+                                 fresh: false,
+                             },
+                             page,
+                             buffer);
+            }
+            _ => {
+                // Starting a new page must leave an erased page for
+                // collection
+                let page = match self.free_page() {
+                    Some(page) if self.free_pages() >= 2 => page,
+                    _ => {
+                        self.buffer.replace(buffer);
+                        return self.collect();
+                    }
+                };
+                seal_page(buffer, self.next_seq.get());
+                self.put_record(buffer, PAGE_HEADER_LEN);
+                self.write(State::SetWrite {
+                               page: page,
+                               fresh: true,
+                           },
+                           page,
+                           buffer);
+            }
+        }
+    }
+
+    /// Adds the pending record to `page` at `offset`.
+    fn put_record(&self, page: &mut [u8], offset: usize) {
+        let deleted = self.operation.get() == Operation::Delete;
+        self.value.map(|value| {
+            write_record(page,
+                         offset,
+                         self.namespace.get(),
+                         self.key.get(),
+                         deleted,
+                         &value[..self.value_len.get()]);
+        });
+    }
+
+    /// Starts collecting the oldest page, or gives up if every page has been
+    /// collected for this set already.
+    fn collect(&self) {
+        if self.collected.get() >= self.pages {
+            return self.finish(ReturnCode::ENOMEM);
+        }
+        self.collected.set(self.collected.get() + 1);
+        match self.page_after(0) {
+            Some(victim) => self.read(State::CollectRead(victim), victim),
+            None => self.finish(ReturnCode::ENOMEM),
+        }
+    }
+
+    /// Clears the live bits of the victim's records that `record` replaces.
+    fn replace_live(&self, victim: &[u8], record: &Record, before: usize) {
+        let mut live = self.live.get();
+        each_record(victim, |index, old| {
+            if index < before && old.namespace == record.namespace && old.key == record.key {
+                live &= !(1 << index);
+            }
+        });
+        self.live.set(live);
+    }
+
+    /// Scans the next page newer than `after` for records that replace the
+    /// victim's, or moves on to writing its live records.
+    fn scan_after(&self, victim: usize, after: usize) {
+        match self.page_after(self.seq(after)) {
+            Some(page) => self.read(State::CollectScan { victim: victim, page: page }, page),
+            None => self.write_live(victim),
+        }
+    }
+
+    fn write_live(&self, victim: usize) {
+        let scratch = match self.scratch.take() {
+            Some(scratch) => scratch,
+            None => return self.finish(ReturnCode::FAIL),
+        };
+        // Deletions in the oldest page have nothing older left to hide
+        let mut live = self.live.get();
+        each_record(scratch, |index, record| if record.deleted {
+            live &= !(1 << index);
+        });
+
+        if live == 0 {
+            self.scratch.replace(scratch);
+            self.erase(State::CollectErase(victim), victim);
+            return;
+        }
+
+        let page = self.free_page();
+        let buffer = self.buffer.take();
+        match (page, buffer) {
+            (Some(page), Some(buffer)) => {
+                seal_page(buffer, self.next_seq.get());
+                let mut offset = PAGE_HEADER_LEN;
+                each_record(scratch, |index, record| if live & (1 << index) != 0 {
+                    let end = offset + record.len();
+                    buffer[offset..end]
+                        .copy_from_slice(&scratch[record.offset..record.offset + record.len()]);
+                    offset = end;
+                });
+                self.scratch.replace(scratch);
+                self.write(State::CollectWrite {
+                               victim: victim,
+                               page: page,
+                           },
+                           page,
+                           buffer);
+            }
+            (_, buffer) => {
+                buffer.map(|buffer| self.buffer.replace(buffer));
+                self.scratch.replace(scratch);
+                self.finish(ReturnCode::ENOMEM);
+            }
+        }
+    }
+
+    /// Records that `page` now holds the next sequence number.
+    fn written(&self, page: usize) {
+        self.set_seq(page, self.next_seq.get());
+        self.next_seq.set(self.next_seq.get() + 1);
+        self.last_written.set(page);
+    }
+
+    fn mount_next(&self, page: usize) {
+        if page + 1 < self.pages {
+            self.read(State::MountRead(page + 1), page + 1);
+        } else {
+            self.last_written.set(self.newest_page().unwrap_or(self.pages - 1));
+            self.mounted.set(true);
+            self.finish(ReturnCode::SUCCESS);
+        }
+    }
+
+    fn finish(&self, result: ReturnCode) {
+        self.state.set(State::Idle);
+        self.client.get().map(|client| match self.operation.get() {
+            Operation::Mount => client.mount_complete(result),
+            Operation::Get => client.get_complete(result, &[]),
+            Operation::Set => client.set_complete(result),
+            Operation::Delete => client.delete_complete(result),
+        });
+    }
+
+    /// Completes a get with the value at `range` of `buffer`.
+    fn finish_get(&self, buffer: &'static mut [u8], range: (usize, usize)) {
+        let len = range.1 - range.0;
+        // The value is handed over in the scratch buffer, leaving the page
+        // buffer free for an operation the client starts from the callback
+        let scratch = self.scratch.take();
+        scratch.map(|scratch| {
+            scratch[..len].copy_from_slice(&buffer[range.0..range.1]);
+            self.buffer.replace(buffer);
+            self.state.set(State::Idle);
+            self.client
+                .get()
+                .map(|client| client.get_complete(ReturnCode::SUCCESS, &scratch[..len]));
+            self.scratch.replace(scratch);
+        });
+    }
+}
+
+impl<'a, F: flash::Flash> flash::Client for KVStore<'a, F> {
+    fn read_complete(&self, buffer: &'static mut [u8], error: flash::Error) {
+        let state = self.state.get();
+        if error != flash::Error::CommandComplete {
+            match state {
+                State::CollectRead(_) => self.scratch.replace(buffer),
+                _ => self.buffer.replace(buffer),
+            };
+            return self.finish(ReturnCode::FAIL);
+        }
+
+        match state {
+            State::MountRead(page) => {
+                let erased = buffer.iter().all(|byte| *byte == 0xff);
+                let seq = page_seq(buffer);
+                self.buffer.replace(buffer);
+                match seq {
+                    Some(seq) => {
+                        self.set_seq(page, seq);
+                        self.next_seq.set(cmp::max(self.next_seq.get(), seq + 1));
+                        self.mount_next(page);
+                    }
+                    None if erased => self.mount_next(page),
+                    // Torn by a loss of power
+                    None => self.erase(State::MountErase(page), page),
+                }
+            }
+            State::GetRead(page) => {
+                let mut found = None;
+                each_record(buffer, |_, record| if self.is_key(&record) {
+                    found = Some(record);
+                });
+                match found {
+                    Some(record) if record.deleted => {
+                        self.buffer.replace(buffer);
+                        self.finish(ReturnCode::ENOSUPPORT);
+                    }
+                    Some(record) => self.finish_get(buffer, record.value_range()),
+                    None => {
+                        self.buffer.replace(buffer);
+                        match self.page_before(self.seq(page)) {
+                            Some(older) => self.read(State::GetRead(older), older),
+                            None => self.finish(ReturnCode::ENOSUPPORT),
+                        }
+                    }
+                }
+            }
+            State::SetRead(page) => self.write_record(buffer, Some(page)),
+            State::CollectRead(victim) => {
+                // A record is live unless a later one in the page replaces it
+                self.live.set(0);
+                each_record(buffer, |index, record| {
+                    self.replace_live(&buffer[..], &record, index);
+                    self.live.set(self.live.get() | 1 << index);
+                });
+                self.scratch.replace(buffer);
+                self.scan_after(victim, victim);
+            }
+            State::CollectScan { victim, page } => {
+                self.scratch.map(|scratch| {
+                    let count = each_record(scratch, |_, _| {}).0;
+                    each_record(buffer,
+                                |_, record| self.replace_live(&scratch[..], &record, count));
+                });
+                self.buffer.replace(buffer);
+                self.scan_after(victim, page);
+            }
+            _ => {
+                self.buffer.replace(buffer);
+            }
+        }
+    }
+
+    fn write_complete(&self, buffer: &'static mut [u8], error: flash::Error) {
+        self.buffer.replace(buffer);
+        if error != flash::Error::CommandComplete {
+            return self.finish(ReturnCode::FAIL);
+        }
+
+        match self.state.get() {
+            State::SetWrite { page, fresh } => {
+                if fresh {
+                    self.written(page);
+                }
+                self.finish(ReturnCode::SUCCESS);
+            }
+            State::CollectWrite { victim, page } => {
+                self.written(page);
+                self.erase(State::CollectErase(victim), victim);
+            }
+            _ => {}
+        }
+    }
+
+    fn erase_complete(&self, error: flash::Error) {
+        match self.state.get() {
+            State::MountErase(page) => {
+                if error != flash::Error::CommandComplete {
+                    return self.finish(ReturnCode::FAIL);
+                }
+                self.mount_next(page);
+            }
+            State::CollectErase(victim) => {
+                if error != flash::Error::CommandComplete {
+                    return self.finish(ReturnCode::FAIL);
+                }
+                self.set_seq(victim, 0);
+                self.start_write();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// An opaque value maintaining state for one application's request
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    get_buffer: Option<AppSlice<Shared, u8>>,
+    set_buffer: Option<ReadOnlyAppSlice<u8>>,
+
+    // if Some, the application is waiting for this operation on the key
+    pending: Option<(Operation, u32)>,
+}
+
+pub struct KVStoreDriver<'a, F: flash::Flash + 'a> {
+    store: &'a KVStore<'a, F>,
+    // Namespace of each application's keys, by package name
+    namespaces: &'static [(&'static str, u32)],
+    apps: Container<App>,
+    serving_app: Cell<Option<AppId>>,
+}
+
+impl<'a, F: flash::Flash> KVStoreDriver<'a, F> {
+    pub fn new(store: &'a KVStore<'a, F>,
+               namespaces: &'static [(&'static str, u32)],
+               apps: Container<App>)
+               -> KVStoreDriver<'a, F> {
+        KVStoreDriver {
+            store: store,
+            namespaces: namespaces,
+            apps: apps,
+            serving_app: Cell::new(None),
+        }
+    }
+
+    /// The namespace the board gave the app's keys, if any.
+    fn namespace(&self, appid: AppId) -> Option<u32> {
+        process::package_entry(appid, self.namespaces).map(|&namespace| namespace)
+    }
+
+    fn serve_waiting_apps(&self) {
+        if self.serving_app.get().is_some() {
+            // An operation is in progress
+            return;
+        }
+
+        // Find a waiting app and start its operation
+        for app in self.apps.iter() {
+            let started = app.enter(|app, _| {
+                let (operation, key) = match app.pending {
+                    Some(pending) => pending,
+                    None => return None,
+                };
+                let result = match (self.namespace(app.appid()), operation) {
+                    (None, _) => ReturnCode::ENOACCESS,
+                    (Some(namespace), Operation::Get) => self.store.get(namespace, key),
+                    (Some(namespace), Operation::Set) => {
+                        match app.set_buffer {
+                            Some(ref value) => self.store.set(namespace, key, value.as_ref()),
+                            None => ReturnCode::EINVAL,
+                        }
+                    }
+                    (Some(namespace), _) => self.store.delete(namespace, key),
+                };
+                if result == ReturnCode::SUCCESS {
+                    Some(app.appid())
+                } else {
+                    // The app's request failed
+                    app.pending = None;
+                    app.callback.map(|mut cb| { cb.schedule(From::from(result), 0, 0); });
+                    None
+                }
+            });
+            if started.is_some() {
+                self.serving_app.set(started);
+                return;
+            }
+        }
+    }
+
+    /// Reports the result of the served app's operation.
+    fn complete(&self, result: ReturnCode, value: &[u8]) {
+        self.serving_app.get().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                if let Some(ref mut buffer) = app.get_buffer {
+                    let len = cmp::min(buffer.len(), value.len());
+                    buffer.as_mut()[..len].copy_from_slice(&value[..len]);
+                }
+                app.pending = None;
+                app.callback.map(|mut cb| { cb.schedule(From::from(result), value.len(), 0); });
+            });
+        });
+        self.serving_app.set(None);
+        self.serve_waiting_apps();
+    }
+}
+
+impl<'a, F: flash::Flash> Client for KVStoreDriver<'a, F> {
+    fn mount_complete(&self, _result: ReturnCode) {
+        self.serve_waiting_apps();
+    }
+
+    fn get_complete(&self, result: ReturnCode, value: &[u8]) {
+        self.complete(result, value);
+    }
+
+    fn set_complete(&self, result: ReturnCode) {
+        self.complete(result, &[]);
+    }
+
+    fn delete_complete(&self, result: ReturnCode) {
+        self.complete(result, &[]);
+    }
+}
+
+impl<'a, F: flash::Flash> Driver for KVStoreDriver<'a, F> {
+    fn allow(&self, appid: AppId, allow_num: usize, slice: AppSlice<Shared, u8>) -> ReturnCode {
+        match allow_num {
+            // Buffer for values that are got
+            0 => {
+                self.apps
+                    .enter(appid, |app, _| slice.store_in(&mut app.get_buffer))
+                    .unwrap_or_else(|err| match err {
+                        Error::OutOfMemory => ReturnCode::ENOMEM,
+                        Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                        Error::NoSuchApp => ReturnCode::EINVAL,
+                    })
+            }
+            1 => self.allow_readonly(appid, allow_num, slice.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn allow_readonly(&self,
+                      appid: AppId,
+                      allow_num: usize,
+                      slice: ReadOnlyAppSlice<u8>)
+                      -> ReturnCode {
+        match allow_num {
+            // Value to set
+            1 => {
+                self.apps
+                    .enter(appid, |app, _| slice.store_in(&mut app.set_buffer))
+                    .unwrap_or_else(|err| match err {
+                        Error::OutOfMemory => ReturnCode::ENOMEM,
+                        Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                        Error::NoSuchApp => ReturnCode::EINVAL,
+                    })
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn subscribe(&self, subscribe_num: usize, callback: Callback) -> ReturnCode {
+        match subscribe_num {
+            // Set callback for results
+            0 => {
+                self.apps
+                    .enter(callback.app_id(), |app, _| {
+                        app.callback = Some(callback);
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| match err {
+                        Error::OutOfMemory => ReturnCode::ENOMEM,
+                        Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                        Error::NoSuchApp => ReturnCode::EINVAL,
+                    })
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn command(&self, command_num: usize, data: usize, appid: AppId) -> ReturnCode {
+        let operation = match command_num {
+            // This driver is present
+            0 => return ReturnCode::SUCCESS,
+            1 => Operation::Get,
+            2 => Operation::Set,
+            3 => Operation::Delete,
+            _ => return ReturnCode::ENOSUPPORT,
+        };
+
+        let result = self.apps
+            .enter(appid, |app, _| if app.pending.is_some() {
+                // Each app may make only one request at a time
+                ReturnCode::EBUSY
+            } else if self.namespace(appid).is_none() {
+                ReturnCode::ENOACCESS
+            } else if operation == Operation::Set && app.set_buffer.is_none() {
+                ReturnCode::EINVAL
+            } else {
+                app.pending = Some((operation, data as u32));
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| match err {
+                Error::OutOfMemory => ReturnCode::ENOMEM,
+                Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                Error::NoSuchApp => ReturnCode::EINVAL,
+            });
+
+        if result == ReturnCode::SUCCESS {
+            self.serve_waiting_apps();
+        }
+        result
+    }
+}
@@ -6,11 +6,15 @@
 extern crate kernel;
 
 pub mod button;
+pub mod bus_recorder;
 pub mod console;
 pub mod fm25cl;
 pub mod gpio;
 pub mod isl29035;
+pub mod kv_store;
 pub mod led;
+pub mod log;
+pub mod nonvolatile_storage;
 pub mod nrf51822_serialization;
 pub mod timer;
 pub mod tmp006;
@@ -20,7 +24,10 @@ pub mod spi;
 pub mod virtual_alarm;
 pub mod virtual_i2c;
 pub mod virtual_spi;
+pub mod virtual_timer;
+pub mod virtual_uart;
 pub mod adc;
+pub mod i2c_master;
 pub mod i2c_master_slave_driver;
 pub mod lps25hb;
 pub mod tsl2561;
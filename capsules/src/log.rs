@@ -0,0 +1,835 @@
+//! Circular log in flash
+//!
+//! `Log` appends entries of varying length to a ring of flash pages. Each
+//! entry gets the next entry ID, counting from 1, and IDs keep counting
+//! across reboots. Once every page is in use, the page holding the oldest
+//! entries is erased to make room, so the log keeps the newest entries.
+//!
+//! Kernel capsules use the log through `LogStorage`, and applications
+//! through `LogDriver`. Entries are read by ID, so they can be walked from
+//! the oldest entry forwards or from the newest backwards.
+//!
+//! Each page starts with a header holding the ID of its first entry. Each
+//! entry is its length, its data and a CRC-32 of both. An append adds the
+//! entry to a copy of the newest page kept in RAM and programs the page
+//! without erasing it, which only changes the erased bytes after the entries
+//! already there. A page is only written, erasing it, when it gets its first
+//! entry. If power is lost during an append, the torn entry fails its check
+//! while the entries before it are untouched, and the log carries on in a
+//! new page after a reboot.
+//!
+//! The page buffers must each be one flash page long. An entry must fit in
+//! a page along with the page header and its own length and check.
+//!
+//! Syscalls
+//! --------
+//!
+//! All applications share one log.
+//!
+//! The `allow` syscall supports `allow_number` zero for the buffer that
+//! reads fill, and one for the data that appends add. The data may also be
+//! provided with the read-only `allow`, in which case it may reside in
+//! flash.
+//!
+//! The `subscribe` syscall supports `subscribe_number` zero for a callback
+//! receiving a status, the number of bytes read and the ID of the entry
+//! read, and one for a callback receiving a status and the ID of an
+//! appended entry. A read into a buffer shorter than the entry is cut short
+//! and completes with `ESIZE`.
+//!
+//! The `command` syscall supports these `command_number`s:
+//!
+//!   *   `0`: Returns non-zero to indicate the driver is present
+//!
+//!   *   `1`: Returns the ID of the oldest entry.
+//!
+//!   *   `2`: Returns the ID the next entry will get, one past the newest.
+//!
+//!   *   `3`: Reads the entry with the ID given as the argument. Returns
+//!       `EINVAL` if the log does not hold it.
+//!
+//!   *   `4`: Appends the contents of the append buffer as an entry.
+//!
+//! Each application may have one read or append outstanding, and `EBUSY` is
+//! returned otherwise. Requests from different applications are queued.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let log = static_init!(
+//!     capsules::log::Log<'static, sam4l::flashcalw::FLASHCALW>,
+//!     capsules::log::Log::new(&sam4l::flashcalw::FLASH_CONTROLLER,
+//!                             0x60000,
+//!                             64,
+//!                             &mut capsules::log::HEAD_BUFFER,
+//!                             &mut capsules::log::PAGE_BUFFER),
+//!     2752/8);
+//! hil::flash::Flash::set_client(&sam4l::flashcalw::FLASH_CONTROLLER, log);
+//! let log_driver = static_init!(
+//!     capsules::log::LogDriver<'static, capsules::log::Log<'static, sam4l::flashcalw::FLASHCALW>>,
+//!     capsules::log::LogDriver::new(log,
+//!                                   &mut capsules::log::ENTRY_BUFFER,
+//!                                   kernel::Container::create()),
+//!     192/8);
+//! log.set_client(log_driver);
+//! log.mount();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::{AppId, AppSlice, Container, Callback, Driver, ReadOnlyAppSlice, ReturnCode, Shared};
+use kernel::common::crc32::crc32;
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::flash;
+use kernel::process::Error;
+
+/// Most pages a log can span.
+pub const MAX_PAGES: usize = 64;
+
+const PAGE_MAGIC: u32 = 0x4c4f4731; // "LOG1"
+// Magic, ID of the first entry and its complement
+const PAGE_HEADER_LEN: usize = 12;
+const LENGTH_LEN: usize = 2;
+const CRC_LEN: usize = 4;
+// Length read from erased flash, which ends the entries of a page
+const ERASED: u16 = 0xffff;
+
+pub static mut HEAD_BUFFER: [u8; 512] = [0; 512];
+pub static mut PAGE_BUFFER: [u8; 512] = [0; 512];
+pub static mut ENTRY_BUFFER: [u8; 512] = [0; 512];
+
+/// A log that kernel capsules can append entries to and read them back.
+pub trait LogStorage<'a> {
+    fn set_client(&self, client: &'a LogClient);
+
+    /// Appends the first `len` bytes of `buffer` as a new entry. The buffer
+    /// is handed back if the append cannot be started.
+    fn append(&self,
+              buffer: &'static mut [u8],
+              len: usize)
+              -> Result<(), (ReturnCode, &'static mut [u8])>;
+
+    /// Reads the entry with ID `id` into `buffer`. Fails with `EINVAL` if the
+    /// log does not hold the entry.
+    fn read(&self,
+            id: usize,
+            buffer: &'static mut [u8])
+            -> Result<(), (ReturnCode, &'static mut [u8])>;
+
+    /// The ID of the oldest entry, or `next_id` if the log is empty.
+    fn oldest_id(&self) -> usize;
+
+    /// The ID the next entry will get.
+    fn next_id(&self) -> usize;
+}
+
+pub trait LogClient {
+    /// Called when the log has been read and can be used.
+    fn mount_done(&self, result: ReturnCode);
+
+    /// `id` is the ID of the new entry if `result` is `SUCCESS`.
+    fn append_done(&self, buffer: &'static mut [u8], id: usize, result: ReturnCode);
+
+    /// `len` bytes of the entry were copied to `buffer`. `result` is `ESIZE`
+    /// if the entry did not fit.
+    fn read_done(&self, buffer: &'static mut [u8], len: usize, id: usize, result: ReturnCode);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    // Reading the header of each page when mounting
+    MountRead(usize),
+    // Erasing a damaged page found when mounting
+    MountErase(usize),
+    // Reading the newest page to find its last entry
+    MountReadHead(usize),
+    // Erasing a newest page that holds no entries
+    MountEraseHead(usize),
+    // Erasing the oldest page to start a new one there
+    AppendErase(usize),
+    // Writing the page that gets the entry
+    AppendWrite { page: usize, fresh: bool },
+    // Erasing a new page that could not be written
+    AppendCleanup(usize),
+    Read { page: usize, id: usize },
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    buf[offset] as u16 | (buf[offset + 1] as u16) << 8
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    read_u16(buf, offset) as u32 | (read_u16(buf, offset + 2) as u32) << 16
+}
+
+fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    for i in 0..4 {
+        buf[offset + i] = (value >> (8 * i)) as u8;
+    }
+}
+
+/// The ID of the first entry of a page, if it has a valid header.
+fn page_first_id(page: &[u8]) -> Option<u32> {
+    let id = read_u32(page, 4);
+    if read_u32(page, 0) == PAGE_MAGIC && read_u32(page, 8) == !id && id != 0 {
+        Some(id)
+    } else {
+        None
+    }
+}
+
+/// The length of the data of the entry at `offset`, if there is a valid one.
+fn entry_len(page: &[u8], offset: usize) -> Option<usize> {
+    if offset + LENGTH_LEN > page.len() {
+        return None;
+    }
+    let len = read_u16(page, offset);
+    let end = offset + LENGTH_LEN + len as usize;
+    if len == ERASED || end + CRC_LEN > page.len() ||
+       read_u32(page, end) != crc32(&page[offset..end]) {
+        return None;
+    }
+    Some(len as usize)
+}
+
+/// Finds entry `index` of a page, returning its offset and length, or the
+/// number of entries and the offset past them if the page has fewer.
+fn find_entry(page: &[u8], index: usize) -> Result<(usize, usize), (usize, usize)> {
+    let mut offset = PAGE_HEADER_LEN;
+    let mut count = 0;
+    while let Some(len) = entry_len(page, offset) {
+        if count == index {
+            return Ok((offset, len));
+        }
+        offset += LENGTH_LEN + len + CRC_LEN;
+        count += 1;
+    }
+    Err((count, offset))
+}
+
+pub struct Log<'a, F: flash::Flash + 'a> {
+    flash: &'a F,
+    client: Cell<Option<&'a LogClient>>,
+    // Flash offset of the first page
+    start: usize,
+    pages: usize,
+    page_size: usize,
+    // ID of the first entry of each page, or 0 for an erased page
+    first_ids: Cell<[u32; MAX_PAGES]>,
+    // The page entries are appended to, a copy of which is in `head_buffer`
+    head: Cell<Option<usize>>,
+    // Offset just past the last entry of the head page
+    head_end: Cell<usize>,
+    // Set when the head page cannot take more entries, such as after an
+    // append was torn by a loss of power
+    head_full: Cell<bool>,
+    next_id: Cell<u32>,
+    mounted: Cell<bool>,
+    head_buffer: TakeCell<'static, [u8]>,
+    page_buffer: TakeCell<'static, [u8]>,
+    client_buffer: TakeCell<'static, [u8]>,
+    client_len: Cell<usize>,
+    state: Cell<State>,
+}
+
+impl<'a, F: flash::Flash> Log<'a, F> {
+    /// A log of `pages` pages from flash offset `start`, which must be page
+    /// aligned.
+    pub fn new(flash: &'a F,
+               start: usize,
+               pages: usize,
+               head_buffer: &'static mut [u8],
+               page_buffer: &'static mut [u8])
+               -> Log<'a, F> {
+        Log {
+            flash: flash,
+            client: Cell::new(None),
+            start: start,
+            pages: cmp::min(pages, MAX_PAGES),
+            page_size: head_buffer.len(),
+            first_ids: Cell::new([0; MAX_PAGES]),
+            head: Cell::new(None),
+            head_end: Cell::new(PAGE_HEADER_LEN),
+            head_full: Cell::new(false),
+            next_id: Cell::new(1),
+            mounted: Cell::new(false),
+            head_buffer: TakeCell::new(head_buffer),
+            page_buffer: TakeCell::new(page_buffer),
+            client_buffer: TakeCell::empty(),
+            client_len: Cell::new(0),
+            state: Cell::new(State::Idle),
+        }
+    }
+
+    /// Reads the log from flash, erasing any damaged pages. Must complete
+    /// before the log is used.
+    pub fn mount(&self) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if self.pages < 2 {
+            return ReturnCode::EINVAL;
+        }
+        self.mounted.set(false);
+        self.first_ids.set([0; MAX_PAGES]);
+        self.head.set(None);
+        self.next_id.set(1);
+        self.read_page(State::MountRead(0), 0);
+        ReturnCode::SUCCESS
+    }
+
+    fn check_ready(&self) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            ReturnCode::EBUSY
+        } else if !self.mounted.get() {
+            ReturnCode::EOFF
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn first_id(&self, page: usize) -> u32 {
+        self.first_ids.get()[page]
+    }
+
+    fn set_first_id(&self, page: usize, id: u32) {
+        let mut first_ids = self.first_ids.get();
+        first_ids[page] = id;
+        self.first_ids.set(first_ids);
+    }
+
+    /// The used page holding entry `id`.
+    fn page_of(&self, id: u32) -> Option<usize> {
+        (0..self.pages)
+            .filter(|&page| self.first_id(page) != 0 && self.first_id(page) <= id)
+            .max_by_key(|&page| self.first_id(page))
+    }
+
+    fn read_page(&self, state: State, page: usize) {
+        let buffer = match state {
+            State::MountReadHead(_) => self.head_buffer.take(),
+            _ => self.page_buffer.take(),
+        };
+        buffer.map(|buffer| {
+            self.state.set(state);
+            self.flash.read(self.start + page * self.page_size, buffer);
+        });
+    }
+
+    fn erase_page(&self, state: State, page: usize) {
+        self.state.set(state);
+        self.flash.erase(self.start + page * self.page_size, self.page_size);
+    }
+
+    fn mount_next(&self, page: usize) {
+        if page + 1 < self.pages {
+            self.read_page(State::MountRead(page + 1), page + 1);
+        } else {
+            self.mount_head();
+        }
+    }
+
+    /// Reads the newest page, or finishes mounting an empty log.
+    fn mount_head(&self) {
+        match self.page_of(u32::max_value()) {
+            Some(head) => self.read_page(State::MountReadHead(head), head),
+            None => self.mount_done(ReturnCode::SUCCESS),
+        }
+    }
+
+    fn mount_done(&self, result: ReturnCode) {
+        self.state.set(State::Idle);
+        self.mounted.set(result == ReturnCode::SUCCESS);
+        self.client.get().map(|client| client.mount_done(result));
+    }
+
+    /// Writes the entry in the client buffer to a new page starting with the
+    /// next ID.
+    fn write_fresh(&self, page: usize) {
+        self.head_buffer.take().map(|head_buffer| {
+            for byte in head_buffer.iter_mut() {
+                *byte = 0xff;
+            }
+            let id = self.next_id.get();
+            write_u32(head_buffer, 0, PAGE_MAGIC);
+            write_u32(head_buffer, 4, id);
+            write_u32(head_buffer, 8, !id);
+            self.write_entry(head_buffer, PAGE_HEADER_LEN, page, true);
+        });
+    }
+
+    /// Adds the entry in the client buffer at `offset` of `head_buffer` and
+    /// writes it to `page`.
+    fn write_entry(&self,
+                   head_buffer: &'static mut [u8],
+                   offset: usize,
+                   page: usize,
+                   fresh: bool) {
+        let len = self.client_len.get();
+        head_buffer[offset] = len as u8;
+        head_buffer[offset + 1] = (len >> 8) as u8;
+        let end = offset + LENGTH_LEN + len;
+        self.client_buffer.map(|buffer| {
+            head_buffer[offset + LENGTH_LEN..end].copy_from_slice(&buffer[..len]);
+        });
+        let crc = crc32(&head_buffer[offset..end]);
+        write_u32(head_buffer, end, crc);
+
+        self.state.set(State::AppendWrite {
+            page: page,
+            fresh: fresh,
+        });
+        let offset = self.start + page * self.page_size;
+        if fresh {
+            self.flash.write(offset, head_buffer);
+        } else {
+            self.flash.program(offset, head_buffer);
+        }
+    }
+
+    fn append_done(&self, id: usize, result: ReturnCode) {
+        self.state.set(State::Idle);
+        self.client_buffer.take().map(|buffer| {
+            self.client.get().map(move |client| client.append_done(buffer, id, result));
+        });
+    }
+
+    fn read_done(&self, len: usize, id: usize, result: ReturnCode) {
+        self.state.set(State::Idle);
+        self.client_buffer.take().map(|buffer| {
+            self.client.get().map(move |client| client.read_done(buffer, len, id, result));
+        });
+    }
+}
+
+impl<'a, F: flash::Flash> LogStorage<'a> for Log<'a, F> {
+    fn set_client(&self, client: &'a LogClient) {
+        self.client.set(Some(client));
+    }
+
+    fn append(&self,
+              buffer: &'static mut [u8],
+              len: usize)
+              -> Result<(), (ReturnCode, &'static mut [u8])> {
+        let ready = self.check_ready();
+        if ready != ReturnCode::SUCCESS {
+            return Err((ready, buffer));
+        }
+        let entry_len = LENGTH_LEN + len + CRC_LEN;
+        if len > buffer.len() || PAGE_HEADER_LEN + entry_len > self.page_size {
+            return Err((ReturnCode::ESIZE, buffer));
+        }
+        self.client_buffer.replace(buffer);
+        self.client_len.set(len);
+
+        let head_end = self.head_end.get();
+        match self.head.get() {
+            Some(head) if !self.head_full.get() && head_end + entry_len <= self.page_size => {
+                self.head_buffer.take().map(|head_buffer| {
+                    self.write_entry(head_buffer, head_end, head, false);
+                });
+            }
+            head => {
+                let page = head.map_or(0, |head| (head + 1) % self.pages);
+                if self.first_id(page) != 0 {
+                    // Overwrite the oldest entries
+                    self.erase_page(State::AppendErase(page), page);
+                } else {
+                    self.write_fresh(page);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&self,
+            id: usize,
+            buffer: &'static mut [u8])
+            -> Result<(), (ReturnCode, &'static mut [u8])> {
+        let ready = self.check_ready();
+        if ready != ReturnCode::SUCCESS {
+            return Err((ready, buffer));
+        }
+        if id < self.oldest_id() || id >= self.next_id() {
+            return Err((ReturnCode::EINVAL, buffer));
+        }
+        match self.page_of(id as u32) {
+            Some(page) => {
+                self.client_buffer.replace(buffer);
+                self.read_page(State::Read { page: page, id: id }, page);
+                Ok(())
+            }
+            None => Err((ReturnCode::EINVAL, buffer)),
+        }
+    }
+
+    fn oldest_id(&self) -> usize {
+        (0..self.pages)
+            .map(|page| self.first_id(page))
+            .filter(|&id| id != 0)
+            .min()
+            .unwrap_or(self.next_id.get()) as usize
+    }
+
+    fn next_id(&self) -> usize {
+        self.next_id.get() as usize
+    }
+}
+
+impl<'a, F: flash::Flash> flash::Client for Log<'a, F> {
+    fn read_complete(&self, buffer: &'static mut [u8], error: flash::Error) {
+        let state = self.state.get();
+        let failed = error != flash::Error::CommandComplete;
+        match state {
+            State::MountRead(page) => {
+                let first_id = page_first_id(buffer);
+                let erased = buffer.iter().all(|byte| *byte == 0xff);
+                self.page_buffer.replace(buffer);
+                if failed {
+                    return self.mount_done(ReturnCode::FAIL);
+                }
+                match first_id {
+                    Some(id) => {
+                        self.set_first_id(page, id);
+                        self.mount_next(page);
+                    }
+                    None if erased => self.mount_next(page),
+                    // Torn by a loss of power
+                    None => self.erase_page(State::MountErase(page), page),
+                }
+            }
+            State::MountReadHead(head) => {
+                let (count, end) = find_entry(buffer, usize::max_value()).unwrap_err();
+                let full = buffer[end..].iter().any(|byte| *byte != 0xff);
+                self.head_buffer.replace(buffer);
+                if failed {
+                    return self.mount_done(ReturnCode::FAIL);
+                }
+                if count == 0 {
+                    // A new page whose first entry was torn
+                    self.erase_page(State::MountEraseHead(head), head);
+                } else {
+                    self.head.set(Some(head));
+                    self.head_end.set(end);
+                    self.head_full.set(full);
+                    self.next_id.set(self.first_id(head) + count as u32);
+                    self.mount_done(ReturnCode::SUCCESS);
+                }
+            }
+            State::Read { page, id } => {
+                let index = id - self.first_id(page) as usize;
+                let found = if failed { None } else { find_entry(buffer, index).ok() };
+                let copied = found.and_then(|(offset, len)| {
+                    let start = offset + LENGTH_LEN;
+                    self.client_buffer.map(|client_buffer| {
+                        let copied = cmp::min(len, client_buffer.len());
+                        client_buffer[..copied].copy_from_slice(&buffer[start..start + copied]);
+                        (copied, copied == len)
+                    })
+                });
+                self.page_buffer.replace(buffer);
+                match copied {
+                    Some((copied, true)) => self.read_done(copied, id, ReturnCode::SUCCESS),
+                    Some((copied, false)) => self.read_done(copied, id, ReturnCode::ESIZE),
+                    None => self.read_done(0, id, ReturnCode::FAIL),
+                }
+            }
+            _ => {
+                self.page_buffer.replace(buffer);
+            }
+        }
+    }
+
+    fn write_complete(&self, buffer: &'static mut [u8], error: flash::Error) {
+        self.head_buffer.replace(buffer);
+        if let State::AppendWrite { page, fresh } = self.state.get() {
+            let entry_len = LENGTH_LEN + self.client_len.get() + CRC_LEN;
+            if error != flash::Error::CommandComplete {
+                if fresh {
+                    self.erase_page(State::AppendCleanup(page), page);
+                } else {
+                    // Part of the entry may have been programmed
+                    self.head_full.set(true);
+                    self.append_done(0, ReturnCode::FAIL);
+                }
+                return;
+            }
+
+            let id = self.next_id.get();
+            if fresh {
+                self.set_first_id(page, id);
+                self.head.set(Some(page));
+                self.head_end.set(PAGE_HEADER_LEN + entry_len);
+                self.head_full.set(false);
+            } else {
+                self.head_end.set(self.head_end.get() + entry_len);
+            }
+            self.next_id.set(id + 1);
+            self.append_done(id as usize, ReturnCode::SUCCESS);
+        }
+    }
+
+    fn erase_complete(&self, error: flash::Error) {
+        let failed = error != flash::Error::CommandComplete;
+        match self.state.get() {
+            State::MountErase(page) => {
+                if failed {
+                    return self.mount_done(ReturnCode::FAIL);
+                }
+                self.mount_next(page);
+            }
+            State::MountEraseHead(head) => {
+                if failed {
+                    return self.mount_done(ReturnCode::FAIL);
+                }
+                self.set_first_id(head, 0);
+                self.mount_head();
+            }
+            State::AppendErase(page) => {
+                if failed {
+                    return self.append_done(0, ReturnCode::FAIL);
+                }
+                self.set_first_id(page, 0);
+                self.write_fresh(page);
+            }
+            State::AppendCleanup(_) => self.append_done(0, ReturnCode::FAIL),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Read,
+    Append,
+}
+
+/// An opaque value maintaining state for one application's request
+#[derive(Default)]
+pub struct App {
+    read_callback: Option<Callback>,
+    append_callback: Option<Callback>,
+    read_buffer: Option<AppSlice<Shared, u8>>,
+    append_buffer: Option<ReadOnlyAppSlice<u8>>,
+
+    // if Some, the application is waiting for this operation, with the ID of
+    // the entry to read
+    pending: Option<(Operation, usize)>,
+}
+
+pub struct LogDriver<'a, L: LogStorage<'a> + 'a> {
+    log: &'a L,
+    buffer: TakeCell<'static, [u8]>,
+    apps: Container<App>,
+    serving_app: Cell<Option<AppId>>,
+}
+
+impl<'a, L: LogStorage<'a>> LogDriver<'a, L> {
+    pub fn new(log: &'a L, buffer: &'static mut [u8], apps: Container<App>) -> LogDriver<'a, L> {
+        LogDriver {
+            log: log,
+            buffer: TakeCell::new(buffer),
+            apps: apps,
+            serving_app: Cell::new(None),
+        }
+    }
+
+    /// Starts an app's operation, returning why it could not be started.
+    fn start(&self, app: &App, operation: Operation, id: usize) -> ReturnCode {
+        let buffer = match self.buffer.take() {
+            Some(buffer) => buffer,
+            None => return ReturnCode::EBUSY,
+        };
+        let started = match operation {
+            Operation::Read => self.log.read(id, buffer),
+            Operation::Append => {
+                match app.append_buffer {
+                    Some(ref data) if data.len() <= buffer.len() => {
+                        buffer[..data.len()].copy_from_slice(data.as_ref());
+                        self.log.append(buffer, data.len())
+                    }
+                    Some(_) => Err((ReturnCode::ESIZE, buffer)),
+                    None => Err((ReturnCode::EINVAL, buffer)),
+                }
+            }
+        };
+        started.map(|_| ReturnCode::SUCCESS).unwrap_or_else(|(result, buffer)| {
+            self.buffer.replace(buffer);
+            result
+        })
+    }
+
+    fn serve_waiting_apps(&self) {
+        if self.serving_app.get().is_some() {
+            // An operation is in progress
+            return;
+        }
+
+        // Find a waiting app and start its operation
+        for app in self.apps.iter() {
+            let started = app.enter(|app, _| {
+                let (operation, id) = match app.pending {
+                    Some(pending) => pending,
+                    None => return None,
+                };
+                let result = self.start(app, operation, id);
+                if result == ReturnCode::SUCCESS {
+                    Some(app.appid())
+                } else {
+                    // The app's request failed
+                    app.pending = None;
+                    let callback = match operation {
+                        Operation::Read => app.read_callback,
+                        Operation::Append => app.append_callback,
+                    };
+                    callback.map(|mut cb| { cb.schedule(From::from(result), 0, id); });
+                    None
+                }
+            });
+            if started.is_some() {
+                self.serving_app.set(started);
+                return;
+            }
+        }
+    }
+}
+
+impl<'a, L: LogStorage<'a>> LogClient for LogDriver<'a, L> {
+    fn mount_done(&self, _result: ReturnCode) {
+        self.serve_waiting_apps();
+    }
+
+    fn append_done(&self, buffer: &'static mut [u8], id: usize, result: ReturnCode) {
+        self.buffer.replace(buffer);
+        self.serving_app.get().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.pending = None;
+                app.append_callback.map(|mut cb| { cb.schedule(From::from(result), id, 0); });
+            });
+        });
+        self.serving_app.set(None);
+        self.serve_waiting_apps();
+    }
+
+    fn read_done(&self, buffer: &'static mut [u8], len: usize, id: usize, result: ReturnCode) {
+        self.serving_app.get().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                let mut result = result;
+                let mut copied = 0;
+                if let Some(ref mut read_buffer) = app.read_buffer {
+                    copied = cmp::min(len, read_buffer.len());
+                    read_buffer.as_mut()[..copied].copy_from_slice(&buffer[..copied]);
+                }
+                if copied < len {
+                    result = ReturnCode::ESIZE;
+                }
+                app.pending = None;
+                app.read_callback.map(|mut cb| { cb.schedule(From::from(result), copied, id); });
+            });
+        });
+        self.buffer.replace(buffer);
+        self.serving_app.set(None);
+        self.serve_waiting_apps();
+    }
+}
+
+impl<'a, L: LogStorage<'a>> Driver for LogDriver<'a, L> {
+    fn allow(&self, appid: AppId, allow_num: usize, slice: AppSlice<Shared, u8>) -> ReturnCode {
+        match allow_num {
+            // Buffer for entries that are read
+            0 => {
+                self.apps
+                    .enter(appid, |app, _| slice.store_in(&mut app.read_buffer))
+                    .unwrap_or_else(|err| match err {
+                        Error::OutOfMemory => ReturnCode::ENOMEM,
+                        Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                        Error::NoSuchApp => ReturnCode::EINVAL,
+                    })
+            }
+            1 => self.allow_readonly(appid, allow_num, slice.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn allow_readonly(&self,
+                      appid: AppId,
+                      allow_num: usize,
+                      slice: ReadOnlyAppSlice<u8>)
+                      -> ReturnCode {
+        match allow_num {
+            // Data to append
+            1 => {
+                self.apps
+                    .enter(appid, |app, _| slice.store_in(&mut app.append_buffer))
+                    .unwrap_or_else(|err| match err {
+                        Error::OutOfMemory => ReturnCode::ENOMEM,
+                        Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                        Error::NoSuchApp => ReturnCode::EINVAL,
+                    })
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn subscribe(&self, subscribe_num: usize, callback: Callback) -> ReturnCode {
+        self.apps
+            .enter(callback.app_id(), |app, _| match subscribe_num {
+                // Set callback for reads
+                0 => {
+                    app.read_callback = Some(callback);
+                    ReturnCode::SUCCESS
+                }
+                // Set callback for appends
+                1 => {
+                    app.append_callback = Some(callback);
+                    ReturnCode::SUCCESS
+                }
+                _ => ReturnCode::ENOSUPPORT,
+            })
+            .unwrap_or_else(|err| match err {
+                Error::OutOfMemory => ReturnCode::ENOMEM,
+                Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                Error::NoSuchApp => ReturnCode::EINVAL,
+            })
+    }
+
+    fn command(&self, command_num: usize, data: usize, appid: AppId) -> ReturnCode {
+        let operation = match command_num {
+            // This driver is present
+            0 => return ReturnCode::SUCCESS,
+            1 => return ReturnCode::SuccessWithValue { value: self.log.oldest_id() },
+            2 => return ReturnCode::SuccessWithValue { value: self.log.next_id() },
+            3 => Operation::Read,
+            4 => Operation::Append,
+            _ => return ReturnCode::ENOSUPPORT,
+        };
+
+        if operation == Operation::Read &&
+           (data < self.log.oldest_id() || data >= self.log.next_id()) {
+            return ReturnCode::EINVAL;
+        }
+
+        let result = self.apps
+            .enter(appid, |app, _| if app.pending.is_some() {
+                // Each app may make only one request at a time
+                ReturnCode::EBUSY
+            } else if operation == Operation::Append && app.append_buffer.is_none() {
+                ReturnCode::EINVAL
+            } else {
+                app.pending = Some((operation, data));
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| match err {
+                Error::OutOfMemory => ReturnCode::ENOMEM,
+                Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                Error::NoSuchApp => ReturnCode::EINVAL,
+            });
+
+        if result == ReturnCode::SUCCESS {
+            self.serve_waiting_apps();
+        }
+        result
+    }
+}
@@ -0,0 +1,494 @@
+//! Nonvolatile storage driver
+//!
+//! This capsule gives each application a private region of flash in which
+//! to keep data across reboots. The board sets aside a page-aligned area of
+//! flash, the size of each region in it, and a table giving the number of
+//! the region that belongs to each application, by package name. An
+//! application keeps its region wherever it is loaded, and applications not
+//! in the table, or whose name another loaded application also declares,
+//! have no region. Applications address their region from zero and cannot
+//! reach outside it.
+//!
+//! Flash is written a page at a time, so the capsule reads any page that a
+//! write only partly covers and writes it back with the new bytes merged in.
+//! Its kernel buffer must be exactly one flash page.
+//!
+//! The `allow` syscall supports these `allow_number`s:
+//!
+//!   *   `0`: The buffer that reads fill.
+//!
+//!   *   `1`: The buffer that writes store. It may also be provided with the
+//!       read-only `allow`, in which case it may reside in flash.
+//!
+//! The `subscribe` syscall supports `subscribe_number` zero for the callback
+//! of a read and one for the callback of a write. Both receive a status,
+//! which is `SUCCESS`, `FAIL` if the flash reported an error, or `EINVAL` if
+//! the buffer was revoked, and the number of bytes transferred.
+//!
+//! The `command` syscall supports these `command_number`s:
+//!
+//!   *   `0`: Returns non-zero to indicate the driver is present
+//!
+//!   *   `1`: Returns the size of the application's region in bytes,
+//!       `ENOACCESS` if the application has no region, or `ENOMEM` if its
+//!       region lies beyond the area set aside.
+//!
+//!   *   `2`: Reads into the whole read buffer, starting at the offset in the
+//!       argument.
+//!
+//!   *   `3`: Writes the whole write buffer, starting at the offset in the
+//!       argument.
+//!
+//! Reads and writes return the same errors as command `1` if the application
+//! has no region, `EINVAL` if no buffer was provided or if the
+//! buffer would extend past the end of the application's region, and
+//! `EBUSY` if the application already has an operation outstanding.
+//! Operations from different applications are queued and served in turn. An
+//! operation whose buffer is revoked or made shorter before it finishes stops
+//! at the page it had reached, and its callback reports `EINVAL` and the
+//! number of bytes transferred until then.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! static REGIONS: [(&'static str, usize); 2] = [("sensors", 0), ("logger", 1)];
+//! let nv_storage = static_init!(
+//!     capsules::nonvolatile_storage::NonvolatileStorage<'static, sam4l::flashcalw::FLASHCALW>,
+//!     capsules::nonvolatile_storage::NonvolatileStorage::new(
+//!         &sam4l::flashcalw::FLASH_CONTROLLER,
+//!         0x70000, 0x10000, 0x2000,
+//!         &REGIONS,
+//!         &mut capsules::nonvolatile_storage::BUFFER,
+//!         kernel::Container::create()),
+//!     544/8);
+//! hil::flash::Flash::set_client(&sam4l::flashcalw::FLASH_CONTROLLER, nv_storage);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::{AppId, AppSlice, Container, Callback, Driver, ReadOnlyAppSlice, ReturnCode, Shared};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::flash;
+use kernel::process::{self, Error};
+
+/// A buffer of one page of SAM4L flash.
+pub static mut BUFFER: [u8; 512] = [0; 512];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Read,
+    Write,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    Reading,
+    // Reading a page that a write only partly covers
+    ReadingForWrite,
+    Writing,
+}
+
+#[derive(Default)]
+pub struct App {
+    read_callback: Option<Callback>,
+    write_callback: Option<Callback>,
+    read_buffer: Option<AppSlice<Shared, u8>>,
+    write_buffer: Option<ReadOnlyAppSlice<u8>>,
+
+    // if Some, the application is waiting for this operation, starting at
+    //   the given offset in its region
+    pending: Option<(Operation, usize)>,
+}
+
+pub struct NonvolatileStorage<'a, F: flash::Flash + 'a> {
+    flash: &'a F,
+    apps: Container<App>,
+    // Area of flash holding every app's region
+    storage_start: usize,
+    storage_len: usize,
+    region_len: usize,
+    // Number of the region each application owns, by package name
+    regions: &'static [(&'static str, usize)],
+    buffer: TakeCell<'static, [u8]>,
+    page_size: usize,
+    state: Cell<State>,
+    serving_app: Cell<Option<AppId>>,
+    operation: Cell<Operation>,
+    // Flash offsets of the start and end of the operation being served and of
+    // the next byte to transfer
+    start: Cell<usize>,
+    position: Cell<usize>,
+    end: Cell<usize>,
+}
+
+impl<'a, F: flash::Flash> NonvolatileStorage<'a, F> {
+    /// `storage_start` and `region_len` must be multiples of the flash page
+    /// size, which is the length of `buffer`. Region `n` in `regions` starts
+    /// `n * region_len` bytes into the area.
+    pub fn new(flash: &'a F,
+               storage_start: usize,
+               storage_len: usize,
+               region_len: usize,
+               regions: &'static [(&'static str, usize)],
+               buffer: &'static mut [u8],
+               apps: Container<App>)
+               -> NonvolatileStorage<'a, F> {
+        NonvolatileStorage {
+            flash: flash,
+            apps: apps,
+            storage_start: storage_start,
+            storage_len: storage_len,
+            region_len: region_len,
+            regions: regions,
+            page_size: buffer.len(),
+            buffer: TakeCell::new(buffer),
+            state: Cell::new(State::Idle),
+            serving_app: Cell::new(None),
+            operation: Cell::new(Operation::Read),
+            start: Cell::new(0),
+            position: Cell::new(0),
+            end: Cell::new(0),
+        }
+    }
+
+    /// The flash offset of the start of the app's region, or the error to
+    /// report if it has none.
+    fn region_start(&self, appid: AppId) -> Result<usize, ReturnCode> {
+        let region = match process::package_entry(appid, self.regions) {
+            Some(&region) => region,
+            None => return Err(ReturnCode::ENOACCESS),
+        };
+        let start = region * self.region_len;
+        if start + self.region_len <= self.storage_len {
+            Ok(self.storage_start + start)
+        } else {
+            Err(ReturnCode::ENOMEM)
+        }
+    }
+
+    /// Whether `len` bytes from `offset` lie within an app's region.
+    fn fits(&self, offset: usize, len: usize) -> bool {
+        offset <= self.region_len && len <= self.region_len - offset
+    }
+
+    fn request(&self, appid: AppId, operation: Operation, offset: usize) -> ReturnCode {
+        if let Err(result) = self.region_start(appid) {
+            return result;
+        }
+
+        let result = self.apps
+            .enter(appid, |app, _| {
+                let len = match operation {
+                    Operation::Read => app.read_buffer.as_ref().map(|buffer| buffer.len()),
+                    Operation::Write => app.write_buffer.as_ref().map(|buffer| buffer.len()),
+                };
+                if app.pending.is_some() {
+                    // Each app may make only one request at a time
+                    ReturnCode::EBUSY
+                } else if len.map_or(true, |len| !self.fits(offset, len)) {
+                    ReturnCode::EINVAL
+                } else {
+                    app.pending = Some((operation, offset));
+                    ReturnCode::SUCCESS
+                }
+            })
+            .unwrap_or_else(|err| match err {
+                Error::OutOfMemory => ReturnCode::ENOMEM,
+                Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                Error::NoSuchApp => ReturnCode::EINVAL,
+            });
+
+        if result == ReturnCode::SUCCESS {
+            self.serve_waiting_apps();
+        }
+        result
+    }
+
+    fn serve_waiting_apps(&self) {
+        if self.serving_app.get().is_some() {
+            // An operation is in progress
+            return;
+        }
+
+        // Find a waiting app and start its operation
+        for app in self.apps.iter() {
+            let found = app.enter(|app, _| {
+                let (operation, offset) = match app.pending {
+                    Some(pending) => pending,
+                    None => return None,
+                };
+                let len = match operation {
+                    Operation::Read => app.read_buffer.as_ref().map(|buffer| buffer.len()),
+                    Operation::Write => app.write_buffer.as_ref().map(|buffer| buffer.len()),
+                };
+                match (self.region_start(app.appid()), len) {
+                    (Ok(region_start), Some(len)) if self.fits(offset, len) => {
+                        self.operation.set(operation);
+                        self.start.set(region_start + offset);
+                        self.position.set(region_start + offset);
+                        self.end.set(region_start + offset + len);
+                        Some(app.appid())
+                    }
+                    _ => {
+                        // The app revoked or replaced its buffer before the
+                        // request could be served
+                        app.pending = None;
+                        let callback = match operation {
+                            Operation::Read => app.read_callback,
+                            Operation::Write => app.write_callback,
+                        };
+                        callback.map(|mut cb| {
+                            cb.schedule(From::from(ReturnCode::EINVAL), 0, 0);
+                        });
+                        None
+                    }
+                }
+            });
+            if found.is_some() {
+                self.serving_app.set(found);
+                self.next_page();
+                return;
+            }
+        }
+    }
+
+    /// Starts the flash operation for the page holding the next byte.
+    fn next_page(&self) {
+        let position = self.position.get();
+        let page = position - position % self.page_size;
+        self.buffer.take().map(|buffer| match self.operation.get() {
+            Operation::Read => {
+                self.state.set(State::Reading);
+                self.flash.read(page, buffer);
+            }
+            Operation::Write => {
+                if position == page && self.end.get() - position >= self.page_size {
+                    // The whole page is overwritten, so it need not be read
+                    self.state.set(State::Writing);
+                    if self.copy_from_app(buffer) {
+                        self.flash.write(page, buffer);
+                    } else {
+                        self.buffer.replace(buffer);
+                        self.finish(ReturnCode::EINVAL);
+                    }
+                } else {
+                    self.state.set(State::ReadingForWrite);
+                    self.flash.read(page, buffer);
+                }
+            }
+        });
+    }
+
+    /// The range of `buffer`, which holds the current page, to transfer to or
+    /// from the app, and the offset in the app's buffer it corresponds to.
+    fn page_chunk(&self) -> (usize, usize, usize) {
+        let position = self.position.get();
+        let page = position - position % self.page_size;
+        let chunk_end = cmp::min(self.end.get() - page, self.page_size);
+        (position - page, chunk_end, position - self.start.get())
+    }
+
+    /// Whether the app's buffer, which it may have replaced since the
+    /// operation started, still covers the whole operation.
+    fn covers(&self, len: usize) -> bool {
+        len >= self.end.get() - self.start.get()
+    }
+
+    /// Copies the app's bytes for the current page into `buffer`. Returns
+    /// false if the app's write buffer no longer covers the operation.
+    fn copy_from_app(&self, buffer: &mut [u8]) -> bool {
+        let (from, to, app_offset) = self.page_chunk();
+        self.serving_app.get().map_or(false, |appid| {
+            self.apps
+                .enter(appid, |app, _| {
+                    app.write_buffer.as_ref().map_or(false, |slice| {
+                        if !self.covers(slice.len()) {
+                            return false;
+                        }
+                        let data = &slice.as_ref()[app_offset..app_offset + to - from];
+                        buffer[from..to].clone_from_slice(data);
+                        true
+                    })
+                })
+                .unwrap_or(false)
+        })
+    }
+
+    /// Copies the current page's bytes from `buffer` to the app. Returns
+    /// false if the app's read buffer no longer covers the operation.
+    fn copy_to_app(&self, buffer: &[u8]) -> bool {
+        let (from, to, app_offset) = self.page_chunk();
+        self.serving_app.get().map_or(false, |appid| {
+            self.apps
+                .enter(appid, |app, _| {
+                    app.read_buffer.as_mut().map_or(false, |slice| {
+                        if !self.covers(slice.len()) {
+                            return false;
+                        }
+                        let data = &mut slice.as_mut()[app_offset..app_offset + to - from];
+                        data.clone_from_slice(&buffer[from..to]);
+                        true
+                    })
+                })
+                .unwrap_or(false)
+        })
+    }
+
+    /// Moves past the current page, starting the next one or finishing the
+    /// operation.
+    fn page_done(&self) {
+        let position = self.position.get();
+        let page = position - position % self.page_size;
+        self.position.set(cmp::min(page + self.page_size, self.end.get()));
+        if self.position.get() < self.end.get() {
+            self.next_page();
+        } else {
+            self.finish(ReturnCode::SUCCESS);
+        }
+    }
+
+    fn finish(&self, result: ReturnCode) {
+        self.state.set(State::Idle);
+        self.serving_app.get().map(|appid| {
+            let operation = self.operation.get();
+            let len = self.position.get() - self.start.get();
+            let _ = self.apps.enter(appid, |app, _| {
+                app.pending = None;
+                let callback = match operation {
+                    Operation::Read => app.read_callback,
+                    Operation::Write => app.write_callback,
+                };
+                callback.map(|mut cb| { cb.schedule(From::from(result), len, 0); });
+            });
+        });
+        self.serving_app.set(None);
+        self.serve_waiting_apps();
+    }
+}
+
+impl<'a, F: flash::Flash> flash::Client for NonvolatileStorage<'a, F> {
+    fn read_complete(&self, buffer: &'static mut [u8], error: flash::Error) {
+        if error != flash::Error::CommandComplete {
+            self.buffer.replace(buffer);
+            self.finish(ReturnCode::FAIL);
+            return;
+        }
+
+        match self.state.get() {
+            State::ReadingForWrite => {
+                // Merge the new bytes into the page and write it back
+                if self.copy_from_app(buffer) {
+                    self.state.set(State::Writing);
+                    let position = self.position.get();
+                    self.flash.write(position - position % self.page_size, buffer);
+                } else {
+                    self.buffer.replace(buffer);
+                    self.finish(ReturnCode::EINVAL);
+                }
+            }
+            _ => {
+                let copied = self.copy_to_app(buffer);
+                self.buffer.replace(buffer);
+                if copied {
+                    self.page_done();
+                } else {
+                    self.finish(ReturnCode::EINVAL);
+                }
+            }
+        }
+    }
+
+    fn write_complete(&self, buffer: &'static mut [u8], error: flash::Error) {
+        self.buffer.replace(buffer);
+        if error != flash::Error::CommandComplete {
+            self.finish(ReturnCode::FAIL);
+        } else {
+            self.page_done();
+        }
+    }
+
+    fn erase_complete(&self, _error: flash::Error) {}
+}
+
+impl<'a, F: flash::Flash> Driver for NonvolatileStorage<'a, F> {
+    fn allow(&self, appid: AppId, allow_num: usize, slice: AppSlice<Shared, u8>) -> ReturnCode {
+        match allow_num {
+            // Buffer for reads
+            0 => {
+                self.apps
+                    .enter(appid, |app, _| slice.store_in(&mut app.read_buffer))
+                    .unwrap_or_else(|err| match err {
+                        Error::OutOfMemory => ReturnCode::ENOMEM,
+                        Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                        Error::NoSuchApp => ReturnCode::EINVAL,
+                    })
+            }
+            1 => self.allow_readonly(appid, allow_num, slice.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn allow_readonly(&self,
+                      appid: AppId,
+                      allow_num: usize,
+                      slice: ReadOnlyAppSlice<u8>)
+                      -> ReturnCode {
+        match allow_num {
+            // Buffer for writes
+            1 => {
+                self.apps
+                    .enter(appid, |app, _| slice.store_in(&mut app.write_buffer))
+                    .unwrap_or_else(|err| match err {
+                        Error::OutOfMemory => ReturnCode::ENOMEM,
+                        Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                        Error::NoSuchApp => ReturnCode::EINVAL,
+                    })
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn subscribe(&self, subscribe_num: usize, callback: Callback) -> ReturnCode {
+        self.apps
+            .enter(callback.app_id(), |app, _| {
+                match subscribe_num {
+                    // Read done
+                    0 => app.read_callback = Some(callback),
+                    // Write done
+                    1 => app.write_callback = Some(callback),
+                    _ => return ReturnCode::ENOSUPPORT,
+                }
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| match err {
+                Error::OutOfMemory => ReturnCode::ENOMEM,
+                Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                Error::NoSuchApp => ReturnCode::EINVAL,
+            })
+    }
+
+    fn command(&self, command_num: usize, data: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            // This driver is present
+            0 => ReturnCode::SUCCESS,
+
+            // Size of the app's region
+            1 => {
+                match self.region_start(appid) {
+                    Ok(_) => ReturnCode::SuccessWithValue { value: self.region_len },
+                    Err(result) => result,
+                }
+            }
+
+            // Read
+            2 => self.request(appid, Operation::Read, data),
+
+            // Write
+            3 => self.request(appid, Operation::Write, data),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -8,6 +8,7 @@ use kernel::{AppId, Callback, AppSlice, Driver, ReturnCode, Shared};
 use kernel::common::take_cell::{MapCell, TakeCell};
 use kernel::hil::uart::{self, UARTAdvanced, Client};
 
+#[derive(Default)]
 struct App {
     callback: Option<Callback>,
     tx_buffer: Option<AppSlice<Shared, u8>>,
@@ -43,13 +44,13 @@ impl<'a, U: UARTAdvanced> Nrf51822Serialization<'a, U> {
         }
     }
 
-    pub fn initialize(&self) {
+    pub fn initialize(&self) -> ReturnCode {
         self.uart.init(uart::UARTParams {
             baud_rate: 250000,
             stop_bits: uart::StopBits::One,
             parity: uart::Parity::Even,
             hw_flow_control: true,
-        });
+        })
     }
 }
 
@@ -59,46 +60,20 @@ impl<'a, U: UARTAdvanced> Driver for Nrf51822Serialization<'a, U> {
         match allow_type {
             // Provide an RX buffer.
             0 => {
-                let resapp = match self.app.take() {
-                    Some(mut app) => {
-                        app.rx_buffer = Some(slice);
-                        app.rx_recv_so_far = 0;
-                        app.rx_recv_total = 0;
-                        app
-                    }
-                    None => {
-                        App {
-                            callback: None,
-                            tx_buffer: None,
-                            rx_buffer: Some(slice),
-                            rx_recv_so_far: 0,
-                            rx_recv_total: 0,
-                        }
-                    }
-                };
-                self.app.replace(resapp);
-                ReturnCode::SUCCESS
+                let mut app = self.app.take().unwrap_or_default();
+                let res = slice.store_in(&mut app.rx_buffer);
+                app.rx_recv_so_far = 0;
+                app.rx_recv_total = 0;
+                self.app.replace(app);
+                res
             }
 
             // Provide a TX buffer.
             1 => {
-                let resapp = match self.app.take() {
-                    Some(mut app) => {
-                        app.tx_buffer = Some(slice);
-                        app
-                    }
-                    None => {
-                        App {
-                            callback: None,
-                            tx_buffer: Some(slice),
-                            rx_buffer: None,
-                            rx_recv_so_far: 0,
-                            rx_recv_total: 0,
-                        }
-                    }
-                };
-                self.app.replace(resapp);
-                ReturnCode::SUCCESS
+                let mut app = self.app.take().unwrap_or_default();
+                let res = slice.store_in(&mut app.tx_buffer);
+                self.app.replace(app);
+                res
             }
             _ => ReturnCode::ENOSUPPORT,
         }
@@ -14,6 +14,7 @@ use kernel::common::take_cell::{MapCell, TakeCell};
 use kernel::hil::radio;
 use kernel::returncode::ReturnCode;
 
+#[derive(Default)]
 struct App {
     tx_callback: Option<Callback>,
     rx_callback: Option<Callback>,
@@ -47,43 +48,15 @@ impl<'a, R: radio::Radio> RadioDriver<'a, R> {
 impl<'a, R: radio::Radio> Driver for RadioDriver<'a, R> {
     fn allow(&self, _appid: AppId, allow_num: usize, slice: AppSlice<Shared, u8>) -> ReturnCode {
         match allow_num {
-            0 => {
-                let appc = match self.app.take() {
-                    None => {
-                        App {
-                            tx_callback: None,
-                            rx_callback: None,
-                            cfg_callback: None,
-                            app_read: Some(slice),
-                            app_write: None,
-                        }
-                    }
-                    Some(mut appc) => {
-                        appc.app_read = Some(slice);
-                        appc
-                    }
-                };
-                self.app.replace(appc);
-                ReturnCode::SUCCESS
-            }
-            1 => {
-                let appc = match self.app.take() {
-                    None => {
-                        App {
-                            tx_callback: None,
-                            rx_callback: None,
-                            cfg_callback: None,
-                            app_read: None,
-                            app_write: Some(slice),
-                        }
-                    }
-                    Some(mut appc) => {
-                        appc.app_write = Some(slice);
-                        appc
-                    }
+            0 | 1 => {
+                let mut appc = self.app.take().unwrap_or_default();
+                let res = if allow_num == 0 {
+                    slice.store_in(&mut appc.app_read)
+                } else {
+                    slice.store_in(&mut appc.app_write)
                 };
                 self.app.replace(appc);
-                ReturnCode::SUCCESS
+                res
             }
             _ => ReturnCode::ENOSUPPORT,
         }
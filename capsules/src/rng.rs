@@ -124,8 +124,11 @@ impl<'a, RNG: rng::RNG> Driver for SimpleRng<'a, RNG> {
             0 => {
                 self.apps
                     .enter(appid, |app, _| {
-                        app.buffer = Some(slice);
-                        ReturnCode::SUCCESS
+                        if slice.len() == 0 {
+                            // Revoking the buffer cancels an outstanding request
+                            app.remaining = 0;
+                        }
+                        slice.store_in(&mut app.buffer)
                     })
                     .unwrap_or_else(|err| match err {
                         Error::OutOfMemory => ReturnCode::ENOMEM,
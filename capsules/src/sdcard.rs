@@ -1305,6 +1305,7 @@ pub struct SDCardDriver<'a, A: hil::time::Alarm + 'a> {
 }
 
 /// Holds buffers and whatnot that the application has passed us.
+#[derive(Default)]
 struct AppState {
     callback: Option<Callback>,
     write_buffer: Option<AppSlice<Shared, u8>>,
@@ -1397,34 +1398,18 @@ impl<'a, A: hil::time::Alarm + 'a> Driver for SDCardDriver<'a, A> {
         match allow_num {
             // Pass read buffer in from application
             0 => {
-                if self.app_state.is_none() {
-                    // create new app state
-                    self.app_state.put(AppState {
-                        callback: None,
-                        read_buffer: Some(slice),
-                        write_buffer: None,
-                    });
-                } else {
-                    // app state exists, set read buffer
-                    self.app_state.map(|appst| { appst.read_buffer = Some(slice); });
-                }
-                ReturnCode::SUCCESS
+                let mut appst = self.app_state.take().unwrap_or_default();
+                let res = slice.store_in(&mut appst.read_buffer);
+                self.app_state.replace(appst);
+                res
             }
 
             // Pass write buffer in from application
             1 => {
-                if self.app_state.is_none() {
-                    // create new app state
-                    self.app_state.put(AppState {
-                        callback: None,
-                        read_buffer: None,
-                        write_buffer: Some(slice),
-                    });
-                } else {
-                    // app state exists, set write buffer
-                    self.app_state.map(|appst| { appst.write_buffer = Some(slice); });
-                }
-                ReturnCode::SUCCESS
+                let mut appst = self.app_state.take().unwrap_or_default();
+                let res = slice.store_in(&mut appst.write_buffer);
+                self.app_state.replace(appst);
+                res
             }
 
             _ => ReturnCode::ENOSUPPORT,
@@ -152,7 +152,7 @@ impl<'a, A: time::Alarm + 'a> i2c::I2CClient for SI7021<'a, A> {
 
                 // Temperature in hundredths of degrees centigrade
                 let temp_raw = (((buffer[0] as u32) << 8) | (buffer[1] as u32)) as u32;
-                let temp = (((temp_raw * 17572) / 65536) - 4685) as i16;
+                let temp = (((temp_raw * 17572) / 65536) as i32 - 4685) as i16;
 
                 // Humidity in hundredths of percent
                 let humidity_raw = (((buffer[2] as u32) << 8) | (buffer[3] as u32)) as u32;
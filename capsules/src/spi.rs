@@ -1,13 +1,50 @@
 //! The SPI capsule provides userspace applications with the ability
 //! to communicate over the SPI bus.
+//!
+//! Each application has its own bus settings and chip select, kept in its
+//! grant, so applications do not disturb each other's configuration. The
+//! board gives the chip selects applications may use; an application picks
+//! one by its index in that list, and starts out with the first one, idle-low
+//! clock polarity, sampling on the leading edge and `DEFAULT_RATE`.
+//! Transfers from different applications are queued and served in turn,
+//! each with its application's settings.
+//!
+//! The callback for a transfer receives its length. If the application
+//! revokes or shrinks a buffer before the transfer finishes, the transfer is
+//! cancelled and the callback instead receives `EINVAL` and the number of
+//! bytes transferred.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let syscall_spi_device = static_init!(
+//!     VirtualSpiMasterDevice<'static, sam4l::spi::Spi>,
+//!     VirtualSpiMasterDevice::new(mux_spi, 0),
+//!     448/8);
+//! static SPI_CHIP_SELECTS: [u8; 2] = [0, 1];
+//! let spi_syscalls = static_init!(
+//!     capsules::spi::Spi<'static, sam4l::spi::Spi>,
+//!     capsules::spi::Spi::new(syscall_spi_device,
+//!                             &SPI_CHIP_SELECTS,
+//!                             kernel::Container::create()),
+//!     352/8);
+//! spi_syscalls.config_buffers(&mut SPI_READ_BUF, &mut SPI_WRITE_BUF);
+//! syscall_spi_device.set_client(spi_syscalls);
+//! ```
 
 use core::cell::Cell;
 use core::cmp;
-use kernel::{AppId, AppSlice, Callback, Driver, ReturnCode, Shared};
-use kernel::common::take_cell::{MapCell, TakeCell};
-use kernel::hil::spi::{SpiMasterDevice, SpiMasterClient};
+use kernel::{AppId, AppSlice, Container, Callback, Driver, ReturnCode, Shared};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::spi::{SpiMaster, SpiMasterDevice, SpiMasterClient};
 use kernel::hil::spi::ClockPhase;
 use kernel::hil::spi::ClockPolarity;
+use kernel::process::Error;
+use virtual_spi::VirtualSpiMasterDevice;
+
+/// The rate of an application that has not set one, in bps.
+pub const DEFAULT_RATE: u32 = 1000000;
 
 // SPI operations are handled by coping into a kernel buffer for
 // writes and copying out of a kernel buffer for reads.
@@ -18,29 +55,67 @@ use kernel::hil::spi::ClockPolarity;
 // operation, while the index variable keeps track of the
 // index an ongoing operation is at in the buffers.
 
-struct App {
+pub struct App {
     callback: Option<Callback>,
     app_read: Option<AppSlice<Shared, u8>>,
     app_write: Option<AppSlice<Shared, u8>>,
     len: usize,
     index: usize,
+    // Whether the application is waiting for a transfer
+    busy: bool,
+
+    // Index of the application's chip select in the board's list
+    chip_select: usize,
+    polarity: ClockPolarity,
+    phase: ClockPhase,
+    rate: u32,
+}
+
+impl Default for App {
+    fn default() -> App {
+        App {
+            callback: None,
+            app_read: None,
+            app_write: None,
+            len: 0,
+            index: 0,
+            busy: false,
+            chip_select: 0,
+            polarity: ClockPolarity::IdleLow,
+            phase: ClockPhase::SampleLeading,
+            rate: DEFAULT_RATE,
+        }
+    }
 }
 
-pub struct Spi<'a, S: SpiMasterDevice + 'a> {
-    spi_master: &'a S,
-    busy: Cell<bool>,
-    app: MapCell<App>,
+impl App {
+    /// The longest transfer the application's buffers allow.
+    fn max_len(&self) -> usize {
+        let write_len = self.app_write.as_ref().map_or(0, |w| w.len());
+        self.app_read.as_ref().map_or(write_len, |r| cmp::min(write_len, r.len()))
+    }
+}
+
+pub struct Spi<'a, S: SpiMaster + 'a> {
+    spi_master: &'a VirtualSpiMasterDevice<'a, S>,
+    chip_selects: &'a [S::ChipSelect],
+    apps: Container<App>,
+    serving_app: Cell<Option<AppId>>,
     kernel_read: TakeCell<'static, [u8]>,
     kernel_write: TakeCell<'static, [u8]>,
     kernel_len: Cell<usize>,
 }
 
-impl<'a, S: SpiMasterDevice> Spi<'a, S> {
-    pub fn new(spi_master: &'a S) -> Spi<'a, S> {
+impl<'a, S: SpiMaster> Spi<'a, S> {
+    pub fn new(spi_master: &'a VirtualSpiMasterDevice<'a, S>,
+               chip_selects: &'a [S::ChipSelect],
+               apps: Container<App>)
+               -> Spi<'a, S> {
         Spi {
             spi_master: spi_master,
-            busy: Cell::new(false),
-            app: MapCell::empty(),
+            chip_selects: chip_selects,
+            apps: apps,
+            serving_app: Cell::new(None),
             kernel_len: Cell::new(0),
             kernel_read: TakeCell::empty(),
             kernel_write: TakeCell::empty(),
@@ -56,66 +131,108 @@ impl<'a, S: SpiMasterDevice> Spi<'a, S> {
 
     // Assumes checks for busy/etc. already done
     // Updates app.index to be index + length of op
-    fn do_next_read_write(&self, app: &mut App) {
+    fn do_next_read_write(&self, app: &mut App) -> ReturnCode {
+        let kwbuf = match self.kernel_write.take() {
+            Some(kwbuf) => kwbuf,
+            None => return ReturnCode::ENOMEM,
+        };
         let start = app.index;
         let len = cmp::min(app.len - start, self.kernel_len.get());
         let end = start + len;
         app.index = end;
 
-        self.kernel_write.map(|kwbuf| {
-            app.app_write
-                .as_mut()
-                .map(|src| for (i, c) in src.as_ref()[start..end].iter().enumerate() {
-                    kwbuf[i] = *c;
-                });
-        });
-        self.spi_master.read_write_bytes(self.kernel_write.take().unwrap(),
-                                         self.kernel_read.take(),
-                                         len);
+        app.app_write
+            .as_mut()
+            .map(|src| for (i, c) in src.as_ref()[start..end].iter().enumerate() {
+                kwbuf[i] = *c;
+            });
+        self.spi_master.read_write_bytes(kwbuf, self.kernel_read.take(), len);
+        ReturnCode::SUCCESS
     }
-}
 
-impl<'a, S: SpiMasterDevice> Driver for Spi<'a, S> {
-    fn allow(&self, _appid: AppId, allow_num: usize, slice: AppSlice<Shared, u8>) -> ReturnCode {
-        match allow_num {
-            0 => {
-                let appc = match self.app.take() {
-                    None => {
-                        App {
-                            callback: None,
-                            app_read: Some(slice),
-                            app_write: None,
-                            len: 0,
-                            index: 0,
-                        }
-                    }
-                    Some(mut appc) => {
-                        appc.app_read = Some(slice);
-                        appc
-                    }
-                };
-                self.app.replace(appc);
-                ReturnCode::SUCCESS
-            }
-            1 => {
-                let appc = match self.app.take() {
-                    None => {
-                        App {
-                            callback: None,
-                            app_read: None,
-                            app_write: Some(slice),
-                            len: 0,
-                            index: 0,
-                        }
-                    }
-                    Some(mut appc) => {
-                        appc.app_write = Some(slice);
-                        appc
+    fn read_write_bytes(&self, appid: AppId, len: usize) -> ReturnCode {
+        let result = self.apps
+            .enter(appid, |app, _| {
+                if app.busy {
+                    ReturnCode::EBUSY
+                } else if app.max_len() < len {
+                    ReturnCode::EINVAL /* buffer too small */
+                } else {
+                    app.len = len;
+                    app.index = 0;
+                    app.busy = true;
+                    ReturnCode::SUCCESS
+                }
+            })
+            .unwrap_or_else(|err| match err {
+                Error::OutOfMemory => ReturnCode::ENOMEM,
+                Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                Error::NoSuchApp => ReturnCode::EINVAL,
+            });
+
+        if result == ReturnCode::SUCCESS {
+            self.serve_waiting_apps();
+        }
+        result
+    }
+
+    fn serve_waiting_apps(&self) {
+        if self.serving_app.get().is_some() {
+            // A transfer is in progress
+            return;
+        }
+
+        // Find a waiting app and start its transfer with its settings
+        for app in self.apps.iter() {
+            let found = app.enter(|app, _| {
+                if !app.busy {
+                    return None;
+                }
+                let chip_select = self.chip_selects.get(app.chip_select);
+                let result = match chip_select {
+                    Some(&chip_select) if app.max_len() >= app.len => {
+                        self.spi_master.set_chip_select(chip_select);
+                        self.spi_master.configure(app.polarity, app.phase, app.rate);
+                        self.do_next_read_write(app)
                     }
+                    // The app revoked or replaced a buffer before the
+                    // transfer could start
+                    _ => ReturnCode::EINVAL,
                 };
-                self.app.replace(appc);
-                ReturnCode::SUCCESS
+                if result == ReturnCode::SUCCESS {
+                    Some(app.appid())
+                } else {
+                    app.busy = false;
+                    app.callback.map(|mut cb| cb.schedule(From::from(result), 0, 0));
+                    None
+                }
+            });
+            if found.is_some() {
+                self.serving_app.set(found);
+                return;
             }
+        }
+    }
+
+    /// Runs `f` on the grant of the calling app.
+    fn with_app<F>(&self, appid: AppId, f: F) -> ReturnCode
+        where F: FnOnce(&mut App) -> ReturnCode
+    {
+        self.apps
+            .enter(appid, |app, _| f(app))
+            .unwrap_or_else(|err| match err {
+                Error::OutOfMemory => ReturnCode::ENOMEM,
+                Error::AddressOutOfBounds => ReturnCode::EINVAL,
+                Error::NoSuchApp => ReturnCode::EINVAL,
+            })
+    }
+}
+
+impl<'a, S: SpiMaster> Driver for Spi<'a, S> {
+    fn allow(&self, appid: AppId, allow_num: usize, slice: AppSlice<Shared, u8>) -> ReturnCode {
+        match allow_num {
+            0 => self.with_app(appid, |app| slice.store_in(&mut app.app_read)),
+            1 => self.with_app(appid, |app| slice.store_in(&mut app.app_write)),
             _ => ReturnCode::ENOSUPPORT,
         }
     }
@@ -124,168 +241,155 @@ impl<'a, S: SpiMasterDevice> Driver for Spi<'a, S> {
     fn subscribe(&self, subscribe_num: usize, callback: Callback) -> ReturnCode {
         match subscribe_num {
             0 /* read_write */ => {
-                let appc = match self.app.take() {
-                    None => App {
-                        callback: Some(callback),
-                        app_read: None,
-                        app_write: None,
-                        len: 0,
-                        index: 0,
-                    },
-                    Some(mut appc) => {
-                        appc.callback = Some(callback);
-                        appc
-                    }
-                };
-                self.app.replace(appc);
-                ReturnCode::SUCCESS
+                self.with_app(callback.app_id(), |app| {
+                    app.callback = Some(callback);
+                    ReturnCode::SUCCESS
+                })
             },
             _ => ReturnCode::ENOSUPPORT
         }
     }
-    // 0: read/write a single byte (blocking)
-    // 1: read/write buffers
+    // 0: check if present
+    // 1: read/write a single byte (no longer supported)
+    // 2: read/write buffers
     //   - requires write buffer registered with allow
     //   - read buffer optional
-    // 2: set chip select
+    //   - queued behind other apps' transfers
+    // 3: set chip select
     //   - selects which peripheral (CS line) the SPI should
-    //     activate
-    //   - valid values are 0-3 for SAM4L
-    //   - invalid value will result in CS 0
-    // 3: get chip select
-    //   - returns current selected peripheral
-    //   - If none selected, returns 255
-    // 4: set rate on current peripheral
+    //     activate, as an index into the board's chip selects
+    //   - invalid value returns EINVAL
+    // 4: get chip select
+    //   - returns the index of the app's chip select
+    // 5: set rate for this app
     //   - parameter in bps
-    // 5: get rate on current peripheral
+    // 6: get rate for this app
     //   - value in bps
-    // 6: set clock phase on current peripheral
+    // 7: set clock phase for this app
     //   - 0 is sample leading
     //   - non-zero is sample trailing
-    // 7: get clock phase on current peripheral
+    // 8: get clock phase for this app
     //   - 0 is sample leading
     //   - non-zero is sample trailing
-    // 8: set clock polarity on current peripheral
+    // 9: set clock polarity for this app
     //   - 0 is idle low
     //   - non-zero is idle high
-    // 9: get clock polarity on current peripheral
+    // 10: get clock polarity for this app
     //   - 0 is idle low
     //   - non-zero is idle high
-    // 10: hold CS line low between transfers
-    //   - set CSAAT bit of control register
-    // 11: release CS line (high) between transfers
-    //   - clear CSAAT bit of control register
-    //
-    // x: lock spi
-    //   - if you perform an operation without the lock,
-    //     it implicitly acquires the lock before the
-    //     operation and releases it after
-    //   - while an app holds the lock no other app can issue
-    //     operations on SPI (they are buffered)
-    // x+1: unlock spi
-    //   - does nothing if lock not held
     //
+    // Settings take effect at the app's next transfer.
 
-    fn command(&self, cmd_num: usize, arg1: usize, _: AppId) -> ReturnCode {
+    fn command(&self, cmd_num: usize, arg1: usize, appid: AppId) -> ReturnCode {
         match cmd_num {
             0 /* check if present */ => ReturnCode::SUCCESS,
             // No longer supported, wrap inside a read_write_bytes
             1 /* read_write_byte */ => ReturnCode::ENOSUPPORT,
-            2 /* read_write_bytes */ => {
-                if self.busy.get() {
-                    return ReturnCode::EBUSY;
+            2 /* read_write_bytes */ => self.read_write_bytes(appid, arg1),
+            3 /* set chip select */ => {
+                if arg1 >= self.chip_selects.len() {
+                    return ReturnCode::EINVAL;
                 }
-                self.app.map_or(ReturnCode::FAIL /* XXX app is null? */, |app| {
-                    let mut mlen = 0;
-                    app.app_write.as_mut().map(|w| {
-                        mlen = w.len();
-                    });
-                    app.app_read.as_mut().map(|r| {
-                        mlen = cmp::min(mlen, r.len());
-                    });
-                    if mlen >= arg1 {
-                        app.len = arg1;
-                        app.index = 0;
-                        self.busy.set(true);
-                        self.do_next_read_write(app);
-                        ReturnCode::SUCCESS
-                    } else {
-                        ReturnCode::EINVAL /* write buffer too small */
-                    }
+                self.with_app(appid, |app| {
+                    app.chip_select = arg1;
+                    ReturnCode::SUCCESS
                 })
             }
-            3 /* set chip select */ => {
-                // do nothing, for now, until we fix interface
-                // so virtual instances can use multiple chip selects
-                ReturnCode::ENOSUPPORT
-            }
             4 /* get chip select */ => {
-                //XXX Was a naked, uncommented zero. I'm assuming that's
-                //    because the only valid chip select for now is 0,
-                //    and wrapping it appropriately -Pat, ReturnCode fixes
-                ReturnCode::SuccessWithValue { value: 0 }
+                self.with_app(appid, |app| {
+                    ReturnCode::SuccessWithValue { value: app.chip_select }
+                })
             }
             5 /* set baud rate */ => {
-                self.spi_master.set_rate(arg1 as u32);
-                ReturnCode::SUCCESS
+                self.with_app(appid, |app| {
+                    app.rate = arg1 as u32;
+                    ReturnCode::SUCCESS
+                })
             }
             6 /* get baud rate */ => {
-                ReturnCode::SuccessWithValue { value: self.spi_master.get_rate() as usize }
+                self.with_app(appid, |app| {
+                    ReturnCode::SuccessWithValue { value: app.rate as usize }
+                })
             }
             7 /* set phase */ => {
-                match arg1 {
-                    0 => self.spi_master.set_phase(ClockPhase::SampleLeading),
-                    _ => self.spi_master.set_phase(ClockPhase::SampleTrailing),
-                };
-                ReturnCode::SUCCESS
+                self.with_app(appid, |app| {
+                    app.phase = match arg1 {
+                        0 => ClockPhase::SampleLeading,
+                        _ => ClockPhase::SampleTrailing,
+                    };
+                    ReturnCode::SUCCESS
+                })
             }
             8 /* get phase */ => {
-                ReturnCode::SuccessWithValue { value: self.spi_master.get_phase() as usize }
+                self.with_app(appid, |app| {
+                    ReturnCode::SuccessWithValue { value: app.phase as usize }
+                })
             }
             9 /* set polarity */ => {
-                match arg1 {
-                    0 => self.spi_master.set_polarity(ClockPolarity::IdleLow),
-                    _ => self.spi_master.set_polarity(ClockPolarity::IdleHigh),
-                };
-                ReturnCode::SUCCESS
+                self.with_app(appid, |app| {
+                    app.polarity = match arg1 {
+                        0 => ClockPolarity::IdleLow,
+                        _ => ClockPolarity::IdleHigh,
+                    };
+                    ReturnCode::SUCCESS
+                })
             }
             10 /* get polarity */ => {
-                ReturnCode::SuccessWithValue { value: self.spi_master.get_polarity() as usize }
+                self.with_app(appid, |app| {
+                    ReturnCode::SuccessWithValue { value: app.polarity as usize }
+                })
             }
             _ => ReturnCode::ENOSUPPORT
         }
     }
 }
 
-impl<'a, S: SpiMasterDevice> SpiMasterClient for Spi<'a, S> {
+impl<'a, S: SpiMaster> SpiMasterClient for Spi<'a, S> {
     fn read_write_done(&self,
                        writebuf: &'static mut [u8],
                        readbuf: Option<&'static mut [u8]>,
                        length: usize) {
-        self.app.map(move |app| {
-            if app.app_read.is_some() {
-                let src = readbuf.as_ref().unwrap();
-                let dest = app.app_read.as_mut().unwrap();
-                let start = app.index - length;
-                let end = start + length;
+        self.kernel_read.put(readbuf);
+        self.kernel_write.replace(writebuf);
+        let appid = match self.serving_app.get() {
+            Some(appid) => appid,
+            None => return,
+        };
 
-                let d = &mut dest.as_mut()[start..end];
-                for (i, c) in src[0..length].iter().enumerate() {
-                    d[i] = *c;
-                }
-            }
-
-            self.kernel_read.put(readbuf);
-            self.kernel_write.replace(writebuf);
+        let mut done = true;
+        let _ = self.apps.enter(appid, |app, _| {
+            let start = app.index - length;
+            app.app_read.as_mut().map(|dest| {
+                self.kernel_read.map(|src| {
+                    let end = cmp::min(start + length, dest.len());
+                    if start < end {
+                        dest.as_mut()[start..end].copy_from_slice(&src[..end - start]);
+                    }
+                });
+            });
 
             if app.index == app.len {
-                self.busy.set(false);
-                app.len = 0;
-                app.index = 0;
-                app.callback.take().map(|mut cb| { cb.schedule(app.len, 0, 0); });
+                app.busy = false;
+                app.callback.map(|mut cb| { cb.schedule(app.len, 0, 0); });
+                return;
+            }
+            let result = if app.max_len() >= app.len {
+                self.do_next_read_write(app)
             } else {
-                self.do_next_read_write(app);
+                // The app revoked a buffer partway through
+                ReturnCode::EINVAL
+            };
+            if result == ReturnCode::SUCCESS {
+                done = false;
+            } else {
+                app.busy = false;
+                app.callback.map(|mut cb| { cb.schedule(From::from(result), app.index, 0); });
             }
         });
+
+        if done {
+            self.serving_app.set(None);
+            self.serve_waiting_apps();
+        }
     }
 }
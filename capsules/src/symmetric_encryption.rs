@@ -17,6 +17,9 @@
 //!     * 4: A buffer to configure to initial counter when counter mode of
 //!          block cipher is used.
 //!
+//! The key (0) and counter (4) buffers are only read, so they may also be
+//! provided with the read-only 'allow' system call, e.g. from flash.
+//!
 //! The possible return codes from the 'allow' system call indicate the following:
 //!     * SUCCESS: The buffer has successfully been filled
 //!     * ENOSUPPORT: Invalid allow_num
@@ -62,7 +65,7 @@
 //! Date: March 31, 2017
 
 use core::cell::Cell;
-use kernel::{AppId, AppSlice, Container, Callback, Driver, ReturnCode, Shared};
+use kernel::{AppId, AppSlice, Container, Callback, Driver, ReadOnlyAppSlice, ReturnCode, Shared};
 use kernel::common::take_cell::TakeCell;
 use kernel::hil::symmetric_encryption::{SymmetricEncryptionDriver, Client};
 use kernel::process::Error;
@@ -84,9 +87,9 @@ pub enum CryptoState {
 
 pub struct App {
     callback: Option<Callback>,
-    key_buf: Option<AppSlice<Shared, u8>>,
+    key_buf: Option<ReadOnlyAppSlice<u8>>,
     data_buf: Option<AppSlice<Shared, u8>>,
-    ctr_buf: Option<AppSlice<Shared, u8>>,
+    ctr_buf: Option<ReadOnlyAppSlice<u8>>,
 }
 
 impl Default for App {
@@ -171,24 +174,29 @@ impl<'a, E: SymmetricEncryptionDriver + 'a> Client for Crypto<'a, E> {
 impl<'a, E: SymmetricEncryptionDriver> Driver for Crypto<'a, E> {
     fn allow(&self, appid: AppId, allow_num: usize, slice: AppSlice<Shared, u8>) -> ReturnCode {
         match allow_num {
-            0 => {
+            0 | 4 => self.allow_readonly(appid, allow_num, slice.into()),
+            1 => {
                 self.apps
-                    .enter(appid, |app, _| {
-                        app.key_buf = Some(slice);
-                        ReturnCode::SUCCESS
-                    })
+                    .enter(appid, |app, _| slice.store_in(&mut app.data_buf))
                     .unwrap_or_else(|err| match err {
                         Error::OutOfMemory => ReturnCode::ENOMEM,
                         Error::AddressOutOfBounds => ReturnCode::EINVAL,
                         Error::NoSuchApp => ReturnCode::EINVAL,
                     })
             }
-            1 => {
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn allow_readonly(&self,
+                      appid: AppId,
+                      allow_num: usize,
+                      slice: ReadOnlyAppSlice<u8>)
+                      -> ReturnCode {
+        match allow_num {
+            0 => {
                 self.apps
-                    .enter(appid, |app, _| {
-                        app.data_buf = Some(slice);
-                        ReturnCode::SUCCESS
-                    })
+                    .enter(appid, |app, _| slice.store_in(&mut app.key_buf))
                     .unwrap_or_else(|err| match err {
                         Error::OutOfMemory => ReturnCode::ENOMEM,
                         Error::AddressOutOfBounds => ReturnCode::EINVAL,
@@ -197,10 +205,7 @@ impl<'a, E: SymmetricEncryptionDriver> Driver for Crypto<'a, E> {
             }
             4 => {
                 self.apps
-                    .enter(appid, |app, _| {
-                        app.ctr_buf = Some(slice);
-                        ReturnCode::SUCCESS
-                    })
+                    .enter(appid, |app, _| slice.store_in(&mut app.ctr_buf))
                     .unwrap_or_else(|err| match err {
                         Error::OutOfMemory => ReturnCode::ENOMEM,
                         Error::AddressOutOfBounds => ReturnCode::EINVAL,
@@ -378,7 +378,7 @@ impl<'a> i2c::I2CClient for TSL2561<'a> {
                 buffer[2] = buffer[0];
                 buffer[3] = buffer[1];
                 buffer[0] = Registers::Data0Low as u8 | COMMAND_REG | WORD_PROTOCOL;
-                self.i2c.write(buffer, 2);
+                self.i2c.write(buffer, 1);
                 self.state.set(State::ReadMeasurement3);
             }
             State::ReadMeasurement3 => {
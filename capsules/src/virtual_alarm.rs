@@ -1,13 +1,37 @@
 //! Virtualize the Alarm interface to enable multiple users of an underlying
 //! alarm hardware peripheral.
+//!
+//! `MuxAlarm` extends the hardware counter, whatever its width, to 64 bits by
+//! counting its wraps, and virtual alarms are kept as 64-bit times. This
+//! makes them immune to wraps of the counter: an alarm can be set arbitrarily
+//! far in the future with `Alarm64::set_alarm64`, and an alarm whose time has
+//! already passed fires right away instead of after a full wrap. Virtual
+//! alarms present the low 32 bits of the 64-bit time through `Alarm`, so
+//! their clients see a 32-bit counter even on narrower hardware.
+//!
+//! To see every wrap, the mux keeps the hardware alarm armed at most half a
+//! wrap ahead once its clock has been read, even when no virtual alarm is
+//! armed. At 32kHz that is one extra interrupt every 18 hours with a 32-bit
+//! counter, or every 4 minutes with the nRF51's 24-bit one.
 
 use core::cell::Cell;
+use core::cmp;
 use kernel::common::{List, ListLink, ListNode};
-use kernel::hil::time::{self, Alarm, Time};
+use kernel::hil::time::{self, Alarm, Alarm64, Time};
+
+/// Alarms due within this many tics fire together, since the hardware alarm
+/// cannot reliably be set that close to the current time.
+const MIN_DT: u64 = 100;
+
+/// The number of values a counter with the bits of `mask` takes before it
+/// wraps.
+fn wrap(mask: u32) -> u64 {
+    mask as u64 + 1
+}
 
 pub struct VirtualMuxAlarm<'a, Alrm: Alarm + 'a> {
     mux: &'a MuxAlarm<'a, Alrm>,
-    when: Cell<u32>,
+    when: Cell<u64>,
     armed: Cell<bool>,
     next: ListLink<'a, VirtualMuxAlarm<'a, Alrm>>,
     client: Cell<Option<&'a time::Client>>,
@@ -45,15 +69,7 @@ impl<'a, Alrm: Alarm> Time for VirtualMuxAlarm<'a, Alrm> {
         }
 
         self.armed.set(false);
-
-        let enabled = self.mux.enabled.get() - 1;
-        self.mux.enabled.set(enabled);
-
-        // If there are not more enabled alarms, disable the underlying alarm
-        // completely.
-        if enabled == 0 {
-            self.mux.alarm.disable();
-        }
+        self.mux.arm();
     }
 
     fn is_armed(&self) -> bool {
@@ -65,35 +81,40 @@ impl<'a, Alrm: Alarm> Alarm for VirtualMuxAlarm<'a, Alrm> {
     type Frequency = Alrm::Frequency;
 
     fn now(&self) -> u32 {
-        self.mux.alarm.now()
+        (self.mux.now64() & self.counter_mask() as u64) as u32
     }
 
+    /// Sets the alarm to the next time the counter reads `when`, if that is
+    /// within half a wrap. Otherwise `when` is taken to be in the recent past
+    /// and the alarm fires right away.
     fn set_alarm(&self, when: u32) {
-        let enabled = self.mux.enabled.get();
-
-        if !self.is_armed() {
-            self.mux.enabled.set(enabled + 1);
-            self.armed.set(true);
-
+        let wrap = wrap(self.counter_mask());
+        let now = self.mux.now64();
+        let dt = (when as u64).wrapping_sub(now) & (wrap - 1);
+        if dt < wrap / 2 {
+            self.set_alarm64(now + dt);
+        } else {
+            self.set_alarm64(now.saturating_sub(wrap - dt));
         }
+    }
 
-        if enabled > 0 {
-            let cur_alarm = self.mux.alarm.get_alarm();
-            let now = self.now();
+    fn get_alarm(&self) -> u32 {
+        (self.when.get() & self.counter_mask() as u64) as u32
+    }
+}
 
-            if cur_alarm.wrapping_sub(now) > when.wrapping_sub(now) {
-                self.mux.prev.set(self.mux.alarm.now());
-                self.mux.alarm.set_alarm(when);
-            }
-        } else {
-            self.mux.prev.set(self.mux.alarm.now());
-            self.mux.alarm.set_alarm(when);
-        }
+impl<'a, Alrm: Alarm> Alarm64 for VirtualMuxAlarm<'a, Alrm> {
+    fn now64(&self) -> u64 {
+        self.mux.now64()
+    }
 
+    fn set_alarm64(&self, when: u64) {
         self.when.set(when);
+        self.armed.set(true);
+        self.mux.arm();
     }
 
-    fn get_alarm(&self) -> u32 {
+    fn get_alarm64(&self) -> u64 {
         self.when.get()
     }
 }
@@ -108,8 +129,12 @@ impl<'a, Alrm: Alarm> time::Client for VirtualMuxAlarm<'a, Alrm> {
 
 pub struct MuxAlarm<'a, Alrm: Alarm + 'a> {
     virtual_alarms: List<'a, VirtualMuxAlarm<'a, Alrm>>,
-    enabled: Cell<usize>,
-    prev: Cell<u32>,
+    // Number of times the hardware counter has wrapped
+    wraps: Cell<u32>,
+    // Counter value when the clock was last read, to notice wraps
+    last: Cell<u32>,
+    // Whether the clock has been read, after which wraps must be watched
+    tracking: Cell<bool>,
     alarm: &'a Alrm,
 }
 
@@ -117,58 +142,68 @@ impl<'a, Alrm: Alarm> MuxAlarm<'a, Alrm> {
     pub const fn new(alarm: &'a Alrm) -> MuxAlarm<'a, Alrm> {
         MuxAlarm {
             virtual_alarms: List::new(),
-            enabled: Cell::new(0),
-            prev: Cell::new(0),
+            wraps: Cell::new(0),
+            last: Cell::new(0),
+            tracking: Cell::new(false),
             alarm: alarm,
         }
     }
-}
 
-fn past_from_base(cur: u32, now: u32, prev: u32) -> bool {
-    now.wrapping_sub(prev) >= cur.wrapping_sub(prev)
+    /// Returns the 64-bit time. This must be called at least once per wrap
+    /// of the hardware counter, which `arm` ensures.
+    pub fn now64(&self) -> u64 {
+        let mask = self.alarm.counter_mask();
+        let now = self.alarm.now() & mask;
+        if now < self.last.get() {
+            self.wraps.set(self.wraps.get() + 1);
+        }
+        self.last.set(now);
+        self.tracking.set(true);
+        self.wraps.get() as u64 * wrap(mask) + now as u64
+    }
+
+    /// The earliest time any virtual alarm is armed for.
+    fn next_alarm(&self) -> Option<u64> {
+        self.virtual_alarms
+            .iter()
+            .filter(|cur| cur.armed.get())
+            .map(|cur| cur.when.get())
+            .min()
+    }
+
+    /// Sets the hardware alarm for the earliest virtual alarm, but no more
+    /// than half a wrap ahead so the clock sees every wrap.
+    fn arm(&self) {
+        let next = self.next_alarm();
+        if next.is_none() && !self.tracking.get() {
+            self.alarm.disable();
+            return;
+        }
+
+        let mask = self.alarm.counter_mask();
+        let half_wrap = wrap(mask) / 2;
+        let now = self.now64();
+        let target = next.map_or(now + half_wrap, |when| cmp::min(when, now + half_wrap));
+        // An alarm that is already due is set just ahead so it still fires
+        self.alarm.set_alarm((cmp::max(target, now + MIN_DT) & mask as u64) as u32);
+    }
 }
 
 impl<'a, Alrm: Alarm> time::Client for MuxAlarm<'a, Alrm> {
     fn fired(&self) {
-        // Disable the alarm. If there are remaining armed alarms at the end we
-        // will enable the alarm again via `set_alarm`
-        self.alarm.disable();
-
-        let now = self.alarm.now();
-
-        // Capture this before the loop because it can change while checking
-        // each alarm. If a timer fires, it can immediately set a new timer
-        // by calling `VirtualMuxAlarm.set_alarm()` which can change `self.prev`
-        // to the current timer time.
-        let prev = self.prev.get();
+        let now = self.now64();
 
         // Check whether to fire each alarm. At this level, alarms are one-shot,
         // so a repeating client will set it again in the fired() callback.
         for cur in self.virtual_alarms.iter() {
-            let should_fire = past_from_base(cur.when.get(), now + 100, prev);
-            if cur.armed.get() && should_fire {
+            if cur.armed.get() && cur.when.get() <= now + MIN_DT {
                 cur.armed.set(false);
-                self.enabled.set(self.enabled.get() - 1);
                 cur.fired();
             }
         }
 
-        // Find the soonest alarm client (if any) and set the "next" underlying
-        // alarm based on it.
-        let mut next = None;
-        let mut min_distance: u32 = u32::max_value();
-        for cur in self.virtual_alarms.iter() {
-            if cur.armed.get() {
-                let distance = cur.when.get().wrapping_sub(now);
-                if cur.armed.get() && distance < min_distance {
-                    min_distance = distance;
-                    next = Some(cur);
-                }
-            }
-        }
-
-        self.prev.set(now);
-        // If there is an alarm to fire, set the underlying alarm to it
-        next.map(|valrm| self.alarm.set_alarm(valrm.when.get()));
+        // Clients may have set new alarms while firing, which are included
+        // here
+        self.arm();
     }
 }
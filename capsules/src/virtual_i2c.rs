@@ -3,39 +3,138 @@
 //! `MuxI2C` provides shared access to a single I2C Master Bus
 //! for multiple users.
 //! `I2CDevice` provides access to a specific I2C address.
+//!
+//! Every transaction has `DEFAULT_TIMEOUT_MS` to complete, or the time given
+//! to `set_timeout`. A transaction that runs out of time, usually because a
+//! slave is holding a bus line low, is aborted and completes with
+//! `Error::Timeout`, and the mux then tries to recover the bus. If the bus is
+//! still stuck, the transactions that were queued behind it also complete
+//! with `Error::Timeout` instead of waiting their turn; transactions issued
+//! after that try the bus again.
+//!
+//! A slave that is busy, such as an EEPROM during a write cycle, does not
+//! acknowledge its address. With `set_address_nak_retries`, the mux retries a
+//! transaction that fails with `Error::AddressNak`, `RETRY_DELAY_MS` apart,
+//! before passing the error on.
+//!
+//! Once a transaction completes, the next one is started, and transactions
+//! abandoned on a stuck bus are completed, from a deferred call if one has
+//! been registered with `register_deferred_call`, rather than from within the
+//! completion callback.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let i2c_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm),
+//!     32);
+//! let mux_i2c = static_init!(
+//!     MuxI2C<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     MuxI2C::new(&sam4l::i2c::I2C1, i2c_alarm),
+//!     52);
+//! mux_i2c.register_deferred_call();
+//! sam4l::i2c::I2C1.set_master_client(mux_i2c);
+//! i2c_alarm.set_client(mux_i2c);
+//! ```
 
 use core::cell::Cell;
 use kernel::common::{List, ListLink, ListNode};
 use kernel::common::take_cell::TakeCell;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
 use kernel::hil::i2c::{self, I2CClient, I2CHwMasterClient, Error};
+use kernel::hil::time::{self, Alarm};
+
+/// How long a transaction may take before it is aborted.
+pub const DEFAULT_TIMEOUT_MS: u32 = 100;
 
-pub struct MuxI2C<'a> {
+/// How long to wait before retrying a transaction whose address was not
+/// acknowledged.
+pub const RETRY_DELAY_MS: u32 = 1;
+
+pub struct MuxI2C<'a, A: Alarm + 'a> {
     i2c: &'a i2c::I2CMaster,
-    devices: List<'a, I2CDevice<'a>>,
+    alarm: &'a A,
+    devices: List<'a, I2CDevice<'a, A>>,
     enabled: Cell<usize>,
-    inflight: Cell<Option<&'a I2CDevice<'a>>>,
+    inflight: Cell<Option<&'a I2CDevice<'a, A>>>,
+    inflight_op: Cell<Op>,
+    timeout_ms: Cell<u32>,
+    retries: Cell<u8>,
+    attempts: Cell<u8>,
+    // Holds the buffer of the inflight transaction while it waits to be
+    // retried
+    retry_buffer: TakeCell<'static, [u8]>,
+    deferred_call: DeferredCall,
 }
 
-impl<'a> I2CHwMasterClient for MuxI2C<'a> {
+impl<'a, A: Alarm> I2CHwMasterClient for MuxI2C<'a, A> {
     fn command_complete(&self, buffer: &'static mut [u8], error: Error) {
+        self.alarm.disable();
+        if error == Error::AddressNak && self.attempts.get() < self.retries.get() {
+            self.attempts.set(self.attempts.get() + 1);
+            self.retry_buffer.replace(buffer);
+            self.set_alarm(RETRY_DELAY_MS);
+            return;
+        }
         self.inflight.get().map(move |device| {
             self.inflight.set(None);
             device.command_complete(buffer, error);
         });
-        self.do_next_op();
+        self.schedule_next_op();
     }
 }
 
-impl<'a> MuxI2C<'a> {
-    pub const fn new(i2c: &'a i2c::I2CMaster) -> MuxI2C<'a> {
+impl<'a, A: Alarm> time::Client for MuxI2C<'a, A> {
+    fn fired(&self) {
+        if let Some(buffer) = self.retry_buffer.take() {
+            self.inflight.get().map(|device| self.start(device, self.inflight_op.get(), buffer));
+            return;
+        }
+        self.inflight.get().map(|device| {
+            self.inflight.set(None);
+            let buffer = self.i2c.abort();
+            if !self.i2c.recover_bus() {
+                for queued in self.devices.iter().filter(|node| node.operation.get() != Op::Idle) {
+                    queued.abandoned.set(true);
+                }
+            }
+            buffer.map(|buffer| device.command_complete(buffer, Error::Timeout));
+        });
+        self.schedule_next_op();
+    }
+}
+
+impl<'a, A: Alarm> MuxI2C<'a, A> {
+    pub const fn new(i2c: &'a i2c::I2CMaster, alarm: &'a A) -> MuxI2C<'a, A> {
         MuxI2C {
             i2c: i2c,
+            alarm: alarm,
             devices: List::new(),
             enabled: Cell::new(0),
             inflight: Cell::new(None),
+            inflight_op: Cell::new(Op::Idle),
+            timeout_ms: Cell::new(DEFAULT_TIMEOUT_MS),
+            retries: Cell::new(0),
+            attempts: Cell::new(0),
+            retry_buffer: TakeCell::empty(),
+            deferred_call: DeferredCall::new(),
         }
     }
 
+    /// Sets how long a transaction may take before it is aborted.
+    pub fn set_timeout(&self, ms: u32) {
+        self.timeout_ms.set(ms);
+    }
+
+    /// Sets how many times a transaction whose address is not acknowledged
+    /// is retried before it fails with `Error::AddressNak`. The default is
+    /// not to retry.
+    pub fn set_address_nak_retries(&self, retries: u8) {
+        self.retries.set(retries);
+    }
+
     fn enable(&self) {
         let enabled = self.enabled.get();
         self.enabled.set(enabled + 1);
@@ -52,27 +151,77 @@ impl<'a> MuxI2C<'a> {
         }
     }
 
+    fn set_alarm(&self, ms: u32) {
+        let tics = time::ms_to_tics::<A::Frequency>(ms as u64) as u32;
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(tics));
+    }
+
+    fn start(&self, node: &I2CDevice<'a, A>, op: Op, buf: &'static mut [u8]) {
+        self.set_alarm(self.timeout_ms.get());
+        let addr = node.addr.get();
+        match op {
+            Op::Write(len) => self.i2c.write(addr, buf, len),
+            Op::Read(len) => self.i2c.read(addr, buf, len),
+            Op::WriteRead(wlen, rlen) => self.i2c.write_read(addr, buf, wlen, rlen),
+            Op::Idle => {} // Can't get here...
+        }
+    }
+
+    /// Completes abandoned operations and starts the next queued one from
+    /// the main loop, or right away if no deferred call is registered.
+    fn schedule_next_op(&self) {
+        if !self.deferred_call.set() {
+            self.complete_abandoned();
+            self.do_next_op();
+        }
+    }
+
+    /// Fails the operations that were queued when the bus could not be
+    /// recovered. Their clients may queue more, which start as usual.
+    fn complete_abandoned(&self) {
+        for node in self.devices.iter().filter(|node| node.abandoned.get()) {
+            node.abandoned.set(false);
+            node.operation.set(Op::Idle);
+            node.buffer.take().map(|buf| node.command_complete(buf, Error::Timeout));
+        }
+    }
+
     fn do_next_op(&self) {
         if self.inflight.get().is_none() {
-            let mnode = self.devices.iter().find(|node| node.operation.get() != Op::Idle);
+            // Abandoned operations wait for `complete_abandoned`
+            let mnode = self.devices
+                .iter()
+                .find(|node| node.operation.get() != Op::Idle && !node.abandoned.get());
             mnode.map(|node| {
+                let op = node.operation.get();
+                node.operation.set(Op::Idle);
                 node.buffer.take().map(|buf| {
-                    match node.operation.get() {
-                        Op::Write(len) => self.i2c.write(node.addr, buf, len),
-                        Op::Read(len) => self.i2c.read(node.addr, buf, len),
-                        Op::WriteRead(wlen, rlen) => {
-                            self.i2c.write_read(node.addr, buf, wlen, rlen)
-                        }
-                        Op::Idle => {} // Can't get here...
-                    }
+                    self.inflight.set(Some(node));
+                    self.inflight_op.set(op);
+                    self.attempts.set(0);
+                    self.start(node, op, buf);
                 });
-                node.operation.set(Op::Idle);
-                self.inflight.set(Some(node));
             });
         }
     }
 }
 
+impl<A: Alarm + 'static> MuxI2C<'static, A> {
+    /// Registers the mux's deferred call. Returns false if the kernel's
+    /// deferred call table is full, in which case operations are started
+    /// synchronously.
+    pub fn register_deferred_call(&'static self) -> bool {
+        self.deferred_call.register(self)
+    }
+}
+
+impl<'a, A: Alarm> DeferredCallClient for MuxI2C<'a, A> {
+    fn handle_deferred_call(&self) {
+        self.complete_abandoned();
+        self.do_next_op();
+    }
+}
+
 #[derive(Copy, Clone,PartialEq)]
 enum Op {
     Idle,
@@ -81,22 +230,25 @@ enum Op {
     WriteRead(u8, u8),
 }
 
-pub struct I2CDevice<'a> {
-    mux: &'a MuxI2C<'a>,
-    addr: u8,
+pub struct I2CDevice<'a, A: Alarm + 'a> {
+    mux: &'a MuxI2C<'a, A>,
+    addr: Cell<u8>,
     enabled: Cell<bool>,
+    // Set on a queued operation when the bus could not be recovered
+    abandoned: Cell<bool>,
     buffer: TakeCell<'static, [u8]>,
     operation: Cell<Op>,
-    next: ListLink<'a, I2CDevice<'a>>,
+    next: ListLink<'a, I2CDevice<'a, A>>,
     client: Cell<Option<&'a I2CClient>>,
 }
 
-impl<'a> I2CDevice<'a> {
-    pub const fn new(mux: &'a MuxI2C<'a>, addr: u8) -> I2CDevice<'a> {
+impl<'a, A: Alarm> I2CDevice<'a, A> {
+    pub const fn new(mux: &'a MuxI2C<'a, A>, addr: u8) -> I2CDevice<'a, A> {
         I2CDevice {
             mux: mux,
-            addr: addr,
+            addr: Cell::new(addr),
             enabled: Cell::new(false),
+            abandoned: Cell::new(false),
             buffer: TakeCell::empty(),
             operation: Cell::new(Op::Idle),
             next: ListLink::empty(),
@@ -108,21 +260,27 @@ impl<'a> I2CDevice<'a> {
         self.mux.devices.push_head(self);
         self.client.set(Some(client));
     }
+
+    /// Changes the address that later operations go to, for users that talk
+    /// to more than one slave.
+    pub fn set_address(&self, addr: u8) {
+        self.addr.set(addr);
+    }
 }
 
-impl<'a> I2CClient for I2CDevice<'a> {
+impl<'a, A: Alarm> I2CClient for I2CDevice<'a, A> {
     fn command_complete(&self, buffer: &'static mut [u8], error: Error) {
         self.client.get().map(move |client| { client.command_complete(buffer, error); });
     }
 }
 
-impl<'a> ListNode<'a, I2CDevice<'a>> for I2CDevice<'a> {
-    fn next(&'a self) -> &'a ListLink<'a, I2CDevice<'a>> {
+impl<'a, A: Alarm> ListNode<'a, I2CDevice<'a, A>> for I2CDevice<'a, A> {
+    fn next(&'a self) -> &'a ListLink<'a, I2CDevice<'a, A>> {
         &self.next
     }
 }
 
-impl<'a> i2c::I2CDevice for I2CDevice<'a> {
+impl<'a, A: Alarm> i2c::I2CDevice for I2CDevice<'a, A> {
     fn enable(&self) {
         if !self.enabled.get() {
             self.enabled.set(true);
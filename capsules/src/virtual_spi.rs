@@ -3,14 +3,20 @@
 use core::cell::Cell;
 use kernel::common::{List, ListLink, ListNode};
 use kernel::common::take_cell::TakeCell;
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
 use kernel::hil;
 
 /// The Mux struct manages multiple Spi clients. Each client may have
 /// at most one outstanding Spi request.
+///
+/// Once a request completes, the next one is started from a deferred call
+/// if one has been registered with `register_deferred_call`, rather than from
+/// within the completion callback.
 pub struct MuxSpiMaster<'a, Spi: hil::spi::SpiMaster + 'a> {
     spi: &'a Spi,
     devices: List<'a, VirtualSpiMasterDevice<'a, Spi>>,
     inflight: Cell<Option<&'a VirtualSpiMasterDevice<'a, Spi>>>,
+    deferred_call: DeferredCall,
 }
 
 impl<'a, Spi: hil::spi::SpiMaster> hil::spi::SpiMasterClient for MuxSpiMaster<'a, Spi> {
@@ -20,7 +26,7 @@ impl<'a, Spi: hil::spi::SpiMaster> hil::spi::SpiMasterClient for MuxSpiMaster<'a
                        len: usize) {
         self.inflight.get().map(move |device| {
             self.inflight.set(None);
-            self.do_next_op();
+            self.schedule_next_op();
             device.read_write_done(write_buffer, read_buffer, len);
         });
     }
@@ -32,6 +38,15 @@ impl<'a, Spi: hil::spi::SpiMaster> MuxSpiMaster<'a, Spi> {
             spi: spi,
             devices: List::new(),
             inflight: Cell::new(None),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    /// Starts the next queued operation from the main loop, or right away if
+    /// no deferred call is registered.
+    fn schedule_next_op(&self) {
+        if !self.deferred_call.set() {
+            self.do_next_op();
         }
     }
 
@@ -40,36 +55,24 @@ impl<'a, Spi: hil::spi::SpiMaster> MuxSpiMaster<'a, Spi> {
             let mnode = self.devices.iter().find(|node| node.operation.get() != Op::Idle);
             mnode.map(|node| {
                 self.spi.specify_chip_select(node.chip_select.get());
+                // The bus may have been set up for another device since this
+                // one last used it
+                node.configuration.get().map(|(cpol, cpal, rate)| {
+                    self.spi.set_clock(cpol);
+                    self.spi.set_phase(cpal);
+                    self.spi.set_rate(rate);
+                });
                 let op = node.operation.get();
                 // Need to set idle here in case callback changes state
                 node.operation.set(Op::Idle);
                 match op {
-                    Op::Configure(cpol, cpal, rate) => {
-
-                        // The `chip_select` type will be correct based on
-                        // what implemented `SpiMaster`.
-                        self.spi.set_clock(cpol);
-                        self.spi.set_phase(cpal);
-                        self.spi.set_rate(rate);
-                    }
                     Op::ReadWriteBytes(len) => {
-                        // Only async operations want to block by setting
-                        // the devices as inflight.
                         self.inflight.set(Some(node));
                         node.txbuffer.take().map(|txbuffer| {
                             let rxbuffer = node.rxbuffer.take();
                             self.spi.read_write_bytes(txbuffer, rxbuffer, len);
                         });
                     }
-                    Op::SetPolarity(pol) => {
-                        self.spi.set_clock(pol);
-                    }
-                    Op::SetPhase(pal) => {
-                        self.spi.set_phase(pal);
-                    }
-                    Op::SetRate(rate) => {
-                        self.spi.set_rate(rate);
-                    }
                     Op::Idle => {} // Can't get here...
                 }
             });
@@ -77,19 +80,34 @@ impl<'a, Spi: hil::spi::SpiMaster> MuxSpiMaster<'a, Spi> {
     }
 }
 
+impl<Spi: hil::spi::SpiMaster + 'static> MuxSpiMaster<'static, Spi> {
+    /// Registers the mux's deferred call. Returns false if the kernel's
+    /// deferred call table is full, in which case operations are started
+    /// synchronously.
+    pub fn register_deferred_call(&'static self) -> bool {
+        self.deferred_call.register(self)
+    }
+}
+
+impl<'a, Spi: hil::spi::SpiMaster> DeferredCallClient for MuxSpiMaster<'a, Spi> {
+    fn handle_deferred_call(&self) {
+        self.do_next_op();
+    }
+}
+
 #[derive(Copy, Clone, PartialEq)]
 enum Op {
     Idle,
-    Configure(hil::spi::ClockPolarity, hil::spi::ClockPhase, u32),
     ReadWriteBytes(usize),
-    SetPolarity(hil::spi::ClockPolarity),
-    SetPhase(hil::spi::ClockPhase),
-    SetRate(u32),
 }
 
+/// A user of the bus with its own chip select and bus settings. The mux
+/// applies a device's settings before each of its transfers; a device that
+/// has never been configured uses whatever the bus was last set to.
 pub struct VirtualSpiMasterDevice<'a, Spi: hil::spi::SpiMaster + 'a> {
     mux: &'a MuxSpiMaster<'a, Spi>,
     chip_select: Cell<Spi::ChipSelect>,
+    configuration: Cell<Option<(hil::spi::ClockPolarity, hil::spi::ClockPhase, u32)>>,
     txbuffer: TakeCell<'static, [u8]>,
     rxbuffer: TakeCell<'static, [u8]>,
     operation: Cell<Op>,
@@ -104,6 +122,7 @@ impl<'a, Spi: hil::spi::SpiMaster> VirtualSpiMasterDevice<'a, Spi> {
         VirtualSpiMasterDevice {
             mux: mux,
             chip_select: Cell::new(chip_select),
+            configuration: Cell::new(None),
             txbuffer: TakeCell::empty(),
             rxbuffer: TakeCell::empty(),
             operation: Cell::new(Op::Idle),
@@ -116,6 +135,20 @@ impl<'a, Spi: hil::spi::SpiMaster> VirtualSpiMasterDevice<'a, Spi> {
         self.mux.devices.push_head(self);
         self.client.set(Some(client));
     }
+
+    /// Changes the chip select that later transfers use, for users that talk
+    /// to more than one peripheral.
+    pub fn set_chip_select(&self, chip_select: Spi::ChipSelect) {
+        self.chip_select.set(chip_select);
+    }
+
+    /// The device's settings, starting from the bus's current ones if it has
+    /// not been configured.
+    fn settings(&self) -> (hil::spi::ClockPolarity, hil::spi::ClockPhase, u32) {
+        self.configuration.get().unwrap_or_else(|| {
+            (self.mux.spi.get_clock(), self.mux.spi.get_phase(), self.mux.spi.get_rate())
+        })
+    }
 }
 
 impl<'a, Spi: hil::spi::SpiMaster> hil::spi::SpiMasterClient for VirtualSpiMasterDevice<'a, Spi> {
@@ -139,8 +172,7 @@ impl<'a, Spi: hil::spi::SpiMaster> ListNode<'a, VirtualSpiMasterDevice<'a, Spi>>
 
 impl<'a, Spi: hil::spi::SpiMaster> hil::spi::SpiMasterDevice for VirtualSpiMasterDevice<'a, Spi> {
     fn configure(&self, cpol: hil::spi::ClockPolarity, cpal: hil::spi::ClockPhase, rate: u32) {
-        self.operation.set(Op::Configure(cpol, cpal, rate));
-        self.mux.do_next_op();
+        self.configuration.set(Some((cpol, cpal, rate)));
     }
 
     fn read_write_bytes(&self,
@@ -156,29 +188,29 @@ impl<'a, Spi: hil::spi::SpiMaster> hil::spi::SpiMasterDevice for VirtualSpiMaste
     }
 
     fn set_polarity(&self, cpol: hil::spi::ClockPolarity) {
-        self.operation.set(Op::SetPolarity(cpol));
-        self.mux.do_next_op();
+        let (_, cpal, rate) = self.settings();
+        self.configure(cpol, cpal, rate);
     }
 
     fn set_phase(&self, cpal: hil::spi::ClockPhase) {
-        self.operation.set(Op::SetPhase(cpal));
-        self.mux.do_next_op();
+        let (cpol, _, rate) = self.settings();
+        self.configure(cpol, cpal, rate);
     }
 
     fn set_rate(&self, rate: u32) {
-        self.operation.set(Op::SetRate(rate));
-        self.mux.do_next_op();
+        let (cpol, cpal, _) = self.settings();
+        self.configure(cpol, cpal, rate);
     }
 
     fn get_polarity(&self) -> hil::spi::ClockPolarity {
-        self.mux.spi.get_clock()
+        self.settings().0
     }
 
     fn get_phase(&self) -> hil::spi::ClockPhase {
-        self.mux.spi.get_phase()
+        self.settings().1
     }
 
     fn get_rate(&self) -> u32 {
-        self.mux.spi.get_rate()
+        self.settings().2
     }
 }
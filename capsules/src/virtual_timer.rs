@@ -0,0 +1,104 @@
+//! Virtual timers on a shared `MuxAlarm`.
+//!
+//! `VirtualTimer` implements `hil::time::Timer` for a single kernel client,
+//! so capsules that need periodic work do not each re-arm an alarm by hand.
+//! Any number of timers can share one `MuxAlarm`.
+//!
+//! Repeating timers are rescheduled from their previous deadline rather than
+//! from the time the callback ran, so they do not drift. If the main loop
+//! falls so far behind that whole periods are missed, those periods are
+//! skipped rather than fired back to back. The next deadline is set before
+//! the client is called, so the client may call `disable` from `fired` to
+//! cancel a repeating timer, or start it again with a new interval.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let blink_timer = static_init!(
+//!     VirtualTimer<'static, sam4l::ast::Ast>,
+//!     VirtualTimer::new(mux_alarm),
+//!     56);
+//! blink_timer.set_client(blinker);
+//! blink_timer.repeat(time::ms_to_tics::<Freq32KHz>(500) as u32);
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::time::{self, Alarm, Alarm64, Time, Timer};
+use virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+
+pub struct VirtualTimer<'a, Alrm: Alarm + 'a> {
+    alarm: VirtualMuxAlarm<'a, Alrm>,
+    deadline: Cell<u64>,
+    // Period of a repeating timer, or 0 for a oneshot timer
+    interval: Cell<u64>,
+    client: Cell<Option<&'a time::Client>>,
+}
+
+impl<'a, Alrm: Alarm> VirtualTimer<'a, Alrm> {
+    pub fn new(mux_alarm: &'a MuxAlarm<'a, Alrm>) -> VirtualTimer<'a, Alrm> {
+        VirtualTimer {
+            alarm: VirtualMuxAlarm::new(mux_alarm),
+            deadline: Cell::new(0),
+            interval: Cell::new(0),
+            client: Cell::new(None),
+        }
+    }
+
+    /// Sets the client and adds the timer to its mux. Must be called once
+    /// before the timer is started.
+    pub fn set_client(&'a self, client: &'a time::Client) {
+        self.alarm.set_client(self);
+        self.client.set(Some(client));
+    }
+
+    fn start(&self, interval: u32, repeating: bool) {
+        let interval = interval as u64;
+        self.interval.set(if repeating { interval } else { 0 });
+        self.deadline.set(self.alarm.now64() + interval);
+        self.alarm.set_alarm64(self.deadline.get());
+    }
+}
+
+impl<'a, Alrm: Alarm> Time for VirtualTimer<'a, Alrm> {
+    fn disable(&self) {
+        self.interval.set(0);
+        self.alarm.disable();
+    }
+
+    fn is_armed(&self) -> bool {
+        self.alarm.is_armed()
+    }
+}
+
+impl<'a, Alrm: Alarm> Timer for VirtualTimer<'a, Alrm> {
+    fn oneshot(&self, interval: u32) {
+        self.start(interval, false);
+    }
+
+    fn repeat(&self, interval: u32) {
+        // A zero period would fire continuously
+        if interval == 0 {
+            self.disable();
+            return;
+        }
+        self.start(interval, true);
+    }
+}
+
+impl<'a, Alrm: Alarm> time::Client for VirtualTimer<'a, Alrm> {
+    fn fired(&self) {
+        let interval = self.interval.get();
+        if interval > 0 {
+            let now = self.alarm.now64();
+            let mut deadline = self.deadline.get() + interval;
+            if deadline <= now {
+                deadline += (now - deadline) / interval * interval + interval;
+            }
+            self.deadline.set(deadline);
+            self.alarm.set_alarm64(deadline);
+        }
+
+        self.client.get().map(|client| client.fired());
+    }
+}
@@ -0,0 +1,248 @@
+//! Mux and Virtualize a UART
+//!
+//! `MuxUart` provides shared access to a single UART for multiple users.
+//! `VirtualUartDevice` implements `hil::uart::UART`, so capsules such as the
+//! console can use one in place of the hardware UART.
+//!
+//! Transmits are queued and sent one at a time, taking turns between
+//! devices. Received bytes are copied to every device with an outstanding
+//! receive; each device's receive completes once it has `rx_len` bytes. The
+//! mux reads the line in chunks no longer than the shortest outstanding
+//! receive. A receive started while a chunk is in flight is not given that
+//! chunk; it sees only bytes from the chunks read after it.
+//!
+//! The mux configures the UART, so `init` on a device does nothing. A receive
+//! of no bytes completes right away with `InvalidParameter`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let uart_mux = static_init!(
+//!     MuxUart<'static, usart::USART>,
+//!     MuxUart::new(&usart::USART0, &mut capsules::virtual_uart::RX_BUF, 115200),
+//!     224/8);
+//! hil::uart::UART::set_client(&usart::USART0, uart_mux);
+//! uart_mux.initialize();
+//!
+//! let console_uart = static_init!(
+//!     VirtualUartDevice<'static, usart::USART>,
+//!     VirtualUartDevice::new(uart_mux),
+//!     416/8);
+//! console_uart.setup();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::ReturnCode;
+use kernel::common::{List, ListLink, ListNode};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::uart::{self, UART, Client, Error};
+
+pub static mut RX_BUF: [u8; 64] = [0; 64];
+
+pub struct MuxUart<'a, U: UART + 'a> {
+    uart: &'a U,
+    speed: u32,
+    devices: List<'a, VirtualUartDevice<'a, U>>,
+    inflight: Cell<Option<&'a VirtualUartDevice<'a, U>>>,
+    last_transmitter: Cell<Option<&'a VirtualUartDevice<'a, U>>>,
+    buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a, U: UART> Client for MuxUart<'a, U> {
+    fn transmit_complete(&self, tx_buffer: &'static mut [u8], error: Error) {
+        self.inflight.get().map(move |device| {
+            self.inflight.set(None);
+            device.transmitting.set(false);
+            device.transmit_complete(tx_buffer, error);
+        });
+        self.do_next_transmit();
+    }
+
+    fn receive_complete(&self, rx_buffer: &'static mut [u8], rx_len: usize, error: Error) {
+        let len = cmp::min(rx_len, rx_buffer.len());
+        let in_chunk = |device: &&'a VirtualUartDevice<'a, U>| {
+            device.receiving.get() && !device.rx_skip_chunk.get()
+        };
+        for device in self.devices.iter().filter(&in_chunk) {
+            device.rx_buffer.take().map(|buffer| {
+                let position = device.rx_position.get();
+                let count = cmp::min(len, device.rx_len.get() - position);
+                buffer[position..position + count].copy_from_slice(&rx_buffer[..count]);
+                device.rx_position.set(position + count);
+                device.rx_buffer.replace(buffer);
+            });
+        }
+
+        // Completing a receive may start another one on the same device, so
+        // every device is updated before any client is called. The chunk
+        // still counts as in flight until every client has been called, so
+        // receives they start skip it.
+        for device in self.devices.iter().filter(&in_chunk) {
+            if device.rx_position.get() == device.rx_len.get() || error != Error::CommandComplete {
+                device.receiving.set(false);
+                device.rx_buffer.take().map(|buffer| {
+                    device.receive_complete(buffer, device.rx_position.get(), error);
+                });
+            }
+        }
+        self.buffer.replace(rx_buffer);
+        for device in self.devices.iter() {
+            device.rx_skip_chunk.set(false);
+        }
+        self.start_receive();
+    }
+}
+
+impl<'a, U: UART> MuxUart<'a, U> {
+    pub fn new(uart: &'a U, buffer: &'static mut [u8], speed: u32) -> MuxUart<'a, U> {
+        MuxUart {
+            uart: uart,
+            speed: speed,
+            devices: List::new(),
+            inflight: Cell::new(None),
+            last_transmitter: Cell::new(None),
+            buffer: TakeCell::new(buffer),
+        }
+    }
+
+    pub fn initialize(&self) -> ReturnCode {
+        self.uart.init(uart::UARTParams {
+            baud_rate: self.speed,
+            stop_bits: uart::StopBits::One,
+            parity: uart::Parity::None,
+            hw_flow_control: false,
+        })
+    }
+
+    /// Starts the next queued transmit, beginning the search after the
+    /// device that transmitted last so that no device can starve the rest.
+    fn do_next_transmit(&self) {
+        if self.inflight.get().is_some() {
+            return;
+        }
+        let pending = |device: &&'a VirtualUartDevice<'a, U>| device.tx_buffer.is_some();
+        let after_last = self.last_transmitter.get().and_then(|last| {
+            self.devices
+                .iter()
+                .skip_while(|device| *device as *const _ != last as *const _)
+                .skip(1)
+                .find(&pending)
+        });
+        after_last.or_else(|| self.devices.iter().find(&pending)).map(|device| {
+            device.tx_buffer.take().map(|buffer| {
+                self.inflight.set(Some(device));
+                self.last_transmitter.set(Some(device));
+                self.uart.transmit(buffer, device.tx_len.get());
+            });
+        });
+    }
+
+    /// Reads the next chunk of the line if any device is waiting for bytes.
+    fn start_receive(&self) {
+        let remaining = self.devices
+            .iter()
+            .filter(|device| device.receiving.get())
+            .map(|device| device.rx_len.get() - device.rx_position.get())
+            .min();
+        remaining.map(|remaining| {
+            self.buffer.take().map(|buffer| {
+                let len = cmp::min(remaining, buffer.len());
+                self.uart.receive(buffer, len);
+            });
+        });
+    }
+}
+
+pub struct VirtualUartDevice<'a, U: UART + 'a> {
+    mux: &'a MuxUart<'a, U>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    transmitting: Cell<bool>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_position: Cell<usize>,
+    receiving: Cell<bool>,
+    rx_skip_chunk: Cell<bool>,
+    next: ListLink<'a, VirtualUartDevice<'a, U>>,
+    client: Cell<Option<&'static Client>>,
+}
+
+impl<'a, U: UART> VirtualUartDevice<'a, U> {
+    pub const fn new(mux: &'a MuxUart<'a, U>) -> VirtualUartDevice<'a, U> {
+        VirtualUartDevice {
+            mux: mux,
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            transmitting: Cell::new(false),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_position: Cell::new(0),
+            receiving: Cell::new(false),
+            rx_skip_chunk: Cell::new(false),
+            next: ListLink::empty(),
+            client: Cell::new(None),
+        }
+    }
+
+    /// Adds this device to the mux. Must be called once before use.
+    pub fn setup(&'a self) {
+        self.mux.devices.push_head(self);
+    }
+}
+
+impl<'a, U: UART> Client for VirtualUartDevice<'a, U> {
+    fn transmit_complete(&self, tx_buffer: &'static mut [u8], error: Error) {
+        self.client.get().map(move |client| client.transmit_complete(tx_buffer, error));
+    }
+
+    fn receive_complete(&self, rx_buffer: &'static mut [u8], rx_len: usize, error: Error) {
+        self.client.get().map(move |client| client.receive_complete(rx_buffer, rx_len, error));
+    }
+}
+
+impl<'a, U: UART> ListNode<'a, VirtualUartDevice<'a, U>> for VirtualUartDevice<'a, U> {
+    fn next(&'a self) -> &'a ListLink<'a, VirtualUartDevice<'a, U>> {
+        &self.next
+    }
+}
+
+impl<'a, U: UART> UART for VirtualUartDevice<'a, U> {
+    fn set_client(&self, client: &'static Client) {
+        self.client.set(Some(client));
+    }
+
+    fn init(&self, _params: uart::UARTParams) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn transmit(&self, tx_data: &'static mut [u8], tx_len: usize) {
+        if self.transmitting.get() {
+            self.transmit_complete(tx_data, Error::RepeatCallError);
+            return;
+        }
+        self.tx_len.set(cmp::min(tx_len, tx_data.len()));
+        self.tx_buffer.replace(tx_data);
+        self.transmitting.set(true);
+        self.mux.do_next_transmit();
+    }
+
+    fn receive(&self, rx_buffer: &'static mut [u8], rx_len: usize) {
+        if self.receiving.get() {
+            self.receive_complete(rx_buffer, 0, Error::RepeatCallError);
+            return;
+        }
+        if rx_len == 0 || rx_buffer.len() == 0 {
+            self.receive_complete(rx_buffer, 0, Error::InvalidParameter);
+            return;
+        }
+        self.rx_len.set(cmp::min(rx_len, rx_buffer.len()));
+        self.rx_position.set(0);
+        self.rx_buffer.replace(rx_buffer);
+        self.receiving.set(true);
+        // The mux holds its buffer only while no chunk is in flight
+        self.rx_skip_chunk.set(self.mux.buffer.is_none());
+        self.mux.start_receive();
+    }
+}
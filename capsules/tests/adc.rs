@@ -0,0 +1,150 @@
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+
+mod common;
+
+use capsules::adc::ADC;
+use host::{Apps, Host, mock};
+use kernel::{Chip, Container, Driver, ReturnCode};
+
+const DRIVER_NUM: usize = 7;
+
+const CHANNEL: usize = 1;
+
+/// Channel 1 at 1kHz, as commands 3 and 4 take it.
+const SAMPLE_BUFFER: usize = CHANNEL | 1000 << 8;
+
+struct Test {
+    host: Host,
+    adc: &'static mock::Adc,
+    driver: &'static ADC<'static, mock::Adc>,
+    apps: Apps,
+}
+
+/// The driver, with four sample kernel buffers, for one app.
+fn setup() -> Test {
+    let adc: &'static mock::Adc = host::leak(mock::Adc::new(2));
+    let driver: &'static ADC<mock::Adc> = host::leak(ADC::new(adc,
+                                                              unsafe { Container::create() },
+                                                              host::leak([0u16; 4]),
+                                                              host::leak([0u16; 4])));
+    adc.set_client(driver);
+    adc.set_continuous_client(driver);
+
+    let apps = Apps::load(&["sensors"]);
+    common::subscribe(driver, DRIVER_NUM, &apps, &[0], &[0]);
+    Test {
+        host: Host::with_peripherals(&[adc]),
+        adc: adc,
+        driver: driver,
+        apps: apps,
+    }
+}
+
+impl Test {
+    /// Allows app buffer `allow_num` of `samples` samples and returns its
+    /// address.
+    fn allow(&self, allow_num: usize, samples: usize) -> *const u8 {
+        let buffer = self.apps.buffer(0, b"", samples * 2);
+        let address = buffer.as_ref().as_ptr();
+        assert_eq!(self.driver.allow(self.apps.id(0), allow_num, buffer),
+                   ReturnCode::SUCCESS);
+        address
+    }
+
+    fn revoke(&self, allow_num: usize) {
+        let buffer = self.apps.buffer(0, b"", 0);
+        self.driver.allow(self.apps.id(0), allow_num, buffer);
+    }
+
+    fn command(&self, command_num: usize, data: usize) -> ReturnCode {
+        self.driver.command(command_num, data, self.apps.id(0))
+    }
+
+    /// The `samples` samples in app memory at `address`.
+    fn samples(&self, address: *const u8, samples: usize) -> Vec<u16> {
+        self.apps
+            .read(address, samples * 2)
+            .chunks(2)
+            .map(|sample| sample[0] as u16 | (sample[1] as u16) << 8)
+            .collect()
+    }
+}
+
+#[test]
+fn buffered_sampling_takes_the_sample_count() {
+    let mut test = setup();
+    let buffer = test.allow(0, 10);
+    // No count set yet
+    assert_eq!(test.command(3, SAMPLE_BUFFER), ReturnCode::EINVAL);
+    assert_eq!(test.command(6, 11), ReturnCode::SUCCESS);
+    assert_eq!(test.command(3, SAMPLE_BUFFER), ReturnCode::EINVAL);
+
+    // More samples than a kernel buffer holds
+    assert_eq!(test.command(6, 6), ReturnCode::SUCCESS);
+    assert_eq!(test.command(3, SAMPLE_BUFFER), ReturnCode::SUCCESS);
+    test.host.run_until_idle();
+    assert_eq!(test.apps.callbacks(0), vec![(1, CHANNEL, 6)]);
+    assert_eq!(test.samples(buffer, 10), vec![0, 1, 2, 3, 4, 5, 0, 0, 0, 0]);
+    assert!(!test.adc.is_sampling());
+}
+
+#[test]
+fn continuous_sampling_alternates_buffers() {
+    let mut test = setup();
+    let first = test.allow(0, 3);
+    let second = test.allow(1, 3);
+    assert_eq!(test.command(4, SAMPLE_BUFFER), ReturnCode::SUCCESS);
+
+    // Each kernel buffer of four samples fills the rest of one app buffer
+    // and starts on the other
+    test.host.service_pending_interrupts();
+    assert_eq!(test.apps.callbacks(0), vec![(2, CHANNEL, 3)]);
+    assert_eq!(test.samples(first, 3), vec![0, 1, 2]);
+    test.host.service_pending_interrupts();
+    assert_eq!(test.apps.callbacks(0), vec![(2, CHANNEL, 3)]);
+    assert_eq!(test.samples(second, 3), vec![3, 4, 5]);
+    assert_eq!(test.samples(first, 3), vec![6, 7, 2]);
+    assert!(test.adc.is_sampling());
+
+    assert_eq!(test.command(5, 0), ReturnCode::SUCCESS);
+    assert!(!test.adc.is_sampling());
+    test.host.run_until_idle();
+    assert!(test.apps.callbacks(0).is_empty());
+}
+
+#[test]
+fn revoking_a_buffer_stops_sampling() {
+    let mut test = setup();
+    test.allow(0, 3);
+    test.allow(1, 3);
+    assert_eq!(test.command(4, SAMPLE_BUFFER), ReturnCode::SUCCESS);
+    test.host.service_pending_interrupts();
+    assert_eq!(test.apps.callbacks(0), vec![(2, CHANNEL, 3)]);
+
+    test.revoke(1);
+    assert!(!test.adc.is_sampling());
+    test.host.run_until_idle();
+    assert!(test.apps.callbacks(0).is_empty());
+    // Nothing is sampling, so single samples are taken again
+    assert_eq!(test.command(2, CHANNEL), ReturnCode::SUCCESS);
+}
+
+#[test]
+fn stopping_recovers_the_kernel_buffers() {
+    let mut test = setup();
+    test.allow(0, 3);
+    test.allow(1, 3);
+    for _ in 0..2 {
+        assert_eq!(test.command(4, SAMPLE_BUFFER), ReturnCode::SUCCESS);
+        // Both kernel buffers are in use, so a second buffer is always
+        // lined up and sampling carries on
+        for _ in 0..3 {
+            test.host.service_pending_interrupts();
+            assert!(test.adc.is_sampling());
+        }
+        assert_eq!(test.command(5, 0), ReturnCode::SUCCESS);
+        assert_eq!(test.apps.callbacks(0).len(), 4);
+    }
+}
@@ -0,0 +1,88 @@
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+
+mod common;
+
+use capsules::bus_recorder::{I2CRecorder, I2CReplay, Operation, Transaction, TransactionLog};
+use capsules::si7021::SI7021;
+use host::{Host, mock};
+use kernel::{AppId, Driver};
+
+fn leak_transactions(len: usize) -> &'static mut [Transaction] {
+    &mut **host::leak(vec![Transaction::empty(); len].into_boxed_slice())
+}
+
+fn measure(host: &mut Host, alarm: &mock::Alarm, si7021: &SI7021<mock::Alarm>) {
+    si7021.subscribe(0, common::callback());
+    si7021.command(1, 0, AppId::kernel_new(100));
+    host.run_until_idle();
+    alarm.advance_to_alarm();
+    host.run_until_idle();
+}
+
+// Deferred calls are global, so recording and replaying share one test
+// rather than running on parallel threads.
+#[test]
+fn replay_reproduces_recording() {
+    let i2c: &'static mock::i2c::Device = host::leak(mock::i2c::Device::new());
+    let alarm: &'static mock::Alarm = host::leak(mock::Alarm::new());
+    let log: &'static TransactionLog<mock::Alarm> =
+        host::leak(TransactionLog::new(alarm, leak_transactions(3)));
+    let recorder: &'static I2CRecorder<mock::Alarm> =
+        host::leak(I2CRecorder::new(i2c, 0x40, log));
+    let si7021: &'static SI7021<mock::Alarm> =
+        host::leak(SI7021::new(recorder, alarm, host::leak_buffer(14)));
+    i2c.set_client(recorder);
+    recorder.set_client(si7021);
+    alarm.set_client(si7021);
+
+    let mut host = Host::with_peripherals(&[i2c, alarm]);
+
+    i2c.expect_write(&[0xf5]);
+    i2c.expect_read(&[0x80, 0x00]);
+    i2c.expect_write(&[0xe0]);
+    i2c.expect_read(&[0x66, 0x66]);
+    measure(&mut host, alarm, si7021);
+    i2c.assert_done();
+    assert_eq!(common::calls(), vec![(2343, 5650, 0)]);
+
+    // The log only has room for the last three transactions
+    assert_eq!(log.len(), 3);
+    assert_eq!(log.overwritten(), 1);
+    let recorded = leak_transactions(4);
+    assert_eq!(log.copy_to(recorded), 3);
+    assert_eq!(recorded[0].operation, Operation::I2CRead);
+    assert_eq!(recorded[0].timestamp, 655);
+    assert_eq!(recorded[0].read_bytes(), &[0x80, 0x00]);
+    assert_eq!(recorded[1].operation, Operation::I2CWrite);
+    assert_eq!(recorded[1].written(), &[0xe0]);
+    assert_eq!(recorded[2].read_bytes(), &[0x66, 0x66]);
+
+    // Replay on a fresh driver, putting back the transaction that was
+    // overwritten
+    recorded[3] = recorded[2];
+    recorded[2] = recorded[1];
+    recorded[1] = recorded[0];
+    recorded[0] = Transaction::empty();
+    recorded[0].address = 0x40;
+    recorded[0].operation = Operation::I2CWrite;
+    recorded[0].write_len = 1;
+    recorded[0].write[0] = 0xf5;
+    recorded[0].error = Some(kernel::hil::i2c::Error::CommandComplete);
+
+    let replay: &'static I2CReplay = host::leak(I2CReplay::new(recorded));
+    assert!(replay.register_deferred_call());
+    let alarm: &'static mock::Alarm = host::leak(mock::Alarm::new());
+    let si7021: &'static SI7021<mock::Alarm> =
+        host::leak(SI7021::new(replay, alarm, host::leak_buffer(14)));
+    replay.set_client(si7021);
+    alarm.set_client(si7021);
+
+    let mut host = Host::with_peripherals(&[alarm]);
+
+    measure(&mut host, alarm, si7021);
+    assert_eq!(common::calls(), vec![(2343, 5650, 0)]);
+    assert!(replay.is_finished());
+    assert_eq!(replay.mismatches(), 0);
+}
@@ -0,0 +1,74 @@
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+
+use capsules::button::Button;
+use host::{Apps, Host};
+use host::gpio::Pin;
+use kernel::{Container, Driver, ReturnCode};
+
+const DRIVER_NUM: usize = 3;
+
+struct Test {
+    host: Host,
+    apps: Apps,
+    pins: [&'static Pin; 2],
+    button: &'static Button<'static, Pin>,
+}
+
+fn setup() -> Test {
+    let pins: &'static [&'static Pin; 2] =
+        host::leak([host::leak(Pin::new()) as &Pin, host::leak(Pin::new()) as &Pin]);
+    let button: &'static Button<Pin> =
+        host::leak(Button::new(pins, unsafe { Container::create() }));
+    for pin in pins.iter() {
+        pin.set_client(button);
+    }
+    Test {
+        host: Host::with_peripherals(&[pins[0], pins[1]]),
+        apps: Apps::load(&["first", "second"]),
+        pins: *pins,
+        button: button,
+    }
+}
+
+#[test]
+fn pin_count_and_state() {
+    let test = setup();
+    assert_eq!(test.button.command(0, 0, test.apps.id(0)),
+               ReturnCode::SuccessWithValue { value: 2 });
+    test.pins[1].drive(true);
+    assert_eq!(test.button.command(3, 1, test.apps.id(0)),
+               ReturnCode::SuccessWithValue { value: 1 });
+    assert_eq!(test.button.command(3, 2, test.apps.id(0)), ReturnCode::EINVAL);
+}
+
+#[test]
+fn presses_reach_apps_that_enabled_the_pin() {
+    let mut test = setup();
+    for app in 0..2 {
+        assert_eq!(test.button.subscribe(0, test.apps.callback(app, DRIVER_NUM, 0)),
+                   ReturnCode::SUCCESS);
+    }
+    assert_eq!(test.button.command(1, 1, test.apps.id(0)), ReturnCode::SUCCESS);
+    assert_eq!(test.button.command(1, 0, test.apps.id(1)), ReturnCode::SUCCESS);
+
+    test.pins[1].drive(true);
+    test.host.run_until_idle();
+    test.pins[1].drive(false);
+    test.host.run_until_idle();
+    assert_eq!(test.apps.callbacks(0), vec![(1, 1, 0), (1, 0, 0)]);
+    assert!(test.apps.callbacks(1).is_empty());
+}
+
+#[test]
+fn disabled_pin_is_quiet() {
+    let mut test = setup();
+    test.button.subscribe(0, test.apps.callback(0, DRIVER_NUM, 0));
+    assert_eq!(test.button.command(1, 0, test.apps.id(0)), ReturnCode::SUCCESS);
+    assert_eq!(test.button.command(2, 0, test.apps.id(0)), ReturnCode::SUCCESS);
+    test.pins[0].drive(true);
+    test.host.run_until_idle();
+    assert!(test.apps.callbacks(0).is_empty());
+    assert_eq!(test.button.command(1, 2, test.apps.id(0)), ReturnCode::EINVAL);
+}
@@ -0,0 +1,67 @@
+//! Helpers shared by the capsule test suites.
+//!
+//! Each suite includes this module with `mod common;` and uses only some of
+//! it.
+
+#![allow(dead_code)]
+
+use host::Apps;
+use kernel::{AppId, Callback, Driver, ReturnCode};
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+thread_local!(static CALLS: RefCell<Vec<(usize, usize, usize)>> = RefCell::new(Vec::new()));
+
+fn record(r0: usize, r1: usize, r2: usize, _appdata: usize) {
+    CALLS.with(|calls| calls.borrow_mut().push((r0, r1, r2)));
+}
+
+/// A kernel callback that records the arguments it is scheduled with.
+pub fn callback() -> Callback {
+    Callback::kernel_new(AppId::kernel_new(100), record)
+}
+
+/// The arguments of every callback scheduled on this thread since the last
+/// call to `calls`.
+pub fn calls() -> Vec<(usize, usize, usize)> {
+    CALLS.with(|calls| calls.borrow_mut().drain(..).collect())
+}
+
+/// A path in the temporary directory for the flash file of test `name` in
+/// `suite`, with nothing left there by an earlier run.
+pub fn flash_file(suite: &str, name: &str) -> PathBuf {
+    let path = ::std::env::temp_dir().join(format!("tock-{}-{}", suite, name));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+/// The contents of the flash file at `path`.
+pub fn read_flash_file(path: &Path) -> Vec<u8> {
+    let mut contents = Vec::new();
+    File::open(path).unwrap().read_to_end(&mut contents).unwrap();
+    contents
+}
+
+/// Replaces the contents of the flash file at `path`, as if the flash had
+/// changed while the board was off.
+pub fn write_flash_file(path: &Path, contents: &[u8]) {
+    File::create(path).unwrap().write_all(contents).unwrap();
+}
+
+/// Subscribes each of the apps numbered in `app_ids` to `subscribe_nums` of
+/// `driver`, whose driver number is `driver_num`, as apps do before using it.
+pub fn subscribe(driver: &Driver,
+                 driver_num: usize,
+                 apps: &Apps,
+                 app_ids: &[usize],
+                 subscribe_nums: &[usize]) {
+    for &app in app_ids.iter() {
+        for &subscribe_num in subscribe_nums.iter() {
+            assert_eq!(driver.subscribe(subscribe_num,
+                                        apps.callback(app, driver_num, subscribe_num)),
+                       ReturnCode::SUCCESS);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+
+use capsules::console::{Console, Prefix};
+use host::{Apps, Host};
+use host::uart::Uart;
+use kernel::{Container, Driver, ReturnCode};
+
+const DRIVER_NUM: usize = 0;
+
+struct Test {
+    host: Host,
+    apps: Apps,
+    uart: &'static Uart,
+    console: &'static Console<'static, Uart>,
+}
+
+fn setup(apps: &[&str]) -> Test {
+    let uart: &'static Uart = host::leak(Uart::new());
+    let console: &'static Console<Uart> = host::leak(Console::new(uart,
+                                                                  115200,
+                                                                  host::leak_buffer(64),
+                                                                  host::leak_buffer(1),
+                                                                  unsafe { Container::create() }));
+    uart.set_client(console);
+    console.initialize();
+    let host = Host::with_peripherals(&[uart]);
+    Test {
+        host: host,
+        apps: Apps::load(apps),
+        uart: uart,
+        console: console,
+    }
+}
+
+/// Starts a write of `data` from `app`.
+fn write(test: &Test, app: usize, data: &[u8]) {
+    let buffer = test.apps.read_only_buffer(app, data);
+    assert_eq!(test.console.allow_readonly(test.apps.id(app), 1, buffer),
+               ReturnCode::SUCCESS);
+    assert_eq!(test.console.subscribe(1, test.apps.callback(app, DRIVER_NUM, 1)),
+               ReturnCode::SUCCESS);
+}
+
+#[test]
+fn write_is_transmitted_and_reported() {
+    let mut test = setup(&["app"]);
+    write(&test, 0, b"hello\n");
+    test.host.run_until_idle();
+    assert_eq!(test.uart.take_output(), b"hello\n".to_vec());
+    assert_eq!(test.apps.callbacks(0), vec![(6, 0, 0)]);
+}
+
+#[test]
+fn long_write_is_sent_in_several_transmits() {
+    let mut test = setup(&["app"]);
+    let data: Vec<u8> = (0..150).map(|i| b'a' + (i % 26) as u8).collect();
+    write(&test, 0, &data);
+    test.host.run_until_idle();
+    assert_eq!(test.uart.take_output(), data);
+    assert_eq!(test.apps.callbacks(0), vec![(150, 0, 0)]);
+}
+
+#[test]
+fn lines_are_tagged_with_the_app_name() {
+    let mut test = setup(&["first", "second"]);
+    test.console.set_prefix(Prefix::PackageName);
+    write(&test, 0, b"one\n");
+    write(&test, 1, b"two\n");
+    test.host.run_until_idle();
+    assert_eq!(String::from_utf8(test.uart.take_output()).unwrap(),
+               "[first] one\n[second] two\n");
+    assert_eq!(test.apps.callbacks(0), vec![(4, 0, 0)]);
+    assert_eq!(test.apps.callbacks(1), vec![(4, 0, 0)]);
+}
+
+#[test]
+fn read_line_is_echoed_and_edited() {
+    let mut test = setup(&["app"]);
+    let buffer = test.apps.buffer(0, b"", 16);
+    let address = buffer.as_ref().as_ptr();
+    assert_eq!(test.console.allow(test.apps.id(0), 0, buffer), ReturnCode::SUCCESS);
+    assert_eq!(test.console.subscribe(0, test.apps.callback(0, DRIVER_NUM, 0)),
+               ReturnCode::SUCCESS);
+    assert_eq!(test.console.command(2, 0, test.apps.id(0)), ReturnCode::SUCCESS);
+
+    test.uart.push_input(b"hx\x7fi\r\n");
+    test.host.run_until_idle();
+    assert_eq!(test.apps.callbacks(0), vec![(2, 0, 0)]);
+    assert_eq!(test.apps.read(address, 2), b"hi".to_vec());
+    assert_eq!(test.uart.take_output(), b"hx\x08 \x08i\r\n".to_vec());
+}
+
+#[test]
+fn read_needs_a_buffer() {
+    let test = setup(&["app"]);
+    assert_eq!(test.console.command(2, 0, test.apps.id(0)), ReturnCode::EINVAL);
+}
+
+#[test]
+fn apps_cannot_take_input_from_the_board_foreground_app() {
+    let mut test = setup(&["first", "second"]);
+    let mut addresses = Vec::new();
+    for app in 0..2 {
+        let buffer = test.apps.buffer(app, b"", 4);
+        addresses.push(buffer.as_ref().as_ptr());
+        assert_eq!(test.console.allow(test.apps.id(app), 0, buffer), ReturnCode::SUCCESS);
+        assert_eq!(test.console.subscribe(0, test.apps.callback(app, DRIVER_NUM, 0)),
+                   ReturnCode::SUCCESS);
+        assert_eq!(test.console.command(2, 2, test.apps.id(app)), ReturnCode::SUCCESS);
+    }
+
+    test.console.set_foreground(Some(test.apps.id(1)));
+    assert_eq!(test.console.command(3, 0, test.apps.id(0)), ReturnCode::ENOACCESS);
+    test.uart.push_input(b"ab");
+    test.host.run_until_idle();
+    assert!(test.apps.callbacks(0).is_empty());
+    assert_eq!(test.apps.callbacks(1), vec![(2, 0, 0)]);
+    assert_eq!(test.apps.read(addresses[1], 2), b"ab".to_vec());
+
+    // Once the board lets go, apps can choose again
+    test.console.set_foreground(None);
+    assert_eq!(test.console.command(3, 0, test.apps.id(0)), ReturnCode::SUCCESS);
+}
@@ -0,0 +1,81 @@
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+
+use capsules::crc::Crc;
+use host::{Apps, Host};
+use kernel::{Container, Driver, ReturnCode};
+
+const DRIVER_NUM: usize = 16;
+const CHECK_INPUT: &'static [u8] = b"123456789";
+
+struct Test {
+    host: Host,
+    apps: Apps,
+    crc: &'static Crc<'static, host::crc::Crc>,
+}
+
+fn setup(apps: &[&str]) -> Test {
+    let unit: &'static host::crc::Crc = host::leak(host::crc::Crc::new());
+    let crc: &'static Crc<host::crc::Crc> =
+        host::leak(Crc::new(unit, unsafe { Container::create() }));
+    unit.set_client(crc);
+    let host = Host::with_peripherals(&[unit]);
+    Test {
+        host: host,
+        apps: Apps::load(apps),
+        crc: crc,
+    }
+}
+
+/// Shares `data` and subscribes, as an app does before asking for a CRC.
+fn prepare(test: &Test, app: usize, data: &[u8]) {
+    let buffer = test.apps.read_only_buffer(app, data);
+    assert_eq!(test.crc.allow_readonly(test.apps.id(app), 0, buffer),
+               ReturnCode::SUCCESS);
+    assert_eq!(test.crc.subscribe(0, test.apps.callback(app, DRIVER_NUM, 0)),
+               ReturnCode::SUCCESS);
+}
+
+#[test]
+fn computes_standard_check_values() {
+    let mut test = setup(&["app"]);
+    prepare(&test, 0, CHECK_INPUT);
+    assert_eq!(test.crc.command(2, 0, test.apps.id(0)), ReturnCode::SUCCESS);
+    test.host.run_until_idle();
+    assert_eq!(test.apps.callbacks(0), vec![(0, 0xcbf43926, 0)]);
+
+    assert_eq!(test.crc.command(2, 1, test.apps.id(0)), ReturnCode::SUCCESS);
+    test.host.run_until_idle();
+    assert_eq!(test.apps.callbacks(0), vec![(0, 0xe3069283, 0)]);
+}
+
+#[test]
+fn requests_from_two_apps_are_served_in_turn() {
+    let mut test = setup(&["first", "second"]);
+    prepare(&test, 0, CHECK_INPUT);
+    prepare(&test, 1, b"a");
+    assert_eq!(test.crc.command(2, 0, test.apps.id(0)), ReturnCode::SUCCESS);
+    assert_eq!(test.crc.command(2, 0, test.apps.id(1)), ReturnCode::SUCCESS);
+    // An app can have only one request outstanding
+    assert_eq!(test.crc.command(2, 0, test.apps.id(1)), ReturnCode::EBUSY);
+    test.host.run_until_idle();
+    assert_eq!(test.apps.callbacks(0), vec![(0, 0xcbf43926, 0)]);
+    assert_eq!(test.apps.callbacks(1), vec![(0, 0xe8b7be43, 0)]);
+}
+
+#[test]
+fn unsupported_algorithm_is_reported_in_the_callback() {
+    let mut test = setup(&["app"]);
+    prepare(&test, 0, CHECK_INPUT);
+    assert_eq!(test.crc.command(2, 2, test.apps.id(0)), ReturnCode::SUCCESS);
+    test.host.run_until_idle();
+    assert_eq!(test.apps.callbacks(0), vec![(-10isize as usize, 0, 0)]);
+    assert_eq!(test.crc.command(2, 5, test.apps.id(0)), ReturnCode::EINVAL);
+}
+
+#[test]
+fn request_needs_a_buffer_and_callback() {
+    let test = setup(&["app"]);
+    assert_eq!(test.crc.command(2, 0, test.apps.id(0)), ReturnCode::EINVAL);
+}
@@ -0,0 +1,130 @@
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+
+use capsules::fm25cl::{FM25CL, FM25CLClient};
+use host::{Host, mock};
+use kernel::hil::spi::{ClockPhase, ClockPolarity, SpiMasterDevice};
+use std::cell::RefCell;
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Status(u8),
+    Read(Vec<u8>),
+    Done(Vec<u8>),
+}
+
+thread_local!(static EVENTS: RefCell<Vec<Event>> = RefCell::new(Vec::new()));
+
+fn events() -> Vec<Event> {
+    EVENTS.with(|events| events.borrow_mut().drain(..).collect())
+}
+
+struct Client;
+
+impl FM25CLClient for Client {
+    fn status(&self, status: u8) {
+        EVENTS.with(|events| events.borrow_mut().push(Event::Status(status)));
+    }
+
+    fn read(&self, data: &'static mut [u8], len: usize) {
+        EVENTS.with(|events| events.borrow_mut().push(Event::Read(data[..len].to_vec())));
+    }
+
+    fn done(&self, buffer: &'static mut [u8]) {
+        EVENTS.with(|events| events.borrow_mut().push(Event::Done(buffer.to_vec())));
+    }
+}
+
+struct Test {
+    host: Host,
+    spi: &'static mock::spi::Device,
+    fm25cl: &'static FM25CL<'static, mock::spi::Device>,
+}
+
+fn setup() -> Test {
+    let spi: &'static mock::spi::Device = host::leak(mock::spi::Device::new());
+    let fm25cl: &'static FM25CL<mock::spi::Device> =
+        host::leak(FM25CL::new(spi, host::leak_buffer(512), host::leak_buffer(512)));
+    spi.set_client(fm25cl);
+    fm25cl.set_client(host::leak(Client));
+
+    let host = Host::with_peripherals(&[spi]);
+    Test {
+        host: host,
+        spi: spi,
+        fm25cl: fm25cl,
+    }
+}
+
+#[test]
+fn read_status() {
+    let mut test = setup();
+
+    test.spi.expect_transfer(&[0x05], &[0x00, 0x42, 0x00, 0x00]);
+    test.fm25cl.read_status();
+    test.host.run_until_idle();
+    test.spi.assert_done();
+    assert_eq!(events(), vec![Event::Status(0x42)]);
+
+    assert_eq!(test.spi.get_polarity(), ClockPolarity::IdleLow);
+    assert_eq!(test.spi.get_phase(), ClockPhase::SampleLeading);
+    assert_eq!(test.spi.get_rate(), 4000000);
+}
+
+#[test]
+fn write_enables_then_writes() {
+    let mut test = setup();
+    let buffer = host::leak_buffer(4);
+    buffer.copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+
+    test.spi.expect_transfer(&[0x06], &[]);
+    test.spi.expect_transfer(&[0x02, 0x01, 0x20, 0xde, 0xad, 0xbe, 0xef], &[]);
+    test.fm25cl.write(0x0120, buffer, 4);
+    test.host.run_until_idle();
+    test.spi.assert_done();
+    assert_eq!(events(), vec![Event::Done(vec![0xde, 0xad, 0xbe, 0xef])]);
+}
+
+#[test]
+fn read_skips_command_bytes() {
+    let mut test = setup();
+
+    test.spi.expect_transfer(&[0x03, 0x00, 0x10],
+                             &[0x00, 0x00, 0x00, 0xca, 0xfe, 0xf0, 0x0d]);
+    test.fm25cl.read(0x0010, host::leak_buffer(4), 4);
+    test.host.run_until_idle();
+    test.spi.assert_done();
+    assert_eq!(events(), vec![Event::Read(vec![0xca, 0xfe, 0xf0, 0x0d])]);
+}
+
+#[test]
+fn read_into_short_buffer() {
+    let mut test = setup();
+
+    test.spi.expect_transfer(&[0x03, 0x1f, 0xfe],
+                             &[0x00, 0x00, 0x00, 0xca, 0xfe, 0xf0, 0x0d]);
+    test.fm25cl.read(0x1ffe, host::leak_buffer(2), 4);
+    test.host.run_until_idle();
+    test.spi.assert_done();
+    assert_eq!(events(), vec![Event::Read(vec![0xca, 0xfe])]);
+}
+
+#[test]
+fn operations_in_sequence() {
+    let mut test = setup();
+
+    let buffer = host::leak_buffer(1);
+    buffer[0] = 0x5a;
+
+    test.spi.expect_transfer(&[0x06], &[]);
+    test.spi.expect_transfer(&[0x02, 0x00, 0x00, 0x5a], &[]);
+    test.fm25cl.write(0, buffer, 1);
+    test.host.run_until_idle();
+
+    test.spi.expect_transfer(&[0x05], &[0x00, 0x02, 0x00, 0x00]);
+    test.fm25cl.read_status();
+    test.host.run_until_idle();
+    test.spi.assert_done();
+    assert_eq!(events(), vec![Event::Done(vec![0x5a]), Event::Status(0x02)]);
+}
@@ -0,0 +1,138 @@
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+
+mod common;
+
+use capsules::i2c_master::I2CMasterDriver;
+use capsules::virtual_i2c::{I2CDevice, MuxI2C};
+use host::{Apps, Host, mock};
+use host::mock::i2c::Op;
+use kernel::{Container, Driver, ReturnCode};
+use kernel::hil::i2c;
+
+const DRIVER_NUM: usize = 21;
+
+static ALLOWED: [(&'static str, &'static [u8]); 2] = [("first", &[0x48, 0x49]),
+                                                      ("second", &[0x50])];
+
+struct Test {
+    host: Host,
+    i2c: &'static mock::i2c::Master,
+    driver: &'static I2CMasterDriver<'static, mock::Alarm>,
+    apps: Apps,
+}
+
+/// The driver on a bus of its own, for apps with the given package names.
+fn setup(names: &[&str]) -> Test {
+    let i2c: &'static mock::i2c::Master = host::leak(mock::i2c::Master::new());
+    let alarm: &'static mock::Alarm = host::leak(mock::Alarm::new());
+    let mux: &'static MuxI2C<mock::Alarm> = host::leak(MuxI2C::new(i2c, alarm));
+    i2c.set_client(mux);
+    alarm.set_client(mux);
+
+    let device: &'static I2CDevice<mock::Alarm> = host::leak(I2CDevice::new(mux, 0));
+    let driver: &'static I2CMasterDriver<mock::Alarm> =
+        host::leak(I2CMasterDriver::new(device,
+                                        &ALLOWED,
+                                        host::leak_buffer(16),
+                                        unsafe { Container::create() }));
+    device.set_client(driver);
+
+    let apps = Apps::load(names);
+    common::subscribe(driver, DRIVER_NUM, &apps, &(0..names.len()).collect::<Vec<_>>(), &[0]);
+    Test {
+        host: Host::with_peripherals(&[i2c, alarm]),
+        i2c: i2c,
+        driver: driver,
+        apps: apps,
+    }
+}
+
+impl Test {
+    fn allow_write(&self, app: usize, data: &[u8]) {
+        let buffer = self.apps.read_only_buffer(app, data);
+        assert_eq!(self.driver.allow_readonly(self.apps.id(app), 1, buffer),
+                   ReturnCode::SUCCESS);
+    }
+
+    /// Allows a read buffer of `len` bytes and returns its address.
+    fn allow_read(&self, app: usize, len: usize) -> *const u8 {
+        let buffer = self.apps.buffer(app, b"", len);
+        let address = buffer.as_ref().as_ptr();
+        assert_eq!(self.driver.allow(self.apps.id(app), 0, buffer),
+                   ReturnCode::SUCCESS);
+        address
+    }
+
+    fn command(&self, app: usize, command_num: usize, data: usize) -> ReturnCode {
+        self.driver.command(command_num, data, self.apps.id(app))
+    }
+}
+
+#[test]
+fn apps_reach_only_their_own_addresses() {
+    let mut test = setup(&["first", "second"]);
+    test.allow_write(0, &[1, 2]);
+    test.allow_write(1, &[3]);
+    assert_eq!(test.command(0, 1, 0x50 | 2 << 8), ReturnCode::ENOACCESS);
+    assert_eq!(test.command(1, 1, 0x48 | 1 << 8), ReturnCode::ENOACCESS);
+
+    test.i2c.expect(0x49, Op::Write(vec![1, 2]), i2c::Error::CommandComplete);
+    assert_eq!(test.command(0, 1, 0x49 | 2 << 8), ReturnCode::SUCCESS);
+    test.host.run_until_idle();
+    test.i2c.assert_done();
+    assert_eq!(test.apps.callbacks(0), vec![(0, 2, 0)]);
+    assert!(test.apps.callbacks(1).is_empty());
+}
+
+#[test]
+fn unlisted_and_impersonating_apps_reach_nothing() {
+    let test = setup(&["first", "stranger", "second", "second"]);
+    for app in 1..4 {
+        test.allow_write(app, &[1]);
+        assert_eq!(test.command(app, 1, 0x50 | 1 << 8), ReturnCode::ENOACCESS);
+    }
+}
+
+#[test]
+fn transactions_from_different_apps_are_queued() {
+    let mut test = setup(&["first", "second"]);
+    test.allow_write(0, &[1]);
+    let read = test.allow_read(1, 2);
+    test.i2c.expect(0x48, Op::Write(vec![1]), i2c::Error::CommandComplete);
+    test.i2c.expect(0x50, Op::Read(vec![7, 8]), i2c::Error::CommandComplete);
+    assert_eq!(test.command(0, 1, 0x48 | 1 << 8), ReturnCode::SUCCESS);
+    assert_eq!(test.command(1, 2, 0x50 | 2 << 8), ReturnCode::SUCCESS);
+    // Each app has one transaction at a time
+    assert_eq!(test.command(0, 1, 0x48 | 1 << 8), ReturnCode::EBUSY);
+
+    test.host.run_until_idle();
+    test.i2c.assert_done();
+    assert_eq!(test.apps.callbacks(0), vec![(0, 1, 0)]);
+    assert_eq!(test.apps.callbacks(1), vec![(0, 2, 0)]);
+    assert_eq!(test.apps.read(read, 2), vec![7, 8]);
+}
+
+#[test]
+fn failed_transaction_reports_the_error() {
+    let mut test = setup(&["second"]);
+    test.allow_write(0, &[1]);
+    test.i2c.expect(0x50, Op::Write(vec![1]), i2c::Error::AddressNak);
+    assert_eq!(test.command(0, 1, 0x50 | 1 << 8), ReturnCode::SUCCESS);
+    test.host.run_until_idle();
+    assert_eq!(test.apps.callbacks(0), vec![(-13isize as usize, 0, 0)]);
+}
+
+#[test]
+fn scan_probes_only_allowed_addresses() {
+    let mut test = setup(&["first"]);
+    let found = test.allow_read(0, 2);
+    test.i2c.expect(0x48, Op::Read(vec![0]), i2c::Error::AddressNak);
+    test.i2c.expect(0x49, Op::Read(vec![0]), i2c::Error::CommandComplete);
+    assert_eq!(test.command(0, 4, 0), ReturnCode::SUCCESS);
+    test.host.run_until_idle();
+    test.i2c.assert_done();
+    assert_eq!(test.apps.callbacks(0), vec![(0, 1, 0)]);
+    assert_eq!(test.apps.read(found, 1), vec![0x49]);
+}
@@ -0,0 +1,361 @@
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+
+mod common;
+
+use capsules::kv_store::{self, KVStore, KVStoreDriver};
+use host::{Apps, Host};
+use host::flash::{Flash, PAGE_SIZE};
+use kernel::{Container, Driver, ReturnCode};
+use kernel::hil::flash::Flash as HilFlash;
+use std::cell::RefCell;
+use std::path::PathBuf;
+
+const DRIVER_NUM: usize = 19;
+
+#[derive(PartialEq)]
+enum Event {
+    Mounted(ReturnCode),
+    Got(ReturnCode, Vec<u8>),
+    Set(ReturnCode),
+    Deleted(ReturnCode),
+}
+
+thread_local!(static EVENTS: RefCell<Vec<Event>> = RefCell::new(Vec::new()));
+
+fn last_event() -> Option<Event> {
+    EVENTS.with(|events| events.borrow_mut().drain(..).last())
+}
+
+struct Client;
+
+impl kv_store::Client for Client {
+    fn mount_complete(&self, result: ReturnCode) {
+        EVENTS.with(|events| events.borrow_mut().push(Event::Mounted(result)));
+    }
+
+    fn get_complete(&self, result: ReturnCode, value: &[u8]) {
+        EVENTS.with(|events| events.borrow_mut().push(Event::Got(result, value.to_vec())));
+    }
+
+    fn set_complete(&self, result: ReturnCode) {
+        EVENTS.with(|events| events.borrow_mut().push(Event::Set(result)));
+    }
+
+    fn delete_complete(&self, result: ReturnCode) {
+        EVENTS.with(|events| events.borrow_mut().push(Event::Deleted(result)));
+    }
+}
+
+struct Test {
+    host: Host,
+    path: PathBuf,
+    pages: usize,
+    flash: &'static Flash,
+    store: &'static KVStore<'static, Flash>,
+}
+
+/// A store of `pages` pages in a new flash file.
+fn setup(name: &str, pages: usize) -> Test {
+    boot(common::flash_file("kv_store", name), pages)
+}
+
+/// Starts a store on the flash file at `path`, as after a reset.
+fn boot(path: PathBuf, pages: usize) -> Test {
+    let flash: &'static Flash = host::leak(Flash::new(&path, pages * PAGE_SIZE));
+    let store: &'static KVStore<Flash> = host::leak(KVStore::new(flash,
+                                                                 0,
+                                                                 pages,
+                                                                 host::leak_buffer(PAGE_SIZE),
+                                                                 host::leak_buffer(PAGE_SIZE),
+                                                                 host::leak_buffer(64)));
+    flash.set_client(store);
+    store.set_client(host::leak(Client));
+
+    let host = Host::with_peripherals(&[flash]);
+    let mut test = Test {
+        host: host,
+        path: path,
+        pages: pages,
+        flash: flash,
+        store: store,
+    };
+    assert!(test.store.mount() == ReturnCode::SUCCESS);
+    test.host.run_until_idle();
+    assert!(last_event() == Some(Event::Mounted(ReturnCode::SUCCESS)));
+    test
+}
+
+fn reboot(test: Test) -> Test {
+    boot(test.path.clone(), test.pages)
+}
+
+fn get(test: &mut Test, namespace: u16, key: u32) -> Result<Vec<u8>, ReturnCode> {
+    let result = test.store.get(namespace, key);
+    if result != ReturnCode::SUCCESS {
+        return Err(result);
+    }
+    test.host.run_until_idle();
+    match last_event() {
+        Some(Event::Got(ReturnCode::SUCCESS, value)) => Ok(value),
+        Some(Event::Got(result, _)) => Err(result),
+        _ => panic!("get did not complete"),
+    }
+}
+
+fn set(test: &mut Test, namespace: u16, key: u32, value: &[u8]) -> ReturnCode {
+    assert!(test.store.set(namespace, key, value) == ReturnCode::SUCCESS);
+    test.host.run_until_idle();
+    match last_event() {
+        Some(Event::Set(result)) => result,
+        _ => panic!("set did not complete"),
+    }
+}
+
+fn delete(test: &mut Test, namespace: u16, key: u32) -> ReturnCode {
+    assert!(test.store.delete(namespace, key) == ReturnCode::SUCCESS);
+    test.host.run_until_idle();
+    match last_event() {
+        Some(Event::Deleted(result)) => result,
+        _ => panic!("delete did not complete"),
+    }
+}
+
+#[test]
+fn set_get_and_delete() {
+    let mut test = setup("set_get_and_delete", 4);
+    assert!(get(&mut test, 1, 7) == Err(ReturnCode::ENOSUPPORT));
+
+    assert!(set(&mut test, 1, 7, b"seven") == ReturnCode::SUCCESS);
+    assert!(set(&mut test, 1, 8, b"eight") == ReturnCode::SUCCESS);
+    assert!(set(&mut test, 1, 7, b"SEVEN") == ReturnCode::SUCCESS);
+    assert!(get(&mut test, 1, 7) == Ok(b"SEVEN".to_vec()));
+    assert!(get(&mut test, 1, 8) == Ok(b"eight".to_vec()));
+
+    assert!(delete(&mut test, 1, 7) == ReturnCode::SUCCESS);
+    assert!(get(&mut test, 1, 7) == Err(ReturnCode::ENOSUPPORT));
+    assert!(get(&mut test, 1, 8) == Ok(b"eight".to_vec()));
+
+    assert!(test.store.set(1, 9, &[0; 65]) == ReturnCode::ESIZE);
+}
+
+#[test]
+fn namespaces_are_separate() {
+    let mut test = setup("namespaces_are_separate", 4);
+    assert!(set(&mut test, 1, 7, b"one") == ReturnCode::SUCCESS);
+    assert!(set(&mut test, 2, 7, b"two") == ReturnCode::SUCCESS);
+    assert!(delete(&mut test, 3, 7) == ReturnCode::SUCCESS);
+
+    assert!(get(&mut test, 1, 7) == Ok(b"one".to_vec()));
+    assert!(get(&mut test, 2, 7) == Ok(b"two".to_vec()));
+    assert!(get(&mut test, 3, 7) == Err(ReturnCode::ENOSUPPORT));
+}
+
+#[test]
+fn values_survive_reboot() {
+    let mut test = setup("values_survive_reboot", 4);
+    assert!(set(&mut test, 1, 7, b"seven") == ReturnCode::SUCCESS);
+    assert!(set(&mut test, 1, 8, b"eight") == ReturnCode::SUCCESS);
+    assert!(delete(&mut test, 1, 8) == ReturnCode::SUCCESS);
+
+    let mut test = reboot(test);
+    assert!(get(&mut test, 1, 7) == Ok(b"seven".to_vec()));
+    assert!(get(&mut test, 1, 8) == Err(ReturnCode::ENOSUPPORT));
+}
+
+#[test]
+fn repeated_sets_are_collected() {
+    let mut test = setup("repeated_sets_are_collected", 4);
+    assert!(set(&mut test, 1, 100, b"kept") == ReturnCode::SUCCESS);
+    // Many pages' worth of records
+    for i in 0..200u32 {
+        let value = [i as u8; 60];
+        assert!(set(&mut test, 1, i % 3, &value) == ReturnCode::SUCCESS);
+    }
+
+    let mut test = reboot(test);
+    assert!(get(&mut test, 1, 100) == Ok(b"kept".to_vec()));
+    for key in 0..3 {
+        let last = (197..200).find(|i| i % 3 == key).unwrap();
+        assert!(get(&mut test, 1, key) == Ok(vec![last as u8; 60]));
+    }
+}
+
+#[test]
+fn full_store_reports_enomem() {
+    let mut test = setup("full_store_reports_enomem", 3);
+    let value = [0x5a; 60];
+    let mut stored = 0;
+    while set(&mut test, 1, stored, &value) == ReturnCode::SUCCESS {
+        stored += 1;
+        assert!(stored < 100);
+    }
+    // Two of the pages are full, and one is kept erased
+    assert!(stored >= 12);
+
+    for key in 0..stored {
+        assert!(get(&mut test, 1, key) == Ok(value.to_vec()));
+    }
+    // A smaller record still fits in the newest page
+    assert!(set(&mut test, 1, stored - 1, b"small") == ReturnCode::SUCCESS);
+}
+
+#[test]
+fn damaged_record_falls_back_to_older_value() {
+    let mut test = setup("damaged_record_falls_back_to_older_value", 4);
+    assert!(set(&mut test, 1, 7, b"first value") == ReturnCode::SUCCESS);
+    assert!(set(&mut test, 1, 7, b"second value") == ReturnCode::SUCCESS);
+
+    let mut contents = common::read_flash_file(&test.path);
+    let at = contents.windows(12).position(|window| window == b"second value").unwrap();
+    contents[at] ^= 0x01;
+    common::write_flash_file(&test.path, &contents);
+
+    let mut test = reboot(test);
+    assert!(get(&mut test, 1, 7) == Ok(b"first value".to_vec()));
+}
+
+#[test]
+fn power_loss_while_writing_keeps_old_value() {
+    let mut test = setup("power_loss_while_writing_keeps_old_value", 4);
+    assert!(set(&mut test, 1, 7, b"old") == ReturnCode::SUCCESS);
+
+    test.flash.cut_power_after(0);
+    assert!(test.store.set(1, 7, b"new") == ReturnCode::SUCCESS);
+    test.host.run_until_idle();
+    assert!(last_event().is_none());
+
+    let mut test = reboot(test);
+    assert!(get(&mut test, 1, 7) == Ok(b"old".to_vec()));
+    assert!(set(&mut test, 1, 7, b"newer") == ReturnCode::SUCCESS);
+    assert!(get(&mut test, 1, 7) == Ok(b"newer".to_vec()));
+}
+
+#[test]
+fn sets_are_appended_to_the_newest_page() {
+    let mut test = setup("sets_are_appended_to_the_newest_page", 4);
+    for key in 0..5 {
+        assert!(set(&mut test, 1, key, b"value") == ReturnCode::SUCCESS);
+    }
+    let contents = common::read_flash_file(&test.path);
+    let used = contents.chunks(PAGE_SIZE).filter(|page| page.iter().any(|byte| *byte != 0xff));
+    assert_eq!(used.count(), 1);
+}
+
+#[test]
+fn power_loss_at_any_point_keeps_committed_values() {
+    for cut in 0..40 {
+        let mut test = setup(&format!("power_loss_at_any_point-{}", cut), 3);
+        test.flash.cut_power_after(cut);
+        // The last value committed for each key, and the set cut short
+        let mut committed = [None; 4];
+        let mut torn = None;
+        for i in 0..30u32 {
+            assert!(test.store.set(1, i % 4, &[i as u8; 40]) == ReturnCode::SUCCESS);
+            test.host.run_until_idle();
+            match last_event() {
+                Some(Event::Set(ReturnCode::SUCCESS)) => committed[(i % 4) as usize] = Some(i),
+                None => {
+                    torn = Some(i);
+                    break;
+                }
+                _ => panic!("set failed"),
+            }
+        }
+
+        let mut test = reboot(test);
+        for key in 0..4u32 {
+            let found = get(&mut test, 1, key).ok();
+            let old = committed[key as usize].map(|i| vec![i as u8; 40]);
+            let new = torn.and_then(|i| if i % 4 == key { Some(vec![i as u8; 40]) } else { None });
+            assert!(found == old || (found.is_some() && found == new),
+                    "power cut after {} operations lost key {}",
+                    cut,
+                    key);
+        }
+    }
+}
+
+/// Namespaces given out by package name.
+static NAMESPACES: [(&'static str, u32); 2] = [("first", 1), ("second", 2)];
+
+/// A driver over a store on the flash file at `path`, as after a reset.
+fn boot_driver(path: &PathBuf) -> (Host, &'static KVStoreDriver<'static, Flash>) {
+    let flash: &'static Flash = host::leak(Flash::new(path, 4 * PAGE_SIZE));
+    let store: &'static KVStore<Flash> = host::leak(KVStore::new(flash,
+                                                                 0,
+                                                                 4,
+                                                                 host::leak_buffer(PAGE_SIZE),
+                                                                 host::leak_buffer(PAGE_SIZE),
+                                                                 host::leak_buffer(64)));
+    let driver: &'static KVStoreDriver<Flash> =
+        host::leak(KVStoreDriver::new(store, &NAMESPACES, unsafe { Container::create() }));
+    flash.set_client(store);
+    store.set_client(driver);
+
+    let mut host = Host::with_peripherals(&[flash]);
+    assert_eq!(store.mount(), ReturnCode::SUCCESS);
+    host.run_until_idle();
+    (host, driver)
+}
+
+fn driver_set(host: &mut Host,
+              driver: &KVStoreDriver<Flash>,
+              apps: &Apps,
+              app: usize,
+              key: usize,
+              value: &[u8]) {
+    let buffer = apps.read_only_buffer(app, value);
+    assert_eq!(driver.allow_readonly(apps.id(app), 1, buffer), ReturnCode::SUCCESS);
+    assert_eq!(driver.subscribe(0, apps.callback(app, DRIVER_NUM, 0)),
+               ReturnCode::SUCCESS);
+    assert_eq!(driver.command(2, key, apps.id(app)), ReturnCode::SUCCESS);
+    host.run_until_idle();
+    assert_eq!(apps.callbacks(app), vec![(0, 0, 0)]);
+}
+
+fn driver_get(host: &mut Host,
+              driver: &KVStoreDriver<Flash>,
+              apps: &Apps,
+              app: usize,
+              key: usize)
+              -> Vec<u8> {
+    let buffer = apps.buffer(app, b"", 16);
+    let address = buffer.as_ref().as_ptr();
+    assert_eq!(driver.allow(apps.id(app), 0, buffer), ReturnCode::SUCCESS);
+    assert_eq!(driver.subscribe(0, apps.callback(app, DRIVER_NUM, 0)),
+               ReturnCode::SUCCESS);
+    assert_eq!(driver.command(1, key, apps.id(app)), ReturnCode::SUCCESS);
+    host.run_until_idle();
+    let callbacks = apps.callbacks(app);
+    assert_eq!(callbacks.len(), 1);
+    assert_eq!(callbacks[0].0, 0);
+    apps.read(address, callbacks[0].1)
+}
+
+#[test]
+fn apps_keep_their_keys_when_loaded_in_another_order() {
+    let path = common::flash_file("kv_store", "driver");
+    {
+        let (mut host, driver) = boot_driver(&path);
+        let apps = Apps::load(&["first", "second"]);
+        driver_set(&mut host, driver, &apps, 0, 7, b"one");
+        driver_set(&mut host, driver, &apps, 1, 7, b"two");
+    }
+
+    let (mut host, driver) = boot_driver(&path);
+    let apps = Apps::load(&["second", "first"]);
+    assert_eq!(driver_get(&mut host, driver, &apps, 1, 7), b"one".to_vec());
+    assert_eq!(driver_get(&mut host, driver, &apps, 0, 7), b"two".to_vec());
+}
+
+#[test]
+fn unlisted_and_impersonating_apps_are_refused() {
+    let (mut host, driver) = boot_driver(&common::flash_file("kv_store", "refused"));
+    let apps = Apps::load(&["first", "stranger", "second", "second"]);
+    driver_set(&mut host, driver, &apps, 0, 7, b"one");
+    for app in 1..4 {
+        assert_eq!(driver.command(1, 7, apps.id(app)), ReturnCode::ENOACCESS);
+    }
+}
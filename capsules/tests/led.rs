@@ -0,0 +1,55 @@
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+
+use capsules::led::{ActivationMode, LED};
+use host::gpio::{Mode, Pin};
+use kernel::{AppId, Driver, ReturnCode};
+
+fn setup() -> (&'static LED<'static, Pin>, &'static Pin, &'static Pin) {
+    let high: &'static Pin = host::leak(Pin::new());
+    let low: &'static Pin = host::leak(Pin::new());
+    let pins: &'static [(&'static Pin, ActivationMode)] =
+        host::leak([(high, ActivationMode::ActiveHigh), (low, ActivationMode::ActiveLow)]);
+    let led: &'static LED<Pin> = host::leak(LED::new(pins));
+    (led, high, low)
+}
+
+#[test]
+fn leds_start_off() {
+    let (led, high, low) = setup();
+    assert_eq!(led.command(0, 0, AppId::new(0)),
+               ReturnCode::SuccessWithValue { value: 2 });
+    assert_eq!(high.mode(), Mode::Output);
+    assert_eq!(low.mode(), Mode::Output);
+    assert!(!high.level());
+    assert!(low.level());
+}
+
+#[test]
+fn on_off_and_toggle_follow_the_activation_mode() {
+    let (led, high, low) = setup();
+    assert_eq!(led.command(1, 0, AppId::new(0)), ReturnCode::SUCCESS);
+    assert_eq!(led.command(1, 1, AppId::new(0)), ReturnCode::SUCCESS);
+    assert!(high.level());
+    assert!(!low.level());
+
+    assert_eq!(led.command(2, 0, AppId::new(0)), ReturnCode::SUCCESS);
+    assert_eq!(led.command(2, 1, AppId::new(0)), ReturnCode::SUCCESS);
+    assert!(!high.level());
+    assert!(low.level());
+
+    assert_eq!(led.command(3, 1, AppId::new(0)), ReturnCode::SUCCESS);
+    assert!(!low.level());
+}
+
+#[test]
+fn unknown_led_is_rejected() {
+    let (led, high, low) = setup();
+    for command in 1..4 {
+        assert_eq!(led.command(command, 2, AppId::new(0)), ReturnCode::EINVAL);
+    }
+    assert_eq!(led.command(4, 0, AppId::new(0)), ReturnCode::ENOSUPPORT);
+    assert!(!high.level());
+    assert!(low.level());
+}
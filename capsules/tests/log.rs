@@ -0,0 +1,248 @@
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+
+mod common;
+
+use capsules::log::{Log, LogClient, LogStorage};
+use host::Host;
+use host::flash::{Flash, PAGE_SIZE};
+use kernel::ReturnCode;
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::flash::Flash as HilFlash;
+use std::cell::RefCell;
+use std::path::PathBuf;
+
+#[derive(PartialEq)]
+enum Event {
+    Mounted(ReturnCode),
+    Appended(usize, ReturnCode),
+    Read(Vec<u8>, usize, ReturnCode),
+}
+
+struct Client {
+    events: RefCell<Vec<Event>>,
+    buffer: TakeCell<'static, [u8]>,
+}
+
+impl Client {
+    fn last_event(&self) -> Option<Event> {
+        self.events.borrow_mut().drain(..).last()
+    }
+}
+
+impl LogClient for Client {
+    fn mount_done(&self, result: ReturnCode) {
+        self.events.borrow_mut().push(Event::Mounted(result));
+    }
+
+    fn append_done(&self, buffer: &'static mut [u8], id: usize, result: ReturnCode) {
+        self.buffer.replace(buffer);
+        self.events.borrow_mut().push(Event::Appended(id, result));
+    }
+
+    fn read_done(&self, buffer: &'static mut [u8], len: usize, id: usize, result: ReturnCode) {
+        self.events.borrow_mut().push(Event::Read(buffer[..len].to_vec(), id, result));
+        self.buffer.replace(buffer);
+    }
+}
+
+struct Test {
+    host: Host,
+    path: PathBuf,
+    pages: usize,
+    flash: &'static Flash,
+    log: &'static Log<'static, Flash>,
+    client: &'static Client,
+}
+
+/// A log of `pages` pages in a new flash file.
+fn setup(name: &str, pages: usize) -> Test {
+    boot(common::flash_file("log", name), pages)
+}
+
+/// Starts a log on the flash file at `path`, as after a reset.
+fn boot(path: PathBuf, pages: usize) -> Test {
+    let flash: &'static Flash = host::leak(Flash::new(&path, pages * PAGE_SIZE));
+    let log: &'static Log<Flash> = host::leak(Log::new(flash,
+                                                       0,
+                                                       pages,
+                                                       host::leak_buffer(PAGE_SIZE),
+                                                       host::leak_buffer(PAGE_SIZE)));
+    let client: &'static Client = host::leak(Client {
+        events: RefCell::new(Vec::new()),
+        buffer: TakeCell::new(host::leak_buffer(PAGE_SIZE)),
+    });
+    flash.set_client(log);
+    log.set_client(client);
+
+    let host = Host::with_peripherals(&[flash]);
+    let mut test = Test {
+        host: host,
+        path: path,
+        pages: pages,
+        flash: flash,
+        log: log,
+        client: client,
+    };
+    assert!(test.log.mount() == ReturnCode::SUCCESS);
+    test.host.run_until_idle();
+    assert!(test.client.last_event() == Some(Event::Mounted(ReturnCode::SUCCESS)));
+    test
+}
+
+fn reboot(test: Test) -> Test {
+    boot(test.path.clone(), test.pages)
+}
+
+/// Appends `data`, returning the ID of the new entry.
+fn append(test: &mut Test, data: &[u8]) -> Result<usize, ReturnCode> {
+    let buffer = test.client.buffer.take().unwrap();
+    buffer[..data.len()].copy_from_slice(data);
+    if let Err((result, buffer)) = test.log.append(buffer, data.len()) {
+        test.client.buffer.replace(buffer);
+        return Err(result);
+    }
+    test.host.run_until_idle();
+    match test.client.last_event() {
+        Some(Event::Appended(id, ReturnCode::SUCCESS)) => Ok(id),
+        Some(Event::Appended(_, result)) => Err(result),
+        _ => panic!("append did not complete"),
+    }
+}
+
+fn read(test: &mut Test, id: usize) -> Result<Vec<u8>, ReturnCode> {
+    let buffer = test.client.buffer.take().unwrap();
+    if let Err((result, buffer)) = test.log.read(id, buffer) {
+        test.client.buffer.replace(buffer);
+        return Err(result);
+    }
+    test.host.run_until_idle();
+    match test.client.last_event() {
+        Some(Event::Read(data, read_id, ReturnCode::SUCCESS)) => {
+            assert_eq!(read_id, id);
+            Ok(data)
+        }
+        Some(Event::Read(_, _, result)) => Err(result),
+        _ => panic!("read did not complete"),
+    }
+}
+
+fn entry(id: usize) -> Vec<u8> {
+    format!("reading {}", id).into_bytes()
+}
+
+#[test]
+fn entries_are_read_in_both_directions() {
+    let mut test = setup("entries_are_read_in_both_directions", 4);
+    assert_eq!(test.log.oldest_id(), 1);
+    assert_eq!(test.log.next_id(), 1);
+    assert!(read(&mut test, 1) == Err(ReturnCode::EINVAL));
+
+    for id in 1..11 {
+        assert!(append(&mut test, &entry(id)) == Ok(id));
+    }
+    assert!(append(&mut test, &[]) == Ok(11));
+    assert_eq!(test.log.oldest_id(), 1);
+    assert_eq!(test.log.next_id(), 12);
+
+    for id in 1..11 {
+        assert!(read(&mut test, id) == Ok(entry(id)));
+    }
+    for id in (1..11).rev() {
+        assert!(read(&mut test, id) == Ok(entry(id)));
+    }
+    assert!(read(&mut test, 11) == Ok(Vec::new()));
+    assert!(read(&mut test, 12) == Err(ReturnCode::EINVAL));
+}
+
+#[test]
+fn entries_span_pages_and_survive_reboot() {
+    let mut test = setup("entries_span_pages_and_survive_reboot", 4);
+    // Three entries fill a page
+    for id in 1..8 {
+        assert!(append(&mut test, &vec![id as u8; 150]) == Ok(id));
+    }
+
+    let mut test = reboot(test);
+    assert_eq!(test.log.oldest_id(), 1);
+    assert_eq!(test.log.next_id(), 8);
+    for id in 1..8 {
+        assert!(read(&mut test, id) == Ok(vec![id as u8; 150]));
+    }
+    assert!(append(&mut test, b"after reboot") == Ok(8));
+    assert!(read(&mut test, 8) == Ok(b"after reboot".to_vec()));
+}
+
+#[test]
+fn oldest_page_is_overwritten_when_full() {
+    let mut test = setup("oldest_page_is_overwritten_when_full", 3);
+    // Three pages of three entries, then two more pages' worth
+    for id in 1..16 {
+        assert!(append(&mut test, &vec![id as u8; 150]) == Ok(id));
+    }
+    assert_eq!(test.log.oldest_id(), 7);
+    assert_eq!(test.log.next_id(), 16);
+    assert!(read(&mut test, 6) == Err(ReturnCode::EINVAL));
+    for id in 7..16 {
+        assert!(read(&mut test, id) == Ok(vec![id as u8; 150]));
+    }
+
+    let mut test = reboot(test);
+    assert_eq!(test.log.oldest_id(), 7);
+    assert!(append(&mut test, b"next") == Ok(16));
+}
+
+#[test]
+fn oversized_entries_are_rejected() {
+    let mut test = setup("oversized_entries_are_rejected", 3);
+    assert!(append(&mut test, &[0; PAGE_SIZE - 18]) == Ok(1));
+    assert!(append(&mut test, &[0; PAGE_SIZE - 17]) == Err(ReturnCode::ESIZE));
+    assert_eq!(test.log.next_id(), 2);
+}
+
+#[test]
+fn power_loss_during_append_keeps_earlier_entries() {
+    let mut test = setup("power_loss_during_append_keeps_earlier_entries", 4);
+    // The first entry reaches past the middle of the page, so rewriting the
+    // page would risk it
+    assert!(append(&mut test, &[1; 300]) == Ok(1));
+
+    test.flash.cut_power_after(0);
+    let buffer = test.client.buffer.take().unwrap();
+    assert!(test.log.append(buffer, 100).is_ok());
+    test.host.run_until_idle();
+    assert!(test.client.last_event().is_none());
+
+    let mut test = reboot(test);
+    assert_eq!(test.log.next_id(), 2);
+    assert!(read(&mut test, 1) == Ok(vec![1; 300]));
+    // The torn entry's page cannot be added to, so the log moves on
+    assert!(append(&mut test, b"after") == Ok(2));
+    assert!(append(&mut test, b"and more") == Ok(3));
+
+    let mut test = reboot(test);
+    assert!(read(&mut test, 1) == Ok(vec![1; 300]));
+    assert!(read(&mut test, 2) == Ok(b"after".to_vec()));
+    assert!(read(&mut test, 3) == Ok(b"and more".to_vec()));
+}
+
+#[test]
+fn power_loss_starting_a_page_loses_only_that_entry() {
+    let mut test = setup("power_loss_starting_a_page_loses_only_that_entry", 4);
+    for id in 1..4 {
+        assert!(append(&mut test, &vec![id as u8; 150]) == Ok(id));
+    }
+
+    // The fourth entry starts the second page, and is torn
+    test.flash.cut_power_after(0);
+    let buffer = test.client.buffer.take().unwrap();
+    assert!(test.log.append(buffer, 300).is_ok());
+    test.host.run_until_idle();
+
+    let mut test = reboot(test);
+    assert_eq!(test.log.next_id(), 4);
+    assert!(append(&mut test, b"four") == Ok(4));
+    assert!(read(&mut test, 3) == Ok(vec![3; 150]));
+    assert!(read(&mut test, 4) == Ok(b"four".to_vec()));
+}
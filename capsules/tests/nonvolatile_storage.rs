@@ -0,0 +1,169 @@
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+
+mod common;
+
+use capsules::nonvolatile_storage::NonvolatileStorage;
+use host::{Apps, Host};
+use host::flash::{Flash, PAGE_SIZE};
+use kernel::{Chip, Container, Driver, ReturnCode};
+use kernel::hil::flash::Flash as HilFlash;
+use std::path::PathBuf;
+
+const DRIVER_NUM: usize = 18;
+const REGION_LEN: usize = 4 * PAGE_SIZE;
+
+/// Regions given out by package name, in the opposite order to the apps in
+/// `setup`.
+static REGIONS: [(&'static str, usize); 2] = [("first", 1), ("second", 0)];
+
+struct Test {
+    host: Host,
+    path: PathBuf,
+    apps: Apps,
+    storage: &'static NonvolatileStorage<'static, Flash>,
+}
+
+/// Storage for apps `first` and `second` in a new flash file.
+fn setup(name: &str) -> Test {
+    boot(common::flash_file("nonvolatile_storage", name), &["first", "second"])
+}
+
+/// Starts storage on the flash file at `path` and loads `apps`, as after a
+/// reset.
+fn boot(path: PathBuf, apps: &[&str]) -> Test {
+    let flash: &'static Flash = host::leak(Flash::new(&path, 2 * REGION_LEN));
+    let storage: &'static NonvolatileStorage<Flash> =
+        host::leak(NonvolatileStorage::new(flash,
+                                           0,
+                                           2 * REGION_LEN,
+                                           REGION_LEN,
+                                           &REGIONS,
+                                           host::leak_buffer(PAGE_SIZE),
+                                           unsafe { Container::create() }));
+    flash.set_client(storage);
+    let host = Host::with_peripherals(&[flash]);
+    let test = Test {
+        host: host,
+        path: path,
+        apps: Apps::load(apps),
+        storage: storage,
+    };
+    let app_ids: Vec<usize> = (0..apps.len()).collect();
+    common::subscribe(test.storage, DRIVER_NUM, &test.apps, &app_ids, &[0, 1]);
+    test
+}
+
+fn write(test: &mut Test, app: usize, offset: usize, data: &[u8]) {
+    let buffer = test.apps.read_only_buffer(app, data);
+    assert_eq!(test.storage.allow_readonly(test.apps.id(app), 1, buffer),
+               ReturnCode::SUCCESS);
+    assert_eq!(test.storage.command(3, offset, test.apps.id(app)),
+               ReturnCode::SUCCESS);
+    test.host.run_until_idle();
+    assert_eq!(test.apps.callbacks(app), vec![(0, data.len(), 0)]);
+}
+
+/// Reads `len` bytes at `offset` into a new read buffer, returning its
+/// address.
+fn start_read(test: &Test, app: usize, offset: usize, len: usize) -> *const u8 {
+    let buffer = test.apps.buffer(app, b"", len);
+    let address = buffer.as_ref().as_ptr();
+    assert_eq!(test.storage.allow(test.apps.id(app), 0, buffer),
+               ReturnCode::SUCCESS);
+    assert_eq!(test.storage.command(2, offset, test.apps.id(app)),
+               ReturnCode::SUCCESS);
+    address
+}
+
+#[test]
+fn apps_have_separate_regions() {
+    let mut test = setup("separate");
+    write(&mut test, 0, 10, b"first");
+    write(&mut test, 1, 10, b"second");
+
+    let address = start_read(&test, 0, 10, 6);
+    test.host.run_until_idle();
+    assert_eq!(test.apps.callbacks(0), vec![(0, 6, 0)]);
+    assert_eq!(test.apps.read(address, 6), b"first\xff".to_vec());
+    assert_eq!(test.storage.command(1, 0, test.apps.id(1)),
+               ReturnCode::SuccessWithValue { value: REGION_LEN });
+}
+
+#[test]
+fn regions_stay_with_the_app_when_loaded_in_another_order() {
+    let mut test = setup("order");
+    write(&mut test, 0, 0, b"first");
+    write(&mut test, 1, 0, b"second");
+    let path = test.path.clone();
+    drop(test);
+
+    let mut test = boot(path, &["second", "first"]);
+    let address = start_read(&test, 0, 0, 6);
+    test.host.run_until_idle();
+    assert_eq!(test.apps.callbacks(0), vec![(0, 6, 0)]);
+    assert_eq!(test.apps.read(address, 6), b"second".to_vec());
+    let address = start_read(&test, 1, 0, 5);
+    test.host.run_until_idle();
+    assert_eq!(test.apps.read(address, 5), b"first".to_vec());
+}
+
+#[test]
+fn unlisted_and_impersonating_apps_have_no_region() {
+    let test = boot(common::flash_file("nonvolatile_storage", "unlisted"),
+                    &["first", "stranger", "first"]);
+    for app in 0..3 {
+        assert_eq!(test.storage.command(1, 0, test.apps.id(app)),
+                   ReturnCode::ENOACCESS);
+        let buffer = test.apps.buffer(app, b"", 4);
+        assert_eq!(test.storage.allow(test.apps.id(app), 0, buffer),
+                   ReturnCode::SUCCESS);
+        assert_eq!(test.storage.command(2, 0, test.apps.id(app)),
+                   ReturnCode::ENOACCESS);
+    }
+}
+
+#[test]
+fn write_across_pages_keeps_neighbouring_bytes() {
+    let mut test = setup("across");
+    write(&mut test, 0, 0, &vec![1; PAGE_SIZE + 20]);
+    write(&mut test, 0, PAGE_SIZE - 10, &[2; 20]);
+
+    let address = start_read(&test, 0, PAGE_SIZE - 20, 40);
+    test.host.run_until_idle();
+    let mut expected = vec![1; 10];
+    expected.extend_from_slice(&[2; 20]);
+    expected.extend_from_slice(&[1; 10]);
+    assert_eq!(test.apps.read(address, 40), expected);
+}
+
+#[test]
+fn request_outside_the_region_is_rejected() {
+    let test = setup("outside");
+    let buffer = test.apps.buffer(0, b"", 11);
+    assert_eq!(test.storage.allow(test.apps.id(0), 0, buffer),
+               ReturnCode::SUCCESS);
+    assert_eq!(test.storage.command(2, REGION_LEN - 10, test.apps.id(0)),
+               ReturnCode::EINVAL);
+    assert_eq!(test.storage.command(3, 0, test.apps.id(0)), ReturnCode::EINVAL);
+}
+
+#[test]
+fn revoking_the_buffer_stops_the_read() {
+    let mut test = setup("revoke");
+    start_read(&test, 0, 0, 3 * PAGE_SIZE);
+    // Let the first page through
+    test.host.service_pending_interrupts();
+
+    let empty = test.apps.buffer(0, b"", 0);
+    match test.storage.allow(test.apps.id(0), 0, empty) {
+        ReturnCode::SuccessWithBuffer { len, .. } => assert_eq!(len, 3 * PAGE_SIZE),
+        result => panic!("revoke returned {:?}", result),
+    }
+    test.host.run_until_idle();
+    assert_eq!(test.apps.callbacks(0), vec![(-6isize as usize, PAGE_SIZE, 0)]);
+
+    // The storage serves the next request
+    write(&mut test, 0, 0, b"more");
+}
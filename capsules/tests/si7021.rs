@@ -0,0 +1,118 @@
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+
+mod common;
+
+use capsules::si7021::SI7021;
+use host::{Host, mock};
+use kernel::{AppId, Driver, ReturnCode};
+use kernel::hil::time::{Alarm, Time};
+
+struct Test {
+    host: Host,
+    i2c: &'static mock::i2c::Device,
+    alarm: &'static mock::Alarm,
+    si7021: &'static SI7021<'static, mock::Alarm>,
+}
+
+fn setup() -> Test {
+    let i2c: &'static mock::i2c::Device = host::leak(mock::i2c::Device::new());
+    let alarm: &'static mock::Alarm = host::leak(mock::Alarm::new());
+    let si7021: &'static SI7021<mock::Alarm> =
+        host::leak(SI7021::new(i2c, alarm, host::leak_buffer(14)));
+    i2c.set_client(si7021);
+    alarm.set_client(si7021);
+
+    let host = Host::with_peripherals(&[i2c, alarm]);
+    Test {
+        host: host,
+        i2c: i2c,
+        alarm: alarm,
+        si7021: si7021,
+    }
+}
+
+/// Starts a measurement and runs until the sensor is waiting out its
+/// conversion time.
+fn start_measurement(test: &mut Test) {
+    assert!(test.si7021.subscribe(0, common::callback()) == ReturnCode::SUCCESS);
+
+    // Measure relative humidity, no hold master mode
+    test.i2c.expect_write(&[0xf5]);
+    assert!(test.si7021.command(1, 0, AppId::kernel_new(100)) == ReturnCode::SUCCESS);
+    test.host.run_until_idle();
+    test.i2c.assert_done();
+}
+
+#[test]
+fn measurement_waits_for_conversion() {
+    let mut test = setup();
+    start_measurement(&mut test);
+
+    // 20ms on the 32kHz clock
+    assert!(test.alarm.is_armed());
+    assert_eq!(test.alarm.get_alarm(), 655);
+    assert!(!test.i2c.is_enabled());
+
+    // Reading before the alarm fires would fail the script
+    test.alarm.advance(654);
+    test.host.run_until_idle();
+    assert!(common::calls().is_empty());
+}
+
+#[test]
+fn measurement_reports_temperature_and_humidity() {
+    let mut test = setup();
+    start_measurement(&mut test);
+
+    test.i2c.expect_read(&[0x80, 0x00]);
+    // Read temperature from the previous humidity measurement
+    test.i2c.expect_write(&[0xe0]);
+    test.i2c.expect_read(&[0x66, 0x66]);
+    test.alarm.advance_to_alarm();
+    test.host.run_until_idle();
+    test.i2c.assert_done();
+
+    // 23.43C and 56.50%RH
+    assert_eq!(common::calls(), vec![(2343, 5650, 0)]);
+    assert!(!test.i2c.is_enabled());
+}
+
+#[test]
+fn measurement_below_freezing() {
+    let mut test = setup();
+    start_measurement(&mut test);
+
+    test.i2c.expect_read(&[0x80, 0x00]);
+    test.i2c.expect_write(&[0xe0]);
+    test.i2c.expect_read(&[0x35, 0xb0]);
+    test.alarm.advance_to_alarm();
+    test.host.run_until_idle();
+
+    let calls = common::calls();
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0].0 as i16, -1000);
+}
+
+#[test]
+fn read_id() {
+    let mut test = setup();
+
+    test.i2c.expect_write(&[0xfa, 0x0f]);
+    test.i2c.expect_read(&[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]);
+    test.i2c.expect_write(&[0xfc, 0xc9]);
+    test.i2c.expect_read(&[0x15, 0xff, 0x01, 0x02, 0x03, 0x04]);
+    test.si7021.read_id();
+    test.host.run_until_idle();
+    test.i2c.assert_done();
+    assert!(!test.i2c.is_enabled());
+}
+
+#[test]
+fn unknown_command() {
+    let test = setup();
+    assert!(test.si7021.command(0, 0, AppId::kernel_new(100)) == ReturnCode::SUCCESS);
+    assert!(test.si7021.command(2, 0, AppId::kernel_new(100)) == ReturnCode::ENOSUPPORT);
+    assert!(test.si7021.subscribe(1, common::callback()) == ReturnCode::ENOSUPPORT);
+}
@@ -0,0 +1,153 @@
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+
+mod common;
+
+use capsules::spi::{self, Spi};
+use capsules::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
+use host::{Apps, Host, mock};
+use kernel::{Container, Driver, ReturnCode};
+use kernel::hil::spi::{ClockPhase, ClockPolarity, SpiMaster};
+
+const DRIVER_NUM: usize = 4;
+
+static CHIP_SELECTS: [u8; 2] = [3, 5];
+
+const DEFAULT_CLOCK: (ClockPolarity, ClockPhase, u32) =
+    (ClockPolarity::IdleLow, ClockPhase::SampleLeading, spi::DEFAULT_RATE);
+
+struct Test {
+    host: Host,
+    master: &'static mock::spi::Master,
+    spi: &'static Spi<'static, mock::spi::Master>,
+    apps: Apps,
+}
+
+/// The driver, with four byte kernel buffers, for two apps.
+fn setup() -> Test {
+    let master: &'static mock::spi::Master = host::leak(mock::spi::Master::new());
+    let mux: &'static MuxSpiMaster<mock::spi::Master> = host::leak(MuxSpiMaster::new(master));
+    master.set_client(mux);
+    let device: &'static VirtualSpiMasterDevice<mock::spi::Master> =
+        host::leak(VirtualSpiMasterDevice::new(mux, 0));
+    let spi: &'static mut Spi<mock::spi::Master> =
+        host::leak(Spi::new(device, &CHIP_SELECTS, unsafe { Container::create() }));
+    spi.config_buffers(host::leak_buffer(4), host::leak_buffer(4));
+    let spi: &'static Spi<mock::spi::Master> = spi;
+    device.set_client(spi);
+
+    let apps = Apps::load(&["first", "second"]);
+    common::subscribe(spi, DRIVER_NUM, &apps, &[0, 1], &[0]);
+    Test {
+        host: Host::with_peripherals(&[master]),
+        master: master,
+        spi: spi,
+        apps: apps,
+    }
+}
+
+impl Test {
+    fn allow_write(&self, app: usize, data: &[u8]) {
+        let buffer = self.apps.buffer(app, data, data.len());
+        assert_eq!(self.spi.allow(self.apps.id(app), 1, buffer), ReturnCode::SUCCESS);
+    }
+
+    /// Allows a read buffer of `len` bytes and returns its address.
+    fn allow_read(&self, app: usize, len: usize) -> *const u8 {
+        let buffer = self.apps.buffer(app, b"", len);
+        let address = buffer.as_ref().as_ptr();
+        assert_eq!(self.spi.allow(self.apps.id(app), 0, buffer), ReturnCode::SUCCESS);
+        address
+    }
+
+    fn revoke_write(&self, app: usize) {
+        let buffer = self.apps.buffer(app, b"", 0);
+        self.spi.allow(self.apps.id(app), 1, buffer);
+    }
+
+    fn command(&self, app: usize, command_num: usize, data: usize) -> ReturnCode {
+        self.spi.command(command_num, data, self.apps.id(app))
+    }
+}
+
+#[test]
+fn apps_keep_their_own_settings() {
+    let mut test = setup();
+    assert_eq!(test.command(0, 3, 1), ReturnCode::SUCCESS);
+    assert_eq!(test.command(0, 5, 400000), ReturnCode::SUCCESS);
+    assert_eq!(test.command(0, 9, 1), ReturnCode::SUCCESS);
+    assert_eq!(test.command(0, 3, 2), ReturnCode::EINVAL);
+    assert_eq!(test.command(0, 4, 0), ReturnCode::SuccessWithValue { value: 1 });
+    assert_eq!(test.command(0, 6, 0),
+               ReturnCode::SuccessWithValue { value: 400000 });
+    // The other app still has the defaults
+    assert_eq!(test.command(1, 4, 0), ReturnCode::SuccessWithValue { value: 0 });
+    assert_eq!(test.command(1, 6, 0),
+               ReturnCode::SuccessWithValue { value: spi::DEFAULT_RATE as usize });
+    assert_eq!(test.command(1, 10, 0), ReturnCode::SuccessWithValue { value: 0 });
+
+    test.allow_write(0, &[1, 2]);
+    test.allow_write(1, &[3, 4]);
+    test.master.expect_transfer(5,
+                                (ClockPolarity::IdleHigh, ClockPhase::SampleLeading, 400000),
+                                &[1, 2],
+                                &[]);
+    test.master.expect_transfer(3, DEFAULT_CLOCK, &[3, 4], &[]);
+    assert_eq!(test.command(0, 2, 2), ReturnCode::SUCCESS);
+    assert_eq!(test.command(1, 2, 2), ReturnCode::SUCCESS);
+    test.host.run_until_idle();
+    test.master.assert_done();
+    assert_eq!(test.apps.callbacks(0), vec![(2, 0, 0)]);
+    assert_eq!(test.apps.callbacks(1), vec![(2, 0, 0)]);
+}
+
+#[test]
+fn transfers_are_queued_between_apps() {
+    let mut test = setup();
+    test.allow_write(0, &[1, 2, 3, 4, 5, 6]);
+    test.allow_write(1, &[7, 8]);
+    let read = test.allow_read(1, 2);
+    // Longer than the kernel buffers, so it takes two transfers on the bus,
+    // and the other app's transfer waits for both
+    test.master.expect_transfer(3, DEFAULT_CLOCK, &[1, 2, 3, 4], &[]);
+    test.master.expect_transfer(3, DEFAULT_CLOCK, &[5, 6], &[]);
+    test.master.expect_transfer(3, DEFAULT_CLOCK, &[7, 8], &[9, 10]);
+    assert_eq!(test.command(0, 2, 6), ReturnCode::SUCCESS);
+    assert_eq!(test.command(1, 2, 2), ReturnCode::SUCCESS);
+    // Each app has one transfer at a time
+    assert_eq!(test.command(1, 2, 2), ReturnCode::EBUSY);
+
+    test.host.run_until_idle();
+    test.master.assert_done();
+    assert_eq!(test.apps.callbacks(0), vec![(6, 0, 0)]);
+    assert_eq!(test.apps.callbacks(1), vec![(2, 0, 0)]);
+    assert_eq!(test.apps.read(read, 2), vec![9, 10]);
+}
+
+#[test]
+fn revoking_a_buffer_cancels_the_transfer_with_an_error() {
+    let mut test = setup();
+    test.allow_write(0, &[1, 2, 3, 4, 5, 6]);
+    test.allow_write(1, &[7, 8]);
+    test.master.expect_transfer(3, DEFAULT_CLOCK, &[1, 2, 3, 4], &[]);
+    assert_eq!(test.command(0, 2, 6), ReturnCode::SUCCESS);
+    assert_eq!(test.command(1, 2, 2), ReturnCode::SUCCESS);
+    // One app revokes its buffer partway through its transfer, and the
+    // other before its transfer starts
+    test.revoke_write(0);
+    test.revoke_write(1);
+
+    test.host.run_until_idle();
+    test.master.assert_done();
+    assert_eq!(test.apps.callbacks(0), vec![(-6isize as usize, 4, 0)]);
+    assert_eq!(test.apps.callbacks(1), vec![(-6isize as usize, 0, 0)]);
+
+    // The kernel buffers were recovered
+    test.allow_write(0, &[1]);
+    test.master.expect_transfer(3, DEFAULT_CLOCK, &[1], &[]);
+    assert_eq!(test.command(0, 2, 1), ReturnCode::SUCCESS);
+    test.host.run_until_idle();
+    test.master.assert_done();
+    assert_eq!(test.apps.callbacks(0), vec![(1, 0, 0)]);
+}
@@ -0,0 +1,100 @@
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+
+mod common;
+
+use capsules::timer::TimerDriver;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use host::{Apps, Host, mock};
+use kernel::{Container, Driver, ReturnCode};
+use kernel::hil::time::Alarm;
+
+const DRIVER_NUM: usize = 3;
+
+struct Test {
+    host: Host,
+    apps: Apps,
+    hardware: &'static mock::Alarm,
+    timer: &'static TimerDriver<'static, VirtualMuxAlarm<'static, mock::Alarm>>,
+}
+
+fn setup() -> Test {
+    let hardware: &'static mock::Alarm = host::leak(mock::Alarm::new());
+    let mux: &'static MuxAlarm<mock::Alarm> = host::leak(MuxAlarm::new(hardware));
+    hardware.set_client(mux);
+    let alarm: &'static VirtualMuxAlarm<mock::Alarm> = host::leak(VirtualMuxAlarm::new(mux));
+    let timer: &'static TimerDriver<VirtualMuxAlarm<mock::Alarm>> =
+        host::leak(TimerDriver::new(alarm, unsafe { Container::create() }));
+    alarm.set_client(timer);
+
+    let host = Host::with_peripherals(&[hardware]);
+    let test = Test {
+        host: host,
+        apps: Apps::load(&["app"]),
+        hardware: hardware,
+        timer: timer,
+    };
+    common::subscribe(test.timer, DRIVER_NUM, &test.apps, &[0], &[0]);
+    test
+}
+
+/// The command argument for timer `id` and `value`.
+fn arg(id: usize, value: usize) -> usize {
+    id << 28 | value
+}
+
+/// Runs until the next alarm, returning the ids of the timers that fired.
+fn fire(test: &mut Test) -> Vec<usize> {
+    test.hardware.advance_to_alarm();
+    test.host.run_until_idle();
+    test.apps.callbacks(0).into_iter().map(|(_, id, _)| id).collect()
+}
+
+#[test]
+fn frequency_and_time() {
+    let test = setup();
+    assert_eq!(test.timer.command(5, 0, test.apps.id(0)),
+               ReturnCode::SuccessWithValue { value: 32768 });
+    test.hardware.advance(1000);
+    assert_eq!(test.timer.command(4, 0, test.apps.id(0)),
+               ReturnCode::SuccessWithValue { value: 1000 });
+}
+
+#[test]
+fn oneshot_fires_once() {
+    let mut test = setup();
+    assert_eq!(test.timer.command(1, arg(0, 10), test.apps.id(0)),
+               ReturnCode::SUCCESS);
+    assert_eq!(fire(&mut test), vec![0]);
+    // 10ms on the 32kHz clock
+    assert_eq!(test.hardware.now(), 327);
+    assert_eq!(fire(&mut test), Vec::<usize>::new());
+    // It is no longer armed to cancel
+    assert_eq!(test.timer.command(3, arg(0, 0), test.apps.id(0)),
+               ReturnCode::EINVAL);
+}
+
+#[test]
+fn timers_fire_in_deadline_order() {
+    let mut test = setup();
+    test.timer.command(2, arg(1, 10), test.apps.id(0));
+    test.timer.command(1, arg(2, 15), test.apps.id(0));
+    assert_eq!(fire(&mut test), vec![1]);
+    assert_eq!(fire(&mut test), vec![2]);
+    assert_eq!(fire(&mut test), vec![1]);
+
+    // Cancelling the repeating timer leaves nothing armed
+    assert_eq!(test.timer.command(3, arg(1, 0), test.apps.id(0)),
+               ReturnCode::SUCCESS);
+    assert_eq!(fire(&mut test), Vec::<usize>::new());
+}
+
+#[test]
+fn bad_timer_id_or_interval_is_rejected() {
+    let test = setup();
+    assert_eq!(test.timer.command(1, arg(4, 10), test.apps.id(0)),
+               ReturnCode::EINVAL);
+    assert_eq!(test.timer.command(1, arg(0, 0), test.apps.id(0)),
+               ReturnCode::EINVAL);
+}
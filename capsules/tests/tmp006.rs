@@ -0,0 +1,128 @@
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+
+mod common;
+
+use capsules::tmp006::TMP006;
+use host::{Host, mock};
+use host::gpio::Mode;
+use kernel::{AppId, Driver, ReturnCode};
+
+struct Test {
+    host: Host,
+    i2c: &'static mock::i2c::Device,
+    pin: &'static mock::Pin,
+    tmp006: &'static TMP006<'static>,
+}
+
+fn setup() -> Test {
+    let i2c: &'static mock::i2c::Device = host::leak(mock::i2c::Device::new());
+    let pin: &'static mock::Pin = host::leak(mock::Pin::new());
+    let tmp006: &'static TMP006 = host::leak(TMP006::new(i2c, pin, host::leak_buffer(3)));
+    i2c.set_client(tmp006);
+    pin.set_client(tmp006);
+
+    let host = Host::with_peripherals(&[i2c, pin]);
+    Test {
+        host: host,
+        i2c: i2c,
+        pin: pin,
+        tmp006: tmp006,
+    }
+}
+
+/// Signals that a conversion is ready, as the open-drain DRDY line does.
+fn data_ready(test: &mut Test) {
+    test.pin.drive(true);
+    test.pin.drive(false);
+    test.host.run_until_idle();
+}
+
+/// Expects the sensor voltage and die temperature to be read.
+fn expect_sample(test: &Test, sensor_voltage: i16, die_temperature: i16) {
+    test.i2c.expect_write(&[0x00]);
+    test.i2c.expect_read(&[(sensor_voltage >> 8) as u8, sensor_voltage as u8]);
+    test.i2c.expect_write(&[0x01]);
+    test.i2c.expect_read(&[(die_temperature >> 8) as u8, die_temperature as u8]);
+}
+
+#[test]
+fn single_reading() {
+    let mut test = setup();
+
+    // Enable conversions at the fastest rate
+    test.i2c.expect_write(&[0x02, 0x71, 0x00]);
+    assert!(test.tmp006.subscribe(0, common::callback()) == ReturnCode::SUCCESS);
+    test.host.run_until_idle();
+    test.i2c.assert_done();
+    assert_eq!(test.pin.mode(), Mode::Input);
+    assert!(!test.i2c.is_enabled());
+
+    // A die temperature of 25C. The callback waits for the sensor to be
+    // disabled again.
+    expect_sample(&test, 100, 0x0c80);
+    test.i2c.expect_write(&[0x02, 0x00, 0x00]);
+    data_ready(&mut test);
+    test.i2c.assert_done();
+
+    assert_eq!(common::calls(), vec![(33, 0, 0)]);
+    assert_eq!(test.pin.mode(), Mode::Disabled);
+    assert!(!test.i2c.is_enabled());
+
+    // Further edges are ignored
+    data_ready(&mut test);
+    assert!(common::calls().is_empty());
+}
+
+#[test]
+fn single_reading_negative_voltage() {
+    let mut test = setup();
+
+    test.i2c.expect_write(&[0x02, 0x71, 0x00]);
+    test.tmp006.subscribe(0, common::callback());
+    test.host.run_until_idle();
+
+    expect_sample(&test, -100, 0x0c80);
+    test.i2c.expect_write(&[0x02, 0x00, 0x00]);
+    data_ready(&mut test);
+    test.i2c.assert_done();
+
+    assert_eq!(common::calls(), vec![(27, 0, 0)]);
+}
+
+#[test]
+fn periodic_readings() {
+    let mut test = setup();
+
+    assert!(test.tmp006.command(1, 5, AppId::kernel_new(100)) == ReturnCode::SUCCESS);
+    test.i2c.expect_write(&[0x02, 0x7b, 0x00]);
+    test.tmp006.subscribe(1, common::callback());
+    test.host.run_until_idle();
+    test.i2c.assert_done();
+
+    // Each conversion is reported without disabling the sensor
+    expect_sample(&test, 100, 0x0c80);
+    data_ready(&mut test);
+    expect_sample(&test, 0, 0x0c80);
+    data_ready(&mut test);
+    test.i2c.assert_done();
+    assert_eq!(common::calls(), vec![(33, 0, 0), (30, 0, 0)]);
+    assert_eq!(test.pin.mode(), Mode::Input);
+    assert!(!test.i2c.is_enabled());
+
+    // Unsubscribing disables the sensor without a callback
+    test.i2c.expect_write(&[0x02, 0x00, 0x00]);
+    assert!(test.tmp006.command(2, 0, AppId::kernel_new(100)) == ReturnCode::SUCCESS);
+    test.host.run_until_idle();
+    test.i2c.assert_done();
+    assert!(common::calls().is_empty());
+    assert_eq!(test.pin.mode(), Mode::Disabled);
+}
+
+#[test]
+fn invalid_sampling_period() {
+    let test = setup();
+    assert!(test.tmp006.command(1, 8, AppId::kernel_new(100)) == ReturnCode::EINVAL);
+    assert!(test.tmp006.command(3, 0, AppId::kernel_new(100)) == ReturnCode::ENOSUPPORT);
+}
@@ -0,0 +1,119 @@
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+
+mod common;
+
+use capsules::tsl2561::TSL2561;
+use host::{Host, mock};
+use host::gpio::Mode;
+use kernel::{AppId, Driver, ReturnCode};
+
+struct Test {
+    host: Host,
+    i2c: &'static mock::i2c::Device,
+    pin: &'static mock::Pin,
+    tsl2561: &'static TSL2561<'static>,
+}
+
+fn setup() -> Test {
+    let i2c: &'static mock::i2c::Device = host::leak(mock::i2c::Device::new());
+    let pin: &'static mock::Pin = host::leak(mock::Pin::new());
+    let tsl2561: &'static TSL2561 = host::leak(TSL2561::new(i2c, pin, host::leak_buffer(4)));
+    i2c.set_client(tsl2561);
+    pin.set_client(tsl2561);
+
+    let host = Host::with_peripherals(&[i2c, pin]);
+    Test {
+        host: host,
+        i2c: i2c,
+        pin: pin,
+        tsl2561: tsl2561,
+    }
+}
+
+/// Starts a measurement and runs until the sensor is integrating.
+fn start_measurement(test: &mut Test) {
+    assert!(test.tsl2561.subscribe(0, common::callback()) == ReturnCode::SUCCESS);
+
+    // Power on, 101ms integration at low gain, level interrupt when the ADC
+    // is done, then power cycle to start a conversion
+    test.i2c.expect_write(&[0x80, 0x03]);
+    test.i2c.expect_write(&[0x81, 0x01]);
+    test.i2c.expect_write(&[0x86, 0x10]);
+    test.i2c.expect_write(&[0x80, 0x00]);
+    test.i2c.expect_write(&[0x80, 0x03]);
+    assert!(test.tsl2561.command(1, 0, AppId::kernel_new(100)) == ReturnCode::SUCCESS);
+    test.host.run_until_idle();
+    test.i2c.assert_done();
+    assert_eq!(test.pin.mode(), Mode::Input);
+    assert!(!test.i2c.is_enabled());
+    assert!(common::calls().is_empty());
+}
+
+/// Expects both channels to be read and the sensor to be powered off.
+fn expect_channels(test: &Test, chan0: u16, chan1: u16) {
+    test.i2c.expect_write(&[0xae]);
+    test.i2c.expect_read(&[chan1 as u8, (chan1 >> 8) as u8]);
+    test.i2c.expect_write(&[0xac]);
+    test.i2c.expect_read(&[chan0 as u8, (chan0 >> 8) as u8]);
+    test.i2c.expect_write(&[0x80, 0x00]);
+}
+
+/// Signals the end of the integration, as the open-drain INT line does.
+fn adc_done(test: &mut Test) {
+    test.pin.drive(true);
+    test.pin.drive(false);
+    test.host.run_until_idle();
+}
+
+#[test]
+fn measurement_reports_lux() {
+    let mut test = setup();
+    start_measurement(&mut test);
+
+    expect_channels(&test, 1000, 200);
+    adc_done(&mut test);
+    test.i2c.assert_done();
+    assert_eq!(common::calls(), vec![(0, 1506, 0)]);
+    assert!(!test.i2c.is_enabled());
+
+    // The interrupt is disabled until the next measurement
+    adc_done(&mut test);
+    assert!(common::calls().is_empty());
+}
+
+#[test]
+fn measurement_in_darkness() {
+    let mut test = setup();
+    start_measurement(&mut test);
+
+    expect_channels(&test, 0, 0);
+    adc_done(&mut test);
+    assert_eq!(common::calls(), vec![(0, 0, 0)]);
+}
+
+#[test]
+fn measurement_without_infrared() {
+    let mut test = setup();
+    start_measurement(&mut test);
+
+    expect_channels(&test, 1000, 0);
+    adc_done(&mut test);
+    assert_eq!(common::calls(), vec![(0, 1933, 0)]);
+}
+
+#[test]
+fn repeated_measurements() {
+    let mut test = setup();
+    start_measurement(&mut test);
+    expect_channels(&test, 1000, 200);
+    adc_done(&mut test);
+    assert_eq!(common::calls(), vec![(0, 1506, 0)]);
+
+    start_measurement(&mut test);
+    expect_channels(&test, 1000, 0);
+    adc_done(&mut test);
+    test.i2c.assert_done();
+    assert_eq!(common::calls(), vec![(0, 1933, 0)]);
+}
@@ -0,0 +1,189 @@
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use host::{Host, mock};
+use kernel::hil::time::{self, Alarm, Alarm64, Freq16KHz, Freq32KHz, Time};
+use std::cell::Cell;
+
+struct Counter {
+    fired: Cell<usize>,
+}
+
+impl time::Client for Counter {
+    fn fired(&self) {
+        self.fired.set(self.fired.get() + 1);
+    }
+}
+
+struct Test {
+    host: Host,
+    hardware: &'static mock::Alarm,
+    mux: &'static MuxAlarm<'static, mock::Alarm>,
+}
+
+fn setup() -> Test {
+    setup_with_counter_mask(0xffffffff)
+}
+
+fn setup_with_counter_mask(mask: u32) -> Test {
+    let hardware: &'static mock::Alarm = host::leak(mock::Alarm::with_counter_mask(mask));
+    let mux: &'static MuxAlarm<mock::Alarm> = host::leak(MuxAlarm::new(hardware));
+    hardware.set_client(mux);
+
+    let host = Host::with_peripherals(&[hardware]);
+    Test {
+        host: host,
+        hardware: hardware,
+        mux: mux,
+    }
+}
+
+fn virtual_alarm(test: &Test)
+                 -> (&'static VirtualMuxAlarm<'static, mock::Alarm>, &'static Counter) {
+    let alarm: &'static VirtualMuxAlarm<mock::Alarm> =
+        host::leak(VirtualMuxAlarm::new(test.mux));
+    let counter: &'static Counter = host::leak(Counter { fired: Cell::new(0) });
+    alarm.set_client(counter);
+    (alarm, counter)
+}
+
+fn advance(test: &mut Test, tics: u32) {
+    test.hardware.advance(tics);
+    test.host.run_until_idle();
+}
+
+#[test]
+fn alarm_across_counter_wrap() {
+    let mut test = setup();
+    let (alarm, counter) = virtual_alarm(&test);
+    test.hardware.advance(u32::max_value() - 999);
+
+    let when = alarm.now().wrapping_add(2000);
+    alarm.set_alarm(when);
+    assert_eq!(alarm.get_alarm(), when);
+
+    advance(&mut test, 1999);
+    assert_eq!(counter.fired.get(), 0);
+    advance(&mut test, 1);
+    assert_eq!(counter.fired.get(), 1);
+    assert_eq!(alarm.now64(), (1 << 32) + 1000);
+    assert!(!alarm.is_armed());
+}
+
+#[test]
+fn narrow_counter_is_extended() {
+    let mut test = setup_with_counter_mask(0xffffff);
+    let (alarm, counter) = virtual_alarm(&test);
+    test.hardware.advance(0xffffff - 999);
+
+    // The virtual alarm counts on past the hardware's 24 bits
+    let when = alarm.now() + 2000;
+    alarm.set_alarm(when);
+    advance(&mut test, 1999);
+    assert_eq!(counter.fired.get(), 0);
+    advance(&mut test, 1);
+    assert_eq!(counter.fired.get(), 1);
+    assert_eq!(alarm.now(), (1 << 24) + 1000);
+    assert_eq!(test.hardware.now(), 1000);
+
+    // Watching for wraps keeps the hardware alarm within half of its range
+    alarm.set_alarm64((1 << 26) + 1000);
+    let mut stops = 0;
+    while counter.fired.get() == 1 {
+        test.hardware.advance_to_alarm();
+        test.host.run_until_idle();
+        stops += 1;
+    }
+    assert_eq!(stops, 6);
+    assert_eq!(alarm.now64(), (1 << 26) + 1000);
+}
+
+#[test]
+fn missed_alarm_fires_right_away() {
+    let mut test = setup();
+    let (alarm, counter) = virtual_alarm(&test);
+    test.hardware.advance(5000);
+
+    // Set just after the time it was meant for, as a client computing the
+    // next deadline from an old reference would
+    let when = alarm.now().wrapping_sub(10);
+    alarm.set_alarm(when);
+
+    advance(&mut test, 99);
+    assert_eq!(counter.fired.get(), 0);
+    advance(&mut test, 1);
+    assert_eq!(counter.fired.get(), 1);
+}
+
+#[test]
+fn alarm_beyond_one_wrap() {
+    let mut test = setup();
+    let (alarm, counter) = virtual_alarm(&test);
+
+    alarm.set_alarm64(5000000000);
+    assert_eq!(alarm.get_alarm64(), 5000000000);
+
+    // The hardware alarm stops at least every half wrap to count wraps
+    let mut stops = 0;
+    while counter.fired.get() == 0 {
+        test.hardware.advance_to_alarm();
+        test.host.run_until_idle();
+        stops += 1;
+    }
+    assert_eq!(stops, 3);
+    assert_eq!(alarm.now64(), 5000000000);
+}
+
+#[test]
+fn alarms_fire_in_order() {
+    let mut test = setup();
+    let (late, late_counter) = virtual_alarm(&test);
+    let (early, early_counter) = virtual_alarm(&test);
+
+    late.set_alarm(late.now() + 1000);
+    early.set_alarm(early.now() + 500);
+
+    test.hardware.advance_to_alarm();
+    test.host.run_until_idle();
+    assert_eq!(early_counter.fired.get(), 1);
+    assert_eq!(late_counter.fired.get(), 0);
+    assert_eq!(test.hardware.now(), 500);
+
+    test.hardware.advance_to_alarm();
+    test.host.run_until_idle();
+    assert_eq!(late_counter.fired.get(), 1);
+    assert_eq!(test.hardware.now(), 1000);
+}
+
+#[test]
+fn disabled_alarm_does_not_fire() {
+    let mut test = setup();
+    let (alarm, counter) = virtual_alarm(&test);
+    let (other, other_counter) = virtual_alarm(&test);
+
+    alarm.set_alarm(alarm.now() + 500);
+    other.set_alarm(other.now() + 1000);
+    alarm.disable();
+
+    advance(&mut test, 1000);
+    assert_eq!(counter.fired.get(), 0);
+    assert_eq!(other_counter.fired.get(), 1);
+}
+
+#[test]
+fn conversions() {
+    assert_eq!(time::ms_to_tics::<Freq32KHz>(1000), 32768);
+    assert_eq!(time::ms_to_tics::<Freq32KHz>(20), 655);
+    assert_eq!(time::tics_to_ms::<Freq32KHz>(32768), 1000);
+    assert_eq!(time::tics_to_us::<Freq32KHz>(1), 30);
+    assert_eq!(time::us_to_tics::<Freq16KHz>(1000000), 16000);
+
+    // Products that would overflow 64 bits if multiplied first
+    assert_eq!(time::tics_to_ms::<Freq32KHz>(1 << 60), (1 << 45) * 1000);
+    assert_eq!(time::ms_to_tics::<Freq32KHz>(1 << 52), 147573952589676412);
+
+    assert!(time::has_elapsed(u32::max_value() - 5, 10, 4));
+    assert!(!time::has_elapsed(u32::max_value() - 5, 10, 3));
+}
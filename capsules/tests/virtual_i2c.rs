@@ -0,0 +1,213 @@
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+
+use capsules::virtual_i2c::{I2CDevice, MuxI2C};
+use host::{Host, mock};
+use host::mock::i2c::Op;
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::i2c::{self, I2CDevice as I2CDeviceTrait};
+use kernel::hil::time::{Alarm, Time};
+use std::cell::RefCell;
+
+struct Client {
+    results: RefCell<Vec<i2c::Error>>,
+    buffer: TakeCell<'static, [u8]>,
+}
+
+impl i2c::I2CClient for Client {
+    fn command_complete(&self, buffer: &'static mut [u8], error: i2c::Error) {
+        self.results.borrow_mut().push(error);
+        self.buffer.replace(buffer);
+    }
+}
+
+struct User {
+    device: &'static I2CDevice<'static, mock::Alarm>,
+    client: &'static Client,
+}
+
+impl User {
+    fn write(&self, data: &[u8]) {
+        let buffer = self.client.buffer.take().unwrap();
+        buffer[..data.len()].copy_from_slice(data);
+        self.device.write(buffer, data.len() as u8);
+    }
+
+    fn results(&self) -> Vec<i2c::Error> {
+        self.client.results.borrow_mut().split_off(0)
+    }
+}
+
+struct Test {
+    host: Host,
+    i2c: &'static mock::i2c::Master,
+    alarm: &'static mock::Alarm,
+    mux: &'static MuxI2C<'static, mock::Alarm>,
+    users: Vec<User>,
+}
+
+fn setup(addrs: &[u8]) -> Test {
+    let i2c: &'static mock::i2c::Master = host::leak(mock::i2c::Master::new());
+    let alarm: &'static mock::Alarm = host::leak(mock::Alarm::new());
+    let mux: &'static MuxI2C<mock::Alarm> = host::leak(MuxI2C::new(i2c, alarm));
+    i2c.set_client(mux);
+    alarm.set_client(mux);
+
+    let users = addrs.iter()
+        .map(|&addr| {
+            let device: &'static I2CDevice<mock::Alarm> = host::leak(I2CDevice::new(mux, addr));
+            let client: &'static Client = host::leak(Client {
+                results: RefCell::new(Vec::new()),
+                buffer: TakeCell::new(host::leak_buffer(4)),
+            });
+            device.set_client(client);
+            device.enable();
+            User {
+                device: device,
+                client: client,
+            }
+        })
+        .collect();
+
+    let host = Host::with_peripherals(&[i2c, alarm]);
+    Test {
+        host: host,
+        i2c: i2c,
+        alarm: alarm,
+        mux: mux,
+        users: users,
+    }
+}
+
+#[test]
+fn transactions_complete_in_turn() {
+    let mut test = setup(&[0x40, 0x44]);
+    test.i2c.expect(0x40, Op::Write(vec![1]), i2c::Error::CommandComplete);
+    test.i2c.expect(0x44, Op::Write(vec![2]), i2c::Error::DataNak);
+    test.users[0].write(&[1]);
+    test.users[1].write(&[2]);
+    test.host.run_until_idle();
+    test.i2c.assert_done();
+    assert!(test.i2c.is_enabled());
+    assert_eq!(test.users[0].results(), vec![i2c::Error::CommandComplete]);
+    assert_eq!(test.users[1].results(), vec![i2c::Error::DataNak]);
+    // Nothing is left to time out
+    assert!(!test.alarm.is_armed());
+}
+
+#[test]
+fn hung_transaction_times_out() {
+    let mut test = setup(&[0x40]);
+    test.i2c.expect_hang(0x40, Op::Write(vec![1]));
+    test.users[0].write(&[1]);
+    test.host.run_until_idle();
+    assert!(test.users[0].results().is_empty());
+
+    // 100ms on the 32kHz clock
+    assert_eq!(test.alarm.get_alarm(), 3276);
+    test.alarm.advance_to_alarm();
+    test.host.run_until_idle();
+    assert_eq!(test.users[0].results(), vec![i2c::Error::Timeout]);
+    assert_eq!(test.i2c.recoveries(), 1);
+    test.i2c.assert_done();
+
+    // The bus was recovered, so the next transaction goes ahead
+    test.i2c.expect(0x40, Op::Write(vec![2]), i2c::Error::CommandComplete);
+    test.users[0].write(&[2]);
+    test.host.run_until_idle();
+    assert_eq!(test.users[0].results(), vec![i2c::Error::CommandComplete]);
+}
+
+#[test]
+fn queued_transactions_fail_when_the_bus_stays_stuck() {
+    let mut test = setup(&[0x40, 0x44]);
+    test.i2c.set_stuck(true);
+    test.i2c.expect_hang(0x40, Op::Write(vec![1]));
+    test.users[0].write(&[1]);
+    test.users[1].write(&[2]);
+    test.alarm.advance_to_alarm();
+    test.host.run_until_idle();
+    assert_eq!(test.users[0].results(), vec![i2c::Error::Timeout]);
+    // Failed without being issued, or the script would have panicked
+    assert_eq!(test.users[1].results(), vec![i2c::Error::Timeout]);
+    test.i2c.assert_done();
+    assert!(!test.alarm.is_armed());
+
+    // Later transactions try again
+    test.i2c.set_stuck(false);
+    test.i2c.expect(0x44, Op::Write(vec![3]), i2c::Error::CommandComplete);
+    test.users[1].write(&[3]);
+    test.host.run_until_idle();
+    assert_eq!(test.users[1].results(), vec![i2c::Error::CommandComplete]);
+}
+
+#[test]
+fn shorter_timeout() {
+    let mut test = setup(&[0x40]);
+    test.mux.set_timeout(10);
+    test.i2c.expect_hang(0x40, Op::Write(vec![1]));
+    test.users[0].write(&[1]);
+    test.host.run_until_idle();
+    assert_eq!(test.alarm.get_alarm(), 327);
+}
+
+#[test]
+fn address_nak_is_not_retried_by_default() {
+    let mut test = setup(&[0x50]);
+    test.i2c.expect(0x50, Op::Write(vec![1]), i2c::Error::AddressNak);
+    test.users[0].write(&[1]);
+    test.host.run_until_idle();
+    test.i2c.assert_done();
+    assert_eq!(test.users[0].results(), vec![i2c::Error::AddressNak]);
+}
+
+#[test]
+fn address_nak_is_retried() {
+    let mut test = setup(&[0x50]);
+    test.mux.set_address_nak_retries(2);
+    test.i2c.expect(0x50, Op::Write(vec![1]), i2c::Error::AddressNak);
+    test.i2c.expect(0x50, Op::Write(vec![1]), i2c::Error::AddressNak);
+    test.i2c.expect(0x50, Op::Write(vec![1]), i2c::Error::CommandComplete);
+    test.users[0].write(&[1]);
+    test.host.run_until_idle();
+    assert!(test.users[0].results().is_empty());
+
+    // Retries wait out the delay
+    for _ in 0..2 {
+        assert_eq!(test.alarm.get_alarm().wrapping_sub(test.alarm.now()), 32);
+        test.alarm.advance_to_alarm();
+        test.host.run_until_idle();
+    }
+    test.i2c.assert_done();
+    assert_eq!(test.users[0].results(), vec![i2c::Error::CommandComplete]);
+}
+
+#[test]
+fn retries_run_out() {
+    let mut test = setup(&[0x50]);
+    test.mux.set_address_nak_retries(1);
+    test.i2c.expect(0x50, Op::Write(vec![1]), i2c::Error::AddressNak);
+    test.i2c.expect(0x50, Op::Write(vec![1]), i2c::Error::AddressNak);
+    test.users[0].write(&[1]);
+    test.host.run_until_idle();
+    test.alarm.advance_to_alarm();
+    test.host.run_until_idle();
+    test.i2c.assert_done();
+    assert_eq!(test.users[0].results(), vec![i2c::Error::AddressNak]);
+}
+
+#[test]
+fn device_address_can_change() {
+    let mut test = setup(&[0x40]);
+    test.i2c.expect(0x40, Op::Write(vec![1]), i2c::Error::CommandComplete);
+    test.i2c.expect(0x48, Op::Write(vec![2]), i2c::Error::CommandComplete);
+    test.users[0].write(&[1]);
+    test.host.run_until_idle();
+    test.users[0].device.set_address(0x48);
+    test.users[0].write(&[2]);
+    test.host.run_until_idle();
+    test.i2c.assert_done();
+    assert_eq!(test.users[0].results(),
+               vec![i2c::Error::CommandComplete, i2c::Error::CommandComplete]);
+}
@@ -0,0 +1,159 @@
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+
+use capsules::virtual_alarm::MuxAlarm;
+use capsules::virtual_timer::VirtualTimer;
+use host::{Host, mock};
+use kernel::hil::time::{self, Alarm, Time, Timer};
+use std::cell::{Cell, RefCell};
+
+struct Counter {
+    fired: Cell<usize>,
+    // Records the time of each firing
+    times: RefCell<Vec<u32>>,
+    hardware: &'static mock::Alarm,
+    // Cancels the timer from the callback once it has fired this many times
+    cancel_after: Cell<Option<usize>>,
+    timer: Cell<Option<&'static VirtualTimer<'static, mock::Alarm>>>,
+}
+
+impl time::Client for Counter {
+    fn fired(&self) {
+        self.fired.set(self.fired.get() + 1);
+        self.times.borrow_mut().push(self.hardware.now());
+        if self.cancel_after.get() == Some(self.fired.get()) {
+            self.timer.get().map(|timer| timer.disable());
+        }
+    }
+}
+
+struct Test {
+    host: Host,
+    hardware: &'static mock::Alarm,
+    mux: &'static MuxAlarm<'static, mock::Alarm>,
+}
+
+fn setup() -> Test {
+    let hardware: &'static mock::Alarm = host::leak(mock::Alarm::new());
+    let mux: &'static MuxAlarm<mock::Alarm> = host::leak(MuxAlarm::new(hardware));
+    hardware.set_client(mux);
+
+    let host = Host::with_peripherals(&[hardware]);
+    Test {
+        host: host,
+        hardware: hardware,
+        mux: mux,
+    }
+}
+
+fn virtual_timer(test: &Test)
+                 -> (&'static VirtualTimer<'static, mock::Alarm>, &'static Counter) {
+    let timer: &'static VirtualTimer<mock::Alarm> = host::leak(VirtualTimer::new(test.mux));
+    let counter: &'static Counter = host::leak(Counter {
+        fired: Cell::new(0),
+        times: RefCell::new(Vec::new()),
+        hardware: test.hardware,
+        cancel_after: Cell::new(None),
+        timer: Cell::new(Some(timer)),
+    });
+    timer.set_client(counter);
+    (timer, counter)
+}
+
+fn advance_to_alarm(test: &mut Test) {
+    test.hardware.advance_to_alarm();
+    test.host.run_until_idle();
+}
+
+#[test]
+fn oneshot_fires_once() {
+    let mut test = setup();
+    let (timer, counter) = virtual_timer(&test);
+
+    timer.oneshot(1000);
+    assert!(timer.is_armed());
+    test.hardware.advance(999);
+    test.host.run_until_idle();
+    assert_eq!(counter.fired.get(), 0);
+    test.hardware.advance(1);
+    test.host.run_until_idle();
+    assert_eq!(counter.fired.get(), 1);
+    assert!(!timer.is_armed());
+
+    test.hardware.advance(5000);
+    test.host.run_until_idle();
+    assert_eq!(counter.fired.get(), 1);
+}
+
+#[test]
+fn repeating_timer_does_not_drift() {
+    let mut test = setup();
+    let (timer, counter) = virtual_timer(&test);
+
+    timer.repeat(1000);
+    for _ in 0..3 {
+        advance_to_alarm(&mut test);
+        // Time passes while the client's work is done
+        test.hardware.advance(150);
+        test.host.run_until_idle();
+    }
+    assert_eq!(*counter.times.borrow(), vec![1000, 2000, 3000]);
+    assert!(timer.is_armed());
+}
+
+#[test]
+fn missed_periods_are_skipped() {
+    let mut test = setup();
+    let (timer, counter) = virtual_timer(&test);
+
+    timer.repeat(1000);
+    // The interrupt is handled three and a half periods late
+    test.hardware.advance(4500);
+    test.host.run_until_idle();
+    assert_eq!(counter.fired.get(), 1);
+
+    advance_to_alarm(&mut test);
+    assert_eq!(*counter.times.borrow(), vec![4500, 5000]);
+}
+
+#[test]
+fn timer_cancelled_from_callback() {
+    let mut test = setup();
+    let (timer, counter) = virtual_timer(&test);
+    counter.cancel_after.set(Some(2));
+
+    timer.repeat(500);
+    advance_to_alarm(&mut test);
+    advance_to_alarm(&mut test);
+    assert_eq!(counter.fired.get(), 2);
+    assert!(!timer.is_armed());
+
+    test.hardware.advance(5000);
+    test.host.run_until_idle();
+    assert_eq!(counter.fired.get(), 2);
+}
+
+#[test]
+fn timers_share_one_alarm() {
+    let mut test = setup();
+    let (fast, fast_counter) = virtual_timer(&test);
+    let (slow, slow_counter) = virtual_timer(&test);
+    let (once, once_counter) = virtual_timer(&test);
+
+    fast.repeat(400);
+    slow.repeat(1000);
+    once.oneshot(600);
+    while test.hardware.now() < 2000 {
+        advance_to_alarm(&mut test);
+    }
+
+    assert_eq!(*fast_counter.times.borrow(), vec![400, 800, 1200, 1600, 2000]);
+    assert_eq!(*slow_counter.times.borrow(), vec![1000, 2000]);
+    assert_eq!(*once_counter.times.borrow(), vec![600]);
+
+    slow.disable();
+    fast.repeat(0);
+    assert!(!fast.is_armed());
+    assert!(!slow.is_armed());
+}
@@ -0,0 +1,193 @@
+extern crate capsules;
+extern crate host;
+extern crate kernel;
+
+use capsules::virtual_uart::{MuxUart, VirtualUartDevice};
+use host::Host;
+use host::uart::Uart;
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::uart::{self, UART};
+use std::cell::{Cell, RefCell};
+
+struct Client {
+    device: Cell<Option<&'static VirtualUartDevice<'static, Uart>>>,
+    resends: Cell<usize>,
+    transmitted: Cell<usize>,
+    rejected: Cell<usize>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    received: RefCell<Vec<Vec<u8>>>,
+    rx_rejected: Cell<usize>,
+    rx_buffer: TakeCell<'static, [u8]>,
+}
+
+impl uart::Client for Client {
+    fn transmit_complete(&self, tx_buffer: &'static mut [u8], error: uart::Error) {
+        if error != uart::Error::CommandComplete {
+            self.rejected.set(self.rejected.get() + 1);
+        }
+        self.transmitted.set(self.transmitted.get() + 1);
+        if self.resends.get() > 0 {
+            self.resends.set(self.resends.get() - 1);
+            self.device.get().unwrap().transmit(tx_buffer, 1);
+        } else {
+            self.tx_buffer.replace(tx_buffer);
+        }
+    }
+
+    fn receive_complete(&self, rx_buffer: &'static mut [u8], rx_len: usize, error: uart::Error) {
+        if error == uart::Error::InvalidParameter {
+            self.rx_rejected.set(self.rx_rejected.get() + 1);
+        } else {
+            assert!(error == uart::Error::CommandComplete);
+            self.received.borrow_mut().push(rx_buffer[..rx_len].to_vec());
+        }
+        self.rx_buffer.replace(rx_buffer);
+    }
+}
+
+struct User {
+    device: &'static VirtualUartDevice<'static, Uart>,
+    client: &'static Client,
+}
+
+impl User {
+    fn transmit(&self, data: &[u8]) {
+        let buffer = self.client.tx_buffer.take().unwrap();
+        buffer[..data.len()].copy_from_slice(data);
+        self.device.transmit(buffer, data.len());
+    }
+
+    fn receive(&self, len: usize) {
+        self.device.receive(self.client.rx_buffer.take().unwrap(), len);
+    }
+
+    fn received(&self) -> Vec<Vec<u8>> {
+        self.client.received.borrow_mut().split_off(0)
+    }
+}
+
+fn setup(users: usize) -> (Host, &'static Uart, Vec<User>) {
+    let uart: &'static Uart = host::leak(Uart::new());
+    let mux: &'static MuxUart<Uart> = host::leak(MuxUart::new(uart, host::leak_buffer(8), 115200));
+    uart.set_client(mux);
+    mux.initialize();
+
+    let users = (0..users)
+        .map(|_| {
+            let device: &'static VirtualUartDevice<Uart> = host::leak(VirtualUartDevice::new(mux));
+            device.setup();
+            let client: &'static Client = host::leak(Client {
+                device: Cell::new(Some(device)),
+                resends: Cell::new(0),
+                transmitted: Cell::new(0),
+                rejected: Cell::new(0),
+                tx_buffer: TakeCell::new(host::leak_buffer(16)),
+                received: RefCell::new(Vec::new()),
+                rx_rejected: Cell::new(0),
+                rx_buffer: TakeCell::new(host::leak_buffer(16)),
+            });
+            device.set_client(client);
+            User {
+                device: device,
+                client: client,
+            }
+        })
+        .collect();
+
+    let host = Host::with_peripherals(&[uart]);
+    (host, uart, users)
+}
+
+#[test]
+fn transmits_are_queued() {
+    let (mut host, uart, users) = setup(2);
+    users[0].transmit(b"first ");
+    users[1].transmit(b"second");
+    host.run_until_idle();
+    assert_eq!(uart.take_output(), b"first second".to_vec());
+    assert_eq!(users[0].client.transmitted.get(), 1);
+    assert_eq!(users[1].client.transmitted.get(), 1);
+}
+
+#[test]
+fn devices_take_turns_transmitting() {
+    let (mut host, uart, users) = setup(2);
+    // One device transmits again as soon as each transmit completes,
+    // but the second still gets its turn
+    users[1].client.resends.set(2);
+    users[1].transmit(b"b");
+    users[0].transmit(b"a");
+    host.run_until_idle();
+    assert_eq!(uart.take_output(), b"babb".to_vec());
+}
+
+#[test]
+fn repeated_transmit_is_rejected() {
+    let (mut host, uart, users) = setup(1);
+    users[0].transmit(b"one");
+    users[0].device.transmit(host::leak_buffer(4), 4);
+    // The second buffer comes straight back
+    assert_eq!(users[0].client.rejected.get(), 1);
+    host.run_until_idle();
+    assert_eq!(users[0].client.transmitted.get(), 2);
+    assert_eq!(users[0].client.rejected.get(), 1);
+    assert_eq!(uart.take_output(), b"one".to_vec());
+}
+
+#[test]
+fn received_bytes_go_to_waiting_devices() {
+    let (mut host, uart, users) = setup(3);
+    users[0].receive(2);
+    users[1].receive(4);
+    uart.push_input(b"abcd");
+    host.run_until_idle();
+    assert_eq!(users[0].received(), vec![b"ab".to_vec()]);
+    assert_eq!(users[1].received(), vec![b"abcd".to_vec()]);
+    // Nobody was listening for these
+    assert!(users[2].received().is_empty());
+
+    users[2].receive(3);
+    uart.push_input(b"xyz");
+    host.run_until_idle();
+    assert_eq!(users[2].received(), vec![b"xyz".to_vec()]);
+}
+
+#[test]
+fn receives_started_mid_chunk_wait_for_the_next_chunk() {
+    let (mut host, uart, users) = setup(2);
+    users[0].receive(4);
+    host.run_until_idle();
+    // The mux is now reading a four byte chunk for the first device
+    users[1].receive(2);
+    uart.push_input(b"abcd");
+    host.run_until_idle();
+    assert_eq!(users[0].received(), vec![b"abcd".to_vec()]);
+    assert!(users[1].received().is_empty());
+
+    uart.push_input(b"xy");
+    host.run_until_idle();
+    assert_eq!(users[1].received(), vec![b"xy".to_vec()]);
+}
+
+#[test]
+fn receives_longer_than_the_mux_buffer_complete() {
+    let (mut host, uart, users) = setup(1);
+    users[0].receive(12);
+    uart.push_input(b"hello, world");
+    host.run_until_idle();
+    assert_eq!(users[0].received(), vec![b"hello, world".to_vec()]);
+}
+
+#[test]
+fn empty_receive_is_rejected() {
+    let (mut host, uart, users) = setup(2);
+    users[0].receive(0);
+    // The buffer comes straight back
+    assert_eq!(users[0].client.rx_rejected.get(), 1);
+    users[0].receive(2);
+    users[1].receive(2);
+    uart.push_input(b"ab");
+    host.run_until_idle();
+    assert_eq!(users[0].received(), vec![b"ab".to_vec()]);
+    assert_eq!(users[1].received(), vec![b"ab".to_vec()]);
+}
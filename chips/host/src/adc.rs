@@ -0,0 +1,174 @@
+//! `hil::adc::AdcSingle` and `hil::adc::AdcContinuous` with made-up samples.
+//!
+//! Each sample is the next value of a counter starting at 0, whatever the
+//! channel, so tests can tell whether samples were lost or repeated. Time
+//! does not pass on the host, so the frequency is ignored: a single sample,
+//! or a whole buffer of continuous samples, is ready each time the chip
+//! services the ADC.
+
+use chip::Peripheral;
+use kernel::ReturnCode;
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::adc;
+use std::cell::Cell;
+
+pub struct Adc {
+    channels: usize,
+    next_sample: Cell<u16>,
+    // The channel of the single sample in progress
+    single: Cell<Option<u8>>,
+    sampling: Cell<bool>,
+    buffer: TakeCell<'static, [u16]>,
+    length: Cell<usize>,
+    queued_buffer: TakeCell<'static, [u16]>,
+    queued_length: Cell<usize>,
+    // Buffers held after sampling was cancelled
+    stopped_buffer: TakeCell<'static, [u16]>,
+    stopped_queued_buffer: TakeCell<'static, [u16]>,
+    client: Cell<Option<&'static adc::Client>>,
+    continuous_client: Cell<Option<&'static adc::ContinuousClient>>,
+}
+
+impl Adc {
+    /// An ADC with `channels` channels.
+    pub fn new(channels: usize) -> Adc {
+        Adc {
+            channels: channels,
+            next_sample: Cell::new(0),
+            single: Cell::new(None),
+            sampling: Cell::new(false),
+            buffer: TakeCell::empty(),
+            length: Cell::new(0),
+            queued_buffer: TakeCell::empty(),
+            queued_length: Cell::new(0),
+            stopped_buffer: TakeCell::empty(),
+            stopped_queued_buffer: TakeCell::empty(),
+            client: Cell::new(None),
+            continuous_client: Cell::new(None),
+        }
+    }
+
+    pub fn set_client(&self, client: &'static adc::Client) {
+        self.client.set(Some(client));
+    }
+
+    pub fn set_continuous_client(&self, client: &'static adc::ContinuousClient) {
+        self.continuous_client.set(Some(client));
+    }
+
+    /// Whether continuous sampling is running.
+    pub fn is_sampling(&self) -> bool {
+        self.sampling.get()
+    }
+
+    fn take_sample(&self) -> u16 {
+        let sample = self.next_sample.get();
+        self.next_sample.set(sample.wrapping_add(1));
+        sample
+    }
+}
+
+impl adc::AdcSingle for Adc {
+    fn initialize(&self) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn sample(&self, channel: u8) -> ReturnCode {
+        if channel as usize >= self.channels {
+            ReturnCode::EINVAL
+        } else if self.single.get().is_some() || self.sampling.get() {
+            ReturnCode::EBUSY
+        } else {
+            self.single.set(Some(channel));
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn cancel_sample(&self) -> ReturnCode {
+        self.single.set(None);
+        ReturnCode::SUCCESS
+    }
+
+    fn channel_count(&self) -> usize {
+        self.channels
+    }
+}
+
+impl adc::AdcContinuous for Adc {
+    fn sample_continuous(&self,
+                         channel: u8,
+                         _frequency: u32,
+                         buffer: &'static mut [u16],
+                         length: usize)
+                         -> (ReturnCode, Option<&'static mut [u16]>) {
+        if channel as usize >= self.channels || length == 0 || length > buffer.len() {
+            return (ReturnCode::EINVAL, Some(buffer));
+        } else if self.single.get().is_some() || self.sampling.get() {
+            return (ReturnCode::EBUSY, Some(buffer));
+        }
+        self.sampling.set(true);
+        self.length.set(length);
+        self.buffer.replace(buffer);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn provide_buffer(&self,
+                      buffer: &'static mut [u16],
+                      length: usize)
+                      -> (ReturnCode, Option<&'static mut [u16]>) {
+        if !self.sampling.get() {
+            return (ReturnCode::EOFF, Some(buffer));
+        } else if length == 0 || length > buffer.len() {
+            return (ReturnCode::EINVAL, Some(buffer));
+        } else if self.queued_buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(buffer));
+        }
+        self.queued_length.set(length);
+        self.queued_buffer.replace(buffer);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn cancel_sampling(&self) -> ReturnCode {
+        if !self.sampling.get() {
+            return ReturnCode::EALREADY;
+        }
+        self.sampling.set(false);
+        self.stopped_buffer.put(self.buffer.take());
+        self.stopped_queued_buffer.put(self.queued_buffer.take());
+        ReturnCode::SUCCESS
+    }
+
+    fn retrieve_buffers(&self) -> (Option<&'static mut [u16]>, Option<&'static mut [u16]>) {
+        (self.stopped_buffer.take(), self.stopped_queued_buffer.take())
+    }
+}
+
+impl Peripheral for Adc {
+    fn has_pending_interrupt(&self) -> bool {
+        self.single.get().is_some() || self.buffer.is_some()
+    }
+
+    fn handle_interrupt(&self) {
+        if self.single.get().is_some() {
+            self.single.set(None);
+            let sample = self.take_sample();
+            self.client.get().map(|client| client.sample_done(sample));
+        }
+
+        if let Some(buffer) = self.buffer.take() {
+            let length = self.length.get();
+            for sample in buffer[..length].iter_mut() {
+                *sample = self.take_sample();
+            }
+            // Carry on into the queued buffer, or stop if there is none
+            match self.queued_buffer.take() {
+                Some(queued) => {
+                    self.length.set(self.queued_length.get());
+                    self.buffer.replace(queued);
+                }
+                None => self.sampling.set(false),
+            }
+            self.continuous_client.get().map(move |client| client.samples_ready(buffer, length));
+        }
+    }
+}
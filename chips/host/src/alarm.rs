@@ -0,0 +1,102 @@
+//! `hil::time::Alarm` on a virtual clock.
+//!
+//! The clock only moves when `advance` is called, so tests control exactly
+//! when alarms fire. The counter is 32 bits wide unless created with
+//! `with_counter_mask`.
+
+use chip::Peripheral;
+use std::cell::Cell;
+use kernel::hil::time::{self, Freq32KHz};
+
+pub struct Alarm {
+    now: Cell<u32>,
+    alarm: Cell<u32>,
+    // Clock value when the alarm was set, to compare against it across wraps
+    set_at: Cell<u32>,
+    armed: Cell<bool>,
+    mask: u32,
+    client: Cell<Option<&'static time::Client>>,
+}
+
+impl Alarm {
+    pub fn new() -> Alarm {
+        Alarm::with_counter_mask(0xffffffff)
+    }
+
+    /// An alarm whose counter has the bits of `mask`, such as `0xffffff` for
+    /// a 24-bit counter.
+    pub fn with_counter_mask(mask: u32) -> Alarm {
+        Alarm {
+            now: Cell::new(0),
+            alarm: Cell::new(0),
+            set_at: Cell::new(0),
+            armed: Cell::new(false),
+            mask: mask,
+            client: Cell::new(None),
+        }
+    }
+
+    pub fn set_client(&self, client: &'static time::Client) {
+        self.client.set(Some(client));
+    }
+
+    /// Moves the clock forward by `tics`.
+    pub fn advance(&self, tics: u32) {
+        self.now.set(self.now.get().wrapping_add(tics) & self.mask);
+    }
+
+    /// Moves the clock forward to the alarm time, if the alarm is armed.
+    pub fn advance_to_alarm(&self) {
+        if self.armed.get() && !self.has_pending_interrupt() {
+            self.now.set(self.alarm.get());
+        }
+    }
+}
+
+impl time::Time for Alarm {
+    fn disable(&self) {
+        self.armed.set(false);
+    }
+
+    fn is_armed(&self) -> bool {
+        self.armed.get()
+    }
+}
+
+impl time::Alarm for Alarm {
+    type Frequency = Freq32KHz;
+
+    fn now(&self) -> u32 {
+        self.now.get()
+    }
+
+    fn set_alarm(&self, tics: u32) {
+        self.alarm.set(tics & self.mask);
+        self.set_at.set(self.now.get());
+        self.armed.set(true);
+    }
+
+    fn get_alarm(&self) -> u32 {
+        self.alarm.get()
+    }
+
+    fn counter_mask(&self) -> u32 {
+        self.mask
+    }
+}
+
+impl Peripheral for Alarm {
+    fn has_pending_interrupt(&self) -> bool {
+        let set_at = self.set_at.get();
+        self.armed.get() &&
+        self.now.get().wrapping_sub(set_at) & self.mask >=
+        self.alarm.get().wrapping_sub(set_at) & self.mask
+    }
+
+    fn handle_interrupt(&self) {
+        if self.has_pending_interrupt() {
+            self.armed.set(false);
+            self.client.get().map(|client| client.fired());
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! Stand-in processes, so capsules that keep per-app state in a `Container`
+//! can be tested.
+//!
+//! `Apps::load` creates processes from minimal app headers, with no code,
+//! and installs them as the kernel's process table. Nothing runs in them:
+//! callbacks the capsules schedule stay queued until the test takes them
+//! with `callbacks`. The table is global, so `Apps` holds a lock that keeps
+//! other tests from loading theirs until it is dropped.
+//!
+//! Containers must be created before the apps are loaded, as on a board,
+//! since each process sets aside room for the containers that exist when it
+//! is created.
+
+use kernel::{AppId, AppSlice, Callback, ReadOnlyAppSlice, Shared};
+use kernel::callback::CallbackId;
+use kernel::process::{self, CallbackQueueConfig, FaultResponse, Process, Task};
+use core::nonzero::NonZero;
+use std::sync::{Mutex, MutexGuard, Once, ONCE_INIT};
+
+/// Memory given to each process, enough for the stack, heap and grants of
+/// the header `load` builds.
+const APP_MEMORY: usize = 8192;
+
+/// Callbacks each process can have queued.
+const CALLBACK_QUEUE_LEN: usize = 16;
+
+static INIT: Once = ONCE_INIT;
+static mut LOCK: *const Mutex<()> = 0 as *const Mutex<()>;
+
+fn lock() -> MutexGuard<'static, ()> {
+    unsafe {
+        INIT.call_once(|| LOCK = Box::into_raw(Box::new(Mutex::new(()))));
+        // A test that panicked while holding the lock leaves nothing broken,
+        // as the next test loads a new table
+        (*LOCK).lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// A version 1 app header for an app called `name`, followed by the name.
+fn header(name: &str) -> &'static [u32] {
+    let name_words = (name.len() + 3) / 4;
+    let mut words = vec![0u32; 19 + name_words];
+    let total_size = (words.len() * 4) as u32;
+    let fields = [1, // version
+                  total_size,
+                  1, // entry offset, odd to look like Thumb code
+                  0, // relocation data offset
+                  0, // relocation data size
+                  0, // text offset
+                  0, // text size
+                  0, // GOT offset
+                  0, // GOT size
+                  0, // data offset
+                  0, // data size
+                  0, // BSS offset
+                  0, // BSS size
+                  256, // stack
+                  1024, // app heap
+                  1024, // kernel heap
+                  19 * 4, // package name offset
+                  name.len() as u32];
+    words[..18].copy_from_slice(&fields);
+    words[18] = fields.iter().fold(0, |checksum, word| checksum ^ word);
+    for (i, byte) in name.bytes().enumerate() {
+        words[19 + i / 4] |= (byte as u32) << (8 * (i % 4));
+    }
+    unsafe { &*Box::into_raw(words.into_boxed_slice()) }
+}
+
+pub struct Apps {
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Apps {
+    /// Loads apps with the given package names, which get app ids 0, 1, ...
+    /// in order.
+    pub fn load(names: &[&str]) -> Apps {
+        let lock = lock();
+        let processes: Vec<Option<Process<'static>>> = names.iter()
+            .map(|name| unsafe {
+                let memory = ::leak_buffer(APP_MEMORY);
+                let (process, _, _) = Process::create(header(name).as_ptr() as *const u8,
+                                                      memory.as_mut_ptr(),
+                                                      APP_MEMORY,
+                                                      FaultResponse::Panic,
+                                                      CallbackQueueConfig {
+                                                          default_len: CALLBACK_QUEUE_LEN,
+                                                          coalesce: false,
+                                                      });
+                let mut process = process.expect("could not create app");
+                // Drop the call to the app's entry point
+                process.dequeue_task();
+                Some(process)
+            })
+            .collect();
+        unsafe {
+            process::PROCS = &mut *Box::into_raw(processes.into_boxed_slice());
+        }
+        Apps { _lock: lock }
+    }
+
+    pub fn id(&self, app: usize) -> AppId {
+        AppId::new(app)
+    }
+
+    /// A callback for `app`, as if it had subscribed to `subscribe_num` of
+    /// `driver_num`.
+    pub fn callback(&self, app: usize, driver_num: usize, subscribe_num: usize) -> Callback {
+        let callback_id = CallbackId {
+            driver_num: driver_num,
+            subscribe_num: subscribe_num,
+        };
+        // Never called, as the app does not run
+        let fn_ptr = unsafe { NonZero::new(1 as *mut ()) };
+        Callback::new(self.id(app), callback_id, 0, fn_ptr)
+    }
+
+    /// A buffer of `len` bytes in `app`'s memory, holding `data` followed by
+    /// zeroes, as if the app had allowed it. Its address, from
+    /// `as_ref().as_ptr()`, can be passed to `read` later.
+    pub fn buffer(&self, app: usize, data: &[u8], len: usize) -> AppSlice<Shared, u8> {
+        unsafe {
+            let process = process::PROCS[app].as_mut().expect("no such app");
+            let ptr = process.sbrk(len as isize).expect("app out of memory") as *mut u8;
+            for i in 0..len {
+                *ptr.offset(i as isize) = data.get(i).cloned().unwrap_or(0);
+            }
+            AppSlice::new(ptr, len, self.id(app))
+        }
+    }
+
+    /// A read-only buffer in `app`'s memory holding `data`.
+    pub fn read_only_buffer(&self, app: usize, data: &[u8]) -> ReadOnlyAppSlice<u8> {
+        self.buffer(app, data, data.len()).into()
+    }
+
+    /// Reads `len` bytes of app memory at `address`.
+    pub fn read(&self, address: *const u8, len: usize) -> Vec<u8> {
+        unsafe { ::std::slice::from_raw_parts(address, len).to_vec() }
+    }
+
+    /// The arguments of the callbacks queued for `app`, which are removed
+    /// from its queue.
+    pub fn callbacks(&self, app: usize) -> Vec<(usize, usize, usize)> {
+        let process = unsafe { process::PROCS[app].as_mut().expect("no such app") };
+        let mut calls = Vec::new();
+        while let Some(task) = process.dequeue_task() {
+            if let Task::FunctionCall(call) = task {
+                calls.push((call.r0, call.r1, call.r2));
+            }
+        }
+        calls
+    }
+}
+
+impl Drop for Apps {
+    fn drop(&mut self) {
+        unsafe {
+            process::PROCS = &mut [];
+        }
+    }
+}
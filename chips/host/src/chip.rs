@@ -0,0 +1,80 @@
+//! The host chip and the peripherals it services.
+
+use kernel::Chip;
+use kernel::deferred_call;
+
+/// A peripheral simulated on the host.
+///
+/// Instead of raising interrupts, peripherals report pending events, which
+/// `Host` handles from `service_pending_interrupts`.
+pub trait Peripheral {
+    /// Whether the peripheral has an event for the kernel to handle.
+    fn has_pending_interrupt(&self) -> bool;
+
+    /// Handles pending events, calling the peripheral's client.
+    fn handle_interrupt(&self);
+}
+
+pub struct Host {
+    peripherals: Vec<&'static Peripheral>,
+    mpu: (),
+    systick: (),
+}
+
+impl Host {
+    pub fn new() -> Host {
+        Host {
+            peripherals: Vec::new(),
+            mpu: (),
+            systick: (),
+        }
+    }
+
+    /// A chip handling the events of `peripherals`.
+    pub fn with_peripherals(peripherals: &[&'static Peripheral]) -> Host {
+        let mut host = Host::new();
+        for &peripheral in peripherals.iter() {
+            host.add_peripheral(peripheral);
+        }
+        host
+    }
+
+    /// Adds a peripheral whose events are handled by this chip.
+    pub fn add_peripheral(&mut self, peripheral: &'static Peripheral) {
+        self.peripherals.push(peripheral);
+    }
+
+    /// Services pending events and deferred calls until there are none
+    /// left, for tests that want every outstanding callback to have run.
+    pub fn run_until_idle(&mut self) {
+        while self.has_pending_interrupts() || deferred_call::has_tasks() {
+            self.service_pending_interrupts();
+            deferred_call::service_pending();
+        }
+    }
+}
+
+impl Chip for Host {
+    type MPU = ();
+    type SysTick = ();
+
+    fn service_pending_interrupts(&mut self) {
+        for peripheral in self.peripherals.iter() {
+            if peripheral.has_pending_interrupt() {
+                peripheral.handle_interrupt();
+            }
+        }
+    }
+
+    fn has_pending_interrupts(&self) -> bool {
+        self.peripherals.iter().any(|peripheral| peripheral.has_pending_interrupt())
+    }
+
+    fn mpu(&self) -> &() {
+        &self.mpu
+    }
+
+    fn systick(&self) -> &() {
+        &self.systick
+    }
+}
@@ -0,0 +1,73 @@
+//! `hil::crc::CRC` in software.
+//!
+//! Only the standard `Crc32` and `Crc32C` algorithms are supported; the
+//! SAM4L-specific ones return `ENOSUPPORT`.
+
+use chip::Peripheral;
+use kernel::ReturnCode;
+use kernel::hil::crc::{self, CrcAlg};
+use std::cell::Cell;
+
+pub struct Crc {
+    client: Cell<Option<&'static crc::Client>>,
+    result: Cell<Option<u32>>,
+}
+
+/// Reflected CRC-32 of `data` with the bit-reversed polynomial `poly`.
+fn crc32(data: &[u8], poly: u32) -> u32 {
+    let mut crc = 0xffffffff;
+    for byte in data.iter() {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ poly } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+impl Crc {
+    pub fn new() -> Crc {
+        Crc {
+            client: Cell::new(None),
+            result: Cell::new(None),
+        }
+    }
+
+    pub fn set_client(&self, client: &'static crc::Client) {
+        self.client.set(Some(client));
+    }
+}
+
+impl crc::CRC for Crc {
+    fn get_version(&self) -> u32 {
+        0
+    }
+
+    fn compute(&self, data: &[u8], alg: CrcAlg) -> ReturnCode {
+        if self.result.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        let result = match alg {
+            CrcAlg::Crc32 => crc32(data, 0xedb88320),
+            CrcAlg::Crc32C => crc32(data, 0x82f63b78),
+            _ => return ReturnCode::ENOSUPPORT,
+        };
+        self.result.set(Some(result));
+        ReturnCode::SUCCESS
+    }
+
+    fn disable(&self) {}
+}
+
+impl Peripheral for Crc {
+    fn has_pending_interrupt(&self) -> bool {
+        self.result.get().is_some()
+    }
+
+    fn handle_interrupt(&self) {
+        if let Some(result) = self.result.get() {
+            self.result.set(None);
+            self.client.get().map(|client| client.receive_result(result));
+        }
+    }
+}
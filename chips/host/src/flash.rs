@@ -0,0 +1,242 @@
+//! `hil::flash::Flash` on a file.
+//!
+//! The file is created if needed and extended to the flash size with erased
+//! (0xff) bytes. As `hil::flash` requires, writes, programs and erases work
+//! on whole pages. Like the SAM4L flash, a write erases its pages before
+//! programming them, while a program only clears bits.
+//!
+//! To test recovery from power loss, `cut_power_after` stops the flash in the
+//! middle of a later write or erase. A new `Flash` on the same file then sees
+//! what a board would after rebooting.
+
+use chip::Peripheral;
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::flash;
+use std::cell::{Cell, RefCell};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub const PAGE_SIZE: usize = 512;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Op {
+    Read,
+    Write,
+    Erase,
+}
+
+pub struct Flash {
+    file: RefCell<File>,
+    size: usize,
+    client: Cell<Option<&'static flash::Client>>,
+    buffer: TakeCell<'static, [u8]>,
+    // The completed operation waiting to be reported, and its result
+    pending: Cell<Option<(Op, flash::Error)>>,
+    // Writes and erases left to complete before power is cut
+    power_cut: Cell<Option<usize>>,
+    powered: Cell<bool>,
+}
+
+impl Flash {
+    /// Flash of `size` bytes stored in the file at `path`.
+    ///
+    /// # Panics if the file cannot be opened or extended
+    pub fn new<P: AsRef<Path>>(path: P, size: usize) -> Flash {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(path)
+            .expect("could not open flash file");
+        let len = file.metadata().expect("could not read flash file").len() as usize;
+        if len < size {
+            file.seek(SeekFrom::End(0)).expect("could not extend flash file");
+            file.write_all(&vec![0xff; size - len]).expect("could not extend flash file");
+        }
+        Flash {
+            file: RefCell::new(file),
+            size: size,
+            client: Cell::new(None),
+            buffer: TakeCell::empty(),
+            pending: Cell::new(None),
+            power_cut: Cell::new(None),
+            powered: Cell::new(true),
+        }
+    }
+
+    /// Cuts power after `ops` more writes, programs and erases have
+    /// completed. The next one never completes: an erase only erases the
+    /// first half of its bytes, a write erases its pages but only programs
+    /// the first half of them, and a program only changes the first half of
+    /// the bytes it would change. The flash ignores every operation after it.
+    pub fn cut_power_after(&self, ops: usize) {
+        self.power_cut.set(Some(ops));
+    }
+
+    /// Whether power is cut during the write or erase being issued.
+    fn cut_now(&self) -> bool {
+        match self.power_cut.get() {
+            Some(0) => {
+                self.power_cut.set(None);
+                self.powered.set(false);
+                true
+            }
+            Some(ops) => {
+                self.power_cut.set(Some(ops - 1));
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn in_bounds(&self, offset: usize, len: usize) -> bool {
+        offset.checked_add(len).map_or(false, |end| end <= self.size)
+    }
+
+    /// Whether `len` bytes at `offset` are whole pages of the flash.
+    fn whole_pages(&self, offset: usize, len: usize) -> bool {
+        self.in_bounds(offset, len) && offset % PAGE_SIZE == 0 && len % PAGE_SIZE == 0
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) {
+        let mut file = self.file.borrow_mut();
+        file.seek(SeekFrom::Start(offset as u64)).expect("flash file seek failed");
+        file.read_exact(buf).expect("flash file read failed");
+    }
+
+    /// Clears the bits of the `len` bytes at `offset` that are clear in
+    /// `buf`, stopping after `limit` bytes have changed.
+    fn program_at(&self, offset: usize, buf: &[u8], limit: usize) {
+        let mut contents = vec![0; buf.len()];
+        self.read_at(offset, &mut contents);
+        let mut changed = 0;
+        for (byte, new) in contents.iter_mut().zip(buf.iter()) {
+            if *byte & *new != *byte {
+                if changed == limit {
+                    break;
+                }
+                *byte &= *new;
+                changed += 1;
+            }
+        }
+        self.write_at(offset, &contents);
+    }
+
+    /// The number of bytes that programming `buf` at `offset` changes.
+    fn changes(&self, offset: usize, buf: &[u8]) -> usize {
+        let mut contents = vec![0; buf.len()];
+        self.read_at(offset, &mut contents);
+        contents.iter().zip(buf.iter()).filter(|&(byte, new)| *byte & *new != *byte).count()
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) {
+        let mut file = self.file.borrow_mut();
+        file.seek(SeekFrom::Start(offset as u64)).expect("flash file seek failed");
+        file.write_all(buf).expect("flash file write failed");
+        file.flush().expect("flash file write failed");
+    }
+}
+
+impl flash::Flash for Flash {
+    fn set_client(&self, client: &'static flash::Client) {
+        self.client.set(Some(client));
+    }
+
+    fn read(&self, offset: usize, buf: &'static mut [u8]) {
+        if !self.powered.get() {
+            return;
+        }
+        let result = if !self.in_bounds(offset, buf.len()) {
+            flash::Error::PageBoundary
+        } else {
+            self.read_at(offset, buf);
+            flash::Error::CommandComplete
+        };
+        self.buffer.replace(buf);
+        self.pending.set(Some((Op::Read, result)));
+    }
+
+    fn write(&self, offset: usize, buf: &'static mut [u8]) {
+        if !self.powered.get() {
+            return;
+        }
+        if self.cut_now() {
+            if self.whole_pages(offset, buf.len()) {
+                self.write_at(offset, &vec![0xff; buf.len()]);
+                self.write_at(offset, &buf[..buf.len() / 2]);
+            }
+            return;
+        }
+        let result = if !self.whole_pages(offset, buf.len()) {
+            flash::Error::PageBoundary
+        } else {
+            // Programming after the erase leaves exactly the new bytes
+            self.write_at(offset, buf);
+            flash::Error::CommandComplete
+        };
+        self.buffer.replace(buf);
+        self.pending.set(Some((Op::Write, result)));
+    }
+
+    fn program(&self, offset: usize, buf: &'static mut [u8]) {
+        if !self.powered.get() {
+            return;
+        }
+        if self.cut_now() {
+            if self.whole_pages(offset, buf.len()) {
+                let half = self.changes(offset, buf) / 2;
+                self.program_at(offset, buf, half);
+            }
+            return;
+        }
+        let result = if !self.whole_pages(offset, buf.len()) {
+            flash::Error::PageBoundary
+        } else {
+            self.program_at(offset, buf, usize::max_value());
+            flash::Error::CommandComplete
+        };
+        self.buffer.replace(buf);
+        self.pending.set(Some((Op::Write, result)));
+    }
+
+    fn erase(&self, offset: usize, len: usize) {
+        if !self.powered.get() {
+            return;
+        }
+        if self.cut_now() {
+            if self.whole_pages(offset, len) {
+                self.write_at(offset, &vec![0xff; len / 2]);
+            }
+            return;
+        }
+        let result = if !self.whole_pages(offset, len) {
+            flash::Error::PageBoundary
+        } else {
+            self.write_at(offset, &vec![0xff; len]);
+            flash::Error::CommandComplete
+        };
+        self.pending.set(Some((Op::Erase, result)));
+    }
+}
+
+impl Peripheral for Flash {
+    fn has_pending_interrupt(&self) -> bool {
+        self.pending.get().is_some()
+    }
+
+    fn handle_interrupt(&self) {
+        if let Some((op, result)) = self.pending.get() {
+            self.pending.set(None);
+            self.client.get().map(|client| match op {
+                Op::Read => {
+                    self.buffer.take().map(|buf| client.read_complete(buf, result));
+                }
+                Op::Write => {
+                    self.buffer.take().map(|buf| client.write_complete(buf, result));
+                }
+                Op::Erase => client.erase_complete(result),
+            });
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! `hil::gpio::Pin` in memory.
+//!
+//! Output levels are read back with `level`. Input levels are set with
+//! `drive`, which also raises the pin's interrupt on a matching edge.
+
+use chip::Peripheral;
+use kernel::hil::gpio;
+use std::cell::Cell;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Mode {
+    Disabled,
+    Input,
+    Output,
+}
+
+pub struct Pin {
+    mode: Cell<Mode>,
+    level: Cell<bool>,
+    // Interrupt identifier and whether rising and falling edges trigger it
+    interrupt: Cell<Option<(usize, bool, bool)>>,
+    pending: Cell<Option<usize>>,
+    client: Cell<Option<&'static gpio::Client>>,
+}
+
+impl Pin {
+    pub fn new() -> Pin {
+        Pin {
+            mode: Cell::new(Mode::Disabled),
+            level: Cell::new(false),
+            interrupt: Cell::new(None),
+            pending: Cell::new(None),
+            client: Cell::new(None),
+        }
+    }
+
+    pub fn set_client(&self, client: &'static gpio::Client) {
+        self.client.set(Some(client));
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode.get()
+    }
+
+    /// The level the pin is driven to, or reads as.
+    pub fn level(&self) -> bool {
+        self.level.get()
+    }
+
+    /// Drives an input pin to `level` from outside, as a button would.
+    pub fn drive(&self, level: bool) {
+        let previous = self.level.get();
+        self.level.set(level);
+        if self.mode.get() != Mode::Input || previous == level {
+            return;
+        }
+        if let Some((identifier, rising, falling)) = self.interrupt.get() {
+            if (level && rising) || (!level && falling) {
+                self.pending.set(Some(identifier));
+            }
+        }
+    }
+}
+
+impl gpio::PinCtl for Pin {
+    fn set_input_mode(&self, mode: gpio::InputMode) {
+        match mode {
+            gpio::InputMode::PullUp => self.level.set(true),
+            gpio::InputMode::PullDown => self.level.set(false),
+            gpio::InputMode::PullNone => {}
+        }
+    }
+}
+
+impl gpio::Pin for Pin {
+    fn make_output(&self) {
+        self.mode.set(Mode::Output);
+    }
+
+    fn make_input(&self) {
+        self.mode.set(Mode::Input);
+    }
+
+    fn disable(&self) {
+        self.mode.set(Mode::Disabled);
+    }
+
+    fn set(&self) {
+        self.level.set(true);
+    }
+
+    fn clear(&self) {
+        self.level.set(false);
+    }
+
+    fn toggle(&self) {
+        self.level.set(!self.level.get());
+    }
+
+    fn read(&self) -> bool {
+        self.level.get()
+    }
+
+    fn enable_interrupt(&self, identifier: usize, mode: gpio::InterruptMode) {
+        let (rising, falling) = match mode {
+            gpio::InterruptMode::RisingEdge => (true, false),
+            gpio::InterruptMode::FallingEdge => (false, true),
+            gpio::InterruptMode::EitherEdge => (true, true),
+        };
+        self.interrupt.set(Some((identifier, rising, falling)));
+    }
+
+    fn disable_interrupt(&self) {
+        self.interrupt.set(None);
+        self.pending.set(None);
+    }
+}
+
+impl Peripheral for Pin {
+    fn has_pending_interrupt(&self) -> bool {
+        self.pending.get().is_some()
+    }
+
+    fn handle_interrupt(&self) {
+        if let Some(identifier) = self.pending.get() {
+            self.pending.set(None);
+            self.client.get().map(|client| client.fired(identifier));
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! Simulated chip for running the Tock kernel and capsules on a Linux host.
+
+#![feature(nonzero)]
+
+extern crate core;
+extern crate kernel;
+
+pub mod adc;
+pub mod alarm;
+pub mod apps;
+pub mod chip;
+pub mod crc;
+pub mod flash;
+pub mod gpio;
+pub mod mock;
+pub mod uart;
+
+pub use apps::Apps;
+pub use chip::{Host, Peripheral};
+
+/// Moves `value` to the heap for the rest of the program, giving the
+/// `'static` reference that capsules and HIL clients expect.
+pub fn leak<T>(value: T) -> &'static mut T {
+    unsafe { &mut *Box::into_raw(Box::new(value)) }
+}
+
+/// A zeroed buffer of `len` bytes, for capsules that take `'static` buffers.
+pub fn leak_buffer(len: usize) -> &'static mut [u8] {
+    let buffer = vec![0; len].into_boxed_slice();
+    unsafe { &mut *Box::into_raw(buffer) }
+}
+
+/// Processes cannot run on the host. This stands in for the context switch
+/// routine the kernel links against on hardware.
+#[no_mangle]
+pub unsafe extern "C" fn switch_to_user(_user_stack: *const u8,
+                                        _got_base: *const u8,
+                                        _process_regs: &mut [usize; 8])
+                                        -> *mut u8 {
+    panic!("Processes cannot run on the host chip");
+}
+
+/// The kernel expects newlib's `__errno`, which glibc does not provide.
+#[no_mangle]
+pub unsafe extern "C" fn __errno() -> *mut i32 {
+    static mut ERRNO: i32 = 0;
+    &mut ERRNO
+}
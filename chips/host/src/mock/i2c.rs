@@ -0,0 +1,291 @@
+//! `hil::i2c::I2CDevice` and `hil::i2c::I2CMaster` that check each
+//! transaction against a script.
+//!
+//! ```rust
+//! let device = host::leak(mock::i2c::Device::new());
+//! device.expect_write(&[0xf5]);
+//! device.expect_read(&[0x66, 0x66]);
+//!
+//! let master = host::leak(mock::i2c::Master::new());
+//! master.expect(0x40, Op::Write(vec![0xf5]), i2c::Error::AddressNak);
+//! master.expect_hang(0x40, Op::Write(vec![0xf5]));
+//! ```
+
+use chip::Peripheral;
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::i2c;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+/// A transaction on the bus.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Op {
+    /// Bytes written to the device.
+    Write(Vec<u8>),
+    /// Bytes the device answers a read with.
+    Read(Vec<u8>),
+    /// Bytes written, then the bytes the device answers with.
+    WriteRead(Vec<u8>, Vec<u8>),
+}
+
+pub struct Device {
+    script: RefCell<VecDeque<(Op, i2c::Error)>>,
+    enabled: Cell<bool>,
+    buffer: TakeCell<'static, [u8]>,
+    result: Cell<i2c::Error>,
+    client: Cell<Option<&'static i2c::I2CClient>>,
+}
+
+impl Device {
+    pub fn new() -> Device {
+        Device {
+            script: RefCell::new(VecDeque::new()),
+            enabled: Cell::new(false),
+            buffer: TakeCell::empty(),
+            result: Cell::new(i2c::Error::CommandComplete),
+            client: Cell::new(None),
+        }
+    }
+
+    pub fn set_client(&self, client: &'static i2c::I2CClient) {
+        self.client.set(Some(client));
+    }
+
+    /// Expects `op` next, completing it with `result`.
+    pub fn expect(&self, op: Op, result: i2c::Error) {
+        self.script.borrow_mut().push_back((op, result));
+    }
+
+    pub fn expect_write(&self, bytes: &[u8]) {
+        self.expect(Op::Write(bytes.to_vec()), i2c::Error::CommandComplete);
+    }
+
+    pub fn expect_read(&self, bytes: &[u8]) {
+        self.expect(Op::Read(bytes.to_vec()), i2c::Error::CommandComplete);
+    }
+
+    pub fn expect_write_read(&self, written: &[u8], read: &[u8]) {
+        self.expect(Op::WriteRead(written.to_vec(), read.to_vec()),
+                    i2c::Error::CommandComplete);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    /// Panics unless every expected transaction has happened and completed.
+    pub fn assert_done(&self) {
+        let script = self.script.borrow();
+        if !script.is_empty() {
+            panic!("I2C transactions never issued: {:?}", script);
+        }
+        if self.buffer.is_some() {
+            panic!("I2C transaction still in progress");
+        }
+    }
+
+    fn start(&self, op: Op, buffer: &'static mut [u8]) {
+        if self.buffer.is_some() {
+            panic!("I2C {:?} issued while another transaction is in progress", op);
+        }
+        let (expected, result) = match self.script.borrow_mut().pop_front() {
+            Some(next) => next,
+            None => panic!("Unexpected I2C {:?}, no transactions left", op),
+        };
+        if let Some(read) = check(&op, &expected) {
+            buffer[..read.len()].copy_from_slice(read);
+        }
+        self.result.set(result);
+        self.buffer.replace(buffer);
+    }
+}
+
+// Panics unless `op` is the `expected` transaction, and returns the bytes to
+// answer a read with.
+fn check<'a>(op: &Op, expected: &'a Op) -> Option<&'a Vec<u8>> {
+    match (op, expected) {
+        (&Op::Write(ref written), &Op::Write(ref expected)) if written == expected => None,
+        (&Op::Read(ref len), &Op::Read(ref read)) if len.len() == read.len() => Some(read),
+        (&Op::WriteRead(ref written, ref len), &Op::WriteRead(ref expected, ref read))
+            if written == expected && len.len() == read.len() => Some(read),
+        _ => panic!("Unexpected I2C {:?}, expected {:?}", op, expected),
+    }
+}
+
+impl i2c::I2CDevice for Device {
+    fn enable(&self) {
+        self.enabled.set(true);
+    }
+
+    fn disable(&self) {
+        self.enabled.set(false);
+    }
+
+    fn write_read(&self, data: &'static mut [u8], write_len: u8, read_len: u8) {
+        let written = data[..write_len as usize].to_vec();
+        // Reads are reported by length, as there is nothing to compare yet
+        let op = Op::WriteRead(written, vec![0; read_len as usize]);
+        self.start(op, data);
+    }
+
+    fn write(&self, data: &'static mut [u8], len: u8) {
+        let op = Op::Write(data[..len as usize].to_vec());
+        self.start(op, data);
+    }
+
+    fn read(&self, buffer: &'static mut [u8], len: u8) {
+        self.start(Op::Read(vec![0; len as usize]), buffer);
+    }
+}
+
+impl Peripheral for Device {
+    fn has_pending_interrupt(&self) -> bool {
+        self.buffer.is_some()
+    }
+
+    fn handle_interrupt(&self) {
+        self.buffer.take().map(|buffer| {
+            let result = self.result.get();
+            self.client.get().map(move |client| client.command_complete(buffer, result));
+        });
+    }
+}
+
+/// A bus master. Each expected transaction names the address it goes to, and
+/// can be left hanging, as if a slave held the bus, to exercise timeouts.
+pub struct Master {
+    // `None` results never complete
+    script: RefCell<VecDeque<(u8, Op, Option<i2c::Error>)>>,
+    enabled: Cell<bool>,
+    buffer: TakeCell<'static, [u8]>,
+    result: Cell<Option<i2c::Error>>,
+    stuck: Cell<bool>,
+    recoveries: Cell<usize>,
+    client: Cell<Option<&'static i2c::I2CHwMasterClient>>,
+}
+
+impl Master {
+    pub fn new() -> Master {
+        Master {
+            script: RefCell::new(VecDeque::new()),
+            enabled: Cell::new(false),
+            buffer: TakeCell::empty(),
+            result: Cell::new(None),
+            stuck: Cell::new(false),
+            recoveries: Cell::new(0),
+            client: Cell::new(None),
+        }
+    }
+
+    pub fn set_client(&self, client: &'static i2c::I2CHwMasterClient) {
+        self.client.set(Some(client));
+    }
+
+    /// Expects `op` to `addr` next, completing it with `result`.
+    pub fn expect(&self, addr: u8, op: Op, result: i2c::Error) {
+        self.script.borrow_mut().push_back((addr, op, Some(result)));
+    }
+
+    /// Expects `op` to `addr` next, and never completes it.
+    pub fn expect_hang(&self, addr: u8, op: Op) {
+        self.script.borrow_mut().push_back((addr, op, None));
+    }
+
+    /// Makes bus recovery fail while `stuck` is true.
+    pub fn set_stuck(&self, stuck: bool) {
+        self.stuck.set(stuck);
+    }
+
+    /// How many times bus recovery has been attempted.
+    pub fn recoveries(&self) -> usize {
+        self.recoveries.get()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    /// Panics unless every expected transaction has happened and completed.
+    pub fn assert_done(&self) {
+        let script = self.script.borrow();
+        if !script.is_empty() {
+            panic!("I2C transactions never issued: {:?}", script);
+        }
+        if self.buffer.is_some() {
+            panic!("I2C transaction still in progress");
+        }
+    }
+
+    fn start(&self, addr: u8, op: Op, buffer: &'static mut [u8]) {
+        if self.buffer.is_some() {
+            panic!("I2C {:?} to {:#x} issued while another transaction is in progress",
+                   op,
+                   addr);
+        }
+        let (expected_addr, expected, result) = match self.script.borrow_mut().pop_front() {
+            Some(next) => next,
+            None => panic!("Unexpected I2C {:?} to {:#x}, no transactions left", op, addr),
+        };
+        if addr != expected_addr {
+            panic!("Unexpected I2C {:?} to {:#x}, expected {:?} to {:#x}",
+                   op,
+                   addr,
+                   expected,
+                   expected_addr);
+        }
+        if let Some(read) = check(&op, &expected) {
+            buffer[..read.len()].copy_from_slice(read);
+        }
+        self.result.set(result);
+        self.buffer.replace(buffer);
+    }
+}
+
+impl i2c::I2CMaster for Master {
+    fn enable(&self) {
+        self.enabled.set(true);
+    }
+
+    fn disable(&self) {
+        self.enabled.set(false);
+    }
+
+    fn write_read(&self, addr: u8, data: &'static mut [u8], write_len: u8, read_len: u8) {
+        let written = data[..write_len as usize].to_vec();
+        let op = Op::WriteRead(written, vec![0; read_len as usize]);
+        self.start(addr, op, data);
+    }
+
+    fn write(&self, addr: u8, data: &'static mut [u8], len: u8) {
+        let op = Op::Write(data[..len as usize].to_vec());
+        self.start(addr, op, data);
+    }
+
+    fn read(&self, addr: u8, buffer: &'static mut [u8], len: u8) {
+        self.start(addr, Op::Read(vec![0; len as usize]), buffer);
+    }
+
+    fn abort(&self) -> Option<&'static mut [u8]> {
+        self.result.set(None);
+        self.buffer.take()
+    }
+
+    fn recover_bus(&self) -> bool {
+        self.recoveries.set(self.recoveries.get() + 1);
+        !self.stuck.get()
+    }
+}
+
+impl Peripheral for Master {
+    fn has_pending_interrupt(&self) -> bool {
+        self.buffer.is_some() && self.result.get().is_some()
+    }
+
+    fn handle_interrupt(&self) {
+        if let Some(result) = self.result.get() {
+            self.buffer.take().map(|buffer| {
+                self.client.get().map(move |client| client.command_complete(buffer, result));
+            });
+        }
+    }
+}
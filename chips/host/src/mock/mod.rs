@@ -0,0 +1,20 @@
+//! Scriptable mocks of the HILs that capsules talk to, for unit tests.
+//!
+//! Bus mocks are given the transactions a test expects, in order, along with
+//! the bytes the device answers with. A capsule that issues any other
+//! transaction fails the test with a panic naming both. Like the other host
+//! peripherals, mocks complete operations from `handle_interrupt`, so a test
+//! registers them with `Host::add_peripheral` and calls
+//! `Host::run_until_idle`.
+//!
+//! The ADC, alarm, GPIO pin and UART of the host chip are deterministic
+//! already and are re-exported here: ADC samples count up from 0, time only
+//! moves with `Alarm::advance`, and pin interrupts fire with `Pin::drive`.
+
+pub mod i2c;
+pub mod spi;
+
+pub use adc::Adc;
+pub use alarm::Alarm;
+pub use gpio::Pin;
+pub use uart::Uart;
@@ -0,0 +1,338 @@
+//! `hil::spi::SpiMasterDevice` and `hil::spi::SpiMaster` that check each
+//! transfer against a script.
+//!
+//! A transfer is as long as the longer of its expected written and read
+//! bytes. Written bytes past the expected ones are not checked, since
+//! drivers often clock out whatever is left in their buffer while reading,
+//! and read bytes past the expected ones are zero.
+//!
+//! ```rust
+//! let device = host::leak(mock::spi::Device::new());
+//! device.expect_transfer(&[0x05], &[0x00, 0x42]);
+//!
+//! let master = host::leak(mock::spi::Master::new());
+//! master.expect_transfer(1, (ClockPolarity::IdleHigh, ClockPhase::SampleLeading, 400000),
+//!                        &[0x05], &[0x00, 0x42]);
+//! ```
+
+use chip::Peripheral;
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::spi::{self, ClockPhase, ClockPolarity};
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::collections::VecDeque;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Transfer {
+    pub write: Vec<u8>,
+    pub read: Vec<u8>,
+}
+
+impl Transfer {
+    fn new(write: &[u8], read: &[u8]) -> Transfer {
+        Transfer {
+            write: write.to_vec(),
+            read: read.to_vec(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        cmp::max(self.write.len(), self.read.len())
+    }
+}
+
+/// Panics unless a transfer of `len` bytes from `write_buffer` is the
+/// `expected` one, and fills the read buffer with the bytes it answers with.
+/// Returns the length of the transfer.
+fn transfer(expected: Option<Transfer>,
+            write_buffer: &[u8],
+            read_buffer: Option<&mut [u8]>,
+            len: usize)
+            -> usize {
+    let len = read_buffer.as_ref().map_or(len, |read_buffer| cmp::min(len, read_buffer.len()));
+    let len = cmp::min(len, write_buffer.len());
+    let written = &write_buffer[..len];
+    let expected = match expected {
+        Some(expected) => expected,
+        None => panic!("Unexpected SPI transfer of {:?}, no transfers left", written),
+    };
+    if len != expected.len() || !written.starts_with(&expected.write) {
+        panic!("Unexpected SPI transfer of {:?}, expected {:?}", written, expected);
+    }
+    read_buffer.map(|read_buffer| for (i, byte) in read_buffer[..len].iter_mut().enumerate() {
+        *byte = expected.read.get(i).cloned().unwrap_or(0);
+    });
+    len
+}
+
+pub struct Device {
+    script: RefCell<VecDeque<Transfer>>,
+    polarity: Cell<ClockPolarity>,
+    phase: Cell<ClockPhase>,
+    rate: Cell<u32>,
+    write_buffer: TakeCell<'static, [u8]>,
+    read_buffer: TakeCell<'static, [u8]>,
+    len: Cell<usize>,
+    client: Cell<Option<&'static spi::SpiMasterClient>>,
+}
+
+impl Device {
+    pub fn new() -> Device {
+        Device {
+            script: RefCell::new(VecDeque::new()),
+            polarity: Cell::new(ClockPolarity::IdleLow),
+            phase: Cell::new(ClockPhase::SampleLeading),
+            rate: Cell::new(0),
+            write_buffer: TakeCell::empty(),
+            read_buffer: TakeCell::empty(),
+            len: Cell::new(0),
+            client: Cell::new(None),
+        }
+    }
+
+    pub fn set_client(&self, client: &'static spi::SpiMasterClient) {
+        self.client.set(Some(client));
+    }
+
+    /// Expects a transfer that writes `write` and reads back `read`.
+    pub fn expect_transfer(&self, write: &[u8], read: &[u8]) {
+        self.script.borrow_mut().push_back(Transfer::new(write, read));
+    }
+
+    /// Panics unless every expected transfer has happened and completed.
+    pub fn assert_done(&self) {
+        let script = self.script.borrow();
+        if !script.is_empty() {
+            panic!("SPI transfers never issued: {:?}", script);
+        }
+        if self.write_buffer.is_some() {
+            panic!("SPI transfer still in progress");
+        }
+    }
+}
+
+impl spi::SpiMasterDevice for Device {
+    fn configure(&self, cpol: ClockPolarity, cpal: ClockPhase, rate: u32) {
+        self.polarity.set(cpol);
+        self.phase.set(cpal);
+        self.rate.set(rate);
+    }
+
+    fn read_write_bytes(&self,
+                        write_buffer: &'static mut [u8],
+                        read_buffer: Option<&'static mut [u8]>,
+                        len: usize)
+                        -> bool {
+        if self.write_buffer.is_some() {
+            panic!("SPI transfer issued while another is in progress");
+        }
+        let mut read_buffer = read_buffer;
+        let expected = self.script.borrow_mut().pop_front();
+        let len = transfer(expected, write_buffer, read_buffer.as_mut().map(|b| &mut b[..]), len);
+        self.read_buffer.put(read_buffer);
+        self.len.set(len);
+        self.write_buffer.replace(write_buffer);
+        true
+    }
+
+    fn set_polarity(&self, cpol: ClockPolarity) {
+        self.polarity.set(cpol);
+    }
+
+    fn set_phase(&self, cpal: ClockPhase) {
+        self.phase.set(cpal);
+    }
+
+    fn set_rate(&self, rate: u32) {
+        self.rate.set(rate);
+    }
+
+    fn get_polarity(&self) -> ClockPolarity {
+        self.polarity.get()
+    }
+
+    fn get_phase(&self) -> ClockPhase {
+        self.phase.get()
+    }
+
+    fn get_rate(&self) -> u32 {
+        self.rate.get()
+    }
+}
+
+impl Peripheral for Device {
+    fn has_pending_interrupt(&self) -> bool {
+        self.write_buffer.is_some()
+    }
+
+    fn handle_interrupt(&self) {
+        self.write_buffer.take().map(|write_buffer| {
+            let read_buffer = self.read_buffer.take();
+            let len = self.len.get();
+            self.client
+                .get()
+                .map(move |client| client.read_write_done(write_buffer, read_buffer, len));
+        });
+    }
+}
+
+/// The chip select and clock polarity, phase and rate of a transfer on a
+/// `Master`.
+pub type Settings = (u8, ClockPolarity, ClockPhase, u32);
+
+/// A bus master, whose chip selects are numbers. Each expected transfer
+/// names the chip select and settings it must be made with.
+pub struct Master {
+    script: RefCell<VecDeque<(Settings, Transfer)>>,
+    chip_select: Cell<u8>,
+    polarity: Cell<ClockPolarity>,
+    phase: Cell<ClockPhase>,
+    rate: Cell<u32>,
+    write_buffer: TakeCell<'static, [u8]>,
+    read_buffer: TakeCell<'static, [u8]>,
+    len: Cell<usize>,
+    client: Cell<Option<&'static spi::SpiMasterClient>>,
+}
+
+impl Master {
+    pub fn new() -> Master {
+        Master {
+            script: RefCell::new(VecDeque::new()),
+            chip_select: Cell::new(0),
+            polarity: Cell::new(ClockPolarity::IdleLow),
+            phase: Cell::new(ClockPhase::SampleLeading),
+            rate: Cell::new(0),
+            write_buffer: TakeCell::empty(),
+            read_buffer: TakeCell::empty(),
+            len: Cell::new(0),
+            client: Cell::new(None),
+        }
+    }
+
+    /// Expects a transfer to `chip_select` with the given clock polarity,
+    /// phase and rate that writes `write` and reads back `read`.
+    pub fn expect_transfer(&self,
+                           chip_select: u8,
+                           clock: (ClockPolarity, ClockPhase, u32),
+                           write: &[u8],
+                           read: &[u8]) {
+        let (polarity, phase, rate) = clock;
+        self.script
+            .borrow_mut()
+            .push_back(((chip_select, polarity, phase, rate), Transfer::new(write, read)));
+    }
+
+    /// Panics unless every expected transfer has happened and completed.
+    pub fn assert_done(&self) {
+        let script = self.script.borrow();
+        if !script.is_empty() {
+            panic!("SPI transfers never issued: {:?}", script);
+        }
+        if self.write_buffer.is_some() {
+            panic!("SPI transfer still in progress");
+        }
+    }
+}
+
+impl spi::SpiMaster for Master {
+    type ChipSelect = u8;
+
+    fn set_client(&self, client: &'static spi::SpiMasterClient) {
+        self.client.set(Some(client));
+    }
+
+    fn init(&self) {}
+
+    fn is_busy(&self) -> bool {
+        self.write_buffer.is_some()
+    }
+
+    fn read_write_bytes(&self,
+                        write_buffer: &'static mut [u8],
+                        read_buffer: Option<&'static mut [u8]>,
+                        len: usize)
+                        -> bool {
+        if self.write_buffer.is_some() {
+            panic!("SPI transfer issued while another is in progress");
+        }
+        let settings = (self.chip_select.get(),
+                        self.polarity.get(),
+                        self.phase.get(),
+                        self.rate.get());
+        let expected = self.script.borrow_mut().pop_front().map(|(expected_settings, expected)| {
+            if settings != expected_settings {
+                panic!("Unexpected SPI transfer with {:?}, expected {:?}",
+                       settings,
+                       expected_settings);
+            }
+            expected
+        });
+        let mut read_buffer = read_buffer;
+        let len = transfer(expected, write_buffer, read_buffer.as_mut().map(|b| &mut b[..]), len);
+        self.read_buffer.put(read_buffer);
+        self.len.set(len);
+        self.write_buffer.replace(write_buffer);
+        true
+    }
+
+    fn write_byte(&self, _val: u8) {
+        panic!("Unexpected single byte SPI transfer");
+    }
+
+    fn read_byte(&self) -> u8 {
+        panic!("Unexpected single byte SPI transfer");
+    }
+
+    fn read_write_byte(&self, _val: u8) -> u8 {
+        panic!("Unexpected single byte SPI transfer");
+    }
+
+    fn specify_chip_select(&self, cs: u8) {
+        self.chip_select.set(cs);
+    }
+
+    fn set_rate(&self, rate: u32) -> u32 {
+        self.rate.set(rate);
+        rate
+    }
+
+    fn get_rate(&self) -> u32 {
+        self.rate.get()
+    }
+
+    fn set_clock(&self, polarity: ClockPolarity) {
+        self.polarity.set(polarity);
+    }
+
+    fn get_clock(&self) -> ClockPolarity {
+        self.polarity.get()
+    }
+
+    fn set_phase(&self, phase: ClockPhase) {
+        self.phase.set(phase);
+    }
+
+    fn get_phase(&self) -> ClockPhase {
+        self.phase.get()
+    }
+
+    fn hold_low(&self) {}
+
+    fn release_low(&self) {}
+}
+
+impl Peripheral for Master {
+    fn has_pending_interrupt(&self) -> bool {
+        self.write_buffer.is_some()
+    }
+
+    fn handle_interrupt(&self) {
+        self.write_buffer.take().map(|write_buffer| {
+            let read_buffer = self.read_buffer.take();
+            let len = self.len.get();
+            self.client
+                .get()
+                .map(move |client| client.read_write_done(write_buffer, read_buffer, len));
+        });
+    }
+}
@@ -0,0 +1,147 @@
+//! `hil::uart::UART` on stdin/stdout or in-memory buffers.
+//!
+//! A UART created with `Uart::stdio` writes transmitted bytes to stdout and
+//! receives bytes typed on stdin. One created with `Uart::new` instead
+//! receives bytes passed to `push_input` and collects transmitted bytes for
+//! `take_output`, which suits tests.
+
+use chip::Peripheral;
+use kernel::ReturnCode;
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::uart;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+pub struct Uart {
+    client: Cell<Option<&'static uart::Client>>,
+    stdio: bool,
+    stdin: RefCell<Option<Receiver<u8>>>,
+    input: RefCell<VecDeque<u8>>,
+    output: RefCell<Vec<u8>>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+}
+
+impl Uart {
+    /// A UART whose input and output are in memory.
+    pub fn new() -> Uart {
+        Uart {
+            client: Cell::new(None),
+            stdio: false,
+            stdin: RefCell::new(None),
+            input: RefCell::new(VecDeque::new()),
+            output: RefCell::new(Vec::new()),
+            tx_buffer: TakeCell::empty(),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+        }
+    }
+
+    /// A UART connected to stdin and stdout.
+    pub fn stdio() -> Uart {
+        Uart { stdio: true, ..Uart::new() }
+    }
+
+    /// Queues bytes to be received, as if they had arrived on the line.
+    pub fn push_input(&self, bytes: &[u8]) {
+        self.input.borrow_mut().extend(bytes.iter());
+    }
+
+    /// Returns and clears the bytes transmitted so far by an in-memory UART.
+    pub fn take_output(&self) -> Vec<u8> {
+        self.output.borrow_mut().split_off(0)
+    }
+
+    /// Moves bytes read from stdin by the reader thread into `input`.
+    fn poll_stdin(&self) {
+        if let Some(ref stdin) = *self.stdin.borrow() {
+            let mut input = self.input.borrow_mut();
+            while let Ok(byte) = stdin.try_recv() {
+                input.push_back(byte);
+            }
+        }
+    }
+
+    fn rx_ready(&self) -> bool {
+        self.poll_stdin();
+        self.rx_buffer.is_some() && self.input.borrow().len() >= self.rx_len.get()
+    }
+}
+
+impl uart::UART for Uart {
+    fn set_client(&self, client: &'static uart::Client) {
+        self.client.set(Some(client));
+    }
+
+    fn init(&self, _params: uart::UARTParams) -> ReturnCode {
+        if self.stdio && self.stdin.borrow().is_none() {
+            // Reading stdin blocks, so do it on a thread and hand the bytes
+            // over to be picked up when the chip polls for events
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || for byte in io::stdin().bytes() {
+                match byte {
+                    Ok(byte) => {
+                        if sender.send(byte).is_err() {
+                            break;
+                        }
+                    }
+                    Err(_) => break,
+                }
+            });
+            *self.stdin.borrow_mut() = Some(receiver);
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn transmit(&self, tx_data: &'static mut [u8], tx_len: usize) {
+        let len = ::std::cmp::min(tx_len, tx_data.len());
+        if self.stdio {
+            let mut stdout = io::stdout();
+            let _ = stdout.write_all(&tx_data[..len]);
+            let _ = stdout.flush();
+        } else {
+            self.output.borrow_mut().extend_from_slice(&tx_data[..len]);
+        }
+        self.tx_buffer.replace(tx_data);
+    }
+
+    fn receive(&self, rx_buffer: &'static mut [u8], rx_len: usize) {
+        self.rx_len.set(::std::cmp::min(rx_len, rx_buffer.len()));
+        self.rx_buffer.replace(rx_buffer);
+    }
+}
+
+impl Peripheral for Uart {
+    fn has_pending_interrupt(&self) -> bool {
+        self.tx_buffer.is_some() || self.rx_ready()
+    }
+
+    fn handle_interrupt(&self) {
+        if let Some(buffer) = self.tx_buffer.take() {
+            self.client
+                .get()
+                .map(move |client| client.transmit_complete(buffer, uart::Error::CommandComplete));
+        }
+
+        if self.rx_ready() {
+            self.rx_buffer.take().map(|buffer| {
+                let len = self.rx_len.get();
+                {
+                    let mut input = self.input.borrow_mut();
+                    for byte in buffer[..len].iter_mut() {
+                        *byte = input.pop_front().unwrap_or(0);
+                    }
+                }
+                self.client
+                    .get()
+                    .map(move |client| {
+                        client.receive_complete(buffer, len, uart::Error::CommandComplete)
+                    });
+            });
+        }
+    }
+}
@@ -97,6 +97,10 @@ impl Alarm for Rtc {
     fn get_alarm(&self) -> u32 {
         rtc1().cc[0].get()
     }
+
+    fn counter_mask(&self) -> u32 {
+        0x00ffffff
+    }
 }
 
 #[no_mangle]
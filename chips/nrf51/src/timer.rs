@@ -231,7 +231,7 @@ impl Timer {
                 if self.timer().event_compare[i].get() != 0 {
                     val = val | 1 << i;
                     self.timer().event_compare[i].set(0);
-                    self.disable_interrupts(1 << (i + 16));
+                    self.disable_interrupts(1 << i);
                 }
             }
             client.compare(val as u8);
@@ -1,12 +1,25 @@
+//! UART driver for the nRF51.
+//!
+//! Transmission and reception are interrupt-driven. Reception reports
+//! overrun, parity and framing errors, and supports `UARTAdvanced`:
+//! `receive_automatic` needs a TIMER, given with `set_timeout_timer`, to
+//! time the gap between bytes.
+//!
+//! The receiver runs from the first receive on, so bytes that arrive
+//! between receives wait in the UART's small FIFO rather than being lost.
+
 use chip;
 use core::cell::Cell;
+use core::cmp;
 use core::mem;
 use kernel::common::VolatileCell;
+use kernel::ReturnCode;
 use kernel::common::take_cell::TakeCell;
 use kernel::hil::uart;
 use nvic;
 use peripheral_interrupts::NvicIdx;
 use pinmux::Pinmux;
+use timer::{CompareClient, Timer};
 
 #[repr(C, packed)]
 pub struct Registers {
@@ -52,12 +65,45 @@ pub struct Registers {
 
 const UART_BASE: u32 = 0x40002000;
 
+// Interrupt enable bits
+const INT_RXDRDY: u32 = 1 << 2;
+const INT_TXDRDY: u32 = 1 << 7;
+const INT_ERROR: u32 = 1 << 9;
+
+// ERRORSRC bits
+const ERROR_OVERRUN: u32 = 1 << 0;
+const ERROR_PARITY: u32 = 1 << 1;
+const ERROR_FRAMING: u32 = 1 << 2;
+const ERROR_BREAK: u32 = 1 << 3;
+
+// The timeout TIMER counts at 1 MHz, from the 16 MHz HFCLK
+const TIMER_PRESCALER: u8 = 4;
+const TIMER_FREQUENCY: u32 = 1000000;
+// SHORTS bit that stops the timer when compare 0 fires
+const TIMER_COMPARE0_STOP: u32 = 1 << 8;
+
+/// When a receive completes.
+#[derive(Copy, Clone, PartialEq)]
+enum RxMode {
+    Idle,
+    Length,
+    Terminator(u8),
+    Automatic(u8),
+}
+
 pub struct UART {
     regs: *mut Registers,
     client: Cell<Option<&'static uart::Client>>,
     buffer: TakeCell<'static, [u8]>,
     len: Cell<usize>,
     index: Cell<usize>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_index: Cell<usize>,
+    rx_mode: Cell<RxMode>,
+    rx_started: Cell<bool>,
+    baud_rate: Cell<u32>,
+    timer: Cell<Option<&'static Timer>>,
 }
 
 #[derive(Copy, Clone)]
@@ -80,9 +126,24 @@ impl UART {
             buffer: TakeCell::empty(),
             len: Cell::new(0),
             index: Cell::new(0),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_index: Cell::new(0),
+            rx_mode: Cell::new(RxMode::Idle),
+            rx_started: Cell::new(false),
+            baud_rate: Cell::new(115200),
+            timer: Cell::new(None),
         }
     }
 
+    /// Gives the UART a TIMER to time `receive_automatic` with. The board
+    /// must also make the UART the timer's client.
+    pub fn set_timeout_timer(&self, timer: &'static Timer) {
+        timer.set_prescaler(TIMER_PRESCALER);
+        timer.set_shortcuts(TIMER_COMPARE0_STOP);
+        self.timer.set(Some(timer));
+    }
+
     pub fn configure(&self, tx: Pinmux, rx: Pinmux, cts: Pinmux, rts: Pinmux) {
         let regs: &mut Registers = unsafe { mem::transmute(self.regs) };
 
@@ -94,6 +155,7 @@ impl UART {
 
     fn set_baud_rate(&self, baud_rate: u32) {
         let regs: &mut Registers = unsafe { mem::transmute(self.regs) };
+        self.baud_rate.set(baud_rate);
         match baud_rate {
             1200 => regs.baudrate.set(0x0004F000),
             2400 => regs.baudrate.set(0x0009D000),
@@ -110,7 +172,11 @@ impl UART {
             250000 => regs.baudrate.set(0x04000000),
             460800 => regs.baudrate.set(0x075F7000),
             1000000 => regs.baudrate.set(0x10000000),
-            _ => regs.baudrate.set(0x01D7E000), //setting default to 115200
+            _ => {
+                //setting default to 115200
+                self.baud_rate.set(115200);
+                regs.baudrate.set(0x01D7E000)
+            }
         }
     }
 
@@ -129,35 +195,55 @@ impl UART {
 
     pub fn enable_rx_interrupts(&self) {
         let regs: &mut Registers = unsafe { mem::transmute(self.regs) };
-        regs.intenset.set(1 << 3 as u32);
+        regs.intenset.set(INT_RXDRDY | INT_ERROR);
     }
 
     pub fn enable_tx_interrupts(&self) {
         let regs: &mut Registers = unsafe { mem::transmute(self.regs) };
-        regs.intenset.set(1 << 7 as u32);
+        regs.intenset.set(INT_TXDRDY);
     }
 
     pub fn disable_rx_interrupts(&self) {
         let regs: &mut Registers = unsafe { mem::transmute(self.regs) };
-        regs.intenclr.set(1 << 3 as u32);
+        regs.intenclr.set(INT_RXDRDY | INT_ERROR);
     }
 
     pub fn disable_tx_interrupts(&self) {
         let regs: &mut Registers = unsafe { mem::transmute(self.regs) };
-        regs.intenclr.set(1 << 7 as u32);
+        regs.intenclr.set(INT_TXDRDY);
     }
 
     pub fn handle_interrupt(&mut self) {
         let regs: &Registers = unsafe { mem::transmute(self.regs) };
-        // let rx = regs.event_rxdrdy.get() != 0;
         let tx = regs.event_txdrdy.get() != 0;
 
-        // if rx {
-        //     let val = regs.rxd.get();
-        //     self.client.map(|client| {
-        //         client.read_done(val as u8);
-        //     });
-        // }
+        if regs.event_error.get() != 0 && self.rx_mode.get() != RxMode::Idle {
+            regs.event_error.set(0);
+            let source = regs.errorsrc.get();
+            // ERRORSRC bits are cleared by writing 1 to them
+            regs.errorsrc.set(source);
+            let error = if source & ERROR_OVERRUN != 0 {
+                uart::Error::OverrunError
+            } else if source & ERROR_PARITY != 0 {
+                uart::Error::ParityError
+            } else if source & (ERROR_FRAMING | ERROR_BREAK) != 0 {
+                uart::Error::FramingError
+            } else {
+                uart::Error::CommandComplete
+            };
+            if error != uart::Error::CommandComplete {
+                self.complete_receive(error);
+            }
+        }
+
+        while regs.event_rxdrdy.get() != 0 && self.rx_mode.get() != RxMode::Idle {
+            // The event must be cleared before RXD is read, so it is raised
+            // again if more bytes are waiting in the FIFO
+            regs.event_rxdrdy.set(0);
+            let byte = regs.rxd.get() as u8;
+            self.receive_byte(byte);
+        }
+
         if tx {
             regs.event_txdrdy.set(0 as u32);
 
@@ -201,9 +287,98 @@ impl UART {
         regs.event_txdrdy.get() & 0b1 != 0
     }
 
-    fn rx_ready(&self) -> bool {
+    /// Stores a received byte, completing the receive if it is done.
+    fn receive_byte(&self, byte: u8) {
+        let index = self.rx_index.get();
+        self.rx_buffer.map(|buffer| buffer[index] = byte);
+        self.rx_index.set(index + 1);
+
+        let full = index + 1 >= self.rx_len.get();
+        let done = match self.rx_mode.get() {
+            RxMode::Idle => false,
+            RxMode::Length | RxMode::Automatic(_) => full,
+            RxMode::Terminator(terminator) => byte == terminator || full,
+        };
+        if done {
+            self.complete_receive(uart::Error::CommandComplete);
+        } else {
+            self.restart_timeout();
+        }
+    }
+
+    /// Starts, or restarts, timing the gap after the last byte received.
+    fn restart_timeout(&self) {
+        if let RxMode::Automatic(interbyte_timeout) = self.rx_mode.get() {
+            self.timer.get().map(|timer| {
+                let ticks = interbyte_timeout as u32 * TIMER_FREQUENCY / self.baud_rate.get();
+                // The TIMER is 16 bits wide
+                timer.stop();
+                timer.clear();
+                timer.set_cc0(cmp::max(1, cmp::min(ticks, 0xffff)));
+                timer.enable_interrupts(1);
+                timer.enable_nvic();
+                timer.start();
+            });
+        }
+    }
+
+    fn start_receive(&self, rx_buffer: &'static mut [u8], rx_len: usize, mode: RxMode) {
         let regs: &Registers = unsafe { mem::transmute(self.regs) };
-        regs.event_rxdrdy.get() & 0b1 != 0
+        if self.rx_mode.get() != RxMode::Idle {
+            self.client.get().map(move |client| {
+                client.receive_complete(rx_buffer, 0, uart::Error::RepeatCallError);
+            });
+            return;
+        }
+        let rx_len = cmp::min(rx_len, rx_buffer.len());
+        if rx_len == 0 {
+            self.client.get().map(move |client| {
+                client.receive_complete(rx_buffer, 0, uart::Error::CommandComplete);
+            });
+            return;
+        }
+
+        self.rx_buffer.replace(rx_buffer);
+        self.rx_len.set(rx_len);
+        self.rx_index.set(0);
+        self.rx_mode.set(mode);
+
+        // Errors from while no receive was outstanding do not belong to this
+        // one. Bytes already in the FIFO do, and raise RXDRDY once enabled.
+        regs.event_error.set(0);
+        regs.errorsrc.set(regs.errorsrc.get());
+        if !self.rx_started.get() {
+            self.rx_started.set(true);
+            regs.event_rxdrdy.set(0);
+            regs.task_startrx.set(1);
+        }
+        self.enable_rx_interrupts();
+        self.enable_nvic();
+    }
+
+    fn complete_receive(&self, error: uart::Error) {
+        self.disable_rx_interrupts();
+        self.timer.get().map(|timer| {
+            timer.stop();
+            timer.disable_interrupts(1);
+        });
+        self.rx_mode.set(RxMode::Idle);
+        self.rx_buffer.take().map(|buffer| {
+            self.client.get().map(move |client| {
+                client.receive_complete(buffer, self.rx_index.get(), error);
+            });
+        });
+    }
+}
+
+impl CompareClient for UART {
+    /// The gap after the last byte ended a `receive_automatic`.
+    fn compare(&self, bitmask: u8) {
+        if let RxMode::Automatic(_) = self.rx_mode.get() {
+            if bitmask & 1 != 0 && self.rx_index.get() > 0 {
+                self.complete_receive(uart::Error::CommandComplete);
+            }
+        }
     }
 }
 
@@ -212,9 +387,22 @@ impl uart::UART for UART {
         self.client.set(Some(client));
     }
 
-    fn init(&self, params: uart::UARTParams) {
+    /// The nRF51 can only add an even parity bit and send one stop bit, so
+    /// odd parity and two stop bits get `ENOSUPPORT`.
+    fn init(&self, params: uart::UARTParams) -> ReturnCode {
+        let regs: &mut Registers = unsafe { mem::transmute(self.regs) };
+        let parity = match params.parity {
+            uart::Parity::None => 0,
+            uart::Parity::Even => 0x7 << 1,
+            uart::Parity::Odd => return ReturnCode::ENOSUPPORT,
+        };
+        if let uart::StopBits::Two = params.stop_bits {
+            return ReturnCode::ENOSUPPORT;
+        }
+        regs.config.set(parity | params.hw_flow_control as u32);
         self.enable();
         self.set_baud_rate(params.baud_rate);
+        ReturnCode::SUCCESS
     }
 
     fn transmit(&self, tx_data: &'static mut [u8], tx_len: usize) {
@@ -236,14 +424,27 @@ impl uart::UART for UART {
     }
 
     fn receive(&self, rx_buffer: &'static mut [u8], rx_len: usize) {
-        let regs: &Registers = unsafe { mem::transmute(self.regs) };
-        regs.task_startrx.set(1);
-        let mut i = 0;
-        while i < rx_len {
-            while !self.rx_ready() {}
-            rx_buffer[i] = regs.rxd.get() as u8;
-            i += 1;
+        self.start_receive(rx_buffer, rx_len, RxMode::Length);
+    }
+}
+
+impl uart::UARTAdvanced for UART {
+    /// Completes right away with `Unsupported` if the board gave the UART no
+    /// timer.
+    fn receive_automatic(&self, rx_buffer: &'static mut [u8], interbyte_timeout: u8) {
+        if self.timer.get().is_none() {
+            self.client.get().map(move |client| {
+                client.receive_complete(rx_buffer, 0, uart::Error::Unsupported);
+            });
+            return;
         }
+        let len = rx_buffer.len();
+        self.start_receive(rx_buffer, len, RxMode::Automatic(interbyte_timeout));
+    }
+
+    fn receive_until_terminator(&self, rx_buffer: &'static mut [u8], terminator: u8) {
+        let len = rx_buffer.len();
+        self.start_receive(rx_buffer, len, RxMode::Terminator(terminator));
     }
 }
 
@@ -27,12 +27,20 @@
 //
 //
 //
+// Continuous sampling triggers conversions from the ADC's internal timer,
+// and the PDCA copies each result from LCV into the buffers, swapping
+// between them with its reload registers. The ADC clock is RCSYS divided by
+// 4, which limits the sampling frequency to MAX_FREQUENCY.
+//
 // Author: Philip Levis <pal@cs.stanford.edu>
 // Date: August 5, 2015
 //
 
 use core::cell::Cell;
 use core::mem;
+use core::slice;
+use dma::{DMAChannel, DMAClient, DMAPeripheral, DMAWidth};
+use kernel::common::take_cell::TakeCell;
 use kernel::common::volatile_cell::VolatileCell;
 use kernel::hil;
 use kernel::hil::adc;
@@ -67,11 +75,36 @@ pub struct AdcRegisters {
 // Page 59 of SAM4L data sheet
 const BASE_ADDRESS: *mut AdcRegisters = 0x40038000 as *mut AdcRegisters;
 
+/// The ADC clock: RCSYS (115kHz) divided by the prescaler of 4.
+const ADC_CLOCK_HZ: u32 = 115000 / 4;
+
+/// The fastest continuous sampling, as a 12-bit conversion takes 6 cycles
+/// of the ADC clock.
+pub const MAX_FREQUENCY: u32 = ADC_CLOCK_HZ / 6;
+
 pub struct Adc {
     registers: *mut AdcRegisters,
     enabled: Cell<bool>,
     channel: Cell<u8>,
     client: Cell<Option<&'static hil::adc::Client>>,
+    dma: Cell<Option<&'static DMAChannel>>,
+    continuous_client: Cell<Option<&'static hil::adc::ContinuousClient>>,
+    sampling_continuous: Cell<bool>,
+    // Lengths of the buffer being filled and the one queued after it
+    length: Cell<usize>,
+    queued_length: Cell<usize>,
+    // Buffers held after sampling was cancelled
+    stopped_buffer: TakeCell<'static, [u16]>,
+    stopped_queued_buffer: TakeCell<'static, [u16]>,
+}
+
+// The PDCA moves bytes, but the buffers hold 16-bit samples.
+fn samples_as_bytes(buffer: &'static mut [u16]) -> &'static mut [u8] {
+    unsafe { slice::from_raw_parts_mut(buffer.as_mut_ptr() as *mut u8, buffer.len() * 2) }
+}
+
+fn bytes_as_samples(buffer: &'static mut [u8]) -> &'static mut [u16] {
+    unsafe { slice::from_raw_parts_mut(buffer.as_mut_ptr() as *mut u16, buffer.len() / 2) }
 }
 
 pub static mut ADC: Adc = Adc::new(BASE_ADDRESS);
@@ -83,6 +116,13 @@ impl Adc {
             enabled: Cell::new(false),
             channel: Cell::new(0),
             client: Cell::new(None),
+            dma: Cell::new(None),
+            continuous_client: Cell::new(None),
+            sampling_continuous: Cell::new(false),
+            length: Cell::new(0),
+            queued_length: Cell::new(0),
+            stopped_buffer: TakeCell::empty(),
+            stopped_queued_buffer: TakeCell::empty(),
         }
     }
 
@@ -90,6 +130,44 @@ impl Adc {
         self.client.set(Some(client));
     }
 
+    pub fn set_continuous_client<C: hil::adc::ContinuousClient>(&self, client: &'static C) {
+        self.continuous_client.set(Some(client));
+    }
+
+    pub fn set_dma(&self, dma: &'static DMAChannel) {
+        self.dma.set(Some(dma));
+    }
+
+    // Sets up the sequencer for a single-ended conversion of `channel`,
+    // started by `trigger`
+    fn configure_sequencer(&self, channel: u8, trigger: u32) {
+        let regs: &mut AdcRegisters = unsafe { mem::transmute(self.registers) };
+        // This configuration sets the ADC to use Pad Ground as the
+        // negative input, and the ADC channel as the positive. Since
+        // this is a single-ended sample, the bipolar bit is set to zero.
+        // Gain is 0.5x (set to 111). Resolution is set to 12 bits
+        // (set to 0).
+
+        let chan_field: u32 = (channel as u32) << 16;
+        let mut cfg: u32 = chan_field;
+        cfg |= 0x00700000; // MUXNEG   = 111 (ground pad)
+        cfg |= 0x00008000; // INTERNAL =  10 (int neg, ext pos)
+        cfg |= 0x00000000; // RES      =   0 (12-bit)
+        cfg |= trigger << 8; // TRGSEL
+        cfg |= 0x00000000; // GCOMP    =   0 (no gain error corr)
+        cfg |= 0x00000070; // GAIN     = 111 (0.5x gain)
+        cfg |= 0x00000000; // BIPOLAR  =   0 (not bipolar)
+        cfg |= 0x00000000; // HWLA     =   0 (no left justify value)
+        regs.seqcfg.set(cfg);
+    }
+
+    fn stop_timer(&self) {
+        let regs: &mut AdcRegisters = unsafe { mem::transmute(self.registers) };
+        // TSTOP
+        regs.cr.set(1 << 1);
+        self.sampling_continuous.set(false);
+    }
+
     pub fn handle_interrupt(&mut self) {
         let val: u16;
         let regs: &mut AdcRegisters = unsafe { mem::transmute(self.registers) };
@@ -156,26 +234,14 @@ impl adc::AdcSingle for Adc {
             return ReturnCode::EOFF;
         } else if channel > 14 {
             return ReturnCode::EINVAL;
+        } else if self.sampling_continuous.get() {
+            return ReturnCode::EBUSY;
         } else {
             self.channel.set(channel);
-            // This configuration sets the ADC to use Pad Ground as the
-            // negative input, and the ADC channel as the positive. Since
-            // this is a single-ended sample, the bipolar bit is set to zero.
-            // Trigger select is set to zero because this denotes a software
-            // sample. Gain is 0.5x (set to 111). Resolution is set to 12 bits
-            // (set to 0).
-
-            let chan_field: u32 = (self.channel.get() as u32) << 16;
-            let mut cfg: u32 = chan_field;
-            cfg |= 0x00700000; // MUXNEG   = 111 (ground pad)
-            cfg |= 0x00008000; // INTERNAL =  10 (int neg, ext pos)
-            cfg |= 0x00000000; // RES      =   0 (12-bit)
-            cfg |= 0x00000000; // TRGSEL   =   0 (software)
-            cfg |= 0x00000000; // GCOMP    =   0 (no gain error corr)
-            cfg |= 0x00000070; // GAIN     = 111 (0.5x gain)
-            cfg |= 0x00000000; // BIPOLAR  =   0 (not bipolar)
-            cfg |= 0x00000000; // HWLA     =   0 (no left justify value)
-            regs.seqcfg.set(cfg);
+            // Trigger select is zero, a software trigger
+            self.configure_sequencer(channel, 0);
+            // Clear any end of conversion left from continuous sampling
+            regs.scr.set(0x0000001);
             // Enable end of conversion interrupt
             regs.ier.set(1);
             // Initiate conversion
@@ -187,22 +253,113 @@ impl adc::AdcSingle for Adc {
     fn cancel_sample(&self) -> ReturnCode {
         return ReturnCode::FAIL;
     }
+
+    fn channel_count(&self) -> usize {
+        15
+    }
 }
 
-/// Not implemented yet. -pal 12/22/16
 impl adc::AdcContinuous for Adc {
-    type Frequency = adc::Freq1KHz;
+    fn sample_continuous(&self,
+                         channel: u8,
+                         frequency: u32,
+                         buffer: &'static mut [u16],
+                         length: usize)
+                         -> (ReturnCode, Option<&'static mut [u16]>) {
+        let regs: &mut AdcRegisters = unsafe { mem::transmute(self.registers) };
+        let dma = match self.dma.get() {
+            Some(dma) => dma,
+            None => return (ReturnCode::ENOSUPPORT, Some(buffer)),
+        };
+        if !self.enabled.get() {
+            return (ReturnCode::EOFF, Some(buffer));
+        } else if channel > 14 || frequency == 0 || frequency > MAX_FREQUENCY ||
+                  length == 0 || length > buffer.len() {
+            return (ReturnCode::EINVAL, Some(buffer));
+        } else if self.sampling_continuous.get() || regs.imr.get() & 0x01 != 0 {
+            // Already sampling, or waiting on a single sample
+            return (ReturnCode::EBUSY, Some(buffer));
+        }
+
+        self.sampling_continuous.set(true);
+        self.length.set(length);
+        dma.enable();
+        dma.set_width(DMAWidth::Width16Bit);
+        dma.do_xfer(DMAPeripheral::ADCIFE_RX, samples_as_bytes(buffer), length);
 
-    fn compute_interval(&self, interval: u32) -> u32 {
-        interval
+        // Trigger select is one, the internal timer
+        self.configure_sequencer(channel, 1);
+        regs.itimer.set(ADC_CLOCK_HZ / frequency - 1);
+        // TSTART
+        regs.cr.set(1 << 2);
+        (ReturnCode::SUCCESS, None)
     }
 
-    fn sample_continuous(&self, _channel: u8, _interval: u32) -> ReturnCode {
-        ReturnCode::FAIL
+    fn provide_buffer(&self,
+                      buffer: &'static mut [u16],
+                      length: usize)
+                      -> (ReturnCode, Option<&'static mut [u16]>) {
+        let dma = match self.dma.get() {
+            Some(dma) => dma,
+            None => return (ReturnCode::ENOSUPPORT, Some(buffer)),
+        };
+        if !self.sampling_continuous.get() {
+            return (ReturnCode::EOFF, Some(buffer));
+        } else if length == 0 || length > buffer.len() {
+            return (ReturnCode::EINVAL, Some(buffer));
+        } else if dma.has_queued_xfer() {
+            return (ReturnCode::EBUSY, Some(buffer));
+        }
+
+        self.queued_length.set(length);
+        dma.queue_xfer(samples_as_bytes(buffer), length);
+        (ReturnCode::SUCCESS, None)
     }
 
     fn cancel_sampling(&self) -> ReturnCode {
-        ReturnCode::FAIL
+        if !self.sampling_continuous.get() {
+            return ReturnCode::EALREADY;
+        }
+        self.stop_timer();
+        self.dma.get().map(|dma| {
+            self.stopped_buffer.put(dma.abort_xfer().map(bytes_as_samples));
+            self.stopped_queued_buffer.put(dma.abort_queued_xfer().map(bytes_as_samples));
+            dma.disable();
+        });
+        ReturnCode::SUCCESS
+    }
+
+    fn retrieve_buffers(&self) -> (Option<&'static mut [u16]>, Option<&'static mut [u16]>) {
+        (self.stopped_buffer.take(), self.stopped_queued_buffer.take())
+    }
+}
+
+impl DMAClient for Adc {
+    fn xfer_done(&self, _pid: DMAPeripheral) {
+        if !self.sampling_continuous.get() {
+            // Cancelled since
+            return;
+        }
+        self.dma.get().map(|dma| {
+            let length = self.length.get();
+            if let Some(buffer) = dma.next_xfer() {
+                // The channel moved on to the queued buffer
+                self.length.set(self.queued_length.get());
+                self.continuous_client
+                    .get()
+                    .map(|client| client.samples_ready(bytes_as_samples(buffer), length));
+            } else if dma.transfer_counter() == 0 {
+                // Out of buffers
+                self.stop_timer();
+                let buffer = dma.abort_xfer();
+                dma.disable();
+                buffer.map(|buffer| {
+                    self.continuous_client
+                        .get()
+                        .map(|client| client.samples_ready(bytes_as_samples(buffer), length));
+                });
+            }
+        });
     }
 }
 
@@ -56,6 +56,9 @@ impl Sam4l {
         i2c::I2C2.set_dma(&dma::DMA_CHANNELS[12]);
         dma::DMA_CHANNELS[12].client = Some(&mut i2c::I2C2);
 
+        adc::ADC.set_dma(&dma::DMA_CHANNELS[13]);
+        dma::DMA_CHANNELS[13].client = Some(&mut adc::ADC);
+
         Sam4l {
             mpu: cortexm4::mpu::MPU::new(),
             systick: cortexm4::systick::SysTick::new(),
@@ -109,6 +109,15 @@ pub enum DMAPeripheral {
     LCDCA_ABMDR_TX = 38,
 }
 
+/// The size of each item a channel moves, set in the mode register
+/// (Section 16.6.7). Transfer lengths count items of this size.
+#[derive(Copy, Clone, PartialEq)]
+pub enum DMAWidth {
+    Width8Bit = 0,
+    Width16Bit = 1,
+    Width32Bit = 2,
+}
+
 pub static mut DMA_CHANNELS: [DMAChannel; 16] =
     [DMAChannel::new(DMAChannelNum::DMAChannel00, nvic::NvicIdx::PDCA0),
      DMAChannel::new(DMAChannelNum::DMAChannel01, nvic::NvicIdx::PDCA1),
@@ -132,7 +141,11 @@ pub struct DMAChannel {
     nvic: nvic::NvicIdx,
     pub client: Option<&'static mut DMAClient>,
     enabled: Cell<bool>,
+    width: Cell<DMAWidth>,
     buffer: TakeCell<'static, [u8]>,
+    // The buffer in the reload registers, which the channel moves on to
+    // once `buffer` is done
+    queued_buffer: TakeCell<'static, [u8]>,
 }
 
 pub trait DMAClient {
@@ -146,7 +159,9 @@ impl DMAChannel {
             nvic: nvic,
             client: None,
             enabled: Cell::new(false),
+            width: Cell::new(DMAWidth::Width8Bit),
             buffer: TakeCell::empty(),
+            queued_buffer: TakeCell::empty(),
         }
     }
 
@@ -202,10 +217,18 @@ impl DMAChannel {
         registers.control.set(0x1);
     }
 
+    /// Sets the size of the items later transfers move.
+    pub fn set_width(&self, width: DMAWidth) {
+        let registers: &mut DMARegisters = unsafe { mem::transmute(self.registers) };
+        registers.mode.set(width as u32);
+        self.width.set(width);
+    }
+
     pub fn prepare_xfer(&self, pid: DMAPeripheral, buf: &'static mut [u8], mut len: usize) {
         // TODO(alevy): take care of zero length case
-        if len > buf.len() {
-            len = buf.len();
+        let max_len = buf.len() >> (self.width.get() as usize);
+        if len > max_len {
+            len = max_len;
         }
 
         let registers: &mut DMARegisters = unsafe { mem::transmute(self.registers) };
@@ -225,6 +248,45 @@ impl DMAChannel {
         self.start_xfer();
     }
 
+    /// Puts a buffer in the reload registers, for the channel to move on to
+    /// as soon as the current transfer is done. The client's `xfer_done` is
+    /// called when the channel does, and should then call `next_xfer`.
+    pub fn queue_xfer(&self, buf: &'static mut [u8], mut len: usize) {
+        let max_len = buf.len() >> (self.width.get() as usize);
+        if len > max_len {
+            len = max_len;
+        }
+
+        let registers: &mut DMARegisters = unsafe { mem::transmute(self.registers) };
+        registers.memory_address_reload.set(&buf[0] as *const u8 as u32);
+        registers.transfer_counter_reload.set(len as u32);
+
+        // Reload counter zero, as well as transfer complete
+        registers.interrupt_enable.set((1 << 0) | (1 << 1));
+
+        self.queued_buffer.replace(buf);
+    }
+
+    /// Whether a buffer is waiting in the reload registers.
+    pub fn has_queued_xfer(&self) -> bool {
+        let registers: &mut DMARegisters = unsafe { mem::transmute(self.registers) };
+        self.queued_buffer.is_some() && registers.transfer_counter_reload.get() != 0
+    }
+
+    /// If the channel has moved on to the queued buffer, returns the buffer
+    /// it finished.
+    pub fn next_xfer(&self) -> Option<&'static mut [u8]> {
+        let registers: &mut DMARegisters = unsafe { mem::transmute(self.registers) };
+        if self.queued_buffer.is_none() || registers.transfer_counter_reload.get() != 0 {
+            return None;
+        }
+        let finished = self.buffer.take();
+        self.queued_buffer.take().map(|buf| self.buffer.replace(buf));
+        // The interrupt handler disabled all interrupts
+        registers.interrupt_enable.set(1 << 1);
+        finished
+    }
+
     /// Aborts any current transactions and returns the buffer used in the
     /// transaction.
     pub fn abort_xfer(&self) -> Option<&'static mut [u8]> {
@@ -237,6 +299,14 @@ impl DMAChannel {
         self.buffer.take()
     }
 
+    /// Drops the queued transfer, if any, and returns its buffer.
+    pub fn abort_queued_xfer(&self) -> Option<&'static mut [u8]> {
+        let registers: &mut DMARegisters = unsafe { mem::transmute(self.registers) };
+        registers.transfer_counter_reload.set(0);
+
+        self.queued_buffer.take()
+    }
+
     pub fn transfer_counter(&self) -> usize {
         let registers: &mut DMARegisters = unsafe { mem::transmute(self.registers) };
         registers.transfer_counter.get() as usize
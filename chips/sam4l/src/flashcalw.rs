@@ -10,10 +10,16 @@
 //! be generated after a command is complete, it doesn't appear to occur for some
 //! commands.
 //!
-//! A clean interface for reading from flash, writing pages and erasing pages is
-//! defined below and should be used to handle the complexity of these tasks.
+//! The controller implements `hil::flash::Flash`, which should be used to
+//! handle the complexity of these tasks. Reads copy straight from the
+//! memory-mapped flash and complete from a deferred call. Writes and erases
+//! run page by page, each page being unlocked, erased, written from the page
+//! buffer and locked again, and complete once the last page is done.
+//! Programs run like writes but skip the erase, so the page buffer only
+//! clears bits of the page.
 //!
-//! The driver should be configure()'d before use, and a Client should be set to
+//! The driver should be configure()'d before use, its deferred call
+//! registered with register_deferred_call(), and a Client should be set to
 //! enable a callback after a command is completed.
 //!
 //! Almost all of the flash controller functionality is implemented (except for
@@ -26,7 +32,9 @@
 use core::cell::Cell;
 use core::mem;
 use kernel::common::VolatileCell;
-use kernel::common::take_cell::MapCell;
+use kernel::common::take_cell::{MapCell, TakeCell};
+use kernel::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::flash;
 use nvic;
 use pm;
 
@@ -77,16 +85,6 @@ enum RegKey {
     GPFRLO,
 }
 
-/// Error codes are used to inform the Client if the command completed successfully
-/// or whether there was an error and what type of error it was.
-pub enum Error {
-    CommandComplete, // Command Complete
-    LockE, // Lock Error (i.e. tried writing to locked page)
-    ProgE, // Program Error (i.e. incorrectly issued flash commands
-    LockProgE, // Lock and Program Error
-    ECC, // Error Correcting Code Error
-}
-
 /// High level commands to issue to the flash. Usually to track the state of
 /// a command especially if it's multiple FlashCMDs.
 ///
@@ -97,6 +95,7 @@ pub enum Error {
 /// continue the steps of the command in handle_interrupt.
 #[derive(Clone, Copy, PartialEq)]
 pub enum Command {
+    Read,
     Write { page: i32 },
     Erase { page: i32 },
     None,
@@ -159,10 +158,23 @@ pub struct FLASHCALW {
     pb_clock: pm::Clock,
     error_status: Cell<u32>,
     ready: Cell<bool>,
-    client: Cell<Option<&'static Client>>,
+    client: Cell<Option<&'static flash::Client>>,
     current_state: Cell<FlashState>,
     current_command: Cell<Command>,
     page_buffer: MapCell<[u8; PAGE_SIZE as usize]>,
+    // The client's buffer while it reads or writes
+    buffer: TakeCell<'static, [u8]>,
+    // Page after the last one of the current write or erase
+    end_page: Cell<i32>,
+    // Whether the current write erases each page before writing it
+    erase_first: Cell<bool>,
+    // Command waiting for the deferred call to report its result
+    pending: Cell<Option<(Command, flash::Error)>>,
+    // Command refused as `Busy`, and its buffer, waiting for the deferred
+    // call to report the refusal
+    refused: Cell<Option<Command>>,
+    refused_buffer: TakeCell<'static, [u8]>,
+    deferred_call: DeferredCall,
 }
 
 // static instance for the board. Only one FLASHCALW on chip.
@@ -195,12 +207,6 @@ macro_rules! bit {
     ($w:expr) => (0x1u32 << $w);
 }
 
-/// Trait for a client of the flash controller.
-pub trait Client {
-    //  Called upon a completed call
-    fn command_complete(&self, err: Error);
-}
-
 impl FLASHCALW {
     const fn new(base_addr: usize,
                  ahb_clk: pm::HSBClock,
@@ -219,6 +225,13 @@ impl FLASHCALW {
             current_state: Cell::new(FlashState::Unconfigured),
             current_command: Cell::new(Command::None),
             page_buffer: MapCell::new([0; PAGE_SIZE as usize]),
+            buffer: TakeCell::empty(),
+            end_page: Cell::new(0),
+            erase_first: Cell::new(true),
+            pending: Cell::new(None),
+            refused: Cell::new(None),
+            refused_buffer: TakeCell::empty(),
+            deferred_call: DeferredCall::new(),
         }
     }
 
@@ -274,39 +287,28 @@ impl FLASHCALW {
         //  Since the only interrupt on is FRDY, a command should have
         //  either completed or failed at this point.
 
-        // Check for errors and report to Client if there are any
+        let command = self.current_command.get();
+
+        // Check for errors (a lock or programming error) and report them to
+        // the Client, abandoning the rest of the command
         if error_status != 0 {
             // reset commands / ready
             self.current_command.set(Command::None);
             self.current_state.set(FlashState::Ready);
-
-            self.client.get().map(|client| {
-                // call command complete with error
-                match error_status {
-                    4 => {
-                        client.command_complete(Error::LockE);
-                    }
-                    8 => {
-                        client.command_complete(Error::ProgE);
-                    }
-                    12 => {
-                        client.command_complete(Error::LockProgE);
-                    }
-                    _ => {}
-                }
-            });
+            self.complete(command, flash::Error::Failed);
+            return;
         }
 
         //  Part of a command succeeded -- continue onto next steps.
 
-        match self.current_command.get() {
+        match command {
             Command::Write { page } => {
                 match self.current_state.get() {
-                    FlashState::Unlocking => {
+                    FlashState::Unlocking if self.erase_first.get() => {
                         self.current_state.set(FlashState::Erasing);
                         self.flashcalw_erase_page(page, true);
                     }
-                    FlashState::Erasing => {
+                    FlashState::Unlocking | FlashState::Erasing => {
                         //  Write page buffer isn't really a command, and
                         //  clear page buffer dosn't trigger an interrupt thus
                         //  I'm combining these with an actual command, write_page,
@@ -324,8 +326,12 @@ impl FLASHCALW {
                         self.lock_page_region(page, true);
                     }
                     FlashState::Locking => {
-                        self.current_state.set(FlashState::Ready);
-                        self.current_command.set(Command::None);
+                        if page + 1 < self.end_page.get() {
+                            self.write_page(page + 1);
+                        } else {
+                            self.current_state.set(FlashState::Ready);
+                            self.current_command.set(Command::None);
+                        }
                     }
                     _ => {
                         assert!(false) /* should never reach here */
@@ -344,15 +350,19 @@ impl FLASHCALW {
                         self.lock_page_region(page, true);
                     }
                     FlashState::Locking => {
-                        self.current_state.set(FlashState::Ready);
-                        self.current_command.set(Command::None);
+                        if page + 1 < self.end_page.get() {
+                            self.erase_page(page + 1);
+                        } else {
+                            self.current_state.set(FlashState::Ready);
+                            self.current_command.set(Command::None);
+                        }
                     }
                     _ => {
                         assert!(false); /* should never happen. */
                     }
                 }
             }
-            Command::None => {
+            Command::Read | Command::None => {
                 self.current_state.set(FlashState::Ready);
             }
 
@@ -361,7 +371,7 @@ impl FLASHCALW {
         //  If the command is finished call the complete CB.
         if self.current_command.get() == Command::None &&
            self.current_state.get() == FlashState::Ready {
-            self.client.get().map(|value| { value.command_complete(Error::CommandComplete); });
+            self.complete(command, flash::Error::CommandComplete);
         }
     }
 
@@ -751,10 +761,6 @@ impl FLASHCALW {
 
 // Implementation of high level calls using the low-lv functions.
 impl FLASHCALW {
-    pub fn set_client(&self, client: &'static Client) {
-        self.client.set(Some(client));
-    }
-
     pub fn configure(&mut self) {
         // enable all clocks (if they aren't on already...)
         unsafe {
@@ -789,6 +795,12 @@ impl FLASHCALW {
         self.current_state.set(FlashState::Ready);
     }
 
+    /// Must be called once before use, so reads can complete from the main
+    /// loop instead of from inside the client's call.
+    pub fn register_deferred_call(&'static self) -> bool {
+        self.deferred_call.register(self)
+    }
+
     pub fn get_page_size(&self) -> u32 {
         PAGE_SIZE
     }
@@ -801,68 +813,188 @@ impl FLASHCALW {
         self.get_page_count()
     }
 
-    // Address is some raw address in flash that you want to read.
-    pub fn read(&self, address: usize, size: usize, buffer: &mut [u8]) -> i32 {
+    fn is_busy(&self) -> bool {
+        self.current_state.get() != FlashState::Ready || self.pending.get().is_some()
+    }
+
+    //  Checks that `len` bytes from `offset` are within the flash and, for
+    //  writes and erases, cover whole pages.
+    fn check_range(&self, offset: usize, len: usize, whole_pages: bool) -> flash::Error {
+        let page_size = PAGE_SIZE as usize;
+        let flash_size = self.get_number_pages() as usize * page_size;
+        if offset.checked_add(len).map_or(true, |end| end > flash_size) {
+            flash::Error::OutOfRange
+        } else if whole_pages && (len == 0 || offset % page_size != 0 || len % page_size != 0) {
+            flash::Error::PageBoundary
+        } else {
+            flash::Error::CommandComplete
+        }
+    }
+
+    //  Starts writing `page` from its part of the client's buffer, which
+    //  ends at `end_page`.
+    fn write_page(&self, page: i32) {
+        let end_page = self.end_page.get();
+        self.buffer.map(|buffer| {
+            let start = buffer.len() - (end_page - page) as usize * PAGE_SIZE as usize;
+            self.page_buffer.map(|value| {
+                value.clone_from_slice(&buffer[start..start + PAGE_SIZE as usize]);
+            });
+        });
+
+        self.current_state.set(FlashState::Unlocking);
+        self.current_command.set(Command::Write { page: page });
+        self.lock_page_region(page, false);
+    }
+
+    //  Starts a write or program of `buf` to the pages from `offset`.
+    fn write_pages(&self, offset: usize, buf: &'static mut [u8], erase_first: bool) {
+        if self.is_busy() {
+            self.refuse(Command::Write { page: (offset / PAGE_SIZE as usize) as i32 },
+                        Some(buf));
+            return;
+        }
+
         // enable clock incase it's off
         unsafe {
             pm::enable_clock(self.ahb_clock);
         }
 
-        //  check that address makes sense and buffer has room
-        if address > (self.get_flash_size() as usize) ||
-           address + size > (self.get_flash_size() as usize) ||
-           address + size < size || buffer.len() < size {
-            // invalid flash address
-            return -1;
+        let error = self.check_range(offset, buf.len(), true);
+        let page = (offset / PAGE_SIZE as usize) as i32;
+        let pages = (buf.len() / PAGE_SIZE as usize) as i32;
+        self.buffer.replace(buf);
+        if error == flash::Error::CommandComplete {
+            self.end_page.set(page + pages);
+            self.erase_first.set(erase_first);
+            self.write_page(page);
+        } else {
+            self.complete_later(Command::Write { page: page }, error);
         }
+    }
 
-        let mut byte: *const u8 = address as *const u8;
-        unsafe {
-            for i in 0..size {
-                buffer[i] = *byte;
-                byte = byte.offset(1);
-            }
+    fn erase_page(&self, page: i32) {
+        self.current_state.set(FlashState::Unlocking);
+        self.current_command.set(Command::Erase { page: page });
+        self.lock_page_region(page, false);
+    }
+
+    //  Reports the result of a command that finishes without an interrupt
+    //  from the deferred call, so the client is not called from inside its
+    //  own request.
+    fn complete_later(&self, command: Command, error: flash::Error) {
+        self.pending.set(Some((command, error)));
+        self.deferred_call.set();
+    }
+
+    fn complete(&self, command: Command, error: flash::Error) {
+        let buffer = self.buffer.take();
+        self.report(command, buffer, error);
+    }
+
+    //  Refuses a command issued while another is in progress, reporting
+    //  `Busy` from the deferred call. Only a client that issues yet another
+    //  command before then has the refusal reported from inside its call.
+    fn refuse(&self, command: Command, buffer: Option<&'static mut [u8]>) {
+        if self.refused.get().is_some() {
+            self.report(command, buffer, flash::Error::Busy);
+            return;
         }
-        0
+        self.refused.set(Some(command));
+        buffer.map(|buffer| self.refused_buffer.replace(buffer));
+        self.deferred_call.set();
+    }
+
+    fn report(&self, command: Command, buffer: Option<&'static mut [u8]>, error: flash::Error) {
+        self.client.get().map(|client| match command {
+            Command::Read => {
+                buffer.map(|buffer| client.read_complete(buffer, error));
+            }
+            Command::Write { .. } => {
+                buffer.map(|buffer| client.write_complete(buffer, error));
+            }
+            Command::Erase { .. } => client.erase_complete(error),
+            Command::None => {}
+        });
+    }
+}
+
+impl DeferredCallClient for FLASHCALW {
+    fn handle_deferred_call(&self) {
+        let pending = self.pending.get();
+        self.pending.set(None);
+        pending.map(|(command, error)| self.complete(command, error));
+
+        let refused = self.refused.get();
+        self.refused.set(None);
+        refused.map(|command| {
+            let buffer = self.refused_buffer.take();
+            self.report(command, buffer, flash::Error::Busy);
+        });
+    }
+}
+
+/// Only one command may be outstanding. A command issued while another is in
+/// progress completes with `Busy`.
+impl flash::Flash for FLASHCALW {
+    fn set_client(&self, client: &'static flash::Client) {
+        self.client.set(Some(client));
     }
 
-    pub fn write_page(&self, page_num: i32, data: &[u8]) -> i32 {
+    // Offset is the raw address in flash to read from.
+    fn read(&self, offset: usize, buf: &'static mut [u8]) {
+        if self.is_busy() {
+            self.refuse(Command::Read, Some(buf));
+            return;
+        }
+
         // enable clock incase it's off
         unsafe {
             pm::enable_clock(self.ahb_clock);
         }
 
-        // if we're not ready don't take the command.
-        if self.current_state.get() != FlashState::Ready {
-            return -1;
+        let error = self.check_range(offset, buf.len(), false);
+        if error == flash::Error::CommandComplete {
+            let mut byte: *const u8 = offset as *const u8;
+            unsafe {
+                for i in 0..buf.len() {
+                    buf[i] = *byte;
+                    byte = byte.offset(1);
+                }
+            }
         }
 
-        //  check data length is of size 'page_size'
-        if data.len() != self.get_page_size() as usize {
-            return -1;
-        }
+        self.buffer.replace(buf);
+        self.complete_later(Command::Read, error);
+    }
 
-        self.page_buffer.map(|value| { value.clone_from_slice(&data); });
+    fn write(&self, offset: usize, buf: &'static mut [u8]) {
+        self.write_pages(offset, buf, true);
+    }
 
-        self.current_state.set(FlashState::Unlocking);
-        self.current_command.set(Command::Write { page: page_num });
-        self.lock_page_region(page_num, false);
-        0
+    fn program(&self, offset: usize, buf: &'static mut [u8]) {
+        self.write_pages(offset, buf, false);
     }
 
-    pub fn erase_page(&self, page_num: i32) -> i32 {
+    fn erase(&self, offset: usize, len: usize) {
+        if self.is_busy() {
+            self.refuse(Command::Erase { page: (offset / PAGE_SIZE as usize) as i32 }, None);
+            return;
+        }
+
         // Enable AHB clock (incase it was off).
         unsafe {
             pm::enable_clock(self.ahb_clock);
         }
-        if self.current_state.get() != FlashState::Ready {
-            return -1;
-        }
 
-        self.current_state.set(FlashState::Unlocking);
-        self.current_command.set(Command::Erase { page: page_num });
-        self.lock_page_region(page_num, false);
-        0
+        let error = self.check_range(offset, len, true);
+        let page = (offset / PAGE_SIZE as usize) as i32;
+        if error == flash::Error::CommandComplete {
+            self.end_page.set(page + (len / PAGE_SIZE as usize) as i32);
+            self.erase_page(page);
+        } else {
+            self.complete_later(Command::Erase { page: page }, error);
+        }
     }
 }
 
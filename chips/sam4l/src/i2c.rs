@@ -14,6 +14,7 @@
 use core::cell::Cell;
 use core::mem;
 use dma::{DMAChannel, DMAClient, DMAPeripheral};
+use gpio::{GPIOPin, PeripheralFunction};
 use kernel::common::take_cell::TakeCell;
 use kernel::common::volatile_cell::VolatileCell;
 
@@ -110,6 +111,7 @@ pub struct I2CHw {
     master_client: Cell<Option<&'static hil::i2c::I2CHwMasterClient>>,
     slave_client: Cell<Option<&'static hil::i2c::I2CHwSlaveClient>>,
     on_deck: Cell<Option<(DMAPeripheral, usize)>>,
+    recovery_pins: Cell<Option<(&'static GPIOPin, &'static GPIOPin, PeripheralFunction)>>,
 
     slave_enabled: Cell<bool>,
     my_slave_address: Cell<u8>,
@@ -182,6 +184,7 @@ impl I2CHw {
             master_client: Cell::new(None),
             slave_client: Cell::new(None),
             on_deck: Cell::new(None),
+            recovery_pins: Cell::new(None),
 
             slave_enabled: Cell::new(false),
             my_slave_address: Cell::new(0),
@@ -235,6 +238,16 @@ impl I2CHw {
         self.slave_client.set(Some(client));
     }
 
+    /// Gives the driver the SCL and SDA pins of the bus, and the peripheral
+    /// function that connects them to this TWIM, so that `recover_bus` can
+    /// take them over as GPIO. Without them bus recovery only resets the TWIM.
+    pub fn set_recovery_pins(&self,
+                             scl: &'static GPIOPin,
+                             sda: &'static GPIOPin,
+                             function: PeripheralFunction) {
+        self.recovery_pins.set(Some((scl, sda, function)));
+    }
+
     pub fn handle_interrupt(&self) {
         use kernel::hil::i2c::Error;
         let regs: &mut TWIMRegisters = unsafe { mem::transmute(self.registers) };
@@ -416,6 +429,24 @@ impl I2CHw {
         }
     }
 
+    /// Stops the TWIM and takes back the buffer of the transaction in
+    /// progress, if any.
+    fn abort_xfer(&self) -> Option<&'static mut [u8]> {
+        let regs: &mut TWIMRegisters = unsafe { mem::transmute(self.registers) };
+        regs.interrupt_disable.set(!0);
+        regs.command.set(0);
+        regs.next_command.set(0);
+
+        // enable, reset, disable
+        regs.control.set(0x1 << 0);
+        regs.control.set(0x1 << 7);
+        regs.control.set(0x1 << 1);
+        regs.status_clear.set(!0);
+
+        self.on_deck.set(None);
+        self.dma.get().and_then(|dma| dma.abort_xfer())
+    }
+
     fn disable_interrupts(&self) {
         let regs: &mut TWIMRegisters = unsafe { mem::transmute(self.registers) };
         regs.interrupt_disable.set(!0);
@@ -727,6 +758,19 @@ impl I2CHw {
     }
 }
 
+// Drives an open-drain line: low drives the pin, high lets the pull-up
+// raise it. The repeated writes also pace the bus at well under 100kHz.
+fn drive_line(pin: &GPIOPin, high: bool) {
+    for _ in 0..100 {
+        if high {
+            pin.disable_output();
+        } else {
+            pin.clear();
+            pin.enable_output();
+        }
+    }
+}
+
 impl DMAClient for I2CHw {
     fn xfer_done(&self, _pid: DMAPeripheral) {}
 }
@@ -784,6 +828,50 @@ impl hil::i2c::I2CMaster for I2CHw {
     fn write_read(&self, addr: u8, data: &'static mut [u8], write_len: u8, read_len: u8) {
         I2CHw::write_read(self, addr, data, write_len, read_len)
     }
+
+    fn abort(&self) -> Option<&'static mut [u8]> {
+        self.abort_xfer()
+    }
+
+    /// Clocks SCL up to nine times, enough for a slave to finish the byte it
+    /// was sending, until SDA is released, then generates a STOP.
+    fn recover_bus(&self) -> bool {
+        let idle = self.recovery_pins.get().map_or(true, |(scl, sda, function)| {
+            sda.enable();
+            sda.disable_output();
+            sda.enable_pull_up();
+            scl.enable();
+            scl.enable_pull_up();
+            drive_line(scl, true);
+
+            for _ in 0..9 {
+                if sda.read() {
+                    break;
+                }
+                drive_line(scl, false);
+                drive_line(scl, true);
+            }
+
+            // STOP: SDA rises while SCL is high
+            drive_line(scl, false);
+            drive_line(sda, false);
+            drive_line(scl, true);
+            drive_line(sda, true);
+            let idle = scl.read() && sda.read();
+
+            scl.select_peripheral(function);
+            sda.select_peripheral(function);
+            idle
+        });
+
+        let regs: &mut TWIMRegisters = unsafe { mem::transmute(self.registers) };
+        // enable, reset, disable
+        regs.control.set(0x1 << 0);
+        regs.control.set(0x1 << 7);
+        regs.control.set(0x1 << 1);
+        regs.status_clear.set(!0);
+        idle
+    }
 }
 
 impl hil::i2c::I2CSlave for I2CHw {
@@ -2,6 +2,7 @@ use core::cell::Cell;
 use core::cmp;
 use core::mem;
 use dma;
+use kernel::ReturnCode;
 use kernel::common::volatile_cell::VolatileCell;
 // other modules
 use kernel::hil;
@@ -92,6 +93,7 @@ pub struct USART {
     client: Cell<Option<UsartClient<'static>>>,
 
     spi_chip_select: Cell<Option<&'static hil::gpio::Pin>>,
+    spi_hold_cs: Cell<bool>,
 }
 
 // USART hardware peripherals on SAM4L
@@ -144,8 +146,9 @@ impl USART {
             // this gets defined later by `main.rs`
             client: Cell::new(None),
 
-            // This is only used if the USART is in SPI mode.
+            // These are only used if the USART is in SPI mode.
             spi_chip_select: Cell::new(None),
+            spi_hold_cs: Cell::new(false),
         }
     }
 
@@ -296,6 +299,17 @@ impl USART {
         let regs: &mut USARTRegisters = unsafe { mem::transmute(self.registers) };
         let status = regs.csr.get();
 
+        if let UsartMode::Spi = self.usart_mode.get() {
+            if status & (1 << 9) != 0 &&
+               self.usart_tx_state.get() == USARTStateTX::Transfer_Completing {
+                // TXEMPTY: the last byte of a write-only transfer is out
+                self.disable_tx_interrupts();
+                self.spi_transfer_done();
+            }
+            regs.cr.set(1 << 8); // RSTSTA
+            return;
+        }
+
         if status & (1 << 12) != 0 {
             // DO NOTHING. Why are we here!?
 
@@ -352,13 +366,71 @@ impl USART {
         // The clock divisor is calculated differently in UART and SPI modes.
         let cd = match self.usart_mode.get() {
             UsartMode::Uart => system_frequency / (8 * baud_rate),
-            UsartMode::Spi => system_frequency / baud_rate,
+            UsartMode::Spi => Self::spi_clock_divisor(system_frequency, baud_rate),
             _ => 0,
         };
 
         regs.brgr.set(cd);
     }
 
+    /// The divisor giving the fastest SPI clock no faster than `rate`.
+    fn spi_clock_divisor(system_frequency: u32, rate: u32) -> u32 {
+        let rate = cmp::max(rate, 1);
+        cmp::min(cmp::max((system_frequency + rate - 1) / rate, 1), 0xffff)
+    }
+
+    /// Ends an SPI transfer: releases chip select, unless it is being held
+    /// low, and returns the buffers to the client.
+    fn spi_transfer_done(&self) {
+        if !self.spi_hold_cs.get() {
+            self.spi_deassert_cs();
+        }
+
+        self.usart_tx_state.set(USARTStateTX::Idle);
+        self.usart_rx_state.set(USARTStateRX::Idle);
+
+        let txbuf = self.tx_dma.get().map_or(None, |dma| {
+            let buf = dma.abort_xfer();
+            dma.disable();
+            buf
+        });
+
+        let rxbuf = self.rx_dma.get().map_or(None, |dma| {
+            let buf = dma.abort_xfer();
+            dma.disable();
+            buf
+        });
+
+        let len = self.tx_len.get();
+        self.tx_len.set(0);
+
+        self.client.get().map(|usartclient| {
+            txbuf.map(|tbuf| match usartclient {
+                UsartClient::Uart(_) => {}
+                UsartClient::SpiMaster(client) => {
+                    client.read_write_done(tbuf, rxbuf, len);
+                }
+            });
+        });
+    }
+
+    fn spi_assert_cs(&self) {
+        self.spi_chip_select.get().map_or_else(|| {
+            // Without a GPIO chip select, the HW RTS pin is the CS line
+            self.rts_enable_spi_assert_cs();
+        }, |cs| {
+            cs.clear();
+        });
+    }
+
+    fn spi_deassert_cs(&self) {
+        self.spi_chip_select.get().map_or_else(|| {
+            self.rts_disable_spi_deassert_cs();
+        }, |cs| {
+            cs.set();
+        });
+    }
+
     /// In non-SPI mode, this drives RTS low.
     /// In SPI mode, this asserts (drives low) the chip select line.
     fn rts_enable_spi_assert_cs(&self) {
@@ -483,47 +555,19 @@ impl dma::DMAClient for USART {
             }
 
             UsartMode::Spi => {
-                if (self.usart_rx_state.get() == USARTStateRX::Idle &&
-                    pid == self.tx_dma_peripheral) ||
-                   pid == self.rx_dma_peripheral {
-                    // SPI transfer was completed
-
-                    self.spi_chip_select.get().map_or_else(|| {
-                        // Do "else" case first. Thanks, rust.
-                        self.rts_disable_spi_deassert_cs();
-                    }, |cs| {
-                        cs.set();
-                    });
-
-                    // note that the DMA has finished but TX cannot be disabled yet
+                if pid == self.rx_dma_peripheral {
+                    // Every byte has been clocked in, so the transfer is over
+                    self.spi_transfer_done();
+                } else if pid == self.tx_dma_peripheral &&
+                          self.usart_rx_state.get() == USARTStateRX::Idle {
+                    // A write-only transfer is over once the last byte has
+                    // left the shift register, which TXEMPTY signals
                     self.usart_tx_state.set(USARTStateTX::Transfer_Completing);
-                    self.usart_rx_state.set(USARTStateRX::Idle);
-
-                    // get buffer
-                    let txbuf = self.tx_dma.get().map_or(None, |dma| {
-                        let buf = dma.abort_xfer();
-                        dma.disable();
-                        buf
-                    });
-
-                    let rxbuf = self.rx_dma.get().map_or(None, |dma| {
-                        let buf = dma.abort_xfer();
-                        dma.disable();
-                        buf
-                    });
-
-                    let len = self.tx_len.get();
-
-                    // alert client
-                    self.client.get().map(|usartclient| {
-                        txbuf.map(|tbuf| match usartclient {
-                            UsartClient::Uart(_) => {}
-                            UsartClient::SpiMaster(client) => {
-                                client.read_write_done(tbuf, rxbuf, len);
-                            }
-                        });
-                    });
-                    self.tx_len.set(0);
+                    let regs: &mut USARTRegisters = unsafe {
+                        mem::transmute(self.registers)
+                    };
+                    regs.ier.set(1 << 9); // TXEMPTY
+                    self.enable_nvic();
                 }
             }
 
@@ -539,7 +583,7 @@ impl hil::uart::UART for USART {
         self.client.set(Some(c));
     }
 
-    fn init(&self, params: hil::uart::UARTParams) {
+    fn init(&self, params: hil::uart::UARTParams) -> ReturnCode {
         self.usart_mode.set(UsartMode::Uart);
 
         // enable USART clock
@@ -579,6 +623,7 @@ impl hil::uart::UART for USART {
 
         // Set baud rate
         self.set_baud_rate(params.baud_rate);
+        ReturnCode::SUCCESS
     }
 
     fn transmit(&self, tx_data: &'static mut [u8], tx_len: usize) {
@@ -667,6 +712,26 @@ impl hil::uart::UARTAdvanced for USART {
 
 
 /// SPI
+///
+/// In SPI master mode, transfers use the USART's DMA channels. Chip select is
+/// either a GPIO pin, given to `specify_chip_select`, or the USART's RTS pin
+/// if `None` is given. Like the SPI peripheral, a USART can be shared between
+/// several devices with `MuxSpiMaster`:
+///
+/// ```rust
+/// let mux_spi = static_init!(
+///     MuxSpiMaster<'static, sam4l::usart::USART>,
+///     MuxSpiMaster::new(&sam4l::usart::USART3),
+///     160/8);
+/// mux_spi.register_deferred_call();
+/// hil::spi::SpiMaster::set_client(&sam4l::usart::USART3, mux_spi);
+/// hil::spi::SpiMaster::init(&sam4l::usart::USART3);
+///
+/// let fram_spi = static_init!(
+///     VirtualSpiMasterDevice<'static, sam4l::usart::USART>,
+///     VirtualSpiMasterDevice::new(mux_spi, Some(&sam4l::gpio::PB[11])),
+///     480/8);
+/// ```
 impl hil::spi::SpiMaster for USART {
     type ChipSelect = Option<&'static hil::gpio::Pin>;
 
@@ -698,18 +763,29 @@ impl hil::spi::SpiMaster for USART {
     }
 
     fn is_busy(&self) -> bool {
-        return false;
+        self.usart_tx_state.get() != USARTStateTX::Idle ||
+        self.usart_rx_state.get() != USARTStateRX::Idle
     }
 
     fn read_write_bytes(&self,
-                        mut write_buffer: &'static mut [u8],
+                        write_buffer: &'static mut [u8],
                         read_buffer: Option<&'static mut [u8]>,
                         len: usize)
                         -> bool {
+        let regs: &mut USARTRegisters = unsafe { mem::transmute(self.registers) };
+
+        if self.is_busy() {
+            return false;
+        }
 
         self.enable_tx();
         self.enable_rx();
 
+        // Discard any byte left from an earlier transfer, so that it is not
+        // read as the first byte of this one
+        regs.rhr.get();
+        regs.cr.set(1 << 8); // RSTSTA
+
         // Calculate the correct length for the transmission
         let buflen = read_buffer.as_ref().map_or(write_buffer.len(),
                                                  |rbuf| cmp::min(rbuf.len(), write_buffer.len()));
@@ -718,22 +794,10 @@ impl hil::spi::SpiMaster for USART {
         self.tx_len.set(count);
 
         // Set !CS low
-        self.spi_chip_select.get().map_or_else(|| {
-            // Do the "else" case first. If a CS pin was provided as the
-            // CS line, we use the HW RTS pin as the CS line instead.
-            self.rts_enable_spi_assert_cs();
-        }, |cs| {
-            cs.clear();
-        });
-
-        // Set up dma transfer and start transmission
-        self.tx_dma.get().map(move |dma| {
-            self.usart_tx_state.set(USARTStateTX::DMA_Transmitting);
-            self.usart_rx_state.set(USARTStateRX::Idle);
-            dma.enable();
-            dma.do_xfer(self.tx_dma_peripheral, write_buffer, count);
-        });
+        self.spi_assert_cs();
 
+        // The receive DMA must be ready before the first byte is clocked
+        // in, so it is set up before transmission starts
         read_buffer.map(|rbuf| {
             self.rx_dma.get().map(move |read| {
                 self.usart_rx_state.set(USARTStateRX::DMA_Receiving);
@@ -742,6 +806,13 @@ impl hil::spi::SpiMaster for USART {
             });
         });
 
+        // Set up dma transfer and start transmission
+        self.tx_dma.get().map(move |dma| {
+            self.usart_tx_state.set(USARTStateTX::DMA_Transmitting);
+            dma.enable();
+            dma.do_xfer(self.tx_dma_peripheral, write_buffer, count);
+        });
+
         true
     }
 
@@ -768,17 +839,21 @@ impl hil::spi::SpiMaster for USART {
 
     /// Pass in a None to use the HW chip select pin on the USART (RTS).
     fn specify_chip_select(&self, cs: Self::ChipSelect) {
+        cs.map(|pin| {
+            // Start deselected
+            pin.make_output();
+            pin.set();
+        });
         self.spi_chip_select.set(cs);
     }
 
-    /// Returns the actual rate set
+    /// Returns the actual rate set, the fastest no faster than `rate`
     fn set_rate(&self, rate: u32) -> u32 {
         self.set_baud_rate(rate);
 
         // Calculate what rate will actually be
         let system_frequency = unsafe { pm::get_system_frequency() };
-        let cd = system_frequency / rate;
-        system_frequency / cd
+        system_frequency / Self::spi_clock_divisor(system_frequency, rate)
     }
 
     fn get_rate(&self) -> u32 {
@@ -830,9 +905,10 @@ impl hil::spi::SpiMaster for USART {
         let regs: &mut USARTRegisters = unsafe { mem::transmute(self.registers) };
         let mode = regs.mr.get();
 
+        // CPHA set means data is sampled on the leading edge
         match mode & (1 << 8) {
-            0 => hil::spi::ClockPhase::SampleLeading,
-            _ => hil::spi::ClockPhase::SampleTrailing,
+            0 => hil::spi::ClockPhase::SampleTrailing,
+            _ => hil::spi::ClockPhase::SampleLeading,
         }
     }
 
@@ -846,11 +922,14 @@ impl hil::spi::SpiMaster for USART {
     // CS line is high or low, such that it can issue multi-byte
     // requests with single byte operations.
     fn hold_low(&self) {
-        panic!("USART: SPI: hold_low() unimplemented. Use `read_write_bytes()` instead.");
+        self.spi_hold_cs.set(true);
     }
 
     fn release_low(&self) {
-        panic!("USART: SPI: release_low() unimplemented. Use `read_write_bytes()` instead.");
+        self.spi_hold_cs.set(false);
+        if !self.is_busy() {
+            self.spi_deassert_cs();
+        }
     }
 }
 
@@ -32,6 +32,13 @@ impl AppId {
     }
 }
 
+/// Identifies the driver and subscribe number a callback was registered with.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CallbackId {
+    pub driver_num: usize,
+    pub subscribe_num: usize,
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum RustOrRawFnPtr {
     Raw { ptr: NonZero<*mut ()> },
@@ -41,14 +48,20 @@ pub enum RustOrRawFnPtr {
 #[derive(Clone, Copy, Debug)]
 pub struct Callback {
     app_id: AppId,
+    callback_id: CallbackId,
     appdata: usize,
     fn_ptr: RustOrRawFnPtr,
 }
 
 impl Callback {
-    pub fn new(appid: AppId, appdata: usize, fn_ptr: NonZero<*mut ()>) -> Callback {
+    pub fn new(appid: AppId,
+               callback_id: CallbackId,
+               appdata: usize,
+               fn_ptr: NonZero<*mut ()>)
+               -> Callback {
         Callback {
             app_id: appid,
+            callback_id: callback_id,
             appdata: appdata,
             fn_ptr: RustOrRawFnPtr::Raw { ptr: fn_ptr },
         }
@@ -57,6 +70,10 @@ impl Callback {
     pub const fn kernel_new(appid: AppId, fn_ptr: fn(usize, usize, usize, usize)) -> Callback {
         Callback {
             app_id: appid,
+            callback_id: CallbackId {
+                driver_num: 0,
+                subscribe_num: 0,
+            },
             appdata: 0,
             fn_ptr: RustOrRawFnPtr::Rust { func: fn_ptr },
         }
@@ -85,6 +102,7 @@ impl Callback {
                                   r2: r2,
                                   r3: self.appdata,
                                   pc: *fn_ptr as usize,
+                                  callback_id: Some(self.callback_id),
                               },
                               self.app_id)
         }
@@ -93,4 +111,8 @@ impl Callback {
     pub fn app_id(&self) -> AppId {
         self.app_id
     }
+
+    pub fn callback_id(&self) -> CallbackId {
+        self.callback_id
+    }
 }
@@ -1,5 +1,6 @@
 //! Common operations in the Tock OS.
 
+pub mod crc32;
 pub mod ring_buffer;
 pub mod queue;
 pub mod utils;
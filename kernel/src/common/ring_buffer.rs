@@ -15,6 +15,64 @@ impl<'a, T: Copy> RingBuffer<'a, T> {
             ring: ring,
         }
     }
+
+    /// Whether any queued element matches.
+    pub fn contains<F>(&self, matches: F) -> bool
+        where F: Fn(&T) -> bool
+    {
+        let tail = unsafe { read_volatile(&self.tail) };
+        let mut i = self.head;
+        while i != tail {
+            if matches(&self.ring[i]) {
+                return true;
+            }
+            i = (i + 1) % self.ring.len();
+        }
+        false
+    }
+
+    /// Replaces the oldest queued element for which `matches` returns true
+    /// with `val`, keeping its position in the queue. Returns false, leaving
+    /// the queue unchanged, if no element matches.
+    pub fn replace_first<F>(&mut self, val: T, matches: F) -> bool
+        where F: Fn(&T) -> bool
+    {
+        let tail = unsafe { read_volatile(&self.tail) };
+        let mut i = self.head;
+        while i != tail {
+            if matches(&self.ring[i]) {
+                self.ring[i] = val;
+                return true;
+            }
+            i = (i + 1) % self.ring.len();
+        }
+        false
+    }
+
+    /// Removes and returns the oldest queued element for which `matches`
+    /// returns true. The remaining elements keep their order.
+    pub fn remove_first<F>(&mut self, matches: F) -> Option<T>
+        where F: Fn(&T) -> bool
+    {
+        let tail = unsafe { read_volatile(&self.tail) };
+        let len = self.ring.len();
+        let mut i = self.head;
+        while i != tail {
+            if matches(&self.ring[i]) {
+                let val = self.ring[i];
+                // Shift the older elements up by one to close the gap
+                while i != self.head {
+                    let prev = (i + len - 1) % len;
+                    self.ring[i] = self.ring[prev];
+                    i = prev;
+                }
+                self.head = (self.head + 1) % len;
+                return Some(val);
+            }
+            i = (i + 1) % len;
+        }
+        None
+    }
 }
 
 impl<'a, T: Copy> queue::Queue<T> for RingBuffer<'a, T> {
@@ -0,0 +1,107 @@
+//! Deferred calls let capsules run work from the kernel's main loop instead
+//! of synchronously from inside another callback.
+//!
+//! A capsule holds a `DeferredCall`, registers itself as its client once
+//! during board setup, and calls `set` whenever it wants its handler to run.
+//! Pending handlers run from `kernel::main` right after interrupts are
+//! serviced. Clients are kept in a fixed table of `MAX_DEFERRED_CALLS`
+//! entries, so no memory is allocated.
+//!
+//! ```rust
+//! impl DeferredCallClient for MyCapsule {
+//!     fn handle_deferred_call(&self) {
+//!         self.client.map(|client| client.done());
+//!     }
+//! }
+//!
+//! // During board setup:
+//! my_capsule.deferred_call.register(my_capsule);
+//! ```
+
+use common::VolatileCell;
+use core::cell::Cell;
+use support;
+
+/// Number of deferred calls that can be registered.
+pub const MAX_DEFERRED_CALLS: usize = 16;
+
+pub trait DeferredCallClient {
+    /// Called from the main loop after `DeferredCall#set`.
+    fn handle_deferred_call(&self);
+}
+
+static mut CLIENTS: [Option<&'static DeferredCallClient>; MAX_DEFERRED_CALLS] =
+    [None; MAX_DEFERRED_CALLS];
+static mut REGISTERED: usize = 0;
+
+// One bit per registered deferred call
+static mut PENDING: VolatileCell<u32> = VolatileCell::new(0);
+
+pub struct DeferredCall {
+    idx: Cell<Option<usize>>,
+}
+
+impl DeferredCall {
+    pub const fn new() -> DeferredCall {
+        DeferredCall { idx: Cell::new(None) }
+    }
+
+    /// Registers `client` to be called when this deferred call is set.
+    ///
+    /// Returns false if it is already registered or the table is full.
+    pub fn register(&self, client: &'static DeferredCallClient) -> bool {
+        if self.idx.get().is_some() {
+            return false;
+        }
+        unsafe {
+            if REGISTERED == MAX_DEFERRED_CALLS {
+                return false;
+            }
+            CLIENTS[REGISTERED] = Some(client);
+            self.idx.set(Some(REGISTERED));
+            REGISTERED += 1;
+        }
+        true
+    }
+
+    /// Requests that the client's handler run from the main loop. Setting a
+    /// call that is already pending has no further effect.
+    ///
+    /// Returns false if no client is registered.
+    pub fn set(&self) -> bool {
+        self.idx.get().map_or(false, |idx| {
+            unsafe {
+                support::atomic(|| PENDING.set(PENDING.get() | (1 << idx)));
+            }
+            true
+        })
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.idx.get().map_or(false, |idx| unsafe { PENDING.get() & (1 << idx) != 0 })
+    }
+}
+
+/// Whether any deferred call is waiting to run.
+pub fn has_tasks() -> bool {
+    unsafe { PENDING.get() != 0 }
+}
+
+/// Runs the handler of every pending deferred call. Calls set by a handler
+/// run on the next pass through the main loop.
+pub fn service_pending() {
+    let pending = unsafe {
+        support::atomic(|| {
+            let pending = PENDING.get();
+            PENDING.set(0);
+            pending
+        })
+    };
+    for idx in 0..MAX_DEFERRED_CALLS {
+        if pending & (1 << idx) != 0 {
+            unsafe {
+                CLIENTS[idx].map(|client| client.handle_deferred_call());
+            }
+        }
+    }
+}
@@ -4,8 +4,9 @@
 //!
 //! # System-call Overview
 //!
-//! Tock supports four system calls. The `yield` system call is handled entirely
-//! by the scheduler, while three others are passed along to drivers:
+//! Tock supports five system calls. The `yield` and `memop` system calls are
+//! handled entirely by the kernel, while the others are passed along to
+//! drivers:
 //!
 //!   * `subscribe` lets an application pass a callback to the driver to be
 //!   called later, when an event has occurred or data of interest is available.
@@ -14,9 +15,12 @@
 //!
 //!   * `allow` provides the driver access to an application buffer.
 //!
+//!   * `allow_readonly` provides the driver read-only access to an application
+//!   buffer, which may be in the application's flash.
+//!
 //! ## Mapping system-calls to drivers
 //!
-//! Each of these three system calls takes at least two parameters. The first is
+//! Each of these system calls takes at least two parameters. The first is
 //! a _driver major number_ and tells the scheduler which driver to forward the
 //! system call to. The second parameters is a _driver minor number_ and is used
 //! by the driver to differentiate system calls with different driver-specific
@@ -99,8 +103,31 @@ pub trait Driver {
     /// The buffer is __shared__ between the application and driver, meaning the
     /// driver should not rely on the contents of the buffer to remain
     /// unchanged.
+    ///
+    /// A zero-length `slice` means the application is revoking the buffer it
+    /// previously allowed for `minor_num`. The driver must stop using that
+    /// buffer and return its address and length, which `AppSlice::store_in`
+    /// does for buffers kept in an `Option`.
     #[allow(unused_variables)]
     fn allow(&self, app: ::AppId, minor_num: usize, slice: ::AppSlice<::Shared, u8>) -> ReturnCode {
         ReturnCode::ENOSUPPORT
     }
+
+    /// `allow_readonly` lets an application give the driver read access to a
+    /// buffer in the application's RAM or flash.
+    ///
+    /// This is meant for input data the driver only consumes, such as bytes to
+    /// print or a key, and lets applications pass constants without first
+    /// copying them into RAM. Drivers that accept a buffer through `allow` only
+    /// to read from it should accept it here as well.
+    ///
+    /// As with `allow`, a zero-length `slice` revokes the previous buffer.
+    #[allow(unused_variables)]
+    fn allow_readonly(&self,
+                      app: ::AppId,
+                      minor_num: usize,
+                      slice: ::ReadOnlyAppSlice<u8>)
+                      -> ReturnCode {
+        ReturnCode::ENOSUPPORT
+    }
 }
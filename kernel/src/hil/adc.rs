@@ -16,23 +16,45 @@ pub trait AdcSingle {
     /// Returns true on success.
     fn sample(&self, channel: u8) -> ReturnCode;
     fn cancel_sample(&self) -> ReturnCode;
-}
 
-pub trait Frequency {
-    fn frequency() -> u32;
+    /// The number of channels, which are numbered from 0.
+    fn channel_count(&self) -> usize;
 }
 
-#[derive(Debug)]
-pub struct Freq1KHz;
-impl Frequency for Freq1KHz {
-    fn frequency() -> u32 {
-        1000
-    }
+/// Trait for handling callbacks from continuous sampling.
+pub trait ContinuousClient {
+    /// Called when a buffer holds `length` samples. Sampling carries on
+    /// into the next buffer, if one was provided.
+    fn samples_ready(&self, buffer: &'static mut [u16], length: usize);
 }
 
+/// Interface for sampling a channel at a fixed frequency into buffers.
+///
+/// Sampling fills the buffer it was started with, then each buffer given to
+/// `provide_buffer` in turn, with no gap between them, so a client that
+/// always has the next buffer in place gets an unbroken stream of samples.
+/// Sampling stops by itself once it runs out of buffers.
 pub trait AdcContinuous {
-    type Frequency: Frequency;
-    fn compute_interval(&self, interval: u32) -> u32;
-    fn sample_continuous(&self, channel: u8, interval: u32) -> ReturnCode;
+    /// Starts sampling `channel` `frequency` times a second into the first
+    /// `length` samples of `buffer`. On failure the buffer is returned.
+    fn sample_continuous(&self,
+                         channel: u8,
+                         frequency: u32,
+                         buffer: &'static mut [u16],
+                         length: usize)
+                         -> (ReturnCode, Option<&'static mut [u16]>);
+
+    /// Gives the buffer to fill once the current one is full. Only one
+    /// buffer can wait at a time; on failure the buffer is returned.
+    fn provide_buffer(&self,
+                      buffer: &'static mut [u16],
+                      length: usize)
+                      -> (ReturnCode, Option<&'static mut [u16]>);
+
+    /// Stops sampling. The buffers are not passed to the client, but can be
+    /// taken back with `retrieve_buffers`.
     fn cancel_sampling(&self) -> ReturnCode;
+
+    /// Returns the buffers held after sampling was cancelled.
+    fn retrieve_buffers(&self) -> (Option<&'static mut [u16]>, Option<&'static mut [u16]>);
 }
@@ -2,21 +2,44 @@
 pub enum Error {
     PageBoundary,
     WordBoundary,
+
+    /// The operation extends past the end of the flash
+    OutOfRange,
+
+    /// Another operation is still in progress
+    Busy,
+
+    /// The flash controller failed to program or erase a page
+    Failed,
+
+    /// No error occurred and the command completed successfully
+    CommandComplete,
 }
 
 /// A block of writable persistent flash memory.
+///
+/// Only one operation may be outstanding at a time. Writes, programs and
+/// erases work on whole pages, so their offset and length must be multiples
+/// of the page size, or they complete with `PageBoundary`.
 pub trait Flash {
     /// Set the client for this flash peripheral. The client will be called
     /// when operations complete.
     fn set_client(&self, client: &'static Client);
 
-    /// Read data
+    /// Read `buf.len()` bytes starting at `offset`
     fn read(&self, offset: usize, buf: &'static mut [u8]);
 
-    /// Write data
+    /// Write `buf` to the pages starting at `offset`, erasing them first
     fn write(&self, offset: usize, buf: &'static mut [u8]);
 
-    /// Erase flash
+    /// Program `buf` into the pages starting at `offset` without erasing
+    /// them. Programming only clears bits, so bytes left as 0xff in `buf`
+    /// keep their contents, and data can be added to the erased part of a
+    /// page without disturbing what the page already holds, even if power
+    /// is lost part way. Completes through `Client::write_complete`.
+    fn program(&self, offset: usize, buf: &'static mut [u8]);
+
+    /// Erase the pages covering `len` bytes starting at `offset`
     fn erase(&self, offset: usize, len: usize);
 }
 
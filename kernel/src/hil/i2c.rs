@@ -15,6 +15,10 @@ pub enum Error {
     /// higher-priority transmission is in progress by a different master.
     ArbitrationLost,
 
+    /// The transaction did not finish in time, most likely because a slave
+    /// is holding the clock or data line low. The transaction was aborted.
+    Timeout,
+
     /// No error occured and the command completed successfully.
     CommandComplete,
 }
@@ -25,6 +29,7 @@ impl Display for Error {
             Error::AddressNak => "I2C Address Not Acknowledged",
             Error::DataNak => "I2C Data Not Acknowledged",
             Error::ArbitrationLost => "I2C Bus Arbitration Lost",
+            Error::Timeout => "I2C Transaction Timed Out",
             Error::CommandComplete => "I2C Command Completed",
         };
         write!(fmt, "{}", display_str)
@@ -45,6 +50,15 @@ pub trait I2CMaster {
     fn write_read(&self, addr: u8, data: &'static mut [u8], write_len: u8, read_len: u8);
     fn write(&self, addr: u8, data: &'static mut [u8], len: u8);
     fn read(&self, addr: u8, buffer: &'static mut [u8], len: u8);
+
+    /// Stops the transaction in progress without calling the client, and
+    /// returns its buffer. Returns `None` if nothing was in progress.
+    fn abort(&self) -> Option<&'static mut [u8]>;
+
+    /// Tries to free a bus that a slave is holding, by clocking SCL until the
+    /// slave releases SDA and then generating a STOP. Returns whether the bus
+    /// is idle afterwards. Call only while no transaction is in progress.
+    fn recover_bus(&self) -> bool;
 }
 
 /// Interface for an I2C Slave hardware driver.
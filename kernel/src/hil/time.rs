@@ -70,6 +70,68 @@ pub trait Alarm: Time {
 
     /// Returns the value set in [`set_alarm`](#tymethod.set_alarm)
     fn get_alarm(&self) -> u32;
+
+    /// The bits of the counter. `now` wraps to 0 after reaching this value,
+    /// and `set_alarm` only looks at these bits. Counters narrower than 32
+    /// bits, such as the nRF51's 24-bit RTC, override the default.
+    fn counter_mask(&self) -> u32 {
+        0xffffffff
+    }
+}
+
+/// An [`Alarm`](trait.Alarm.html) that also keeps a 64-bit clock.
+///
+/// A 32-bit counter at 32kHz wraps about every 36 hours, so comparisons on
+/// `now` are only meaningful between times less than a wrap apart. The
+/// 64-bit clock counts tics since boot and does not wrap in practice, so an
+/// alarm can be set any distance in the future, and one set in the past
+/// fires right away.
+pub trait Alarm64: Alarm {
+    /// Returns the current time in hardware clock units since boot.
+    fn now64(&self) -> u64;
+
+    /// Sets a one-shot alarm to fire when the 64-bit clock reaches `tics`.
+    fn set_alarm64(&self, tics: u64);
+
+    /// Returns the value set in [`set_alarm64`](#tymethod.set_alarm64), or
+    /// the 64-bit time of the value set in `set_alarm`.
+    fn get_alarm64(&self) -> u64;
+}
+
+/// Whether `now` is at least `dt` tics after `reference`.
+///
+/// This is correct across wraps of the counter as long as less than a full
+/// wrap has passed since `reference`, unlike comparing `now` against
+/// `reference.wrapping_add(dt)`.
+pub fn has_elapsed(reference: u32, dt: u32, now: u32) -> bool {
+    now.wrapping_sub(reference) >= dt
+}
+
+// Converts `value` from units of `1/from` seconds to `1/to` seconds, rounding
+// down. Splitting off whole seconds keeps the intermediate products small.
+fn convert(value: u64, from: u32, to: u32) -> u64 {
+    let (from, to) = (from as u64, to as u64);
+    (value / from) * to + (value % from) * to / from
+}
+
+/// Converts clock tics at frequency `F` to milliseconds, rounding down.
+pub fn tics_to_ms<F: Frequency>(tics: u64) -> u64 {
+    convert(tics, F::frequency(), 1000)
+}
+
+/// Converts clock tics at frequency `F` to microseconds, rounding down.
+pub fn tics_to_us<F: Frequency>(tics: u64) -> u64 {
+    convert(tics, F::frequency(), 1000000)
+}
+
+/// Converts milliseconds to clock tics at frequency `F`, rounding down.
+pub fn ms_to_tics<F: Frequency>(ms: u64) -> u64 {
+    convert(ms, 1000, F::frequency())
+}
+
+/// Converts microseconds to clock tics at frequency `F`, rounding down.
+pub fn us_to_tics<F: Frequency>(us: u64) -> u64 {
+    convert(us, 1000000, F::frequency())
 }
 
 /// A client of an implementor of the [`Alarm`](trait.Alarm.html) trait.
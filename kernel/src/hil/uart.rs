@@ -1,4 +1,7 @@
 /// UART hardware interface
+
+use returncode::ReturnCode;
+
 #[derive(Copy, Clone, Debug)]
 pub enum StopBits {
     One = 0,
@@ -38,6 +41,12 @@ pub enum Error {
     /// UART hardware was reset
     ResetError,
 
+    /// An invalid parameter was passed, such as a receive of no bytes
+    InvalidParameter,
+
+    /// The UART cannot do what was asked on this chip or board
+    Unsupported,
+
     /// No error occurred and the command completed successfully
     CommandComplete,
 }
@@ -49,8 +58,9 @@ pub trait UART {
 
     /// Initialize UART
     ///
-    /// # Panics if UARTParams are invalid for the current chip
-    fn init(&self, params: UARTParams);
+    /// Returns `ENOSUPPORT`, leaving the UART as it was, if the chip cannot
+    /// use `params`.
+    fn init(&self, params: UARTParams) -> ReturnCode;
 
     /// Transmit data
     fn transmit(&self, tx_data: &'static mut [u8], tx_len: usize);
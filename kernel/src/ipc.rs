@@ -138,10 +138,7 @@ impl Driver for IPC {
             .enter(appid, |data, _| {
                 data.shared_memory
                     .get_mut(target_id - 1)
-                    .map(|smem| {
-                        *smem = Some(slice);
-                        ReturnCode::SUCCESS
-                    })
+                    .map(|smem| slice.store_in(smem))
                     .unwrap_or(ReturnCode::EINVAL) /* Target process does not exist */
             })
             .unwrap_or(ReturnCode::EBUSY);
@@ -5,6 +5,7 @@ pub mod common;
 
 pub mod callback;
 pub mod container;
+pub mod deferred_call;
 #[macro_use]
 pub mod debug;
 pub mod driver;
@@ -29,8 +30,8 @@ mod platform;
 pub use callback::{AppId, Callback};
 pub use container::Container;
 pub use driver::Driver;
-pub use mem::{AppSlice, AppPtr, Private, Shared};
-pub use platform::{Chip, mpu, Platform, systick};
+pub use mem::{AppSlice, AppPtr, Private, ReadOnlyAppSlice, Shared};
+pub use platform::{Chip, mpu, Platform, systick, KernelAlarm, KERNEL_ALARM_CLIENT};
 pub use platform::systick::SysTick;
 pub use process::{Process, State};
 pub use returncode::ReturnCode;
@@ -47,17 +48,19 @@ pub fn main<P: Platform, C: Chip>(platform: &P,
     loop {
         unsafe {
             chip.service_pending_interrupts();
+            deferred_call::service_pending();
 
             for (i, p) in processes.iter_mut().enumerate() {
                 p.as_mut().map(|process| {
                     sched::do_process(platform, chip, process, AppId::new(i), ipc);
                 });
-                if chip.has_pending_interrupts() {
+                if chip.has_pending_interrupts() || deferred_call::has_tasks() {
                     break;
                 }
             }
 
-            support::atomic(|| if !chip.has_pending_interrupts() && process::processes_blocked() {
+            support::atomic(|| if !chip.has_pending_interrupts() && !deferred_call::has_tasks() &&
+                                   process::processes_blocked() {
                 support::wfi();
             })
         };
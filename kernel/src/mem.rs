@@ -4,6 +4,7 @@ use core::ops::{Deref, DerefMut};
 use core::ptr::Unique;
 use core::slice;
 use process;
+use returncode::ReturnCode;
 
 #[derive(Debug)]
 pub struct Private;
@@ -72,6 +73,8 @@ impl<L, T> AppSlice<L, T> {
         self.ptr.ptr.get() as *const T
     }
 
+    /// Maps the buffer into `appid`'s MPU regions. A process has room for
+    /// four such buffers; beyond that this returns false.
     pub unsafe fn expose_to(&self, appid: AppId) -> bool {
         let ps = &mut process::PROCS;
         if appid.idx() != self.ptr.process.idx() && ps.len() > appid.idx() {
@@ -91,6 +94,42 @@ impl<L, T> AppSlice<L, T> {
     pub fn iter_mut(&mut self) -> slice::IterMut<T> {
         self.as_mut().iter_mut()
     }
+
+    /// Stores this slice in `stored`, the way drivers keep a buffer passed to
+    /// `allow`.
+    ///
+    /// An empty slice revokes the allow instead: `stored` is cleared, so the
+    /// driver no longer touches that memory, and the address and length of
+    /// the buffer it held are returned to the application.
+    pub fn store_in(self, stored: &mut Option<AppSlice<L, T>>) -> ReturnCode {
+        let len = self.len;
+        store_or_revoke(self,
+                        len,
+                        stored,
+                        |old| unsafe { (old.ptr() as usize, old.len()) })
+    }
+}
+
+/// Shared body of the `store_in` methods: stores `slice`, of length `len`, in
+/// `stored`, or if it is empty clears `stored` and returns the address and
+/// length, as given by `bounds`, of the buffer it held.
+fn store_or_revoke<S, F>(slice: S, len: usize, stored: &mut Option<S>, bounds: F) -> ReturnCode
+    where F: FnOnce(&S) -> (usize, usize)
+{
+    if len == 0 {
+        stored.take()
+            .map(|old| {
+                let (ptr, len) = bounds(&old);
+                ReturnCode::SuccessWithBuffer {
+                    ptr: ptr,
+                    len: len,
+                }
+            })
+            .unwrap_or(ReturnCode::SUCCESS)
+    } else {
+        *stored = Some(slice);
+        ReturnCode::SUCCESS
+    }
 }
 
 impl<L, T> AsRef<[T]> for AppSlice<L, T> {
@@ -104,3 +143,52 @@ impl<L, T> AsMut<[T]> for AppSlice<L, T> {
         unsafe { slice::from_raw_parts_mut(self.ptr.ptr.get_mut(), self.len) }
     }
 }
+
+/// A buffer an application has shared with the kernel for reading only.
+///
+/// Unlike `AppSlice`, the buffer may live in the application's flash (e.g.
+/// constants in its text segment), so the kernel must never write to it.
+pub struct ReadOnlyAppSlice<T> {
+    slice: AppSlice<Shared, T>,
+}
+
+impl<T> ReadOnlyAppSlice<T> {
+    pub unsafe fn new(ptr: *const T, len: usize, appid: AppId) -> ReadOnlyAppSlice<T> {
+        ReadOnlyAppSlice { slice: AppSlice::new(ptr as *mut T, len, appid) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.slice.len()
+    }
+
+    pub unsafe fn ptr(&self) -> *const T {
+        self.slice.ptr()
+    }
+
+    pub fn iter(&self) -> slice::Iter<T> {
+        self.slice.iter()
+    }
+
+    /// Read-only equivalent of `AppSlice::store_in`.
+    pub fn store_in(self, stored: &mut Option<ReadOnlyAppSlice<T>>) -> ReturnCode {
+        let len = self.len();
+        store_or_revoke(self,
+                        len,
+                        stored,
+                        |old| unsafe { (old.ptr() as usize, old.len()) })
+    }
+}
+
+impl<T> AsRef<[T]> for ReadOnlyAppSlice<T> {
+    fn as_ref(&self) -> &[T] {
+        self.slice.as_ref()
+    }
+}
+
+/// Buffers shared read-write can always be used where only read access is
+/// needed.
+impl<T> From<AppSlice<Shared, T>> for ReadOnlyAppSlice<T> {
+    fn from(slice: AppSlice<Shared, T>) -> ReadOnlyAppSlice<T> {
+        ReadOnlyAppSlice { slice: slice }
+    }
+}
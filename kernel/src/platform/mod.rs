@@ -1,12 +1,92 @@
+use core::{cmp, u32};
 use driver::Driver;
+use hil::time::{self, Alarm, Frequency};
+use process::Process;
 
 pub mod mpu;
 pub mod systick;
 
 pub trait Platform {
     fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R where F: FnOnce(Option<&Driver>) -> R;
+
+    /// Board-level policy deciding whether `process` may use `driver_num`.
+    ///
+    /// `declared` is whether the process listed the driver in its header (or
+    /// declared no list at all). By default the declaration is honoured, but
+    /// boards can override it to grant or further restrict access, for
+    /// example based on `process.package_name`.
+    #[allow(unused_variables)]
+    fn driver_permitted(&self, process: &Process, driver_num: usize, declared: bool) -> bool {
+        declared
+    }
+
+    /// Alarm the kernel uses to time out `yield` calls and to report the
+    /// current time in each process's info page.
+    ///
+    /// Boards that return `None` reject timed yields with `ENOSUPPORT`, and
+    /// the info page reports a frequency of 0.
+    fn kernel_alarm(&self) -> Option<&KernelAlarm> {
+        None
+    }
 }
 
+/// The parts of an `Alarm` the kernel itself uses.
+///
+/// This is implemented for every `hil::time::Alarm`, so boards can hand the
+/// kernel a virtual alarm of their own. The alarm's client must be set (for
+/// example to `kernel::KERNEL_ALARM_CLIENT`) for virtual alarms to fire.
+pub trait KernelAlarm {
+    fn now(&self) -> u32;
+    fn is_armed(&self) -> bool;
+    fn get_alarm(&self) -> u32;
+    fn set_alarm(&self, tics: u32);
+
+    /// Alarm frequency in Hz.
+    fn frequency(&self) -> u32;
+
+    /// Converts milliseconds to alarm tics, saturating at `u32::MAX` rather
+    /// than wrapping to a shorter timeout.
+    fn ms_to_tics(&self, ms: u32) -> u32;
+}
+
+impl<A: Alarm> KernelAlarm for A {
+    fn now(&self) -> u32 {
+        Alarm::now(self)
+    }
+
+    fn is_armed(&self) -> bool {
+        time::Time::is_armed(self)
+    }
+
+    fn get_alarm(&self) -> u32 {
+        Alarm::get_alarm(self)
+    }
+
+    fn set_alarm(&self, tics: u32) {
+        Alarm::set_alarm(self, tics)
+    }
+
+    fn frequency(&self) -> u32 {
+        <A::Frequency>::frequency()
+    }
+
+    fn ms_to_tics(&self, ms: u32) -> u32 {
+        cmp::min(time::ms_to_tics::<A::Frequency>(ms as u64), u32::MAX as u64) as u32
+    }
+}
+
+/// Client for the alarm returned by `Platform::kernel_alarm`.
+///
+/// There is nothing to do when it fires: the interrupt wakes the main loop,
+/// and the scheduler resumes any process whose timeout has expired.
+pub struct KernelAlarmClient;
+
+impl time::Client for KernelAlarmClient {
+    fn fired(&self) {}
+}
+
+pub static KERNEL_ALARM_CLIENT: KernelAlarmClient = KernelAlarmClient;
+
 pub trait Chip {
     type MPU: mpu::MPU;
     type SysTick: systick::SysTick;
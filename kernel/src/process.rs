@@ -1,14 +1,14 @@
-use callback::AppId;
+use callback::{AppId, CallbackId};
 use common::{RingBuffer, Queue, VolatileCell};
 
 use container;
-use core::{mem, ptr, slice, str};
+use core::{cmp, mem, ptr, slice, str};
 use core::cell::Cell;
 use core::fmt::Write;
 use core::intrinsics;
 use core::ptr::{read_volatile, write_volatile};
 
-use platform::mpu;
+use platform::{mpu, KernelAlarm};
 use returncode::ReturnCode;
 use syscall::Syscall;
 use common::math;
@@ -37,6 +37,36 @@ extern "C" {
 
 pub static mut PROCS: &'static mut [Option<Process<'static>>] = &mut [];
 
+/// The package name of the process with `appid`, if it exists.
+pub fn package_name(appid: AppId) -> Option<&'static str> {
+    let procs = unsafe { &PROCS };
+    procs.get(appid.idx()).and_then(|p| p.as_ref()).map(|p| p.package_name)
+}
+
+/// The entry for the process with `appid` in a board's table keyed by
+/// package name.
+///
+/// Boards use such tables to give the apps they install resources that stay
+/// with an app wherever it is loaded, such as a region of storage. Apps
+/// declare their own names, so the table is only as trustworthy as the
+/// board's control over which apps are installed. So that an app cannot take
+/// over the entry of an app loaded alongside it, an app whose name another
+/// process also declares, or that has no name, gets no entry.
+pub fn package_entry<'a, T>(appid: AppId, table: &'a [(&'static str, T)]) -> Option<&'a T> {
+    let procs = unsafe { &PROCS };
+    let name = match package_name(appid) {
+        Some(name) if name.len() > 0 => name,
+        _ => return None,
+    };
+    let shared = procs.iter()
+        .enumerate()
+        .any(|(idx, p)| idx != appid.idx() && p.as_ref().map_or(false, |p| p.package_name == name));
+    if shared {
+        return None;
+    }
+    table.iter().find(|&&(listed, _)| listed == name).map(|&(_, ref entry)| entry)
+}
+
 pub fn schedule(callback: FunctionCall, appid: AppId) -> bool {
     let procs = unsafe { &mut PROCS };
     let idx = appid.idx();
@@ -48,11 +78,28 @@ pub fn schedule(callback: FunctionCall, appid: AppId) -> bool {
         None => false,
         Some(ref mut p) => {
             // TODO(alevy): validate appid liveness
-            unsafe {
-                HAVE_WORK.set(HAVE_WORK.get() + 1);
+            if p.coalesce_callbacks {
+                if let Some(id) = callback.callback_id {
+                    let replaced = p.tasks.replace_first(Task::FunctionCall(callback), |task| {
+                        match *task {
+                            Task::FunctionCall(ref pending) => pending.callback_id == Some(id),
+                            Task::IPC(_) => false,
+                        }
+                    });
+                    if replaced {
+                        return true;
+                    }
+                }
             }
 
-            p.tasks.enqueue(Task::FunctionCall(callback))
+            let awaited = p.awaits(callback.callback_id);
+            if p.tasks.enqueue(Task::FunctionCall(callback)) {
+                p.add_work(awaited);
+                true
+            } else {
+                p.record_dropped_callback(callback.callback_id);
+                false
+            }
         }
     }
 }
@@ -77,6 +124,18 @@ pub enum FaultResponse {
     Restart,
 }
 
+/// How the callback queue of each process is sized and managed.
+#[derive(Copy, Clone, Debug)]
+pub struct CallbackQueueConfig {
+    /// Number of callbacks that can be pending for a process whose header
+    /// does not request a queue length.
+    pub default_len: usize,
+
+    /// If true, a callback from the same driver and subscribe number as one
+    /// that is still pending replaces it rather than being queued again.
+    pub coalesce: bool,
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum IPCType {
     Service,
@@ -89,6 +148,27 @@ pub enum Task {
     IPC((AppId, IPCType)),
 }
 
+/// Size of the info page shared read-only with each process. It must be a
+/// power of two of at least 32 bytes so a single MPU region covers it.
+pub const INFO_PAGE_SIZE: usize = 32;
+
+/// Layout of the info page. The kernel writes a snapshot of these values
+/// each time it resumes the process, so the process can read them without a
+/// system call. Nothing in it changes while the process runs.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct InfoPage {
+    /// Kernel alarm tics when the process was last resumed. This is not the
+    /// current time; the timer driver gives that.
+    pub ticks: u32,
+    /// Kernel alarm frequency in Hz, or 0 if the board has no kernel alarm
+    pub frequency: u32,
+    /// System calls made by the process
+    pub syscall_count: u32,
+    /// Callbacks delivered to the process
+    pub callback_count: u32,
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct FunctionCall {
     pub r0: usize,
@@ -96,6 +176,8 @@ pub struct FunctionCall {
     pub r2: usize,
     pub r3: usize,
     pub pc: usize,
+    /// The driver and subscribe number this call was scheduled by, if any
+    pub callback_id: Option<CallbackId>,
 }
 
 #[repr(C)]
@@ -119,36 +201,50 @@ struct LoadInfo {
     min_kernel_heap_len: u32,
     pkg_name_offset: u32,
     pkg_name_size: u32,
+    permissions_offset: u32,
+    permissions_size: u32,
+    callback_queue_len: u32,
     checksum: u32,
 }
 
+/// The number of words in each supported version of the header, including
+/// the checksum. Later versions add fields before the checksum, so each is a
+/// prefix of `LoadInfo`.
+fn header_words(version: u32) -> Option<usize> {
+    match version {
+        // No driver permissions or callback queue length
+        1 => Some(19),
+        // Adds driver permissions
+        2 => Some(21),
+        // Adds the callback queue length
+        3 => Some(22),
+        _ => None,
+    }
+}
+
 /// Converts a pointer to memory to a LoadInfo struct
 ///
 /// This function takes a pointer to arbitrary memory and Optionally returns a
 /// LoadInfo struct. This function will validate the header checksum, but does
-/// not perform sanity or security checking on the structure
-unsafe fn parse_and_validate_load_info(address: *const u8) -> Option<&'static LoadInfo> {
-    let load_info = &*(address as *const LoadInfo);
-
-    if load_info.version != 1 {
-        return None;
-    }
+/// not perform sanity or security checking on the structure. Fields that an
+/// older header version lacks are zero.
+unsafe fn parse_and_validate_load_info(address: *const u8) -> Option<LoadInfo> {
+    let words = address as *const u32;
+    let len = match header_words(*words) {
+        Some(len) => len,
+        None => return None,
+    };
+    let header = slice::from_raw_parts(words, len);
 
-    let checksum =
-        load_info.version ^ load_info.total_size ^ load_info.entry_offset ^
-        load_info.rel_data_offset ^ load_info.rel_data_size ^ load_info.text_offset ^
-        load_info.text_size ^ load_info.got_offset ^
-        load_info.got_size ^
-        load_info.data_offset ^ load_info.data_size ^ load_info.bss_mem_offset ^
-        load_info.bss_size ^
-        load_info.min_stack_len ^ load_info.min_app_heap_len ^
-        load_info.min_kernel_heap_len ^ load_info.pkg_name_offset ^ load_info.pkg_name_size;
-
-    if checksum != load_info.checksum {
+    let checksum = header[..len - 1].iter().fold(0, |checksum, word| checksum ^ word);
+    if checksum != header[len - 1] {
         return None;
     }
 
-    Some(load_info)
+    let mut fields = [0u32; 22];
+    fields[..len - 1].copy_from_slice(&header[..len - 1]);
+    fields[21] = header[len - 1];
+    Some(mem::transmute::<[u32; 22], LoadInfo>(fields))
 }
 
 #[derive(Default)]
@@ -200,6 +296,12 @@ pub struct Process<'a> {
     /// How many syscalls have occurred since the process started
     syscall_count: Cell<usize>,
 
+    /// How many callbacks have been delivered since the process started
+    callback_count: Cell<usize>,
+
+    /// The info page, in the process's grant region
+    info_page: *mut InfoPage,
+
     /// What was the most recent syscall
     last_syscall: Cell<Option<Syscall>>,
 
@@ -216,7 +318,9 @@ pub struct Process<'a> {
     /// How to deal with Faults occuring in the process
     fault_response: FaultResponse,
 
-    /// MPU regions are saved as a pointer-size pair.
+    /// MPU regions are saved as a pointer-size pair. They are mapped to
+    /// hardware regions 4-7; region 3 holds the info page, so a process can
+    /// have four buffers shared with it over IPC, not five.
     ///
     /// size is encoded as X where
     /// SIZE = 2^(X + 1) and X >= 4.
@@ -228,11 +332,41 @@ pub struct Process<'a> {
     /// The pointer must be aligned to the size. E.g. if the size is 32 bytes, the pointer must be
     /// 32-byte aligned.
     ///
-    mpu_regions: [Cell<(*const u8, usize)>; 5],
+    mpu_regions: [Cell<(*const u8, usize)>; 4],
 
     tasks: RingBuffer<'a, Task>,
 
+    /// Whether pending callbacks with the same `CallbackId` are coalesced
+    coalesce_callbacks: bool,
+
+    /// How many callbacks were dropped because the task queue was full
+    dropped_callback_count: Cell<usize>,
+
+    /// Dropped callback counts, as (driver number, count) pairs, for the first
+    /// drivers that dropped callbacks for this process
+    dropped_callbacks_per_driver: [Cell<Option<(usize, usize)>>; 4],
+
+    /// Dropped callbacks from drivers beyond those in
+    /// `dropped_callbacks_per_driver`
+    dropped_callbacks_other_drivers: Cell<usize>,
+
+    /// Set while the process waits in a yield with a timeout: the alarm time
+    /// it started waiting at, how many tics it waits for, and the address in
+    /// process memory the outcome is written to (0 if none)
+    yield_timeout: Option<(u32, u32, usize)>,
+
+    /// Set while the process waits for one particular callback
+    yield_for: Option<CallbackId>,
+
+    /// Queued callbacks left out of `HAVE_WORK` while the process waits for
+    /// a callback that is not among them, so the kernel can sleep
+    hidden_work: Option<usize>,
+
     pub package_name: &'static str,
+
+    /// Driver numbers the process declared in its header. `None` if the
+    /// process did not declare any, in which case it may access all drivers.
+    driver_permissions: Option<&'static [u32]>,
 }
 
 // Stores the current number of callbacks enqueued + processes in Running state
@@ -244,10 +378,66 @@ pub fn processes_blocked() -> bool {
 
 impl<'a> Process<'a> {
     pub fn schedule_ipc(&mut self, from: AppId, cb_type: IPCType) {
-        unsafe {
-            HAVE_WORK.set(HAVE_WORK.get() + 1);
+        if self.tasks.enqueue(Task::IPC((from, cb_type))) {
+            self.add_work(false);
+        } else {
+            self.record_dropped_callback(None);
+        }
+    }
+
+    /// Whether the process is waiting for a callback with `callback_id`.
+    fn awaits(&self, callback_id: Option<CallbackId>) -> bool {
+        self.yield_for.map_or(false, |id| callback_id == Some(id))
+    }
+
+    /// Counts a newly queued callback, which wakes the process if it is the
+    /// one it waits for.
+    fn add_work(&mut self, awaited: bool) {
+        match self.hidden_work {
+            Some(hidden) if !awaited => self.hidden_work = Some(hidden + 1),
+            _ => {
+                unsafe {
+                    HAVE_WORK.set(HAVE_WORK.get() + 1);
+                }
+                self.restore_work();
+            }
+        }
+    }
+
+    /// Puts callbacks hidden while waiting back into `HAVE_WORK`.
+    fn restore_work(&mut self) {
+        if let Some(hidden) = self.hidden_work.take() {
+            unsafe {
+                HAVE_WORK.set(HAVE_WORK.get() + hidden);
+            }
+        }
+    }
+
+    fn record_dropped_callback(&self, callback_id: Option<CallbackId>) {
+        self.dropped_callback_count.set(self.dropped_callback_count.get() + 1);
+
+        if let Some(id) = callback_id {
+            for entry in self.dropped_callbacks_per_driver.iter() {
+                match entry.get() {
+                    Some((driver_num, count)) if driver_num == id.driver_num => {
+                        entry.set(Some((driver_num, count + 1)));
+                        return;
+                    }
+                    Some(_) => {}
+                    None => {
+                        entry.set(Some((id.driver_num, 1)));
+                        return;
+                    }
+                }
+            }
+            self.dropped_callbacks_other_drivers
+                .set(self.dropped_callbacks_other_drivers.get() + 1);
         }
-        self.tasks.enqueue(Task::IPC((from, cb_type)));
+    }
+
+    /// Number of callbacks dropped because this process's queue was full.
+    pub fn dropped_callback_count(&self) -> usize {
+        self.dropped_callback_count.get()
     }
 
     pub fn current_state(&self) -> State {
@@ -263,6 +453,70 @@ impl<'a> Process<'a> {
         }
     }
 
+    /// Yields until a callback arrives or `tics` have passed since `now`.
+    ///
+    /// `result` is the address of a word in process memory (or 0) that is
+    /// set to 0 if a callback is delivered, or 1 if the yield times out.
+    pub fn yield_with_timeout(&mut self, now: u32, tics: u32, result: usize) {
+        self.yield_timeout = Some((now, tics, result));
+        self.yield_state();
+    }
+
+    /// Yields until the callback identified by `callback_id` is scheduled.
+    /// Other callbacks stay queued until the process yields again.
+    pub fn yield_for(&mut self, callback_id: CallbackId) {
+        self.yield_for = Some(callback_id);
+        self.yield_state();
+
+        // Until the callback arrives, the queued ones are not work
+        let queued = self.tasks.contains(|task| match *task {
+            Task::FunctionCall(ref call) => call.callback_id == Some(callback_id),
+            Task::IPC(_) => false,
+        });
+        if !queued {
+            let hidden = self.tasks.len();
+            self.hidden_work = Some(hidden);
+            unsafe {
+                HAVE_WORK.set(HAVE_WORK.get() - hidden);
+            }
+        }
+    }
+
+    /// The alarm time the process started waiting at and the number of tics
+    /// it waits for, if it is in a yield with a timeout.
+    pub fn yield_timeout(&self) -> Option<(u32, u32)> {
+        self.yield_timeout.map(|(start, tics, _)| (start, tics))
+    }
+
+    fn set_yield_result(&mut self, timed_out: bool) {
+        if let Some((_, _, result)) = self.yield_timeout.take() {
+            if result != 0 {
+                unsafe {
+                    write_volatile(result as *mut usize, timed_out as usize);
+                }
+            }
+        }
+    }
+
+    /// Returns from a yield whose timeout expired without running a callback.
+    pub unsafe fn resume_after_timeout(&mut self) {
+        self.set_yield_result(true);
+        self.yield_for = None;
+        self.restore_work();
+
+        // Returning to the yield looks like a callback that starts where
+        // the yield returns to
+        let pc = self.yield_pc;
+        self.push_function_call(FunctionCall {
+            pc: pc,
+            r0: 0,
+            r1: 0,
+            r2: 0,
+            r3: 0,
+            callback_id: None,
+        });
+    }
+
     pub unsafe fn fault_state(&mut self) {
         write_volatile(&mut APP_FAULT, 0);
         self.state = State::Fault;
@@ -286,14 +540,46 @@ impl<'a> Process<'a> {
     }
 
     pub fn dequeue_task(&mut self) -> Option<Task> {
-        self.tasks.dequeue().map(|cb| {
+        let task = match self.yield_for {
+            Some(id) => {
+                self.tasks.remove_first(|task| match *task {
+                    Task::FunctionCall(ref call) => call.callback_id == Some(id),
+                    Task::IPC(_) => false,
+                })
+            }
+            None => self.tasks.dequeue(),
+        };
+        task.map(|cb| {
             unsafe {
                 HAVE_WORK.set(HAVE_WORK.get() - 1);
             }
+            self.callback_count.set(self.callback_count.get() + 1);
+            self.yield_for = None;
+            self.restore_work();
+            self.set_yield_result(false);
             cb
         })
     }
 
+    /// Address of the process's info page.
+    pub fn info_page(&self) -> *const u8 {
+        self.info_page as *const u8
+    }
+
+    /// Writes the info page snapshot before the process is resumed.
+    pub fn update_info_page(&self, alarm: Option<&KernelAlarm>) {
+        let (ticks, frequency) = alarm.map_or((0, 0), |alarm| (alarm.now(), alarm.frequency()));
+        unsafe {
+            write_volatile(self.info_page,
+                           InfoPage {
+                               ticks: ticks,
+                               frequency: frequency,
+                               syscall_count: self.syscall_count.get() as u32,
+                               callback_count: self.callback_count.get() as u32,
+                           });
+        }
+    }
+
     pub fn mem_start(&self) -> *const u8 {
         self.memory.as_ptr()
     }
@@ -350,8 +636,15 @@ impl<'a> Process<'a> {
                     mpu::ExecutePermission::ExecutionNotPermitted,
                     mpu::AccessPermission::PrivilegedOnly);
 
+        // Info page read-only, overlapping the grant region
+        mpu.set_mpu(3,
+                    self.info_page as u32,
+                    INFO_PAGE_SIZE.trailing_zeros() - 1,
+                    mpu::ExecutePermission::ExecutionNotPermitted,
+                    mpu::AccessPermission::UnprivilegedReadOnly);
+
         for (i, region) in self.mpu_regions.iter().enumerate() {
-            mpu.set_mpu((i + 3) as u32,
+            mpu.set_mpu((i + 4) as u32,
                         region.get().0 as u32,
                         region.get().1 as u32,
                         mpu::ExecutePermission::ExecutionPermitted,
@@ -378,25 +671,48 @@ impl<'a> Process<'a> {
         return false;
     }
 
+    /// Loads the app at `app_flash_address`, returning the process and how
+    /// much flash and memory it takes. An app with a valid header that fails
+    /// to load comes back as `None` with the flash it takes, so the caller
+    /// can skip it; `None` with no flash means there are no more apps.
     pub unsafe fn create(app_flash_address: *const u8,
                          remaining_app_memory: *mut u8,
                          remaining_app_memory_size: usize,
-                         fault_response: FaultResponse)
+                         fault_response: FaultResponse,
+                         callback_queue: CallbackQueueConfig)
                          -> (Option<Process<'a>>, usize, usize) {
         if let Some(load_info) = parse_and_validate_load_info(app_flash_address) {
             let app_flash_size = load_info.total_size as usize;
 
             // Load the process into memory
             if let Some(load_result) =
-                load(load_info,
+                load(&load_info,
                      app_flash_address,
                      remaining_app_memory,
                      remaining_app_memory_size) {
                 let app_heap_len = align8!(load_info.min_app_heap_len);
                 let kernel_heap_len = align8!(load_info.min_kernel_heap_len);
 
+                // The ring buffer keeps one slot empty to tell full from empty
+                let requested_callback_len = if load_info.callback_queue_len == 0 {
+                    callback_queue.default_len + 1
+                } else {
+                    (load_info.callback_queue_len as usize).saturating_add(1)
+                };
+                // The header is untrusted, so the queue gets at most the
+                // memory left over once everything else fits
+                let other_len = load_result.fixed_len as usize + app_heap_len as usize +
+                                kernel_heap_len as usize +
+                                2 * INFO_PAGE_SIZE;
+                let max_callback_len = remaining_app_memory_size.saturating_sub(other_len) /
+                                       mem::size_of::<Task>();
+                let callback_len = cmp::min(requested_callback_len, max_callback_len);
+                let callback_offset = align8!(callback_len * mem::size_of::<Task>());
+
+                // Twice the info page size leaves room to align it
                 let app_slice_size_unaligned = load_result.fixed_len + app_heap_len +
-                                               kernel_heap_len;
+                                               kernel_heap_len + callback_offset as u32 +
+                                               2 * INFO_PAGE_SIZE as u32;
                 let app_slice_size = math::closest_power_of_two(app_slice_size_unaligned) as usize;
                 // TODO round app_slice_size up to a closer MPU unit.
                 // This is a very conservative approach that rounds up to power of
@@ -429,9 +745,6 @@ impl<'a> Process<'a> {
                 }
 
                 // Allocate memory for callback ring buffer
-                let callback_size = mem::size_of::<Task>();
-                let callback_len = 10;
-                let callback_offset = callback_len * callback_size;
                 kernel_memory_break = kernel_memory_break.offset(-(callback_offset as isize));
 
                 // Set up ring buffer
@@ -439,6 +752,13 @@ impl<'a> Process<'a> {
                                                              callback_len);
                 let tasks = RingBuffer::new(callback_buf);
 
+                // Allocate the info page, aligned to its size for the MPU
+                kernel_memory_break = ((kernel_memory_break as usize - INFO_PAGE_SIZE) &
+                                       !(INFO_PAGE_SIZE - 1)) as
+                                      *mut u8;
+                let info_page = kernel_memory_break as *mut InfoPage;
+                write_volatile(info_page, Default::default());
+
                 let mut process = Process {
                     memory: app_memory,
 
@@ -451,6 +771,8 @@ impl<'a> Process<'a> {
                     min_stack_pointer: load_result.stack_data_boundary,
 
                     syscall_count: Cell::new(0),
+                    callback_count: Cell::new(0),
+                    info_page: info_page,
                     last_syscall: Cell::new(None),
 
                     text: slice::from_raw_parts(app_flash_address, app_flash_size),
@@ -464,12 +786,22 @@ impl<'a> Process<'a> {
                     fault_response: fault_response,
 
                     mpu_regions: [Cell::new((ptr::null(), 0)),
-                                  Cell::new((ptr::null(), 0)),
                                   Cell::new((ptr::null(), 0)),
                                   Cell::new((ptr::null(), 0)),
                                   Cell::new((ptr::null(), 0))],
                     tasks: tasks,
+                    coalesce_callbacks: callback_queue.coalesce,
+                    dropped_callback_count: Cell::new(0),
+                    dropped_callbacks_per_driver: [Cell::new(None),
+                                                   Cell::new(None),
+                                                   Cell::new(None),
+                                                   Cell::new(None)],
+                    dropped_callbacks_other_drivers: Cell::new(0),
+                    yield_timeout: None,
+                    yield_for: None,
+                    hidden_work: None,
                     package_name: load_result.package_name,
+                    driver_permissions: load_result.driver_permissions,
                 };
 
                 if (load_result.init_fn & 0x1) != 1 {
@@ -485,12 +817,16 @@ impl<'a> Process<'a> {
                     r1: process.app_heap_break as usize,
                     r2: process.kernel_memory_break as usize,
                     r3: 0,
+                    callback_id: None,
                 }));
 
                 HAVE_WORK.set(HAVE_WORK.get() + 1);
 
                 return (Some(process), app_flash_size, app_slice_size);
             }
+
+            // The header is valid but the app is not, so skip over it
+            return (None, app_flash_size, 0);
         }
         (None, 0, 0)
     }
@@ -512,6 +848,15 @@ impl<'a> Process<'a> {
         }
     }
 
+    /// Whether the process declared `driver_num` in the list of drivers it
+    /// needs. Processes without a declared list may access any driver.
+    pub fn declares_driver(&self, driver_num: usize) -> bool {
+        match self.driver_permissions {
+            None => true,
+            Some(drivers) => drivers.iter().any(|&driver| driver as usize == driver_num),
+        }
+    }
+
     pub fn in_exposed_bounds(&self, buf_start_addr: *const u8, size: usize) -> bool {
 
         let buf_end_addr = unsafe { buf_start_addr.offset(size as isize) };
@@ -519,6 +864,17 @@ impl<'a> Process<'a> {
         buf_start_addr >= self.mem_start() && buf_end_addr <= self.mem_end()
     }
 
+    /// Like `in_exposed_bounds`, but also accepts buffers in the process's
+    /// flash, which the process may only share for reading.
+    pub fn in_readable_bounds(&self, buf_start_addr: *const u8, size: usize) -> bool {
+        let buf_end_addr = unsafe { buf_start_addr.offset(size as isize) };
+        let text_start = self.text.as_ptr();
+        let text_end = unsafe { text_start.offset(self.text.len() as isize) };
+
+        self.in_exposed_bounds(buf_start_addr, size) ||
+        (buf_start_addr >= text_start && buf_end_addr <= text_end)
+    }
+
     pub unsafe fn alloc(&mut self, size: usize) -> Option<&mut [u8]> {
         let new_break = self.kernel_memory_break.offset(-(size as isize));
         if new_break < self.app_heap_break {
@@ -627,6 +983,9 @@ impl<'a> Process<'a> {
                 2 => Some(Syscall::COMMAND),
                 3 => Some(Syscall::ALLOW),
                 4 => Some(Syscall::MEMOP),
+                5 => Some(Syscall::ALLOW_READONLY),
+                6 => Some(Syscall::YIELD_TIMEOUT),
+                7 => Some(Syscall::YIELD_FOR),
                 _ => None,
             }
         }
@@ -657,6 +1016,10 @@ impl<'a> Process<'a> {
     }
 
     pub fn set_return_code(&mut self, return_code: ReturnCode) {
+        // Buffers are returned as their address in r0 and length in r1
+        if let ReturnCode::SuccessWithBuffer { len, .. } = return_code {
+            self.set_r1(len);
+        }
         let r: isize = return_code.into();
         self.set_r0(r);
     }
@@ -666,6 +1029,11 @@ impl<'a> Process<'a> {
         unsafe { write_volatile(pspr, val) }
     }
 
+    pub fn set_r1(&mut self, val: usize) {
+        let pspr = self.cur_stack as *mut usize;
+        unsafe { write_volatile(pspr.offset(1), val) }
+    }
+
     pub fn r1(&self) -> usize {
         let pspr = self.cur_stack as *const usize;
         unsafe { read_volatile(pspr.offset(1)) }
@@ -890,6 +1258,7 @@ impl<'a> Process<'a> {
 
             // application statistics
             let events_queued = self.tasks.len();
+            let events_dropped = self.dropped_callback_count.get();
             let syscall_count = self.syscall_count.get();
             let last_syscall = self.last_syscall.get();
 
@@ -913,10 +1282,11 @@ impl<'a> Process<'a> {
 
             let _ = writer.write_fmt(format_args!("\
             App: {}   -   [{:?}]\
-            \r\n Events Queued: {}   Syscall Count: {}   ",
+            \r\n Events Queued: {}   Events Dropped: {}   Syscall Count: {}   ",
                                                   self.package_name,
                                                   self.state,
                                                   events_queued,
+                                                  events_dropped,
                                                   syscall_count,
                                                   ));
 
@@ -925,6 +1295,19 @@ impl<'a> Process<'a> {
                 None => writer.write_fmt(format_args!("Last Syscall: None")),
             };
 
+            if events_dropped > 0 {
+                let _ = writer.write_fmt(format_args!("\r\n Dropped by driver:"));
+                for entry in self.dropped_callbacks_per_driver.iter() {
+                    if let Some((driver_num, count)) = entry.get() {
+                        let _ = writer.write_fmt(format_args!("   {:#x}: {}", driver_num, count));
+                    }
+                }
+                let other_drivers = self.dropped_callbacks_other_drivers.get();
+                if other_drivers > 0 {
+                    let _ = writer.write_fmt(format_args!("   other: {}", other_drivers));
+                }
+            }
+
             let _ = writer.write_fmt(format_args!("\
 \r\n\
 \r\n ╔═══════════╤══════════════════════════════════════════╗\
@@ -1020,6 +1403,9 @@ struct LoadResult {
 
     /// The process's package name (used for IPC)
     package_name: &'static str,
+
+    /// The driver numbers the process is permitted to use, if it declared any
+    driver_permissions: Option<&'static [u32]>,
 }
 
 /// Loads the process into memory
@@ -1040,7 +1426,7 @@ struct LoadResult {
 ///
 /// The function returns a `LoadResult` containing metadata about the loaded
 /// process or None if loading failed.
-unsafe fn load(load_info: &'static LoadInfo,
+unsafe fn load(load_info: &LoadInfo,
                flash_start_addr: *const u8,
                mem_base: *mut u8,
                mem_size: usize)
@@ -1051,12 +1437,29 @@ unsafe fn load(load_info: &'static LoadInfo,
     let mut app_name_str = "";
     let _ = str::from_utf8(package_name_byte_array).map(|name_str| { app_name_str = name_str; });
 
+    // A zero offset means the app did not declare which drivers it uses
+    let driver_permissions = if load_info.permissions_offset == 0 {
+        None
+    } else if load_info.permissions_offset % 4 != 0 {
+        // The list is read as words, so it must be word aligned
+        return None;
+    } else if load_info.permissions_offset as u64 + load_info.permissions_size as u64 * 4 >
+              load_info.total_size as u64 {
+        // The list must lie within the app
+        return None;
+    } else {
+        Some(slice::from_raw_parts(flash_start_addr.offset(load_info.permissions_offset as isize) as
+                                   *const u32,
+                                   load_info.permissions_size as usize))
+    };
+
     let mut load_result = LoadResult {
         init_fn: 0,
         app_heap_start: ptr::null(),
         stack_data_boundary: ptr::null(),
         fixed_len: 0,
         package_name: app_name_str,
+        driver_permissions: driver_permissions,
     };
 
     let text_start = flash_start_addr.offset(load_info.text_offset as isize);
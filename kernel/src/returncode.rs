@@ -4,9 +4,10 @@
 //!  Author: Philip Levis <pal@cs.stanford.edu>
 //!  Date: Dec 22, 2016
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ReturnCode {
     SuccessWithValue { value: usize }, // Success value must be positive
+    SuccessWithBuffer { ptr: usize, len: usize }, // Returns a buffer to the caller
     SUCCESS,
     FAIL, //.......... Generic failure condition
     EBUSY, //......... Underlying system is busy; retry
@@ -21,12 +22,14 @@ pub enum ReturnCode {
     ENODEVICE, //..... Device does not exist
     EUNINSTALLED, //.. Device is not physically installed
     ENOACK, //........ Packet transmission not acknowledged
+    ENOACCESS, //..... Caller is not permitted to access the device
 }
 
 impl From<ReturnCode> for isize {
     fn from(original: ReturnCode) -> isize {
         match original {
             ReturnCode::SuccessWithValue { value } => value as isize,
+            ReturnCode::SuccessWithBuffer { ptr, .. } => ptr as isize,
             ReturnCode::SUCCESS => 0,
             ReturnCode::FAIL => -1,
             ReturnCode::EBUSY => -2,
@@ -41,6 +44,7 @@ impl From<ReturnCode> for isize {
             ReturnCode::ENODEVICE => -11,
             ReturnCode::EUNINSTALLED => -12,
             ReturnCode::ENOACK => -13,
+            ReturnCode::ENOACCESS => -14,
         }
     }
 }
@@ -1,11 +1,28 @@
+use callback::CallbackId;
+use core::mem;
 use core::nonzero::NonZero;
-use platform::{Chip, Platform};
+use deferred_call;
+use platform::{Chip, Platform, KernelAlarm};
 use platform::systick::SysTick;
 use process;
 use process::{Process, Task};
 use returncode::ReturnCode;
 use syscall::Syscall;
 
+/// Whether the process may issue system calls to `driver_num`, according to
+/// its declared permissions and the platform's policy.
+fn driver_permitted<P: Platform>(platform: &P, process: &Process, driver_num: usize) -> bool {
+    platform.driver_permitted(process, driver_num, process.declares_driver(driver_num))
+}
+
+/// Arms `alarm` to fire at `when`, unless it is already set to fire sooner.
+fn arm_yield_alarm(alarm: &KernelAlarm, when: u32) {
+    let now = alarm.now();
+    if !alarm.is_armed() || alarm.get_alarm().wrapping_sub(now) > when.wrapping_sub(now) {
+        alarm.set_alarm(when);
+    }
+}
+
 pub unsafe fn do_process<P: Platform, C: Chip>(platform: &P,
                                                chip: &mut C,
                                                process: &mut Process,
@@ -17,20 +34,34 @@ pub unsafe fn do_process<P: Platform, C: Chip>(platform: &P,
     systick.enable(true);
 
     loop {
-        if chip.has_pending_interrupts() || systick.overflowed() || systick.value() <= 500 {
+        if chip.has_pending_interrupts() || deferred_call::has_tasks() || systick.overflowed() ||
+           systick.value() <= 500 {
             break;
         }
 
         match process.current_state() {
             process::State::Running => {
                 process.setup_mpu(chip.mpu());
+                process.update_info_page(platform.kernel_alarm());
                 systick.enable(true);
                 process.switch_to();
                 systick.enable(false);
             }
             process::State::Yielded => {
                 match process.dequeue_task() {
-                    None => break,
+                    None => {
+                        // Nothing to deliver. A yield with a timeout either
+                        // expires now or keeps the alarm set for its deadline.
+                        if let (Some((start, tics)), Some(alarm)) =
+                            (process.yield_timeout(), platform.kernel_alarm()) {
+                            if alarm.now().wrapping_sub(start) >= tics {
+                                process.resume_after_timeout();
+                                continue;
+                            }
+                            arm_yield_alarm(alarm, start.wrapping_add(tics));
+                        }
+                        break;
+                    }
                     Some(cb) => {
                         match cb {
                             Task::FunctionCall(ccb) => {
@@ -80,6 +111,9 @@ pub unsafe fn do_process<P: Platform, C: Chip>(platform: &P,
                             .map(|addr| ReturnCode::SuccessWithValue { value: addr as usize })
                             .unwrap_or(ReturnCode::ENOMEM)
                     },
+                    2 /* Info page address */ => {
+                        ReturnCode::SuccessWithValue { value: process.info_page() as usize }
+                    },
                     _ => ReturnCode::ENOSUPPORT
                 };
                 process.set_return_code(res);
@@ -91,18 +125,59 @@ pub unsafe fn do_process<P: Platform, C: Chip>(platform: &P,
                 // There might be already enqueued callbacks
                 continue;
             }
+            Some(Syscall::YIELD_TIMEOUT) => {
+                let timeout_ms = process.r0() as u32;
+                let result = process.r1();
+                let word_size = mem::size_of::<usize>();
+
+                match platform.kernel_alarm() {
+                    None => process.set_return_code(ReturnCode::ENOSUPPORT),
+                    Some(_) if result != 0 &&
+                               (result % word_size != 0 ||
+                                !process.in_exposed_bounds(result as *const u8, word_size)) => {
+                        process.set_return_code(ReturnCode::EINVAL);
+                    }
+                    Some(alarm) => {
+                        let now = alarm.now();
+                        let tics = alarm.ms_to_tics(timeout_ms);
+                        process.yield_with_timeout(now, tics, result);
+                        process.pop_syscall_stack();
+                        arm_yield_alarm(alarm, now.wrapping_add(tics));
+
+                        // There might be already enqueued callbacks
+                        continue;
+                    }
+                }
+            }
+            Some(Syscall::YIELD_FOR) => {
+                let callback_id = CallbackId {
+                    driver_num: process.r0(),
+                    subscribe_num: process.r1(),
+                };
+                process.yield_for(callback_id);
+                process.pop_syscall_stack();
+
+                // The callback might already be enqueued
+                continue;
+            }
             Some(Syscall::SUBSCRIBE) => {
                 let driver_num = process.r0();
                 let subdriver_num = process.r1();
                 let callback_ptr_raw = process.r2() as *mut ();
                 let appdata = process.r3();
 
-                let res = if callback_ptr_raw as usize == 0 {
+                let res = if !driver_permitted(platform, process, driver_num) {
+                    ReturnCode::ENOACCESS
+                } else if callback_ptr_raw as usize == 0 {
                     ReturnCode::EINVAL
                 } else {
                     let callback_ptr = NonZero::new(callback_ptr_raw);
 
-                    let callback = ::Callback::new(appid, appdata, callback_ptr);
+                    let callback_id = CallbackId {
+                        driver_num: driver_num,
+                        subscribe_num: subdriver_num,
+                    };
+                    let callback = ::Callback::new(appid, callback_id, appdata, callback_ptr);
                     platform.with_driver(driver_num, |driver| match driver {
                         Some(d) => d.subscribe(subdriver_num, callback),
                         None => ReturnCode::ENODEVICE,
@@ -111,28 +186,72 @@ pub unsafe fn do_process<P: Platform, C: Chip>(platform: &P,
                 process.set_return_code(res);
             }
             Some(Syscall::COMMAND) => {
-                let res = platform.with_driver(process.r0(), |driver| match driver {
-                    Some(d) => d.command(process.r1(), process.r2(), appid),
-                    None => ReturnCode::ENODEVICE,
-                });
+                let driver_num = process.r0();
+                let res = if !driver_permitted(platform, process, driver_num) {
+                    ReturnCode::ENOACCESS
+                } else {
+                    platform.with_driver(driver_num, |driver| match driver {
+                        Some(d) => d.command(process.r1(), process.r2(), appid),
+                        None => ReturnCode::ENODEVICE,
+                    })
+                };
                 process.set_return_code(res);
             }
             Some(Syscall::ALLOW) => {
-                let res = platform.with_driver(process.r0(), |driver| {
-                    match driver {
-                        Some(d) => {
-                            let start_addr = process.r2() as *mut u8;
-                            let size = process.r3();
-                            if process.in_exposed_bounds(start_addr, size) {
-                                let slice = ::AppSlice::new(start_addr as *mut u8, size, appid);
-                                d.allow(appid, process.r1(), slice)
-                            } else {
-                                ReturnCode::EINVAL /* memory not allocated to process */
+                let driver_num = process.r0();
+                let res = if !driver_permitted(platform, process, driver_num) {
+                    ReturnCode::ENOACCESS
+                } else {
+                    platform.with_driver(driver_num, |driver| {
+                        match driver {
+                            Some(d) => {
+                                let start_addr = process.r2() as *mut u8;
+                                let size = process.r3();
+                                if start_addr.is_null() || size == 0 {
+                                    // An empty buffer revokes the previous allow
+                                    let slice = ::AppSlice::new(process.mem_start() as *mut u8,
+                                                                0,
+                                                                appid);
+                                    d.allow(appid, process.r1(), slice)
+                                } else if process.in_exposed_bounds(start_addr, size) {
+                                    let slice = ::AppSlice::new(start_addr as *mut u8, size, appid);
+                                    d.allow(appid, process.r1(), slice)
+                                } else {
+                                    ReturnCode::EINVAL /* memory not allocated to process */
+                                }
                             }
+                            None => ReturnCode::ENODEVICE,
                         }
-                        None => ReturnCode::ENODEVICE,
-                    }
-                });
+                    })
+                };
+                process.set_return_code(res);
+            }
+            Some(Syscall::ALLOW_READONLY) => {
+                let driver_num = process.r0();
+                let res = if !driver_permitted(platform, process, driver_num) {
+                    ReturnCode::ENOACCESS
+                } else {
+                    platform.with_driver(driver_num, |driver| {
+                        match driver {
+                            Some(d) => {
+                                let start_addr = process.r2() as *const u8;
+                                let size = process.r3();
+                                if start_addr.is_null() || size == 0 {
+                                    // An empty buffer revokes the previous allow
+                                    let slice =
+                                        ::ReadOnlyAppSlice::new(process.mem_start(), 0, appid);
+                                    d.allow_readonly(appid, process.r1(), slice)
+                                } else if process.in_readable_bounds(start_addr, size) {
+                                    let slice = ::ReadOnlyAppSlice::new(start_addr, size, appid);
+                                    d.allow_readonly(appid, process.r1(), slice)
+                                } else {
+                                    ReturnCode::EINVAL /* memory not readable by process */
+                                }
+                            }
+                            None => ReturnCode::ENODEVICE,
+                        }
+                    })
+                };
                 process.set_return_code(res);
             }
             _ => {}
@@ -1,8 +1,12 @@
 #[derive(Copy, Clone, Debug)]
+#[allow(non_camel_case_types)]
 pub enum Syscall {
     YIELD = 0,
     SUBSCRIBE = 1,
     COMMAND = 2,
     ALLOW = 3,
     MEMOP = 4,
+    ALLOW_READONLY = 5,
+    YIELD_TIMEOUT = 6,
+    YIELD_FOR = 7,
 }
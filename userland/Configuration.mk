@@ -36,6 +36,14 @@ TOCK_ARCHS := cortex-m0 cortex-m4
 ELF2TBF ?= cargo run --manifest-path $(abspath $(TOCK_USERLAND_BASE_DIR))/tools/elf2tbf/Cargo.toml --
 ELF2TBF_ARGS += -n $(PACKAGE_NAME)
 
+# DRIVERS optionally lists the driver numbers the app is permitted to use. If
+# left empty the app is not restricted.
+ELF2TBF_ARGS += $(foreach driver,$(DRIVERS),-d $(driver))
+
+# CALLBACK_QUEUE_LEN optionally sets how many callbacks can be pending for the
+# app. If left empty the board's default is used.
+ELF2TBF_ARGS += $(if $(CALLBACK_QUEUE_LEN),-q $(CALLBACK_QUEUE_LEN))
+
 # Flags for building app Assembly, C, C++ files
 # n.b. make convention is that CPPFLAGS are shared for C and C++ sources
 # [CFLAGS is C only, CXXFLAGS is C++ only]
@@ -0,0 +1,55 @@
+#include <stdio.h>
+
+#include <tock.h>
+#include <console.h>
+#include <adc.h>
+
+#define SAMPLES 100
+#define FREQUENCY 1000
+
+static uint16_t buffers[2][SAMPLES];
+static int next_buffer = 0;
+
+// Report the average of each buffer as it fills. Buffers fill in turn,
+// starting with the first.
+static void buffer_full(int callback_type,
+                        int channel,
+                        int samples,
+                        __attribute__ ((unused)) void* ud) {
+  if (callback_type != ADC_CONTINUOUS_BUFFER_FULL) return;
+
+  uint16_t* buffer = buffers[next_buffer];
+  next_buffer = 1 - next_buffer;
+
+  unsigned sum = 0;
+  for (int i = 0; i < samples; i++) {
+    sum += buffer[i];
+  }
+  unsigned average = sum / samples;
+
+  // 12 bit, reference = VCC/2, gain = 0.5
+  int millivolts = (average * 3300) / 4095;
+  printf("Channel %d: %i mV average over %d samples\n", channel, millivolts, samples);
+}
+
+int main(void) {
+  putstr("[Tock] ADC Continuous Sampling Test\n");
+
+  adc_initialize();
+  adc_set_callback(buffer_full, NULL);
+  adc_set_buffer(buffers[0], SAMPLES);
+  adc_set_double_buffer(buffers[1], SAMPLES);
+
+  // Sample channel 1 a thousand times a second
+  int rc = adc_continuous_buffered_sample(1, FREQUENCY);
+  if (rc < 0) {
+    printf("Error starting sampling: %d\n", rc);
+    return rc;
+  }
+
+  while (1) {
+    yield();
+  }
+
+  return 0;
+}
@@ -0,0 +1,28 @@
+/* vim: set sw=2 expandtab tw=80: */
+
+#include <stdio.h>
+
+#include <i2c_master.h>
+
+// Lists the I2C slaves that answer at the addresses this app may access.
+
+int main(void) {
+  uint8_t found[128];
+
+  if (!i2c_master_exists()) {
+    printf("No I2C master driver\n");
+    return -1;
+  }
+
+  int count = i2c_master_scan(found, sizeof(found));
+  if (count < 0) {
+    printf("Scan failed: %d\n", count);
+    return count;
+  }
+
+  printf("Found %d I2C slave(s)\n", count);
+  for (int i = 0; i < count; i++) {
+    printf("  %#x\n", found[i]);
+  }
+  return 0;
+}
@@ -20,6 +20,10 @@ static void adc_cb(__attribute__ ((unused)) int callback_type,
   data->fired = true;
 }
 
+int adc_channel_count(void) {
+    return command(DRIVER_NUM_ADC, 0, 0);
+}
+
 int adc_set_callback(subscribe_cb callback, void* callback_args) {
     return subscribe(DRIVER_NUM_ADC, 0, callback, callback_args);
 }
@@ -32,6 +36,28 @@ int adc_single_sample(uint8_t channel) {
     return command(DRIVER_NUM_ADC, 2, channel);
 }
 
+int adc_set_buffer(uint16_t* buffer, size_t len) {
+    return allow(DRIVER_NUM_ADC, 0, (void*) buffer, len * sizeof(uint16_t));
+}
+
+int adc_set_double_buffer(uint16_t* buffer, size_t len) {
+    return allow(DRIVER_NUM_ADC, 1, (void*) buffer, len * sizeof(uint16_t));
+}
+
+int adc_buffered_sample(uint8_t channel, uint32_t frequency, size_t count) {
+    int err = command(DRIVER_NUM_ADC, 6, count);
+    if (err < 0) return err;
+    return command(DRIVER_NUM_ADC, 3, channel | (frequency << 8));
+}
+
+int adc_continuous_buffered_sample(uint8_t channel, uint32_t frequency) {
+    return command(DRIVER_NUM_ADC, 4, channel | (frequency << 8));
+}
+
+int adc_stop_sampling(void) {
+    return command(DRIVER_NUM_ADC, 5, 0);
+}
+
 int adc_read_single_sample(uint8_t channel) {
   int err;
 
@@ -47,3 +73,23 @@ int adc_read_single_sample(uint8_t channel) {
 
   return result.reading;
 }
+
+int adc_read_buffered_sample(uint8_t channel, uint32_t frequency, uint16_t* buffer, size_t len) {
+  int err;
+
+  result.fired = false;
+  err = adc_set_callback(adc_cb, (void*) &result);
+  if (err < 0) return err;
+
+  err = adc_set_buffer(buffer, len);
+  if (err < 0) return err;
+
+  err = adc_buffered_sample(channel, frequency, len);
+  if (err < 0) return err;
+
+  // Wait for the buffer to fill. The callback's last argument is the number
+  // of samples.
+  yield_for(&result.fired);
+
+  return result.reading;
+}
@@ -10,13 +10,44 @@ extern "C" {
 
 #define DRIVER_NUM_ADC 7
 
+// Callback types, passed as the first callback argument
+#define ADC_SINGLE_SAMPLE 0
+#define ADC_BUFFER_FULL 1
+#define ADC_CONTINUOUS_BUFFER_FULL 2
+
+// Returns the number of channels, or an error.
+int adc_channel_count(void);
+
+// The callback receives the type, the channel, and either the sample or the
+// number of samples in the full buffer.
 int adc_set_callback(subscribe_cb callback, void* callback_args);
 int adc_initialize(void);
 int adc_single_sample(uint8_t channel);
 
+// Buffers for buffered sampling. Samples are 16 bits each.
+int adc_set_buffer(uint16_t* buffer, size_t len);
+int adc_set_double_buffer(uint16_t* buffer, size_t len);
+
+// Take `count` samples of `channel`, `frequency` times a second, into the
+// first buffer. The callback comes once they are all taken. Fails if the
+// buffer is too small for `count` samples.
+int adc_buffered_sample(uint8_t channel, uint32_t frequency, size_t count);
+
+// Sample `channel` continuously, filling the first buffer, then the second,
+// then the first again, and so on. The callback comes each time a buffer is
+// full; it must be read before the ADC comes back to it.
+int adc_continuous_buffered_sample(uint8_t channel, uint32_t frequency);
+
+// Stop buffered sampling.
+int adc_stop_sampling(void);
+
 // Synchronous function to read a single ADC sample.
 int adc_read_single_sample(uint8_t channel);
 
+// Synchronous function to fill `buffer` with `len` samples. Returns the
+// number of samples taken, or an error.
+int adc_read_buffered_sample(uint8_t channel, uint32_t frequency, uint16_t* buffer, size_t len);
+
 #ifdef __cplusplus
 }
 #endif
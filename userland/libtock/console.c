@@ -7,6 +7,7 @@
 typedef struct putstr_data {
   char* buf;
   int len;
+  int written;
   bool called;
   struct putstr_data* next;
 } putstr_data_t;
@@ -15,11 +16,12 @@ static putstr_data_t *putstr_head = NULL;
 static putstr_data_t *putstr_tail = NULL;
 
 static void putstr_cb(
-                int _x __attribute__ ((unused)),
+                int written,
                 int _y __attribute__ ((unused)),
                 int _z __attribute__ ((unused)),
                 void* ud __attribute__ ((unused))) {
   putstr_data_t* data = putstr_head;
+  data->written = written;
   data->called = true;
   putstr_head = data->next;
 
@@ -30,10 +32,11 @@ static void putstr_cb(
   }
 }
 
-void putnstr(const char *str, size_t len) {
+int putnstr(const char *str, size_t len) {
   putstr_data_t* data = (putstr_data_t*)malloc(sizeof(putstr_data_t));
 
   data->len = len;
+  data->written = 0;
   data->called = false;
   data->buf = (char*)malloc(len * sizeof(char));
   strncpy(data->buf, str, len);
@@ -51,8 +54,10 @@ void putnstr(const char *str, size_t len) {
 
   yield_for(&data->called);
 
+  int written = data->written;
   free(data->buf);
   free(data);
+  return written;
 }
 
 void putnstr_async(const char *str, size_t len, subscribe_cb cb, void* userdata) {
@@ -70,3 +75,49 @@ void putnstr_async(const char *str, size_t len, subscribe_cb cb, void* userdata)
 void putstr(const char *str) {
   putnstr(str, strlen(str));
 }
+
+typedef struct {
+  bool called;
+  int len;
+} getnstr_data_t;
+
+static void getnstr_cb(int len,
+                       int _y __attribute__ ((unused)),
+                       int _z __attribute__ ((unused)),
+                       void* ud) {
+  getnstr_data_t* data = (getnstr_data_t*)ud;
+  data->len = len;
+  data->called = true;
+}
+
+int getnstr_async(char* buf, size_t len, size_t count, subscribe_cb cb, void* userdata) {
+  int err = allow(0, 0, (void*)buf, len);
+  if (err < 0) return err;
+  err = subscribe(0, 0, cb, userdata);
+  if (err < 0) return err;
+  return command(0, 2, count);
+}
+
+static int getnstr_sync(char* buf, size_t len, size_t count) {
+  getnstr_data_t data = { .called = false };
+  int err = getnstr_async(buf, len, count, getnstr_cb, &data);
+  if (err < 0) return err;
+  yield_for(&data.called);
+  return data.len;
+}
+
+int readline(char* buf, size_t len) {
+  if (len == 0) return ESIZE;
+  int n = getnstr_sync(buf, len - 1, 0);
+  if (n < 0) return n;
+  buf[n] = '\0';
+  return n;
+}
+
+int getnstr(char* buf, size_t len) {
+  return getnstr_sync(buf, len, len);
+}
+
+int console_set_foreground(void) {
+  return command(0, 3, 0);
+}
@@ -7,9 +7,32 @@ extern "C" {
 #endif
 
 void putstr(const char* str);
-void putnstr(const char* str, size_t len);
+
+// Writes `len` bytes of `str`, returning the number of bytes written, or an
+// error.
+int putnstr(const char* str, size_t len);
+
+// Starts writing `len` bytes of `str`. The callback receives the number of
+// bytes written.
 void putnstr_async(const char* str, size_t len, subscribe_cb cb, void* userdata);
 
+// Reads a line typed on the console into `buf`, echoing it and handling
+// backspace. The line is null-terminated without its line ending, and is
+// cut short at `len - 1` bytes. Returns its length, or an error.
+int readline(char* buf, size_t len);
+
+// Reads exactly `len` bytes into `buf`, without echo. Returns the number of
+// bytes read, or an error.
+int getnstr(char* buf, size_t len);
+
+// Starts reading a line, if `count` is zero, or `count` bytes into `buf`.
+// The callback receives the number of bytes read.
+int getnstr_async(char* buf, size_t len, size_t count, subscribe_cb cb, void* userdata);
+
+// Makes this app the one that gets console input when several are reading.
+// Returns ENOACCESS if the board has designated the foreground app.
+int console_set_foreground(void);
+
 #ifdef __cplusplus
 }
 #endif
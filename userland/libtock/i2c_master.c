@@ -0,0 +1,103 @@
+#include "i2c_master.h"
+
+int i2c_master_exists(void) {
+  return command(DRIVER_NUM_I2C_MASTER, 0, 0) >= 0;
+}
+
+int i2c_master_subscribe(subscribe_cb callback, void *ud) {
+  return subscribe(DRIVER_NUM_I2C_MASTER, 0, callback, ud);
+}
+
+int i2c_master_set_read_buffer(uint8_t* buf, size_t len) {
+  return allow(DRIVER_NUM_I2C_MASTER, 0, (void *) buf, len);
+}
+
+int i2c_master_set_write_buffer(const uint8_t* buf, size_t len) {
+  return allow_readonly(DRIVER_NUM_I2C_MASTER, 1, (const void *) buf, len);
+}
+
+int i2c_master_write_start(uint8_t address, uint8_t len) {
+  return command(DRIVER_NUM_I2C_MASTER, 1, address | (len << 8));
+}
+
+int i2c_master_read_start(uint8_t address, uint8_t len) {
+  return command(DRIVER_NUM_I2C_MASTER, 2, address | (len << 8));
+}
+
+int i2c_master_write_read_start(uint8_t address, uint8_t write_len, uint8_t read_len) {
+  return command(DRIVER_NUM_I2C_MASTER, 3, address | (write_len << 8) | (read_len << 16));
+}
+
+int i2c_master_scan_start(void) {
+  return command(DRIVER_NUM_I2C_MASTER, 4, 0);
+}
+
+struct data {
+  bool fired;
+  int status;
+  int length;
+};
+
+static void callback(int status, int length, __attribute__((unused)) int v2, void *data)
+{
+  struct data *d = data;
+
+  d->fired = true;
+  d->status = status;
+  d->length = length;
+}
+
+static int wait(struct data *d, int err) {
+  if (err < 0) return err;
+  yield_for(&d->fired);
+
+  if (d->status == SUCCESS)
+    return d->length;
+  return d->status;
+}
+
+int i2c_master_write(uint8_t address, const uint8_t* buf, uint8_t len) {
+  struct data d = { .fired = false };
+  int err;
+
+  err = i2c_master_set_write_buffer(buf, len);
+  if (err < 0) return err;
+  err = i2c_master_subscribe(callback, (void *) &d);
+  if (err < 0) return err;
+  return wait(&d, i2c_master_write_start(address, len));
+}
+
+int i2c_master_read(uint8_t address, uint8_t* buf, uint8_t len) {
+  struct data d = { .fired = false };
+  int err;
+
+  err = i2c_master_set_read_buffer(buf, len);
+  if (err < 0) return err;
+  err = i2c_master_subscribe(callback, (void *) &d);
+  if (err < 0) return err;
+  return wait(&d, i2c_master_read_start(address, len));
+}
+
+int i2c_master_write_read(uint8_t address, uint8_t* buf, uint8_t write_len, uint8_t read_len) {
+  struct data d = { .fired = false };
+  int err;
+
+  err = i2c_master_set_write_buffer(buf, write_len);
+  if (err < 0) return err;
+  err = i2c_master_set_read_buffer(buf, read_len);
+  if (err < 0) return err;
+  err = i2c_master_subscribe(callback, (void *) &d);
+  if (err < 0) return err;
+  return wait(&d, i2c_master_write_read_start(address, write_len, read_len));
+}
+
+int i2c_master_scan(uint8_t* found, size_t len) {
+  struct data d = { .fired = false };
+  int err;
+
+  err = i2c_master_set_read_buffer(found, len);
+  if (err < 0) return err;
+  err = i2c_master_subscribe(callback, (void *) &d);
+  if (err < 0) return err;
+  return wait(&d, i2c_master_scan_start());
+}
@@ -0,0 +1,52 @@
+#pragma once
+
+#include <tock.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define DRIVER_NUM_I2C_MASTER 21
+
+// Raw access to I2C slaves on a bus the kernel shares with its own drivers.
+// The board decides which 7-bit addresses each app may access.
+
+// Does the driver exist?
+int i2c_master_exists(void);
+
+// Register a callback for the end of transactions
+//
+// The callback will receive these parameters, in order:
+//    status: SUCCESS, ENOACK if the slave did not acknowledge, EBUSY if
+//            another master won the bus, or FAIL if the transaction timed out
+//    length: The number of bytes transferred, or for a scan the number of
+//            slaves that answered
+int i2c_master_subscribe(subscribe_cb, void *);
+
+// Provide the buffers reads fill and writes send
+int i2c_master_set_read_buffer(uint8_t*, size_t);
+int i2c_master_set_write_buffer(const uint8_t*, size_t);
+
+// Start a transaction with the slave at `address`
+//
+// Returns ENOACCESS if the app may not access `address`, EINVAL if the
+// buffer is missing or too short, ESIZE if the transfer is longer than the
+// kernel's buffer, and EBUSY if a transaction is already in progress.
+int i2c_master_write_start(uint8_t address, uint8_t len);
+int i2c_master_read_start(uint8_t address, uint8_t len);
+int i2c_master_write_read_start(uint8_t address, uint8_t write_len, uint8_t read_len);
+
+// Start probing every address the app may access. The addresses that answer
+// are stored in the read buffer.
+int i2c_master_scan_start(void);
+
+// Synchronous versions. Return the number of bytes transferred, or for a
+// scan the number of slaves found, or an error.
+int i2c_master_write(uint8_t address, const uint8_t* buf, uint8_t len);
+int i2c_master_read(uint8_t address, uint8_t* buf, uint8_t len);
+int i2c_master_write_read(uint8_t address, uint8_t* buf, uint8_t write_len, uint8_t read_len);
+int i2c_master_scan(uint8_t* found, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
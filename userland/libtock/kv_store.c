@@ -0,0 +1,75 @@
+#include "kv_store.h"
+
+int kv_store_exists(void) {
+  return command(DRIVER_NUM_KV_STORE, 0, 0) >= 0;
+}
+
+int kv_store_subscribe(subscribe_cb callback, void *ud) {
+  return subscribe(DRIVER_NUM_KV_STORE, 0, callback, ud);
+}
+
+int kv_store_set_get_buffer(void* buf, size_t len) {
+  return allow(DRIVER_NUM_KV_STORE, 0, buf, len);
+}
+
+int kv_store_set_value_buffer(const void* buf, size_t len) {
+  return allow_readonly(DRIVER_NUM_KV_STORE, 1, buf, len);
+}
+
+int kv_store_get_start(uint32_t key) {
+  return command(DRIVER_NUM_KV_STORE, 1, key);
+}
+
+int kv_store_set_start(uint32_t key) {
+  return command(DRIVER_NUM_KV_STORE, 2, key);
+}
+
+int kv_store_delete_start(uint32_t key) {
+  return command(DRIVER_NUM_KV_STORE, 3, key);
+}
+
+struct data {
+  bool fired;
+  int status;
+  int length;
+};
+
+static void callback(int status, int length, __attribute__((unused)) int v2, void *data)
+{
+  struct data *d = data;
+
+  d->fired = true;
+  d->status = status;
+  d->length = length;
+}
+
+static int kv_store_wait(int (*start)(uint32_t), uint32_t key) {
+  struct data d = { .fired = false };
+  int err;
+
+  err = kv_store_subscribe(callback, (void *) &d);
+  if (err < 0) return err;
+  err = start(key);
+  if (err < 0) return err;
+  yield_for(&d.fired);
+
+  if (d.status == SUCCESS)
+    return d.length;
+  return d.status;
+}
+
+int kv_store_get(uint32_t key, void* buf, size_t len) {
+  int err = kv_store_set_get_buffer(buf, len);
+  if (err < 0) return err;
+  return kv_store_wait(kv_store_get_start, key);
+}
+
+int kv_store_set(uint32_t key, const void* buf, size_t len) {
+  int err = kv_store_set_value_buffer(buf, len);
+  if (err < 0) return err;
+  return kv_store_wait(kv_store_set_start, key);
+}
+
+int kv_store_delete(uint32_t key) {
+  return kv_store_wait(kv_store_delete_start, key);
+}
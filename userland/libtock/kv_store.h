@@ -0,0 +1,47 @@
+#pragma once
+
+#include <tock.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define DRIVER_NUM_KV_STORE 19
+
+// Small values kept in flash under 32-bit keys. The board gives each app a
+// namespace of keys by its package name; operations from apps it has not
+// listed return ENOACCESS.
+
+// Does the driver exist?
+int kv_store_exists(void);
+
+// Register a callback for the end of gets, sets and deletes
+//
+// The callback will receive these parameters, in order:
+//    status: SUCCESS, ENOSUPPORT if a get found no value, ENOMEM if a set
+//            found the store full, or FAIL if the flash reported an error
+//    length: For gets, the length of the value, which may be longer than
+//            the buffer it was copied to
+int kv_store_subscribe(subscribe_cb, void *);
+
+// Provide the buffer gets fill and the value sets store
+int kv_store_set_get_buffer(void*, size_t);
+int kv_store_set_value_buffer(const void*, size_t);
+
+// Start getting, setting or deleting `key`
+//
+// Returns EBUSY if an operation is already in progress, and EINVAL if a set
+// was started with no value buffer.
+int kv_store_get_start(uint32_t key);
+int kv_store_set_start(uint32_t key);
+int kv_store_delete_start(uint32_t key);
+
+// Synchronous operations. `kv_store_get` returns the length of the value, or
+// an error.
+int kv_store_get(uint32_t key, void* buf, size_t len);
+int kv_store_set(uint32_t key, const void* buf, size_t len);
+int kv_store_delete(uint32_t key);
+
+#ifdef __cplusplus
+}
+#endif
@@ -0,0 +1,86 @@
+#include "log.h"
+
+int log_exists(void) {
+  return command(DRIVER_NUM_LOG, 0, 0) >= 0;
+}
+
+int log_oldest_id(void) {
+  return command(DRIVER_NUM_LOG, 1, 0);
+}
+
+int log_next_id(void) {
+  return command(DRIVER_NUM_LOG, 2, 0);
+}
+
+int log_read_subscribe(subscribe_cb callback, void *ud) {
+  return subscribe(DRIVER_NUM_LOG, 0, callback, ud);
+}
+
+int log_append_subscribe(subscribe_cb callback, void *ud) {
+  return subscribe(DRIVER_NUM_LOG, 1, callback, ud);
+}
+
+int log_set_read_buffer(void* buf, size_t len) {
+  return allow(DRIVER_NUM_LOG, 0, buf, len);
+}
+
+int log_set_append_buffer(const void* buf, size_t len) {
+  return allow_readonly(DRIVER_NUM_LOG, 1, buf, len);
+}
+
+int log_read_start(size_t id) {
+  return command(DRIVER_NUM_LOG, 3, id);
+}
+
+int log_append_start(void) {
+  return command(DRIVER_NUM_LOG, 4, 0);
+}
+
+struct data {
+  bool fired;
+  int status;
+  int value;
+};
+
+static void callback(int status, int value, __attribute__((unused)) int v2, void *data)
+{
+  struct data *d = data;
+
+  d->fired = true;
+  d->status = status;
+  d->value = value;
+}
+
+int log_read(size_t id, void* buf, size_t len) {
+  struct data d = { .fired = false };
+  int err;
+
+  err = log_set_read_buffer(buf, len);
+  if (err < 0) return err;
+  err = log_read_subscribe(callback, (void *) &d);
+  if (err < 0) return err;
+  err = log_read_start(id);
+  if (err < 0) return err;
+  yield_for(&d.fired);
+
+  if (d.status == SUCCESS)
+    return d.value;
+  return d.status;
+}
+
+int log_append(const void* buf, size_t len) {
+  struct data d = { .fired = false };
+  int err;
+
+  err = log_set_append_buffer(buf, len);
+  if (err < 0) return err;
+  err = log_append_subscribe(callback, (void *) &d);
+  if (err < 0) return err;
+  err = log_append_start();
+  if (err < 0) return err;
+  yield_for(&d.fired);
+
+  if (d.status == SUCCESS)
+    return d.value;
+  return d.status;
+}
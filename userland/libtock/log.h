@@ -0,0 +1,54 @@
+#pragma once
+
+#include <tock.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define DRIVER_NUM_LOG 20
+
+// A log in flash shared by all apps. Each entry gets an ID one past the
+// previous entry's, and once the log is full the oldest entries are
+// overwritten.
+
+// Does the driver exist?
+int log_exists(void);
+
+// ID of the oldest entry the log still holds.
+int log_oldest_id(void);
+
+// ID the next entry will get, one past the newest entry.
+int log_next_id(void);
+
+// Register callbacks for the end of reads and appends
+//
+// The read callback will receive these parameters, in order:
+//    status: SUCCESS, ESIZE if the entry was longer than the buffer, or FAIL
+//            if the flash reported an error
+//    length: The number of bytes copied to the buffer
+//    id:     The ID of the entry
+//
+// The append callback will receive the status and the ID of the new entry.
+int log_read_subscribe(subscribe_cb, void *);
+int log_append_subscribe(subscribe_cb, void *);
+
+// Provide the buffer reads fill and the data appends add
+int log_set_read_buffer(void*, size_t);
+int log_set_append_buffer(const void*, size_t);
+
+// Start reading the entry `id`, or appending the whole append buffer
+//
+// Returns EINVAL if the log does not hold the entry or no buffer was
+// provided, and EBUSY if an operation is already in progress.
+int log_read_start(size_t id);
+int log_append_start(void);
+
+// Synchronous reads and appends. `log_read` returns the number of bytes read
+// and `log_append` the ID of the new entry, or an error.
+int log_read(size_t id, void* buf, size_t len);
+int log_append(const void* buf, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
@@ -0,0 +1,82 @@
+#include "nonvolatile_storage.h"
+
+int nv_storage_exists(void) {
+  return command(DRIVER_NUM_NV_STORAGE, 0, 0) >= 0;
+}
+
+int nv_storage_size(void) {
+  return command(DRIVER_NUM_NV_STORAGE, 1, 0);
+}
+
+int nv_storage_read_subscribe(subscribe_cb callback, void *ud) {
+  return subscribe(DRIVER_NUM_NV_STORAGE, 0, callback, ud);
+}
+
+int nv_storage_write_subscribe(subscribe_cb callback, void *ud) {
+  return subscribe(DRIVER_NUM_NV_STORAGE, 1, callback, ud);
+}
+
+int nv_storage_set_read_buffer(void* buf, size_t len) {
+  return allow(DRIVER_NUM_NV_STORAGE, 0, buf, len);
+}
+
+int nv_storage_set_write_buffer(const void* buf, size_t len) {
+  return allow_readonly(DRIVER_NUM_NV_STORAGE, 1, buf, len);
+}
+
+int nv_storage_read_start(size_t offset) {
+  return command(DRIVER_NUM_NV_STORAGE, 2, offset);
+}
+
+int nv_storage_write_start(size_t offset) {
+  return command(DRIVER_NUM_NV_STORAGE, 3, offset);
+}
+
+struct data {
+  bool fired;
+  int status;
+  int length;
+};
+
+static void callback(int status, int length, __attribute__((unused)) int v2, void *data)
+{
+  struct data *d = data;
+
+  d->fired = true;
+  d->status = status;
+  d->length = length;
+}
+
+int nv_storage_read(void* buf, size_t len, size_t offset) {
+  struct data d = { .fired = false };
+  int err;
+
+  err = nv_storage_set_read_buffer(buf, len);
+  if (err < 0) return err;
+  err = nv_storage_read_subscribe(callback, (void *) &d);
+  if (err < 0) return err;
+  err = nv_storage_read_start(offset);
+  if (err < 0) return err;
+  yield_for(&d.fired);
+
+  if (d.status == SUCCESS)
+    return d.length;
+  return d.status;
+}
+
+int nv_storage_write(const void* buf, size_t len, size_t offset) {
+  struct data d = { .fired = false };
+  int err;
+
+  err = nv_storage_set_write_buffer(buf, len);
+  if (err < 0) return err;
+  err = nv_storage_write_subscribe(callback, (void *) &d);
+  if (err < 0) return err;
+  err = nv_storage_write_start(offset);
+  if (err < 0) return err;
+  yield_for(&d.fired);
+
+  if (d.status == SUCCESS)
+    return d.length;
+  return d.status;
+}
@@ -0,0 +1,49 @@
+#pragma once
+
+#include <tock.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define DRIVER_NUM_NV_STORAGE 18
+
+// Apps the board assigns a region of flash, by package name, have a private
+// region addressed from zero whose contents are kept across reboots.
+
+// Does the driver exist?
+int nv_storage_exists(void);
+
+// Size of this app's region in bytes.
+//
+// Returns ENOACCESS if the board gave this app no region, or ENOMEM if its
+// region does not fit in the flash set aside.
+int nv_storage_size(void);
+
+// Register callbacks for the end of reads and writes
+//
+// The callbacks will receive these parameters, in order:
+//    status: SUCCESS, or FAIL if the flash reported an error
+//    length: The number of bytes transferred
+int nv_storage_read_subscribe(subscribe_cb, void *);
+int nv_storage_write_subscribe(subscribe_cb, void *);
+
+// Provide the buffers reads fill and writes store
+int nv_storage_set_read_buffer(void*, size_t);
+int nv_storage_set_write_buffer(const void*, size_t);
+
+// Start reading or writing the whole buffer at `offset` in the region
+//
+// Returns EINVAL if no buffer was provided or it would extend past the end
+// of the region, and EBUSY if an operation is already in progress.
+int nv_storage_read_start(size_t offset);
+int nv_storage_write_start(size_t offset);
+
+// Synchronous reads and writes. Return the number of bytes transferred,
+// or an error.
+int nv_storage_read(void* buf, size_t len, size_t offset);
+int nv_storage_write(const void* buf, size_t len, size_t offset);
+
+#ifdef __cplusplus
+}
+#endif
@@ -8,12 +8,12 @@ extern "C" {
 
 /* SPI system calls */
 int spi_init(void);
-/* All SPI operations depend on which peripheral is
- * active, determined by set_chip_select. Configuration
- * of a peripheral is persistent; e.g., setting the rate R
- * for peripheral 3, then switching to peripheral 2,
- * peripheral 2 will not necessarily have rate R. Then
- * back to peripheral 3, it still has rate R.*/
+/* Each process has its own chip select, rate, phase and
+ * polarity, which the kernel applies before each of its
+ * transfers; other processes' settings do not affect it.
+ * The chip select is an index into the list of chip
+ * selects the board allows, starting at 0, which is also
+ * the default. Settings take effect at the next transfer.*/
 int spi_set_chip_select(unsigned char cs);
 int spi_get_chip_select(void);
 
@@ -38,6 +38,10 @@ int spi_get_polarity(void);
 int spi_hold_low(void);
 int spi_release_low(void);
 
+/* The callback of a transfer receives its length. If the process
+ * revokes or shrinks a buffer before the transfer finishes, it is
+ * cancelled and the callback receives EINVAL and the number of
+ * bytes transferred instead. */
 int spi_write_byte(unsigned char byte);
 int spi_read_buf(const char* str, size_t len);
 int spi_write(const char* str, size_t len, subscribe_cb cb, bool* cond);
@@ -37,8 +37,7 @@ int _open(const char* path, int flags, ...)
 }
 int _write(int fd, const void *buf, uint32_t count)
 {
-    putnstr((const char*)buf, count);
-    return count;
+    return putnstr((const char*)buf, count);
 }
 int _close(int fd)
 {
@@ -1,35 +1,74 @@
 #include "timer.h"
 
-static void delay_cb( __attribute__ ((unused)) int unused0,
-                      __attribute__ ((unused)) int unused1,
-                      __attribute__ ((unused)) int unused2,
-                      void* ud) {
-  *((bool*)ud) = true;
+// The timer `delay_ms` uses
+#define DELAY_TIMER (TIMER_MAX - 1)
+
+// The app's callback, restored once a delay is over
+static subscribe_cb *app_cb = NULL;
+static void *app_userdata = NULL;
+
+static void delay_cb(int now, int id, int unused, void* ud) {
+  if ((unsigned int) id == DELAY_TIMER) {
+    *((bool*)ud) = true;
+  } else if (app_cb != NULL) {
+    // One of the app's own timers fired during the delay
+    app_cb(now, id, unused, app_userdata);
+  }
 }
 
 void delay_ms(uint32_t ms) {
   bool cond = false;
-  timer_subscribe(delay_cb, &cond);
-  timer_oneshot(ms);
+  subscribe(3, 0, delay_cb, &cond);
+  timer_oneshot_id(DELAY_TIMER, ms);
   yield_for(&cond);
+  if (app_cb != NULL) {
+    subscribe(3, 0, app_cb, app_userdata);
+  }
 }
 
 int timer_subscribe(subscribe_cb cb, void *userdata) {
+  app_cb = cb;
+  app_userdata = userdata;
   return subscribe(3, 0, cb, userdata);
 }
 
+// Commands take the timer number in the top four bits of their argument
+static int timer_arg(unsigned int id, uint32_t value) {
+  return (int)((id << 28) | (value & 0x0fffffff));
+}
+
 int timer_oneshot(uint32_t interval_ms) {
-  return command(3, 1, (int)interval_ms);
+  return timer_oneshot_id(0, interval_ms);
 }
 
 int timer_start_repeating(uint32_t interval_ms) {
-  return command(3, 2, (int)interval_ms);
+  return timer_start_repeating_id(0, interval_ms);
 }
 
 int timer_stop(void) {
-  return command(3, 3, 0);
+  return timer_cancel(0);
+}
+
+int timer_oneshot_id(unsigned int id, uint32_t interval_ms) {
+  return command(3, 1, timer_arg(id, interval_ms));
+}
+
+int timer_start_repeating_id(unsigned int id, uint32_t interval_ms) {
+  return command(3, 2, timer_arg(id, interval_ms));
+}
+
+int timer_at(unsigned int id, uint32_t tics) {
+  return command(3, 6, timer_arg(id, tics));
+}
+
+int timer_cancel(unsigned int id) {
+  return command(3, 3, timer_arg(id, 0));
 }
 
 unsigned int timer_read(void) {
   return (unsigned int) command(3, 4, 0);
 }
+
+unsigned int timer_frequency(void) {
+  return (unsigned int) command(3, 5, 0);
+}
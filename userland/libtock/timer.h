@@ -6,10 +6,16 @@ extern "C" {
 
 #include <tock.h>
 
+// Number of timers each app has. Timers are identified by a number below
+// this, and the functions without an `id` argument use timer 0. `delay_ms`
+// uses the last one, TIMER_MAX - 1.
+#define TIMER_MAX 4
+
 /*
  * Sets the callback for timers
  *
- * Side-effects: cancels any existing/outstanding timers
+ * The callback is shared by all of the app's timers. Its first argument is
+ * the tick count when the timer fired, and its second the number of the timer.
  */
 int timer_subscribe(subscribe_cb cb, void *userdata);
 
@@ -18,7 +24,7 @@ int timer_subscribe(subscribe_cb cb, void *userdata);
  *
  * interval_ms - the interval for the timer in milliseconds
  *
- * Side-effects: cancels any existing/outstanding timers
+ * Side-effects: replaces any outstanding use of timer 0
  */
 int timer_start_repeating(uint32_t interval_ms);
 
@@ -27,22 +33,58 @@ int timer_start_repeating(uint32_t interval_ms);
  *
  * interval_ms - the interval for the timer in milliseconds
  *
- * Side-effects: cancels any existing/outstanding timers
+ * Side-effects: replaces any outstanding use of timer 0
  */
 int timer_oneshot(uint32_t interval_ms);
 
 int timer_stop(void);
 
+/*
+ * Starts a oneshot timer
+ *
+ * id - the number of the timer
+ * interval_ms - the interval for the timer in milliseconds, below 2^28
+ */
+int timer_oneshot_id(unsigned int id, uint32_t interval_ms);
+
+/*
+ * Starts a repeating timer
+ *
+ * id - the number of the timer
+ * interval_ms - the interval for the timer in milliseconds, below 2^28
+ */
+int timer_start_repeating_id(unsigned int id, uint32_t interval_ms);
+
+/*
+ * Starts a oneshot timer that fires at an absolute tick count
+ *
+ * id - the number of the timer
+ * tics - fire when the low 28 bits of the tick count next equal those of
+ *        `tics`. A time that has just passed fires right away.
+ */
+int timer_at(unsigned int id, uint32_t tics);
+
+/*
+ * Cancels a timer
+ */
+int timer_cancel(unsigned int id);
+
 /*
  * Get the current counter value of the timer.
  */
 unsigned int timer_read(void);
 
+/*
+ * Get the frequency of the timer's counter in Hz.
+ */
+unsigned int timer_frequency(void);
+
 /*
  * Blocks for the given amount of time in millisecond.
  *
- * This is a wrapper around the `timer` interface, so calling this will cancel
- * any outstanding timers as well as replace the timer callback.
+ * This uses timer TIMER_MAX - 1, cancelling any outstanding use of it. The
+ * callback set with `timer_subscribe` still runs for the app's other timers
+ * during the delay, and is restored once it is over.
  */
 void delay_ms(uint32_t ms);
 
@@ -16,6 +16,24 @@ void yield(void) {
   asm volatile("push {lr}\nsvc 0\npop {pc}" ::: "memory", "r0");
 }
 
+int yield_timeout(unsigned int timeout_ms) {
+  // Set by the kernel to 0 or 1 once it yields; left alone if the call fails
+  volatile int timed_out = -1;
+  register uint32_t r0 __asm__ ("r0") = timeout_ms;
+  register volatile int* r1 __asm__ ("r1") = &timed_out;
+  asm volatile("svc 6" : "+r" (r0), "+r" (r1) : : "memory", "cc", "r2", "r3", "r12", "lr");
+  if (timed_out < 0) {
+    return (int)r0;
+  }
+  return timed_out;
+}
+
+void yield_for_callback(uint32_t driver, uint32_t subscribe) {
+  register uint32_t r0 __asm__ ("r0") = driver;
+  register uint32_t r1 __asm__ ("r1") = subscribe;
+  asm volatile("svc 7" : "+r" (r0), "+r" (r1) : : "memory", "cc", "r2", "r3", "r12", "lr");
+}
+
 int subscribe(uint32_t driver, uint32_t subscribe,
               subscribe_cb cb, void* userdata) {
   register int ret __asm__ ("r0");
@@ -36,12 +54,46 @@ int allow(uint32_t driver, uint32_t allow, void* ptr, size_t size) {
   return ret;
 }
 
+int allow_readonly(uint32_t driver, uint32_t allow, const void* ptr, size_t size) {
+  register int ret __asm__ ("r0");
+  asm volatile("svc 5\nbx lr" ::: "memory", "r0");
+  return ret;
+}
+
+int allow_revoke(uint32_t driver, uint32_t allow, void** prev_ptr, size_t* prev_len) {
+  register uint32_t r0 __asm__ ("r0") = driver;
+  register uint32_t r1 __asm__ ("r1") = allow;
+  register uint32_t r2 __asm__ ("r2") = 0;
+  register uint32_t r3 __asm__ ("r3") = 0;
+  asm volatile("svc 3" : "+r" (r0), "+r" (r1) : "r" (r2), "r" (r3) : "memory");
+
+  int ret = (int) r0;
+  if (ret < 0) {
+    return ret;
+  }
+  if (prev_ptr != NULL) {
+    *prev_ptr = (void*) r0;
+  }
+  if (prev_len != NULL) {
+    *prev_len = r1;
+  }
+  return SUCCESS;
+}
+
 void* memop(uint32_t op_type, int arg1) {
   register void* ret __asm__ ("r0");
   asm volatile("svc 4\nbx lr" ::: "memory", "r0");
   return ret;
 }
 
+const volatile tock_info_page_t* tock_info_page(void) {
+  static const volatile tock_info_page_t* info_page = NULL;
+  if (info_page == NULL) {
+    info_page = memop(2, 0);
+  }
+  return info_page;
+}
+
 bool driver_exists(uint32_t driver) {
   int ret = command(driver, 0, 0);
   return ret >= 0;
@@ -12,16 +12,42 @@ typedef void (subscribe_cb)(int, int, int,void*);
 
 void yield(void);
 void yield_for(bool*);
+// Waits for a callback for at most timeout_ms milliseconds. Returns 0 if a
+// callback ran, 1 if the timeout expired first, or a negative error code.
+int yield_timeout(unsigned int timeout_ms);
+// Waits until the given driver's subscribe callback runs. Other callbacks
+// stay queued until the next yield.
+void yield_for_callback(uint32_t driver, uint32_t subscribe);
 int command(uint32_t driver, uint32_t command, int data);
 int subscribe(uint32_t driver, uint32_t subscribe,
               subscribe_cb cb, void* userdata);
 int allow(uint32_t driver, uint32_t allow, void* ptr, size_t size);
+// Like allow, but the driver may only read the buffer, which can be in flash.
+int allow_readonly(uint32_t driver, uint32_t allow, const void* ptr, size_t size);
+// Revokes the buffer previously allowed to the driver. On success, the
+// previous buffer's address and length (NULL and 0 if there was none) are
+// stored in prev_ptr and prev_len, if they are not NULL.
+int allow_revoke(uint32_t driver, uint32_t allow, void** prev_ptr, size_t* prev_len);
 
 // op_type can be:
 // 0: brk, arg1 is pointer to new memory break
 // 1: sbrk, arg1 is increment to increase/decrease memory break
+// 2: get the address of the info page, arg1 is ignored
 void* memop(uint32_t op_type, int arg1);
 
+// Read-only snapshot the kernel writes each time it resumes the process.
+// `ticks` is the time of that resume, not the current time: it does not
+// advance while the process runs, so it cannot timestamp events. Use
+// `timer_read()` from timer.h for the current time.
+typedef struct {
+  uint32_t ticks;          // kernel alarm tics when the process was resumed
+  uint32_t frequency;      // kernel alarm frequency in Hz, 0 if there is none
+  uint32_t syscall_count;  // system calls made by this process
+  uint32_t callback_count; // callbacks delivered to this process
+} tock_info_page_t;
+
+const volatile tock_info_page_t* tock_info_page(void);
+
 // Checks to see if the given driver number exists on this platform.
 bool driver_exists(uint32_t driver);
 
@@ -39,6 +65,7 @@ bool driver_exists(uint32_t driver);
 #define ENODEVICE  -11
 #define EUNINSTALLED -12
 #define ENOACK -13
+#define ENOACCESS -14
 
 #ifdef __cplusplus
 }
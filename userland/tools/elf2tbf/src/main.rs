@@ -34,6 +34,9 @@ struct LoadInfo {
     min_kernel_heap_len: u32,
     package_name_offset: u32,
     package_name_size: u32,
+    permissions_offset: u32,
+    permissions_size: u32,
+    callback_queue_len: u32,
     checksum: u32,
 }
 
@@ -58,6 +61,9 @@ impl fmt::Display for LoadInfo {
 min_kernel_heap_len: {:>8} {:>#10X}
 package_name_offset: {:>8} {:>#10X}
   package_name_size: {:>8} {:>#10X}
+ permissions_offset: {:>8} {:>#10X}
+   permissions_size: {:>8} {:>#10X}
+ callback_queue_len: {:>8} {:>#10X}
            checksum: {:>8} {:>#10X}
 ",
         self.version, self.version,
@@ -78,6 +84,9 @@ package_name_offset: {:>8} {:>#10X}
         self.min_kernel_heap_len, self.min_kernel_heap_len,
         self.package_name_offset, self.package_name_offset,
         self.package_name_size, self.package_name_size,
+        self.permissions_offset, self.permissions_offset,
+        self.permissions_size, self.permissions_size,
+        self.callback_queue_len, self.callback_queue_len,
         self.checksum, self.checksum,
         )
     }
@@ -89,6 +98,14 @@ fn main() {
     let mut opts = Options::new();
     opts.optopt("o", "", "set output file name", "OUTFILE");
     opts.optopt("n", "", "set package name", "PACKAGE_NAME");
+    opts.optmulti("d",
+                  "driver",
+                  "permit access to driver number DRIVER_NUM (repeatable)",
+                  "DRIVER_NUM");
+    opts.optopt("q",
+                "callback-queue-len",
+                "set the number of callbacks that can be pending (0 for the board default)",
+                "LEN");
     opts.optflag("v", "verbose", "be verbose");
 
     let matches = match opts.parse(&args[1..]) {
@@ -97,6 +114,29 @@ fn main() {
     };
     let output = matches.opt_str("o");
     let package_name = matches.opt_str("n");
+    let permissions = if matches.opt_present("d") {
+        let mut drivers = Vec::new();
+        for arg in matches.opt_strs("d") {
+            for num in arg.split(',').filter(|num| !num.is_empty()) {
+                match parse_driver_num(num) {
+                    Some(driver) => drivers.push(driver),
+                    None => panic!("Error: invalid driver number {:?}", num),
+                }
+            }
+        }
+        Some(drivers)
+    } else {
+        None
+    };
+    let callback_queue_len = match matches.opt_str("q") {
+        Some(len) => {
+            match len.parse::<u32>() {
+                Ok(len) => len,
+                Err(_) => panic!("Error: invalid callback queue length {:?}", len),
+            }
+        }
+        None => 0,
+    };
     let verbose = matches.opt_present("v");
     let input = if !matches.free.is_empty() {
         matches.free[0].clone()
@@ -115,11 +155,21 @@ fn main() {
     match output {
             None => {
                 let mut out = io::stdout();
-                do_work(&file, &mut out, package_name, verbose)
+                do_work(&file,
+                        &mut out,
+                        package_name,
+                        permissions,
+                        callback_queue_len,
+                        verbose)
             }
             Some(name) => {
                 match File::create(Path::new(&name)) {
-                    Ok(mut f) => do_work(&file, &mut f, package_name, verbose),
+                    Ok(mut f) => do_work(&file,
+                                           &mut f,
+                                           package_name,
+                                           permissions,
+                                           callback_queue_len,
+                                           verbose),
                     Err(e) => panic!("Error: {:?}", e),
                 }
             }
@@ -132,6 +182,17 @@ fn print_usage(program: &str, opts: Options) {
     print!("{}", opts.usage(&brief));
 }
 
+/// Parses a driver number given either in decimal or as a `0x` prefixed
+/// hexadecimal value (e.g. `0xff` for IPC).
+fn parse_driver_num(num: &str) -> Option<u32> {
+    let num = num.trim();
+    if num.starts_with("0x") || num.starts_with("0X") {
+        u32::from_str_radix(&num[2..], 16).ok()
+    } else {
+        num.parse::<u32>().ok()
+    }
+}
+
 fn get_section<'a>(input: &'a elf::File, name: &str) -> elf::Section {
     match input.get_section(name) {
         Some(section) => {
@@ -167,6 +228,8 @@ unsafe fn as_byte_slice<'a, T: Copy>(input: &'a T) -> &'a [u8] {
 fn do_work(input: &elf::File,
            output: &mut Write,
            package_name: Option<String>,
+           permissions: Option<Vec<u32>>,
+           callback_queue_len: u32,
            verbose: bool)
            -> io::Result<()> {
     let package_name = package_name.unwrap_or(String::new());
@@ -187,9 +250,18 @@ fn do_work(input: &elf::File,
     let app_heap_len = get_section(input, ".app_heap").data.len() as u32;
     let kernel_heap_len = get_section(input, ".kernel_heap").data.len() as u32;
 
-    let mut total_size = (mem::size_of::<LoadInfo>() + rel_data.len() + text.data.len() +
-                          got.data.len() +
-                          data.data.len() + package_name.len()) as u32;
+    // The list of permitted drivers is an array of u32s placed after the
+    // package name, so it may need padding to be word aligned.
+    let unpadded_len = mem::size_of::<LoadInfo>() + rel_data.len() + text.data.len() +
+                       got.data.len() + data.data.len() + package_name.len();
+    let permissions_pad = match permissions {
+        Some(_) => (4 - unpadded_len % 4) % 4,
+        None => 0,
+    };
+    let permissions_len = permissions.as_ref().map_or(0, |drivers| drivers.len());
+
+    let mut total_size = (unpadded_len + permissions_pad +
+                          permissions_len * mem::size_of::<u32>()) as u32;
 
     let pad = if total_size.count_ones() > 1 {
         let power2len = 1 << (32 - total_size.leading_zeros());
@@ -209,8 +281,15 @@ fn do_work(input: &elf::File,
     let data_size = data.shdr.size as u32;
     let package_name_offset = data_offset + data_size;
     let package_name_size = package_name.len() as u32;
+    // An offset of zero means the app does not declare its drivers and is not
+    // restricted by the kernel.
+    let permissions_offset = match permissions {
+        Some(_) => package_name_offset + package_name_size + permissions_pad as u32,
+        None => 0,
+    };
+    let permissions_size = permissions_len as u32;
 
-    let load_info_version = 1;
+    let load_info_version = 3;
 
     let load_info = LoadInfo {
         version: load_info_version,
@@ -231,13 +310,17 @@ fn do_work(input: &elf::File,
         min_kernel_heap_len: kernel_heap_len,
         package_name_offset: package_name_offset,
         package_name_size: package_name_size,
+        permissions_offset: permissions_offset,
+        permissions_size: permissions_size,
+        callback_queue_len: callback_queue_len,
         checksum: load_info_version ^ total_size ^ entry_offset ^ rel_data_offset ^
                   rel_data_size as u32 ^ text_offset ^ text_size ^ got_offset ^
                   got_size ^
                   data_offset ^ data_size ^ bss.shdr.addr as u32 ^
                   bss.shdr.size as u32 ^
                   stack_len ^ app_heap_len ^
-                  kernel_heap_len ^ package_name_offset ^ package_name_size,
+                  kernel_heap_len ^ package_name_offset ^ package_name_size ^
+                  permissions_offset ^ permissions_size ^ callback_queue_len,
     };
 
     if verbose {
@@ -250,6 +333,12 @@ fn do_work(input: &elf::File,
     try!(output.write_all(got.data.as_ref()));
     try!(output.write_all(data.data.as_ref()));
     try!(output.write_all(package_name.as_ref()));
+    if let Some(drivers) = permissions {
+        try!(output.write_all(&[0u8; 3][..permissions_pad]));
+        for driver in drivers {
+            try!(output.write_all(unsafe { as_byte_slice(&driver) }));
+        }
+    }
 
     let mut pad = pad as usize;
     let zero_buf = [0u8; 512];